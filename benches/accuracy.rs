@@ -0,0 +1,53 @@
+//! Compares the integrators on a two-body orbit with an exact solution, printing how far
+//! each ends up from where it should be and how much energy it loses for a few time
+//! steps. Run with `cargo bench --bench accuracy`. Runge-Kutta runs into the precision of
+//! f32 at small steps, add `--features f64` to see it converge further.
+
+use nbody::{kepler, Integrator, KeplerOrbit, Scalar};
+
+/// Orbits simulated per run. Half of one ends at apoapsis, where errors have not yet
+/// cancelled out as they partly do over whole orbits.
+const ORBITS: Scalar = 0.5;
+
+fn main() {
+    let orbit = KeplerOrbit {
+        semi_major_axis: 100.0,
+        eccentricity: 0.5,
+        mass: 1001.0,
+        g: 1.0,
+    };
+    println!(
+        "a = {}, e = {}, {} orbits of period {:.1}",
+        orbit.semi_major_axis,
+        orbit.eccentricity,
+        ORBITS,
+        orbit.period()
+    );
+    println!(
+        "{:>12} {:>6} {:>8} {:>12} {:>6} {:>12}",
+        "integrator", "steps", "dt", "error", "order", "energy drift"
+    );
+    for &integrator in &[
+        Integrator::Euler,
+        Integrator::Leapfrog,
+        Integrator::RungeKutta4,
+    ] {
+        let mut previous: Option<Scalar> = None;
+        for &steps in &[25, 50, 100, 200, 400] {
+            let time_step = orbit.period() / steps as Scalar;
+            let error = kepler::measure(orbit, 1000.0, integrator, time_step, ORBITS);
+            // Halving the step divides the error by 2^order
+            let order = previous.map(|previous| (previous / error.position).log2());
+            println!(
+                "{:>12} {:>6} {:>8.4} {:>12.3e} {:>6} {:>12.3e}",
+                format!("{:?}", integrator),
+                steps,
+                time_step,
+                error.position,
+                order.map_or_else(String::new, |order| format!("{:.2}", order)),
+                error.energy_drift
+            );
+            previous = Some(error.position);
+        }
+    }
+}
@@ -0,0 +1,45 @@
+//! Times the SIMD force kernel against the scalar one on interaction lists the size of a
+//! leaf, of a typical tree walk and of a brute force sum. Run with
+//! `cargo bench --bench forces`.
+
+use instant::{Duration, Instant};
+use nbody::{forces::LANES, Scalar, Sources};
+
+/// Calls `f` until at least this much time has passed
+const MIN_TIME: Duration = Duration::from_millis(500);
+
+/// Average time per call of `f`
+fn time<F: FnMut() -> cgmath::Vector2<Scalar>>(mut f: F) -> Duration {
+    let mut calls = 0;
+    let mut sink = cgmath::vec2(0.0, 0.0);
+    let start = Instant::now();
+    while start.elapsed() < MIN_TIME {
+        sink += std::hint::black_box(f());
+        calls += 1;
+    }
+    std::hint::black_box(sink);
+    start.elapsed() / calls
+}
+
+fn main() {
+    println!("{} lanes", LANES);
+    for &n in &[16, 256, 4096] {
+        let mut sources = Sources::new();
+        for i in 0..n {
+            let position = cgmath::vec2((i * 37 % 1000) as Scalar, (i * 91 % 1000) as Scalar);
+            sources.push(position, 1.0 + (i % 7) as Scalar);
+        }
+        let sources = std::hint::black_box(sources);
+        let position = cgmath::vec2(420.5, 380.5);
+
+        let scalar = time(|| sources.acceleration_scalar(position, 1.0, None));
+        let simd = time(|| sources.acceleration(position, 1.0, None));
+        println!(
+            "{:>5} sources: scalar {:>10.2?}, simd {:>10.2?}, {:.1}x faster",
+            n,
+            scalar,
+            simd,
+            scalar.as_secs_f64() / simd.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,67 @@
+//! Times the three parts of a step, building the Barnes-Hut tree, walking it to evaluate
+//! forces and resolving collisions, on 1k, 10k and 100k randomly placed particles. The
+//! particles are the same on every run, so criterion compares each run with the last
+//! one. Run with `cargo bench --bench simulation --features bench`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use nbody::{
+    constants, primitives::color, Integrator, Particle, ParticleProperties, Scalar, Simulation,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// Seed the particles are placed with
+const SEED: u64 = 42;
+/// Particles per square of side 1000. The square they are placed in grows with their
+/// number, so that collisions stay as likely at every size.
+const DENSITY: usize = 1000;
+const SIZES: [usize; 3] = [1_000, 10_000, 100_000];
+
+/// Simulation of `n` particles placed at random in a square. Overlapping particles are
+/// merged before it is returned, so that the timed collision passes only look for
+/// collisions among what is left.
+fn simulation(n: usize) -> Simulation {
+    let mut sim = Simulation::new(
+        0.1,
+        0.5,
+        constants::SIM_G,
+        constants::SOFTENING,
+        Integrator::Leapfrog,
+    );
+    let side = 1000.0 * (n as Scalar / DENSITY as Scalar).sqrt();
+    let mut rng = StdRng::seed_from_u64(SEED);
+    sim.add_particles((0..n).map(|_| {
+        let radius = rng.gen_range(0.5..2.0);
+        Particle::new(ParticleProperties {
+            position: cgmath::vec2(rng.gen_range(0.0..side), rng.gen_range(0.0..side)),
+            radius,
+            mass: 50.0 * radius,
+            velocity: cgmath::vec2(0.0, 0.0),
+            acceleration: cgmath::vec2(0.0, 0.0),
+            color: color::WHITE,
+        })
+    }));
+    sim.resolve_collisions();
+    sim
+}
+
+fn step_parts(c: &mut Criterion) {
+    let mut group = c.benchmark_group("step");
+    // A single step of 100k particles takes long enough that fewer samples will do
+    group.sample_size(10);
+    for &n in &SIZES {
+        let mut sim = simulation(n);
+        group.bench_function(BenchmarkId::new("tree build", n), |b| {
+            b.iter(|| sim.build_tree())
+        });
+        group.bench_function(BenchmarkId::new("forces", n), |b| {
+            b.iter(|| sim.evaluate_forces())
+        });
+        group.bench_function(BenchmarkId::new("collisions", n), |b| {
+            b.iter(|| sim.resolve_collisions())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, step_parts);
+criterion_main!(benches);
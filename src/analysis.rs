@@ -0,0 +1,238 @@
+use cgmath::InnerSpace;
+
+use crate::{
+    primitives::{particle::Particle, scalar::Scalar},
+    store::ParticleStore,
+};
+
+/// Energy of a system of particles, as measured by `Simulation::energy`. The total
+/// should stay put as the simulation runs, so watching it drift shows how much the
+/// time step, the integrator and theta cost in accuracy.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Energy {
+    pub kinetic: Scalar,
+    pub potential: Scalar,
+}
+
+impl Energy {
+    pub fn total(&self) -> Scalar {
+        self.kinetic + self.potential
+    }
+
+    /// `2T / |U|`, which settles around 1 once a bound system has relaxed into virial
+    /// equilibrium. Below 1 it collapses, above 1 it expands. `None` without potential
+    /// energy.
+    pub fn virial_ratio(&self) -> Option<Scalar> {
+        Some(2.0 * self.kinetic / self.potential.abs()).filter(|_| self.potential != 0.0)
+    }
+}
+
+/// Total angular momentum of the particles about their center of mass, counterclockwise
+/// positive. Conserved by every force between the particles, so it is a cheap check of a
+/// simulation as long as no boundaries or external potentials push on them.
+pub fn angular_momentum(particles: &ParticleStore) -> Scalar {
+    let center = match particles.center_of_mass() {
+        Some(center) => center,
+        None => return 0.0,
+    };
+    let masses = particles.masses();
+    let mass: Scalar = masses.iter().sum();
+    let momentum = particles
+        .velocities()
+        .iter()
+        .zip(masses)
+        .fold(cgmath::vec2(0.0, 0.0), |sum, (&v, &m)| sum + v * m);
+    let velocity = momentum / mass;
+    particles
+        .positions()
+        .iter()
+        .zip(particles.velocities())
+        .zip(masses)
+        .map(|((&x, &v), &m)| {
+            let (r, u) = (x - center, v - velocity);
+            m * (r.x * u.y - r.y * u.x)
+        })
+        .sum()
+}
+
+/// Barycenter and Lagrange points of a pair of bodies on a circular orbit about each
+/// other. Only meaningful while the pair dominates the system, e.g. a star and a planet.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LagrangePoints {
+    pub barycenter: cgmath::Vector2<Scalar>,
+    /// L1 to L5. L1 lies between the bodies, L2 beyond the lighter one and L3 beyond the
+    /// heavier one. L4 leads the lighter body along its orbit by 60° and L5 trails it.
+    pub points: [cgmath::Vector2<Scalar>; 5],
+}
+
+impl LagrangePoints {
+    /// Lagrange points of `primary` and `secondary`, assuming they orbit each other in a
+    /// circle. `None` if they have no mass or sit on top of each other.
+    pub fn new(primary: &Particle, secondary: &Particle) -> Option<Self> {
+        let (primary, secondary) = if primary.mass >= secondary.mass {
+            (primary, secondary)
+        } else {
+            (secondary, primary)
+        };
+        let mass = primary.mass + secondary.mass;
+        let axis = secondary.position - primary.position;
+        let distance = axis.magnitude();
+        if mass <= 0.0 || distance <= 0.0 {
+            return None;
+        }
+        let mu = secondary.mass / mass;
+        let barycenter =
+            (primary.position * primary.mass + secondary.position * secondary.mass) / mass;
+        let along = axis / distance;
+        // Perpendicular to the axis, on the side the lighter body moves towards
+        let relative_velocity = secondary.velocity - primary.velocity;
+        let turn = axis.x * relative_velocity.y - axis.y * relative_velocity.x;
+        let across = if turn < 0.0 {
+            cgmath::vec2(along.y, -along.x)
+        } else {
+            cgmath::vec2(-along.y, along.x)
+        };
+
+        // The collinear points, in units of the distance from the barycenter. The
+        // heavier body is at -μ and the lighter one at 1 - μ.
+        let collinear = |from: Scalar, to: Scalar| {
+            barycenter + along * distance * bisect(|x| net_force(x, mu), from, to)
+        };
+        let triangular = |side: Scalar| {
+            let height = (3.0 as Scalar).sqrt() / 2.0;
+            barycenter + (along * (0.5 - mu) + across * side * height) * distance
+        };
+        Some(Self {
+            barycenter,
+            points: [
+                collinear(-mu, 1.0 - mu),
+                collinear(1.0 - mu, 2.0),
+                collinear(-2.0, -mu),
+                triangular(1.0),
+                triangular(-1.0),
+            ],
+        })
+    }
+
+    /// Lagrange points of the two heaviest particles, if there are two with mass
+    pub fn of_heaviest(particles: &ParticleStore) -> Option<Self> {
+        let masses = particles.masses();
+        let mut heaviest: Option<usize> = None;
+        let mut runner_up: Option<usize> = None;
+        for (i, &mass) in masses.iter().enumerate() {
+            if heaviest.is_none_or(|h| mass > masses[h]) {
+                runner_up = heaviest;
+                heaviest = Some(i);
+            } else if runner_up.is_none_or(|r| mass > masses[r]) {
+                runner_up = Some(i);
+            }
+        }
+        let (a, b) = (heaviest?, runner_up?);
+        if masses[b] <= 0.0 {
+            return None;
+        }
+        Self::new(&particles.particle(a), &particles.particle(b))
+    }
+}
+
+/// Gravity of both bodies plus the centrifugal force at `x` along the axis of the
+/// co-rotating frame, in units where the distance, the total mass and the angular
+/// velocity are 1
+fn net_force(x: Scalar, mu: Scalar) -> Scalar {
+    let (to_heavy, to_light) = (x + mu, x - 1.0 + mu);
+    x - (1.0 - mu) * to_heavy / to_heavy.abs().powi(3) - mu * to_light / to_light.abs().powi(3)
+}
+
+/// Root of `f` between `from` and `to`, where `f` rises from negative to positive
+fn bisect(f: impl Fn(Scalar) -> Scalar, mut from: Scalar, mut to: Scalar) -> Scalar {
+    for _ in 0..64 {
+        let middle = (from + to) / 2.0;
+        if f(middle) < 0.0 {
+            from = middle;
+        } else {
+            to = middle;
+        }
+    }
+    (from + to) / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(x: Scalar, vy: Scalar, mass: Scalar) -> Particle {
+        let mut p = Particle::empty();
+        p.position = cgmath::vec2(x, 0.0);
+        p.velocity = cgmath::vec2(0.0, vy);
+        p.mass = mass;
+        p
+    }
+
+    #[test]
+    fn it_finds_the_lagrange_points_of_a_star_and_a_planet() {
+        let (star, planet) = (body(0.0, 0.0, 1000.0), body(100.0, 3.0, 1.0));
+        let points = LagrangePoints::new(&planet, &star).unwrap();
+        let [l1, l2, l3, l4, l5] = points.points;
+        assert!((points.barycenter - cgmath::vec2(100.0 / 1001.0, 0.0)).magnitude() < 1e-3);
+
+        // L1 and L2 are about a Hill radius from the planet
+        let hill = 100.0 * (1.0 / 3000.0 as Scalar).cbrt();
+        assert!((planet.position.x - l1.x - hill).abs() < 0.1 * hill);
+        assert!((l2.x - planet.position.x - hill).abs() < 0.1 * hill);
+        assert!((l3.x + 100.0).abs() < 1.0);
+        assert!(l1.y == 0.0 && l2.y == 0.0 && l3.y == 0.0);
+
+        // L4 and L5 make equilateral triangles, L4 ahead of the planet moving up
+        for l in [l4, l5].iter() {
+            assert!(((l - star.position).magnitude() - 100.0).abs() < 1e-3);
+            assert!(((l - planet.position).magnitude() - 100.0).abs() < 1e-3);
+        }
+        assert!(l4.y > 0.0 && l5.y < 0.0);
+    }
+
+    #[test]
+    fn it_measures_angular_momentum_about_the_center_of_mass() {
+        // Two bodies circling counterclockwise, drifting along together
+        let mut particles: ParticleStore = vec![body(0.0, -1.0, 3.0), body(40.0, 3.0, 1.0)]
+            .into_iter()
+            .collect();
+        assert!((angular_momentum(&particles) - 120.0).abs() < 1e-3);
+
+        let drift = cgmath::vec2(5.0, -2.0);
+        let columns = particles.columns_mut();
+        for v in columns.velocities.iter_mut() {
+            *v += drift;
+        }
+        assert!((angular_momentum(&particles) - 120.0).abs() < 1e-3);
+        assert_eq!(angular_momentum(&ParticleStore::new()), 0.0);
+    }
+
+    #[test]
+    fn it_relates_kinetic_to_potential_energy() {
+        let energy = Energy {
+            kinetic: 50.0,
+            potential: -100.0,
+        };
+        assert_eq!(energy.virial_ratio(), Some(1.0));
+        assert_eq!(energy.total(), -50.0);
+        assert_eq!(Energy::default().virial_ratio(), None);
+    }
+
+    #[test]
+    fn it_picks_the_two_heaviest_particles() {
+        let particles: ParticleStore = vec![
+            body(-50.0, 0.0, 5.0),
+            body(0.0, 0.0, 1.0),
+            body(50.0, 0.0, 5.0),
+        ]
+        .into_iter()
+        .collect();
+        let points = LagrangePoints::of_heaviest(&particles).unwrap();
+        // Equal masses put L1 on the barycenter
+        assert!(points.barycenter.magnitude() < 1e-4);
+        assert!(points.points[0].magnitude() < 1e-3);
+
+        let single: ParticleStore = vec![body(0.0, 0.0, 5.0)].into_iter().collect();
+        assert_eq!(LagrangePoints::of_heaviest(&single), None);
+    }
+}
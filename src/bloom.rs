@@ -0,0 +1,461 @@
+use bytemuck::{Pod, Zeroable};
+use wgpu::{
+    util::DeviceExt, BindGroupLayoutDescriptor, PipelineLayoutDescriptor,
+    RenderPassColorAttachmentDescriptor, RenderPassDescriptor, RenderPipelineDescriptor,
+    TextureDescriptor,
+};
+use winit::dpi::PhysicalSize;
+
+/// Format of the offscreen target the scene is drawn into. Floating point so that colors
+/// can go past 1 while blurring.
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+/// Brightest color channel a pixel needs before it starts to glow
+const THRESHOLD: f32 = 0.7;
+/// How strongly the blurred glow is added back onto the scene
+pub const INTENSITY: f32 = 1.5;
+
+/// Uniforms shared by all bloom shaders
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct BloomParams {
+    /// Distance between two texels along the blur direction, in texture coordinates
+    step: [f32; 2],
+    threshold: f32,
+    intensity: f32,
+}
+
+unsafe impl Pod for BloomParams {}
+unsafe impl Zeroable for BloomParams {}
+
+/// Texture along with the view it is rendered into and sampled through
+struct Target {
+    _texture: wgpu::Texture,
+    view: wgpu::TextureView,
+}
+
+impl Target {
+    fn new(device: &wgpu::Device, label: &str, width: u32, height: u32) -> Self {
+        Self::with_samples(
+            device,
+            label,
+            width,
+            height,
+            1,
+            wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+        )
+    }
+
+    fn with_samples(
+        device: &wgpu::Device,
+        label: &str,
+        width: u32,
+        height: u32,
+        sample_count: u32,
+        usage: wgpu::TextureUsage,
+    ) -> Self {
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format: HDR_FORMAT,
+            usage,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        Self {
+            _texture: texture,
+            view,
+        }
+    }
+}
+
+/// Everything that depends on the window size
+struct Targets {
+    scene: Target,
+    /// Multisampled target the scene is drawn into and resolved into `scene` from, when
+    /// multisampling
+    multisampled: Option<Target>,
+    /// Half resolution textures the glow is blurred back and forth between
+    ping: Target,
+    pong: Target,
+    /// Scene to ping, keeping only what is bright enough to glow
+    threshold: wgpu::BindGroup,
+    /// Ping to pong, blurring horizontally
+    horizontal: wgpu::BindGroup,
+    /// Pong to ping, blurring vertically
+    vertical: wgpu::BindGroup,
+    /// Scene and ping to the output
+    composite: wgpu::BindGroup,
+    /// Buffers behind the uniforms of the bind groups
+    _params: [wgpu::Buffer; 3],
+}
+
+/// Post-processing that makes bright particles glow. The scene is drawn into an HDR
+/// texture, whose brightest parts are extracted at half resolution, blurred with a
+/// separable gaussian and added back on top when copying the scene to the output.
+pub struct Bloom {
+    sampler: wgpu::Sampler,
+    layout: wgpu::BindGroupLayout,
+    composite_layout: wgpu::BindGroupLayout,
+    threshold_pipeline: wgpu::RenderPipeline,
+    blur_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+    /// Full screen triangle
+    vertex_buffer: wgpu::Buffer,
+    /// Uniforms of the composite pass. Written on their own to change the intensity.
+    composite_params: wgpu::Buffer,
+    targets: Targets,
+    intensity: f32,
+    /// Samples per pixel of the scene
+    sample_count: u32,
+}
+
+impl Bloom {
+    /// Creates the targets for `size` and pipelines writing into `format`. The scene is
+    /// drawn with `sample_count` samples per pixel.
+    pub fn new(
+        device: &wgpu::Device,
+        size: PhysicalSize<u32>,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let vx_module = device.create_shader_module(&include_wgsl!("shaders/fullscreen.vert.wgsl"));
+        let threshold_module =
+            device.create_shader_module(&include_wgsl!("shaders/bloom_threshold.frag.wgsl"));
+        let blur_module =
+            device.create_shader_module(&include_wgsl!("shaders/bloom_blur.frag.wgsl"));
+        let composite_module =
+            device.create_shader_module(&include_wgsl!("shaders/bloom_composite.frag.wgsl"));
+
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let entries = [
+            texture_entry(0),
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::Sampler {
+                    filtering: true,
+                    comparison: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            texture_entry(3),
+        ];
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Bloom Bind Group Layout"),
+            entries: &entries[..3],
+        });
+        let composite_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Bloom Composite Bind Group Layout"),
+            entries: &entries,
+        });
+
+        let pipeline = |label, layout: &wgpu::BindGroupLayout, module, format| {
+            let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[layout],
+                push_constant_ranges: &[],
+            });
+            device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                primitive: wgpu::PrimitiveState::default(),
+                fragment: Some(wgpu::FragmentState {
+                    entry_point: "main",
+                    module,
+                    targets: &[wgpu::ColorTargetState {
+                        alpha_blend: wgpu::BlendState::REPLACE,
+                        color_blend: wgpu::BlendState::REPLACE,
+                        write_mask: wgpu::ColorWrite::ALL,
+                        format,
+                    }],
+                }),
+                vertex: wgpu::VertexState {
+                    entry_point: "main",
+                    module: &vx_module,
+                    buffers: &[wgpu::VertexBufferLayout {
+                        array_stride: std::mem::size_of::<[f32; 2]>() as wgpu::BufferAddress,
+                        step_mode: wgpu::InputStepMode::Vertex,
+                        attributes: &[wgpu::VertexAttribute {
+                            shader_location: 0,
+                            offset: 0,
+                            format: wgpu::VertexFormat::Float2,
+                        }],
+                    }],
+                },
+            })
+        };
+        let threshold_pipeline = pipeline(
+            "Bloom Threshold Pipeline",
+            &layout,
+            &threshold_module,
+            HDR_FORMAT,
+        );
+        let blur_pipeline = pipeline("Bloom Blur Pipeline", &layout, &blur_module, HDR_FORMAT);
+        let composite_pipeline = pipeline(
+            "Bloom Composite Pipeline",
+            &composite_layout,
+            &composite_module,
+            format,
+        );
+
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Bloom Vertex Buffer"),
+            contents: bytemuck::cast_slice(&[[-1.0f32, -1.0], [3.0, -1.0], [-1.0, 3.0]]),
+            usage: wgpu::BufferUsage::VERTEX,
+        });
+        let composite_params = Self::params_buffer(device, [0.0, 0.0], 0.0);
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Bloom Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..wgpu::SamplerDescriptor::default()
+        });
+        let targets = Self::create_targets(
+            device,
+            size,
+            sample_count,
+            &sampler,
+            &layout,
+            &composite_layout,
+            &composite_params,
+        );
+
+        Self {
+            sampler,
+            layout,
+            composite_layout,
+            threshold_pipeline,
+            blur_pipeline,
+            composite_pipeline,
+            vertex_buffer,
+            composite_params,
+            targets,
+            intensity: 0.0,
+            sample_count,
+        }
+    }
+
+    fn params_buffer(device: &wgpu::Device, step: [f32; 2], intensity: f32) -> wgpu::Buffer {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Bloom Params Buffer"),
+            contents: bytemuck::bytes_of(&BloomParams {
+                step,
+                threshold: THRESHOLD,
+                intensity,
+            }),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        })
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        size: PhysicalSize<u32>,
+        sample_count: u32,
+        sampler: &wgpu::Sampler,
+        layout: &wgpu::BindGroupLayout,
+        composite_layout: &wgpu::BindGroupLayout,
+        composite_params: &wgpu::Buffer,
+    ) -> Targets {
+        let (width, height) = (size.width.max(1), size.height.max(1));
+        let (half_width, half_height) = ((width / 2).max(1), (height / 2).max(1));
+        let scene = Target::new(device, "Bloom Scene Texture", width, height);
+        let multisampled = if sample_count > 1 {
+            Some(Target::with_samples(
+                device,
+                "Multisampled Scene Texture",
+                width,
+                height,
+                sample_count,
+                wgpu::TextureUsage::RENDER_ATTACHMENT,
+            ))
+        } else {
+            None
+        };
+        let ping = Target::new(device, "Bloom Ping Texture", half_width, half_height);
+        let pong = Target::new(device, "Bloom Pong Texture", half_width, half_height);
+
+        let texel = [1.0 / half_width as f32, 1.0 / half_height as f32];
+        let params = [
+            Self::params_buffer(device, [0.0, 0.0], 0.0),
+            Self::params_buffer(device, [texel[0], 0.0], 0.0),
+            Self::params_buffer(device, [0.0, texel[1]], 0.0),
+        ];
+        let bind_group = |label, source: &Target, params: &wgpu::Buffer| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(label),
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&source.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: params.as_entire_binding(),
+                    },
+                ],
+            })
+        };
+        let threshold = bind_group("Bloom Threshold Bind Group", &scene, &params[0]);
+        let horizontal = bind_group("Bloom Horizontal Bind Group", &ping, &params[1]);
+        let vertical = bind_group("Bloom Vertical Bind Group", &pong, &params[2]);
+        let composite = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Bloom Composite Bind Group"),
+            layout: composite_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&scene.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: composite_params.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&ping.view),
+                },
+            ],
+        });
+
+        Targets {
+            scene,
+            multisampled,
+            ping,
+            pong,
+            threshold,
+            horizontal,
+            vertical,
+            composite,
+            _params: params,
+        }
+    }
+
+    /// Recreates the offscreen targets for a new window size
+    pub fn resize(&mut self, device: &wgpu::Device, size: PhysicalSize<u32>) {
+        self.targets = Self::create_targets(
+            device,
+            size,
+            self.sample_count,
+            &self.sampler,
+            &self.layout,
+            &self.composite_layout,
+            &self.composite_params,
+        );
+    }
+
+    /// Attachment and resolve target the scene has to be drawn into, with pipelines
+    /// targeting `HDR_FORMAT` and `sample_count` samples
+    pub fn scene_attachment(&self) -> (&wgpu::TextureView, Option<&wgpu::TextureView>) {
+        match &self.targets.multisampled {
+            Some(multisampled) => (&multisampled.view, Some(&self.targets.scene.view)),
+            None => (&self.targets.scene.view, None),
+        }
+    }
+
+    /// Sets how strongly the glow is added onto the scene. At 0 the scene is copied to
+    /// the output as is and the blur passes are skipped.
+    pub fn set_intensity(&mut self, queue: &wgpu::Queue, intensity: f32) {
+        self.intensity = intensity;
+        queue.write_buffer(
+            &self.composite_params,
+            0,
+            bytemuck::bytes_of(&BloomParams {
+                step: [0.0, 0.0],
+                threshold: THRESHOLD,
+                intensity,
+            }),
+        );
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.intensity > 0.0
+    }
+
+    /// Records the passes extracting and blurring the glow of the scene drawn into
+    /// `scene_attachment`. Only needs to be done once per frame however often it is composited.
+    pub fn blur(&self, encoder: &mut wgpu::CommandEncoder) {
+        if !self.is_enabled() {
+            return;
+        }
+        let targets = &self.targets;
+        let passes = [
+            (&self.threshold_pipeline, &targets.threshold, &targets.ping),
+            (&self.blur_pipeline, &targets.horizontal, &targets.pong),
+            (&self.blur_pipeline, &targets.vertical, &targets.ping),
+        ];
+        for &(pipeline, bind_group, output) in passes.iter() {
+            self.fullscreen_pass(encoder, pipeline, bind_group, &output.view);
+        }
+    }
+
+    /// Records the pass writing the scene with its glow on top into `view`
+    pub fn composite(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        self.fullscreen_pass(
+            encoder,
+            &self.composite_pipeline,
+            &self.targets.composite,
+            view,
+        );
+    }
+
+    fn fullscreen_pass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::RenderPipeline,
+        bind_group: &wgpu::BindGroup,
+        view: &wgpu::TextureView,
+    ) {
+        let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: None,
+            color_attachments: &[RenderPassColorAttachmentDescriptor {
+                attachment: view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        rpass.set_pipeline(pipeline);
+        rpass.set_bind_group(0, bind_group, &[]);
+        rpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        rpass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,187 @@
+use serde::{Deserialize, Serialize};
+
+use crate::constants::{MAX_X, MAX_Y, MIN_X, MIN_Y};
+use crate::primitives::scalar::Scalar;
+use crate::store::ParticleStore;
+
+/// What happens to particles at the edges of the world box
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BoundaryCondition {
+    /// No walls. Particles that drift further than one world size outside of the box
+    /// are removed.
+    Open,
+    /// Particles bounce off the edges elastically
+    #[default]
+    Reflective,
+    /// Particles leaving one edge enter at the opposite one, and feel the pull of the
+    /// nearest image of every other particle
+    Periodic,
+}
+
+impl BoundaryCondition {
+    /// The boundary condition to switch to when cycling through them
+    pub fn next(self) -> Self {
+        match self {
+            BoundaryCondition::Open => BoundaryCondition::Reflective,
+            BoundaryCondition::Reflective => BoundaryCondition::Periodic,
+            BoundaryCondition::Periodic => BoundaryCondition::Open,
+        }
+    }
+
+    /// Size of the repeating cell for periodic boundaries
+    pub fn period(self) -> Option<cgmath::Vector2<Scalar>> {
+        match self {
+            BoundaryCondition::Periodic => Some(cgmath::vec2(MAX_X - MIN_X, MAX_Y - MIN_Y)),
+            _ => None,
+        }
+    }
+
+    /// Moves (or removes) the particles according to this boundary condition. Must be
+    /// called after every position update. Fixed particles are left alone.
+    pub fn apply(self, particles: &mut ParticleStore) {
+        match self {
+            BoundaryCondition::Open => {
+                let (w, h) = (MAX_X - MIN_X, MAX_Y - MIN_Y);
+                particles.retain(|p| {
+                    p.fixed
+                        || (MIN_X - w..=MAX_X + w).contains(&p.position.x)
+                            && (MIN_Y - h..=MAX_Y + h).contains(&p.position.y)
+                });
+            }
+            BoundaryCondition::Reflective => {
+                let columns = particles.columns_mut();
+                let bodies = columns
+                    .positions
+                    .iter_mut()
+                    .zip(columns.velocities.iter_mut())
+                    .zip(columns.radii.iter())
+                    .zip(columns.fixed);
+                for (((position, velocity), &radius), &fixed) in bodies {
+                    if fixed {
+                        continue;
+                    }
+                    let (x, vx) = reflect(position.x, velocity.x, radius, MIN_X, MAX_X);
+                    let (y, vy) = reflect(position.y, velocity.y, radius, MIN_Y, MAX_Y);
+                    *position = cgmath::vec2(x, y);
+                    *velocity = cgmath::vec2(vx, vy);
+                }
+            }
+            BoundaryCondition::Periodic => {
+                let columns = particles.columns_mut();
+                for (position, _) in columns
+                    .positions
+                    .iter_mut()
+                    .zip(columns.fixed)
+                    .filter(|(_, &fixed)| !fixed)
+                {
+                    position.x = MIN_X + (position.x - MIN_X).rem_euclid(MAX_X - MIN_X);
+                    position.y = MIN_Y + (position.y - MIN_Y).rem_euclid(MAX_Y - MIN_Y);
+                }
+            }
+        }
+    }
+}
+
+/// Mirrors a particle that crossed a wall back inside and points its velocity away from
+/// the wall, keeping its speed
+fn reflect(
+    position: Scalar,
+    velocity: Scalar,
+    radius: Scalar,
+    min: Scalar,
+    max: Scalar,
+) -> (Scalar, Scalar) {
+    let (min, max) = (min + radius, max - radius);
+    if position < min {
+        ((2.0 * min - position).min(max), velocity.abs())
+    } else if position > max {
+        ((2.0 * max - position).max(min), -velocity.abs())
+    } else {
+        (position, velocity)
+    }
+}
+
+/// Shortest displacement from one point to another (or any of its images) in a world
+/// that repeats every `period`. Without a period this is just the plain displacement.
+pub fn minimum_image(
+    d: cgmath::Vector2<Scalar>,
+    period: Option<cgmath::Vector2<Scalar>>,
+) -> cgmath::Vector2<Scalar> {
+    match period {
+        Some(period) => cgmath::vec2(
+            d.x - period.x * (d.x / period.x).round(),
+            d.y - period.y * (d.y / period.y).round(),
+        ),
+        None => d,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitives::particle::Particle;
+
+    fn particle(x: Scalar, y: Scalar, vx: Scalar, vy: Scalar) -> Particle {
+        Particle {
+            position: cgmath::vec2(x, y),
+            mass: 50.0,
+            radius: 1.0,
+            velocity: cgmath::vec2(vx, vy),
+            ..Particle::empty()
+        }
+    }
+
+    #[test]
+    fn it_bounces_off_walls_elastically() {
+        let mut particles: ParticleStore = vec![particle(MAX_X + 1.0, 500.0, 3.0, 4.0)]
+            .into_iter()
+            .collect();
+        BoundaryCondition::Reflective.apply(&mut particles);
+        let p = particles.particle(0);
+        assert_eq!(p.position, cgmath::vec2(MAX_X - 3.0, 500.0));
+        assert_eq!(p.velocity, cgmath::vec2(-3.0, 4.0));
+    }
+
+    #[test]
+    fn it_wraps_periodic_positions() {
+        let mut particles: ParticleStore = vec![particle(MIN_X - 10.0, MAX_Y + 10.0, 1.0, 1.0)]
+            .into_iter()
+            .collect();
+        BoundaryCondition::Periodic.apply(&mut particles);
+        assert_eq!(
+            particles.positions()[0],
+            cgmath::vec2(MAX_X - 10.0, MIN_Y + 10.0)
+        );
+
+        let period = BoundaryCondition::Periodic.period();
+        let d = minimum_image(cgmath::vec2(MAX_X - MIN_X - 10.0, 0.0), period);
+        assert_eq!(d, cgmath::vec2(-10.0, 0.0));
+    }
+
+    #[test]
+    fn it_removes_particles_far_outside_open_boundaries() {
+        let mut particles: ParticleStore = vec![
+            particle(MAX_X + 10.0, 500.0, 0.0, 0.0),
+            particle(MAX_X * 5.0, 500.0, 0.0, 0.0),
+        ]
+        .into_iter()
+        .collect();
+        BoundaryCondition::Open.apply(&mut particles);
+        assert_eq!(particles.len(), 1);
+    }
+
+    #[test]
+    fn it_leaves_fixed_particles_alone() {
+        let mut far = particle(MAX_X * 5.0, 500.0, 1.0, 0.0);
+        far.fixed = true;
+        for &boundary in &[
+            BoundaryCondition::Open,
+            BoundaryCondition::Reflective,
+            BoundaryCondition::Periodic,
+        ] {
+            let mut particles: ParticleStore = vec![far.clone()].into_iter().collect();
+            boundary.apply(&mut particles);
+            assert_eq!(particles.positions(), &[far.position], "{:?}", boundary);
+        }
+    }
+}
@@ -0,0 +1,299 @@
+use bytemuck::{Pod, Zeroable};
+use nbody::{
+    constants,
+    primitives::scalar::{from_f32, to_f32, Scalar},
+    utils,
+};
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+
+/// Zoom levels are clamped to this range
+const MIN_ZOOM: f32 = 0.05;
+const MAX_ZOOM: f32 = 100.0;
+
+/// Orthographic 2D camera looking at the world from above
+#[derive(Debug)]
+pub struct Camera {
+    /// World space position at the center of the window
+    pub center: cgmath::Vector2<f32>,
+    /// Magnification. At 1.0 the whole world box fits in the window.
+    pub zoom: f32,
+    /// Counterclockwise angle in radians from the world y axis to the direction pointing
+    /// up on screen
+    pub rotation: f32,
+    /// Window width / window height
+    pub aspect: f32,
+    /// Window height in pixels
+    pub height: f32,
+}
+
+impl Camera {
+    pub fn new(window_size: PhysicalSize<u32>) -> Self {
+        Self {
+            center: cgmath::vec2(
+                to_f32(constants::MIN_X + constants::MAX_X) / 2.0,
+                to_f32(constants::MIN_Y + constants::MAX_Y) / 2.0,
+            ),
+            zoom: 1.0,
+            rotation: 0.0,
+            aspect: window_size.width as f32 / window_size.height as f32,
+            height: window_size.height as f32,
+        }
+    }
+
+    pub fn resize(&mut self, window_size: PhysicalSize<u32>) {
+        self.aspect = window_size.width as f32 / window_size.height as f32;
+        self.height = window_size.height as f32;
+    }
+
+    /// Size of a pixel in world units
+    pub fn pixel_size(&self) -> f32 {
+        2.0 * self.half_extents().y / self.height
+    }
+
+    /// Half of the visible world extent along the x and y axes
+    fn half_extents(&self) -> cgmath::Vector2<f32> {
+        let half_world =
+            to_f32((constants::MAX_X - constants::MIN_X).max(constants::MAX_Y - constants::MIN_Y))
+                / 2.0
+                / self.zoom;
+        // Fit the world box along the shorter window axis
+        if self.aspect >= 1.0 {
+            cgmath::vec2(half_world * self.aspect, half_world)
+        } else {
+            cgmath::vec2(half_world, half_world / self.aspect)
+        }
+    }
+
+    /// Min and max values of the x and y axes that are currently visible, before the
+    /// view is rotated around the center
+    pub fn visible_bounds(&self) -> (utils::MinMax<f64>, utils::MinMax<f64>) {
+        let half = self.half_extents();
+        (
+            utils::MinMax {
+                min: (self.center.x - half.x) as f64,
+                max: (self.center.x + half.x) as f64,
+            },
+            utils::MinMax {
+                min: (self.center.y - half.y) as f64,
+                max: (self.center.y + half.y) as f64,
+            },
+        )
+    }
+
+    pub fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
+        let half = self.half_extents();
+        let center = self.center.extend(0.0);
+        cgmath::ortho(-half.x, half.x, -half.y, half.y, -1.0, 1.0)
+            * cgmath::Matrix4::from_angle_z(cgmath::Rad(-self.rotation))
+            * cgmath::Matrix4::from_translation(-center)
+    }
+
+    /// Converts a position in window space into world space
+    pub fn screen_to_world(
+        &self,
+        pos: PhysicalPosition<f64>,
+        window_size: PhysicalSize<u32>,
+    ) -> cgmath::Vector2<Scalar> {
+        let (xv, yv) = self.visible_bounds();
+        let unrotated = utils::normalize_window_coordinates(&utils::ViewportTransformOptions {
+            window_pos: cgmath::Vector2::new(pos.x, pos.y),
+            xw: utils::MinMax::<f64> {
+                min: 0.0,
+                max: window_size.width as f64,
+            },
+            // Min and max needs to be swapped here as the axes in window space begins at
+            // the top left corner and not the bottom left corner.
+            yw: utils::MinMax::<f64> {
+                min: window_size.height as f64,
+                max: 0.0,
+            },
+            xv,
+            yv,
+        });
+        let center = self.center.map(Scalar::from);
+        center + rotate(unrotated - center, from_f32(self.rotation))
+    }
+
+    /// Converts a position in world space into window space (pixels from the top left
+    /// corner). The inverse of `screen_to_world`.
+    pub fn world_to_screen(
+        &self,
+        pos: cgmath::Vector2<Scalar>,
+        window_size: PhysicalSize<u32>,
+    ) -> cgmath::Vector2<f32> {
+        let (xv, yv) = self.visible_bounds();
+        let center = self.center.map(Scalar::from);
+        let pos = center + rotate(pos - center, -from_f32(self.rotation));
+        let (px, py) = (f64::from(to_f32(pos.x)), f64::from(to_f32(pos.y)));
+        let x = (px - xv.min) / (xv.max - xv.min) * window_size.width as f64;
+        let y = (yv.max - py) / (yv.max - yv.min) * window_size.height as f64;
+        cgmath::vec2(x as f32, y as f32)
+    }
+
+    /// Moves the camera by a delta given in window space (pixels)
+    pub fn pan(&mut self, delta: cgmath::Vector2<f64>, window_size: PhysicalSize<u32>) {
+        let half = self.half_extents();
+        let world_per_pixel = 2.0 * half.y / window_size.height as f32;
+        // Window space y grows downwards whereas world space y grows upwards
+        let offset = cgmath::vec2(-delta.x as f32, delta.y as f32) * world_per_pixel;
+        self.center += rotate(offset, self.rotation);
+    }
+
+    /// Multiplies the zoom by `factor` while keeping the world position under `anchor`
+    /// (in window space) fixed on screen
+    pub fn zoom_at(
+        &mut self,
+        factor: f32,
+        anchor: PhysicalPosition<f64>,
+        window_size: PhysicalSize<u32>,
+    ) {
+        let before = self.screen_to_world(anchor, window_size);
+        self.zoom = (self.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+        let after = self.screen_to_world(anchor, window_size);
+        self.center += (before - after).map(to_f32);
+    }
+}
+
+/// Turns `v` counterclockwise by `angle` radians
+pub fn rotate<S: cgmath::BaseFloat>(v: cgmath::Vector2<S>, angle: S) -> cgmath::Vector2<S> {
+    let (sin, cos) = angle.sin_cos();
+    cgmath::vec2(v.x * cos - v.y * sin, v.x * sin + v.y * cos)
+}
+
+/// Camera data laid out the way `shader.vert.wgsl` expects it
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct CameraUniform {
+    pub view_proj: [[f32; 4]; 4],
+    pub pixel_size: f32,
+    _padding: [f32; 3],
+}
+
+impl CameraUniform {
+    pub fn from_camera(camera: &Camera) -> Self {
+        Self {
+            view_proj: camera.build_view_projection_matrix().into(),
+            pixel_size: camera.pixel_size(),
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+unsafe impl Pod for CameraUniform {}
+unsafe impl Zeroable for CameraUniform {}
+
+/// Converts the OpenGL clip space depth range (-1..1) produced by cgmath into the
+/// 0..1 range wgpu expects
+#[rustfmt::skip]
+const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+);
+
+/// Perspective camera of the 3D mode orbiting around a target point
+#[derive(Debug)]
+pub struct OrbitCamera {
+    pub target: cgmath::Vector3<f32>,
+    /// Distance between the eye and the target
+    pub distance: f32,
+    /// Rotation around the vertical axis in radians
+    pub yaw: f32,
+    /// Elevation above the horizontal plane in radians
+    pub pitch: f32,
+    /// Window width / window height
+    pub aspect: f32,
+    /// Window height in pixels
+    pub height: f32,
+}
+
+impl OrbitCamera {
+    /// Radians the camera rotates per pixel dragged
+    const ORBIT_SPEED: f32 = 0.01;
+    /// Vertical field of view
+    const FOV_Y: cgmath::Deg<f32> = cgmath::Deg(45.0);
+    /// Pitch is kept away from the poles so that the up vector stays valid
+    const MAX_PITCH: f32 = 1.5;
+
+    pub fn new(
+        target: cgmath::Vector3<f32>,
+        distance: f32,
+        window_size: PhysicalSize<u32>,
+    ) -> Self {
+        Self {
+            target,
+            distance,
+            yaw: 0.0,
+            pitch: 0.4,
+            aspect: window_size.width as f32 / window_size.height as f32,
+            height: window_size.height as f32,
+        }
+    }
+
+    pub fn resize(&mut self, window_size: PhysicalSize<u32>) {
+        self.aspect = window_size.width as f32 / window_size.height as f32;
+        self.height = window_size.height as f32;
+    }
+
+    pub fn eye(&self) -> cgmath::Vector3<f32> {
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+        self.target
+            + cgmath::vec3(cos_pitch * sin_yaw, sin_pitch, cos_pitch * cos_yaw) * self.distance
+    }
+
+    pub fn build_view_matrix(&self) -> cgmath::Matrix4<f32> {
+        let eye = self.eye();
+        cgmath::Matrix4::look_at_rh(
+            cgmath::Point3::new(eye.x, eye.y, eye.z),
+            cgmath::Point3::new(self.target.x, self.target.y, self.target.z),
+            cgmath::Vector3::unit_y(),
+        )
+    }
+
+    pub fn build_projection_matrix(&self) -> cgmath::Matrix4<f32> {
+        OPENGL_TO_WGPU_MATRIX * cgmath::perspective(Self::FOV_Y, self.aspect, 1.0, 10000.0)
+    }
+
+    /// Rotates the camera around the target by a delta given in window space (pixels)
+    pub fn orbit(&mut self, delta: cgmath::Vector2<f64>) {
+        self.yaw -= delta.x as f32 * Self::ORBIT_SPEED;
+        self.pitch = (self.pitch + delta.y as f32 * Self::ORBIT_SPEED)
+            .clamp(-Self::MAX_PITCH, Self::MAX_PITCH);
+    }
+
+    /// Moves the eye towards the target by `factor`
+    pub fn zoom(&mut self, factor: f32) {
+        self.distance = (self.distance / factor).clamp(10.0, 5000.0);
+    }
+
+    /// Size of a pixel in world units at a distance of one unit in front of the eye
+    pub fn pixel_size(&self) -> f32 {
+        2.0 * (cgmath::Rad::from(Self::FOV_Y).0 / 2.0).tan() / self.height
+    }
+}
+
+/// Orbit camera data laid out the way `sphere.vert.wgsl` expects it
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct OrbitCameraUniform {
+    pub view: [[f32; 4]; 4],
+    pub proj: [[f32; 4]; 4],
+    pub pixel_size: f32,
+    _padding: [f32; 3],
+}
+
+impl OrbitCameraUniform {
+    pub fn from_camera(camera: &OrbitCamera) -> Self {
+        Self {
+            view: camera.build_view_matrix().into(),
+            proj: camera.build_projection_matrix().into(),
+            pixel_size: camera.pixel_size(),
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+unsafe impl Pod for OrbitCameraUniform {}
+unsafe impl Zeroable for OrbitCameraUniform {}
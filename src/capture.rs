@@ -0,0 +1,329 @@
+use futures::executor::block_on;
+use instant::{Duration, Instant};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use wgpu::{BufferDescriptor, TextureDescriptor};
+use winit::dpi::PhysicalSize;
+
+/// Bytes per pixel of the 8 bit RGBA/BGRA formats the swap chain uses
+const BYTES_PER_PIXEL: u32 = 4;
+/// Frames per second clips are sampled at
+const CLIP_FPS: u32 = 15;
+/// Clip frames are scaled down to at most this many pixels across
+const CLIP_MAX_WIDTH: u32 = 480;
+
+/// Offscreen render target whose contents are read back after every frame
+struct Target {
+    size: PhysicalSize<u32>,
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    buffer: wgpu::Buffer,
+    /// Rows in `buffer` are padded to `wgpu::COPY_BYTES_PER_ROW_ALIGNMENT`
+    padded_bytes_per_row: u32,
+}
+
+impl Target {
+    fn new(device: &wgpu::Device, size: PhysicalSize<u32>, format: wgpu::TextureFormat) -> Self {
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("Capture Texture"),
+            size: wgpu::Extent3d {
+                width: size.width,
+                height: size.height,
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::COPY_SRC,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = (size.width * BYTES_PER_PIXEL).div_ceil(align) * align;
+        let buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Capture Buffer"),
+            size: (padded_bytes_per_row * size.height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            size,
+            texture,
+            view,
+            buffer,
+            padded_bytes_per_row,
+        }
+    }
+
+    /// Records a copy of the texture into the readback buffer
+    fn copy(&self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.copy_texture_to_buffer(
+            wgpu::TextureCopyView {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::BufferCopyView {
+                buffer: &self.buffer,
+                layout: wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: self.padded_bytes_per_row,
+                    rows_per_image: self.size.height,
+                },
+            },
+            wgpu::Extent3d {
+                width: self.size.width,
+                height: self.size.height,
+                depth: 1,
+            },
+        );
+    }
+
+    /// Reads back the copy recorded by `copy` as RGBA. Blocks until the GPU has finished
+    /// the submitted work.
+    fn read(&self, device: &wgpu::Device, format: wgpu::TextureFormat) -> Option<image::RgbaImage> {
+        let slice = self.buffer.slice(..);
+        let mapping = slice.map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        if let Err(e) = block_on(mapping) {
+            eprintln!("could not read back frame: {}", e);
+            return None;
+        }
+
+        let (width, height) = (self.size.width, self.size.height);
+        let mut pixels = Vec::with_capacity((width * height * BYTES_PER_PIXEL) as usize);
+        {
+            let data = slice.get_mapped_range();
+            for row in data.chunks(self.padded_bytes_per_row as usize) {
+                pixels.extend_from_slice(&row[..(width * BYTES_PER_PIXEL) as usize]);
+            }
+        }
+        self.buffer.unmap();
+
+        if let wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb = format {
+            for pixel in pixels.chunks_mut(BYTES_PER_PIXEL as usize) {
+                pixel.swap(0, 2);
+            }
+        }
+        image::RgbaImage::from_raw(width, height, pixels)
+    }
+}
+
+/// Records rendered frames into a numbered PNG sequence. Frames are rendered into an
+/// offscreen texture, copied into a buffer and encoded on a background thread so that
+/// the render loop only waits for the GPU readback.
+pub struct Capture {
+    format: wgpu::TextureFormat,
+    target: Target,
+    frame: usize,
+    dir: PathBuf,
+    sender: Option<mpsc::Sender<(PathBuf, image::RgbaImage)>>,
+    writer: Option<thread::JoinHandle<()>>,
+}
+
+impl Capture {
+    /// Starts a new recording into `dir`, creating it if necessary
+    pub fn new<P: AsRef<Path>>(
+        device: &wgpu::Device,
+        size: PhysicalSize<u32>,
+        format: wgpu::TextureFormat,
+        dir: P,
+    ) -> std::io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)?;
+
+        let (sender, receiver) = mpsc::channel::<(PathBuf, image::RgbaImage)>();
+        let writer = thread::spawn(move || {
+            for (path, frame) in receiver {
+                if let Err(e) = frame.save(&path) {
+                    eprintln!("{}: {}", path.display(), e);
+                }
+            }
+        });
+
+        Ok(Self {
+            format,
+            target: Target::new(device, size, format),
+            frame: 0,
+            dir,
+            sender: Some(sender),
+            writer: Some(writer),
+        })
+    }
+
+    /// Recreates the offscreen target for a new window size. Frames recorded after this
+    /// have the new size.
+    pub fn resize(&mut self, device: &wgpu::Device, size: PhysicalSize<u32>) {
+        self.target = Target::new(device, size, self.format);
+    }
+
+    /// View to render the frame that should be captured into
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.target.view
+    }
+
+    /// Number of frames recorded so far
+    pub fn frames(&self) -> usize {
+        self.frame
+    }
+
+    /// Records a copy of the offscreen texture into the readback buffer. Must be called
+    /// after the frame was rendered into `view` and before the encoder is submitted.
+    pub fn copy_frame(&self, encoder: &mut wgpu::CommandEncoder) {
+        self.target.copy(encoder);
+    }
+
+    /// Reads back the frame copied by `copy_frame` and hands it to the writer thread.
+    /// Blocks until the GPU has finished the submitted work.
+    pub fn save_frame(&mut self, device: &wgpu::Device) {
+        let frame = match self.target.read(device, self.format) {
+            Some(frame) => frame,
+            None => return,
+        };
+        let path = self.dir.join(format!("frame_{:05}.png", self.frame));
+        self.frame += 1;
+        if let Some(sender) = &self.sender {
+            // The writer only goes away when the capture is dropped
+            let _ = sender.send((path, frame));
+        }
+    }
+}
+
+impl Drop for Capture {
+    /// Waits for the remaining frames to be written
+    fn drop(&mut self) {
+        self.sender.take();
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+/// Keeps the last few seconds of rendered frames, scaled down, so that something that
+/// just happened can be saved as an animated GIF. Frames are sampled at `CLIP_FPS`
+/// rather than every frame, which keeps both the readbacks and the memory in check.
+pub struct ClipRecorder {
+    format: wgpu::TextureFormat,
+    target: Target,
+    /// Oldest frame first
+    frames: VecDeque<image::RgbaImage>,
+    capacity: usize,
+    last_sample: Option<Instant>,
+    /// Encodes the last clip that was saved
+    writer: Option<thread::JoinHandle<()>>,
+}
+
+impl ClipRecorder {
+    /// Starts keeping the last `seconds` of frames
+    pub fn new(
+        device: &wgpu::Device,
+        size: PhysicalSize<u32>,
+        format: wgpu::TextureFormat,
+        seconds: f32,
+    ) -> Self {
+        let capacity = ((seconds * CLIP_FPS as f32).round() as usize).max(1);
+        Self {
+            format,
+            target: Target::new(device, size, format),
+            frames: VecDeque::with_capacity(capacity),
+            capacity,
+            last_sample: None,
+            writer: None,
+        }
+    }
+
+    /// Recreates the offscreen target for a new window size. The frames kept so far are
+    /// dropped since all frames of a GIF have the same size.
+    pub fn resize(&mut self, device: &wgpu::Device, size: PhysicalSize<u32>) {
+        self.target = Target::new(device, size, self.format);
+        self.frames.clear();
+    }
+
+    /// View to render the frame that should be kept into
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.target.view
+    }
+
+    /// Length of the clip kept so far
+    pub fn seconds(&self) -> f32 {
+        self.frames.len() as f32 / CLIP_FPS as f32
+    }
+
+    /// Whether enough time has passed since the last kept frame to keep the next one
+    pub fn wants_frame(&self, now: Instant) -> bool {
+        let interval = Duration::from_secs(1) / CLIP_FPS;
+        self.last_sample
+            .is_none_or(|last| now.duration_since(last) >= interval)
+    }
+
+    /// Records a copy of the offscreen texture into the readback buffer. Must be called
+    /// after the frame was rendered into `view` and before the encoder is submitted.
+    pub fn copy_frame(&self, encoder: &mut wgpu::CommandEncoder) {
+        self.target.copy(encoder);
+    }
+
+    /// Reads back the frame copied by `copy_frame` and keeps it in place of the oldest
+    /// one. Blocks until the GPU has finished the submitted work.
+    pub fn keep_frame(&mut self, device: &wgpu::Device, now: Instant) {
+        self.last_sample = Some(now);
+        let frame = match self.target.read(device, self.format) {
+            Some(frame) => frame,
+            None => return,
+        };
+        let (width, height) = frame.dimensions();
+        let frame = if width > CLIP_MAX_WIDTH {
+            let scaled_height = (height * CLIP_MAX_WIDTH / width).max(1);
+            image::imageops::thumbnail(&frame, CLIP_MAX_WIDTH, scaled_height)
+        } else {
+            frame
+        };
+        if self.frames.len() == self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame);
+    }
+
+    /// Encodes the frames kept so far into a looping GIF at `path` on a background
+    /// thread. Keeps recording afterwards.
+    pub fn save<P: AsRef<Path>>(&mut self, path: P) {
+        // Only one clip is encoded at a time
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+        let path = path.as_ref().to_path_buf();
+        let frames: Vec<_> = self.frames.iter().cloned().collect();
+        self.writer = Some(thread::spawn(move || {
+            if let Err(e) = write_gif(&path, frames) {
+                eprintln!("{}: {}", path.display(), e);
+            }
+        }));
+    }
+}
+
+impl Drop for ClipRecorder {
+    /// Waits for the last clip to be written
+    fn drop(&mut self) {
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+fn write_gif(path: &Path, frames: Vec<image::RgbaImage>) -> image::ImageResult<()> {
+    use image::codecs::gif::{GifEncoder, Repeat};
+
+    let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    // Faster color quantization than the default at a barely visible cost
+    let mut encoder = GifEncoder::new_with_speed(file, 10);
+    encoder.set_repeat(Repeat::Infinite)?;
+    let delay = image::Delay::from_numer_denom_ms(1000, CLIP_FPS);
+    encoder.encode_frames(
+        frames
+            .into_iter()
+            .map(|frame| image::Frame::from_parts(frame, 0, 0, delay)),
+    )
+}
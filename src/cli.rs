@@ -0,0 +1,672 @@
+use crate::config::Config;
+use crate::keys::{Action, KeyBinding};
+use clap::{value_t, App, Arg, ArgMatches, ErrorKind};
+use nbody::primitives::{color::ColorMapping, colormap::Colormap};
+use nbody::{
+    AdaptiveTimeStep, BlockTimeSteps, Drag, DragFrame, DragLaw, Expansion, Fragmentation,
+    Integrator, Interaction, MergeModel, Scalar,
+};
+use std::{collections::BTreeMap, path::PathBuf, str::FromStr};
+use winit::dpi::PhysicalSize;
+
+/// Startup configuration taken from the command line, falling back on the `Config` saved
+/// by the last session for what it leaves out
+#[derive(Debug)]
+pub struct Options {
+    /// Number of randomly placed particles to start with
+    pub particles: Option<usize>,
+    pub time_step: Scalar,
+    pub integrator: Integrator,
+    /// Whether the step size is picked from the accelerations each step
+    pub adaptive: bool,
+    /// Accuracy and bounds of the adaptive time step
+    pub adaptive_step: AdaptiveTimeStep,
+    /// Whether particles step by their own fraction of the time step
+    pub block: bool,
+    /// Accuracy and depth of the block time steps
+    pub block_steps: BlockTimeSteps,
+    /// Whether particles are slowed down by drag from the start
+    pub drag: bool,
+    /// Strength and kind of the drag
+    pub drag_settings: Drag,
+    /// Barnes-Hut opening angle
+    pub theta: Scalar,
+    /// What the Barnes-Hut tree nodes keep of the mass below them
+    pub expansion: Expansion,
+    /// Whether masses, charges or both act on each other
+    pub interaction: Interaction,
+    /// Whether the Barnes-Hut tree is updated in place between steps
+    pub incremental_tree: bool,
+    /// Particles a Barnes-Hut tree leaf holds before it is split
+    pub leaf_capacity: usize,
+    /// Steps between sorting the particles in Morton order, if they are sorted
+    pub morton_sort: Option<usize>,
+    /// Simulation steps per physics tick. Below 1 for slow motion. Clamped to the range
+    /// the +/- keys can reach.
+    pub steps_per_frame: f32,
+    /// Ticks per second of the physics thread
+    pub physics_rate: f32,
+    /// Initial inner size of the window. Left up to the platform when not set.
+    pub window_size: Option<PhysicalSize<u32>>,
+    /// Title of the window
+    pub title: String,
+    /// Whether the window starts out borderless fullscreen
+    pub fullscreen: bool,
+    /// How frames are presented. Fifo waits for the vertical blank, Mailbox replaces
+    /// frames still waiting for it and Immediate presents right away.
+    pub present_mode: wgpu::PresentMode,
+    /// Upper bound on the frame rate when presenting does not wait for the vertical blank
+    pub max_fps: Option<f32>,
+    /// How particles are colored at first, and with which colors
+    pub color_mapping: ColorMapping,
+    pub colormap: Colormap,
+    /// Whether bright particles glow from the start
+    pub bloom: bool,
+    /// Graphics APIs adapters are looked for on first
+    pub backends: wgpu::BackendBit,
+    /// Part of the name of the adapter to use, e.g. `nvidia` or `llvmpipe`
+    pub adapter: Option<String>,
+    /// Print the available adapters and exit
+    pub list_adapters: bool,
+    /// Length of the clips F10 saves
+    pub clip_seconds: f32,
+    /// Samples per pixel to anti-alias the scene with. 1 turns multisampling off.
+    pub msaa: u32,
+    /// Whether the GPU time of every render pass is measured and shown with F3
+    pub gpu_timing: bool,
+    /// File the spans of the steps and frames are written to on exit, as a chrome trace
+    pub trace: Option<PathBuf>,
+    /// Seed of the random number generator behind every random choice the app makes.
+    /// Picked at random unless given, so that any run can be repeated.
+    pub seed: u64,
+    /// Scenario file to load the initial particles from
+    pub scenario: Option<PathBuf>,
+    /// File to export the particles into after every step. CSV unless the extension is
+    /// `.json` or `.jsonl`.
+    pub export: Option<PathBuf>,
+    /// Whether the simulation starts out paused
+    pub paused: bool,
+    /// Number of particles Shift + click spawns at once
+    pub burst_size: usize,
+    /// Radius of the disk Shift + click spawns particles in
+    pub burst_radius: Scalar,
+    /// Whether particles spawned with Shift + click rotate around the cursor
+    pub burst_spin: bool,
+    /// How colliding particles are combined
+    pub merge_model: MergeModel,
+    /// Whether fast collisions shatter from the start
+    pub fragment: bool,
+    /// When and into how many pieces collisions shatter
+    pub fragmentation: Fragmentation,
+    /// Run the octree based 3D mode instead
+    pub three_d: bool,
+    /// Benchmark the simulation without a window for `steps` steps
+    pub headless: bool,
+    pub steps: usize,
+    /// Whether the settings of this session are left out of the config for the next one
+    pub no_config: bool,
+    /// Actions bound to other keys than their default ones
+    pub keys: BTreeMap<Action, Vec<KeyBinding>>,
+    /// Compute forces by exact pairwise summation (headless only)
+    pub direct: bool,
+    /// Print the Barnes-Hut acceleration error at the end (headless only)
+    pub compare: bool,
+    /// Address to serve the metrics of a headless run on, e.g. `127.0.0.1:9184`
+    pub metrics: Option<String>,
+}
+
+impl Options {
+    /// Parses the arguments of the current process, exiting with a usage message if they
+    /// are malformed. Headless runs leave the saved config out so that they can be
+    /// repeated.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn parse() -> Self {
+        let matches = app().get_matches();
+        let config = if matches.is_present("headless") || matches.is_present("no-config") {
+            Config::default()
+        } else {
+            Config::load().unwrap_or_else(|e| {
+                eprintln!("{}: {}", Config::path().unwrap_or_default().display(), e);
+                Config::default()
+            })
+        };
+        Self::from_matches(&matches, &config)
+    }
+
+    /// Parses options given as a URL query string such as `?particles=500&running`.
+    /// Keys are the long names of the command line flags.
+    #[cfg(target_arch = "wasm32")]
+    pub fn from_query(query: &str) -> Self {
+        let mut args = vec!["nbody".to_string()];
+        let pairs = query.trim_start_matches('?').split('&');
+        for pair in pairs.filter(|pair| !pair.is_empty()) {
+            let mut parts = pair.splitn(2, '=');
+            args.push(format!("--{}", parts.next().unwrap_or_default()));
+            args.extend(parts.next().map(str::to_string));
+        }
+        Self::from_matches(&app().get_matches_from(args), &Config::default())
+    }
+
+    fn from_matches(matches: &ArgMatches, config: &Config) -> Self {
+        Self {
+            particles: optional(matches, "particles"),
+            time_step: configured(matches, "time-step", config.time_step),
+            integrator: match matches.value_of("integrator") {
+                Some("leapfrog") => Integrator::Leapfrog,
+                Some("rk4") => Integrator::RungeKutta4,
+                _ => Integrator::Euler,
+            },
+            adaptive: matches.is_present("adaptive"),
+            adaptive_step: AdaptiveTimeStep::new(
+                required(matches, "eta"),
+                required(matches, "min-time-step"),
+                optional(matches, "max-time-step")
+                    .unwrap_or_else(|| required(matches, "time-step")),
+            ),
+            block: matches.is_present("block-steps"),
+            block_steps: BlockTimeSteps::new(
+                required(matches, "eta"),
+                required(matches, "block-levels"),
+            ),
+            drag: matches.is_present("drag"),
+            drag_settings: Drag::new(
+                match matches.value_of("drag-law") {
+                    Some("quadratic") => DragLaw::Quadratic,
+                    _ => DragLaw::Linear,
+                },
+                required(matches, "drag-coefficient"),
+                match matches.value_of("drag-frame") {
+                    Some("local") => DragFrame::LocalMean,
+                    _ => DragFrame::Rest,
+                },
+                required(matches, "drag-neighborhood"),
+            ),
+            theta: configured(matches, "theta", config.theta),
+            expansion: if matches.is_present("quadrupole") {
+                Expansion::Quadrupole
+            } else {
+                Expansion::Monopole
+            },
+            interaction: match matches.value_of("interaction") {
+                Some("electrostatic") => Interaction::Electrostatic,
+                Some("both") => Interaction::Both,
+                _ => Interaction::Gravity,
+            },
+            incremental_tree: matches.is_present("incremental-tree"),
+            leaf_capacity: required(matches, "leaf-capacity"),
+            morton_sort: optional(matches, "morton-sort"),
+            steps_per_frame: required(matches, "steps-per-frame"),
+            physics_rate: required(matches, "physics-rate"),
+            window_size: match matches.value_of("size") {
+                Some(size) => Some(parse_size(size).unwrap_or_else(|e| {
+                    clap::Error::with_description(&e, ErrorKind::InvalidValue).exit()
+                })),
+                None => config
+                    .window_size
+                    .map(|(width, height)| PhysicalSize::new(width, height)),
+            },
+            title: required(matches, "title"),
+            fullscreen: matches.is_present("fullscreen"),
+            present_mode: match matches.value_of("present-mode") {
+                Some("mailbox") => wgpu::PresentMode::Mailbox,
+                Some("immediate") => wgpu::PresentMode::Immediate,
+                Some(_) => wgpu::PresentMode::Fifo,
+                None if matches.is_present("no-vsync") => wgpu::PresentMode::Immediate,
+                None => config
+                    .present_mode
+                    .map_or(wgpu::PresentMode::Fifo, Into::into),
+            },
+            max_fps: optional(matches, "max-fps"),
+            color_mapping: config.color_mapping.unwrap_or_default(),
+            colormap: config.colormap.unwrap_or_default(),
+            bloom: matches.is_present("bloom"),
+            backends: match matches.value_of("backend") {
+                Some("vulkan") => wgpu::BackendBit::VULKAN,
+                Some("metal") => wgpu::BackendBit::METAL,
+                Some("dx12") => wgpu::BackendBit::DX12,
+                Some("dx11") => wgpu::BackendBit::DX11,
+                Some("gl") => wgpu::BackendBit::GL,
+                Some("all") => wgpu::BackendBit::all(),
+                _ => wgpu::BackendBit::PRIMARY,
+            },
+            adapter: matches.value_of("adapter").map(str::to_string),
+            list_adapters: matches.is_present("list-adapters"),
+            clip_seconds: required(matches, "clip-seconds"),
+            msaa: required(matches, "msaa"),
+            gpu_timing: matches.is_present("gpu-timing"),
+            trace: matches.value_of("trace").map(PathBuf::from),
+            seed: optional(matches, "seed").unwrap_or_else(rand::random),
+            scenario: match matches.value_of("scenario") {
+                Some(path) => Some(PathBuf::from(path)),
+                // The last scenario is only picked up again if it is still there
+                None if !matches.is_present("particles") => {
+                    config.scenario.clone().filter(|path| path.exists())
+                }
+                None => None,
+            },
+            export: matches.value_of("export").map(PathBuf::from),
+            paused: !matches.is_present("running"),
+            burst_size: required(matches, "burst-size"),
+            burst_radius: required(matches, "burst-radius"),
+            burst_spin: matches.is_present("burst-spin"),
+            merge_model: if matches.is_present("legacy-merge") {
+                MergeModel::Legacy
+            } else {
+                MergeModel::Conservative
+            },
+            fragment: matches.is_present("fragment"),
+            fragmentation: Fragmentation::new(
+                required(matches, "fragment-speed"),
+                required(matches, "fragments"),
+                required(matches, "fragment-dispersion"),
+                required(matches, "fragment-min-mass"),
+            ),
+            three_d: matches.is_present("3d"),
+            headless: matches.is_present("headless"),
+            steps: required(matches, "steps"),
+            no_config: matches.is_present("no-config"),
+            keys: config.keys.clone(),
+            direct: matches.is_present("direct"),
+            compare: matches.is_present("compare"),
+            metrics: matches.value_of("metrics").map(str::to_string),
+        }
+    }
+}
+
+/// Parses the value of an argument that has a default value
+fn required<T: FromStr>(matches: &ArgMatches, name: &str) -> T {
+    value_t!(matches, name, T).unwrap_or_else(|e| e.exit())
+}
+
+/// Parses the value of an argument that has a default value, unless it was left out and
+/// the config has a value for it
+fn configured<T: FromStr>(matches: &ArgMatches, name: &str, saved: Option<T>) -> T {
+    match saved {
+        Some(value) if matches.occurrences_of(name) == 0 => value,
+        _ => required(matches, name),
+    }
+}
+
+/// Parses the value of an argument if it was given
+fn optional<T: FromStr>(matches: &ArgMatches, name: &str) -> Option<T> {
+    if matches.is_present(name) {
+        Some(required(matches, name))
+    } else {
+        None
+    }
+}
+
+/// Parses a window size given as `WIDTHxHEIGHT`
+fn parse_size(size: &str) -> Result<PhysicalSize<u32>, String> {
+    let invalid = || format!("expected a size like 1280x720, got {:?}", size);
+    let mut parts = size.splitn(2, 'x');
+    let mut next = || {
+        parts
+            .next()
+            .and_then(|part| part.trim().parse::<u32>().ok())
+            .filter(|&n| n > 0)
+            .ok_or_else(invalid)
+    };
+    Ok(PhysicalSize::new(next()?, next()?))
+}
+
+fn app() -> App<'static, 'static> {
+    App::new("nbody")
+        .version(clap::crate_version!())
+        .about("Barnes-Hut n-body simulation")
+        .arg(
+            Arg::with_name("particles")
+                .long("particles")
+                .value_name("N")
+                .help("Starts with N randomly placed particles (1000 when headless)"),
+        )
+        .arg(
+            Arg::with_name("time-step")
+                .long("time-step")
+                .value_name("DT")
+                .default_value("0.05")
+                .help("Base time step of the simulation"),
+        )
+        .arg(
+            Arg::with_name("integrator")
+                .long("integrator")
+                .value_name("NAME")
+                .possible_values(&["euler", "leapfrog", "rk4"])
+                .default_value("euler")
+                .help("How particles are moved forward in time"),
+        )
+        .arg(
+            Arg::with_name("adaptive")
+                .long("adaptive")
+                .help("Picks the time step from the largest acceleration every step"),
+        )
+        .arg(
+            Arg::with_name("eta")
+                .long("eta")
+                .value_name("ETA")
+                .default_value("0.2")
+                .help("Accuracy of the adaptive time step. Lower takes smaller steps"),
+        )
+        .arg(
+            Arg::with_name("min-time-step")
+                .long("min-time-step")
+                .value_name("DT")
+                .default_value("0.001")
+                .help("Smallest adaptive time step"),
+        )
+        .arg(
+            Arg::with_name("max-time-step")
+                .long("max-time-step")
+                .value_name("DT")
+                .help("Largest adaptive time step. Defaults to --time-step"),
+        )
+        .arg(
+            Arg::with_name("block-steps")
+                .long("block-steps")
+                .help("Lets every particle take its own power-of-two fraction of the time step"),
+        )
+        .arg(
+            Arg::with_name("block-levels")
+                .long("block-levels")
+                .value_name("N")
+                .default_value("4")
+                .help("Smallest block time step is the time step divided by 2^N"),
+        )
+        .arg(
+            Arg::with_name("drag")
+                .long("drag")
+                .help("Slows particles down as if they moved through gas"),
+        )
+        .arg(
+            Arg::with_name("drag-coefficient")
+                .long("drag-coefficient")
+                .value_name("C")
+                .default_value("0.05")
+                .help("Strength of the drag"),
+        )
+        .arg(
+            Arg::with_name("drag-law")
+                .long("drag-law")
+                .value_name("NAME")
+                .possible_values(&["linear", "quadratic"])
+                .default_value("linear")
+                .help("Whether drag grows with the speed or its square"),
+        )
+        .arg(
+            Arg::with_name("drag-frame")
+                .long("drag-frame")
+                .value_name("NAME")
+                .possible_values(&["rest", "local"])
+                .default_value("rest")
+                .help("Drags toward rest or toward the mean velocity of the neighborhood"),
+        )
+        .arg(
+            Arg::with_name("drag-neighborhood")
+                .long("drag-neighborhood")
+                .value_name("SIZE")
+                .default_value("50")
+                .help("Size of the neighborhoods local drag averages the velocity over"),
+        )
+        .arg(
+            Arg::with_name("theta")
+                .long("theta")
+                .value_name("THETA")
+                .default_value("1.0")
+                .help("Barnes-Hut opening angle. Lower is more accurate but slower"),
+        )
+        .arg(
+            Arg::with_name("quadrupole")
+                .long("quadrupole")
+                .help("Adds quadrupole moments to the tree nodes for more accurate forces"),
+        )
+        .arg(
+            Arg::with_name("interaction")
+                .long("interaction")
+                .value_name("NAME")
+                .possible_values(&["gravity", "electrostatic", "both"])
+                .default_value("gravity")
+                .help("Whether masses, charges or both act on each other"),
+        )
+        .arg(
+            Arg::with_name("incremental-tree")
+                .long("incremental-tree")
+                .help("Updates the Barnes-Hut tree in place between steps instead of rebuilding it"),
+        )
+        .arg(
+            Arg::with_name("leaf-capacity")
+                .long("leaf-capacity")
+                .value_name("N")
+                .default_value("8")
+                .help("Particles a Barnes-Hut tree leaf holds before it is split"),
+        )
+        .arg(
+            Arg::with_name("morton-sort")
+                .long("morton-sort")
+                .value_name("N")
+                .help("Sorts the particles along a Z-order curve every N steps for cache locality"),
+        )
+        .arg(
+            Arg::with_name("steps-per-frame")
+                .long("steps-per-frame")
+                .value_name("N")
+                .default_value("1")
+                .help("Simulation steps per physics tick, e.g. 4 or 0.25 for slow motion"),
+        )
+        .arg(
+            Arg::with_name("physics-rate")
+                .long("physics-rate")
+                .value_name("HZ")
+                .default_value("60")
+                .help("Physics ticks per second, independent of the frame rate"),
+        )
+        .arg(
+            Arg::with_name("size")
+                .long("size")
+                .value_name("WIDTHxHEIGHT")
+                .help("Initial window size in physical pixels, e.g. 1280x720"),
+        )
+        .arg(
+            Arg::with_name("title")
+                .long("title")
+                .value_name("TITLE")
+                .default_value("nbody")
+                .help("Title of the window"),
+        )
+        .arg(
+            Arg::with_name("fullscreen")
+                .long("fullscreen")
+                .help("Starts borderless fullscreen (toggle with F11 or Alt+Enter)"),
+        )
+        .arg(
+            Arg::with_name("no-vsync")
+                .long("no-vsync")
+                .conflicts_with("present-mode")
+                .help("Presents frames as soon as they are rendered, same as --present-mode immediate"),
+        )
+        .arg(
+            Arg::with_name("present-mode")
+                .long("present-mode")
+                .value_name("MODE")
+                .possible_values(&["fifo", "mailbox", "immediate"])
+                .help("How frames are presented (cycle with F2). fifo waits for vsync, mailbox and immediate do not"),
+        )
+        .arg(
+            Arg::with_name("max-fps")
+                .long("max-fps")
+                .value_name("FPS")
+                .help("Caps the frame rate when presenting without vsync"),
+        )
+        .arg(
+            Arg::with_name("bloom")
+                .long("bloom")
+                .help("Makes bright particles glow (toggle with F4)"),
+        )
+        .arg(
+            Arg::with_name("backend")
+                .long("backend")
+                .value_name("BACKEND")
+                .possible_values(&["primary", "vulkan", "metal", "dx12", "dx11", "gl", "all"])
+                .default_value("primary")
+                .help("Graphics API to draw with. Falls back to any other API when nothing is found"),
+        )
+        .arg(
+            Arg::with_name("adapter")
+                .long("adapter")
+                .value_name("NAME")
+                .help("Draws with the adapter whose name contains NAME, see --list-adapters"),
+        )
+        .arg(
+            Arg::with_name("list-adapters")
+                .long("list-adapters")
+                .help("Prints the adapters of every backend and exits"),
+        )
+        .arg(
+            Arg::with_name("clip-seconds")
+                .long("clip-seconds")
+                .value_name("SECONDS")
+                .default_value("10")
+                .help("How much of the past F10 keeps to save as a GIF clip"),
+        )
+        .arg(
+            Arg::with_name("msaa")
+                .long("msaa")
+                .value_name("SAMPLES")
+                .possible_values(&["1", "2", "4", "8"])
+                .default_value("4")
+                .help("Samples per pixel to smooth edges with. 2 and 8 fall back to 4 where unsupported"),
+        )
+        .arg(
+            Arg::with_name("gpu-timing")
+                .long("gpu-timing")
+                .help("Measures the GPU time of every render pass with timestamp queries (shown with F3 and added to --trace)"),
+        )
+        .arg(
+            Arg::with_name("trace")
+                .long("trace")
+                .value_name("FILE")
+                .help("Writes how long every step, tree build, collision pass and frame took to FILE on exit, for chrome://tracing"),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .value_name("SEED")
+                .help("Seed for everything random, to repeat an earlier run exactly"),
+        )
+        .arg(
+            Arg::with_name("scenario")
+                .long("scenario")
+                .value_name("FILE")
+                .help("Loads the initial particles from a RON scenario file"),
+        )
+        .arg(
+            Arg::with_name("export")
+                .long("export")
+                .value_name("FILE")
+                .help(
+                    "Exports every particle after each step to a CSV or JSON lines (.jsonl) file",
+                ),
+        )
+        .arg(
+            Arg::with_name("running")
+                .long("running")
+                .help("Starts the simulation right away instead of paused"),
+        )
+        .arg(
+            Arg::with_name("burst-size")
+                .long("burst-size")
+                .value_name("N")
+                .default_value("100")
+                .help("Number of particles spawned at once with Shift + click"),
+        )
+        .arg(
+            Arg::with_name("burst-radius")
+                .long("burst-radius")
+                .value_name("R")
+                .default_value("60")
+                .help("Radius of the disk Shift + click spawns particles in"),
+        )
+        .arg(
+            Arg::with_name("burst-spin")
+                .long("burst-spin")
+                .help("Makes particles spawned with Shift + click rotate around the cursor"),
+        )
+        .arg(
+            Arg::with_name("legacy-merge").long("legacy-merge").help(
+                "Merges colliding particles with the old rule that does not conserve momentum",
+            ),
+        )
+        .arg(
+            Arg::with_name("fragment")
+                .long("fragment")
+                .help("Shatters particles colliding fast enough instead of merging them"),
+        )
+        .arg(
+            Arg::with_name("fragment-speed")
+                .long("fragment-speed")
+                .value_name("SPEED")
+                .default_value("20")
+                .help("Relative speed above which colliding particles shatter"),
+        )
+        .arg(
+            Arg::with_name("fragments")
+                .long("fragments")
+                .value_name("N")
+                .default_value("6")
+                .help("Number of fragments a collision shatters into"),
+        )
+        .arg(
+            Arg::with_name("fragment-dispersion")
+                .long("fragment-dispersion")
+                .value_name("FRACTION")
+                .default_value("0.5")
+                .help("Fraction of the impact energy the fragments fly apart with"),
+        )
+        .arg(
+            Arg::with_name("fragment-min-mass")
+                .long("fragment-min-mass")
+                .value_name("MASS")
+                .default_value("1")
+                .help("Lightest fragment a collision may produce"),
+        )
+        .arg(
+            Arg::with_name("3d")
+                .long("3d")
+                .help("Simulates a disk galaxy in 3D using an octree"),
+        )
+        .arg(
+            Arg::with_name("no-config")
+                .long("no-config")
+                .help("Neither reads nor saves the settings remembered from the last session"),
+        )
+        .arg(
+            Arg::with_name("headless")
+                .long("headless")
+                .help("Benchmarks the simulation without a window or GPU"),
+        )
+        .arg(
+            Arg::with_name("steps")
+                .long("steps")
+                .value_name("N")
+                .default_value("1000")
+                .help("Number of steps to run when headless"),
+        )
+        .arg(
+            Arg::with_name("direct")
+                .long("direct")
+                .requires("headless")
+                .help("Computes forces by exact pairwise summation when headless"),
+        )
+        .arg(
+            Arg::with_name("compare")
+                .long("compare")
+                .requires("headless")
+                .help("Prints the Barnes-Hut acceleration error at the end of a headless run"),
+        )
+        .arg(
+            Arg::with_name("metrics")
+                .long("metrics")
+                .value_name("ADDRESS")
+                .requires("headless")
+                .help("Serves the step rate, particle count, energy drift and memory of a headless run on http://ADDRESS/metrics for Prometheus"),
+        )
+}
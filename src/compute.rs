@@ -0,0 +1,261 @@
+use bytemuck::{Pod, Zeroable};
+use futures::executor::block_on;
+use nbody::{
+    primitives::scalar::{from_f32, to_f32, Scalar},
+    ParticleStore,
+};
+use std::mem;
+use wgpu::{
+    BindGroupLayoutDescriptor, BufferDescriptor, CommandEncoderDescriptor, ComputePassDescriptor,
+    ComputePipelineDescriptor, PipelineLayoutDescriptor,
+};
+
+/// Number of invocations per workgroup. Must match `workgroup_size` in `gravity.comp.wgsl`
+const WORKGROUP_SIZE: u32 = 64;
+
+/// Per body data uploaded to the storage buffer
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct GpuBody {
+    position: [f32; 2],
+    mass: f32,
+    _padding: f32,
+}
+
+unsafe impl Pod for GpuBody {}
+unsafe impl Zeroable for GpuBody {}
+
+impl GpuBody {
+    /// Positions and masses of the particles, narrowed to f32
+    pub fn from_store(particles: &ParticleStore) -> Vec<Self> {
+        particles
+            .positions()
+            .iter()
+            .zip(particles.masses())
+            .map(|(position, &mass)| GpuBody {
+                position: [to_f32(position.x), to_f32(position.y)],
+                mass: to_f32(mass),
+                _padding: 0.0,
+            })
+            .collect()
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct GpuParams {
+    num_bodies: u32,
+    g: f32,
+    softening: f32,
+    /// 1 if the world wraps around with `period`
+    periodic: u32,
+    period: [f32; 2],
+    _padding: [u32; 2],
+}
+
+unsafe impl Pod for GpuParams {}
+unsafe impl Zeroable for GpuParams {}
+
+/// Buffers sized for a given number of bodies along with the bind group referencing them
+struct ForceBuffers {
+    capacity: usize,
+    bodies: wgpu::Buffer,
+    accelerations: wgpu::Buffer,
+    readback: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+/// Evaluates brute force pairwise gravity on the GPU with a compute shader
+pub struct GpuForces {
+    pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    params_buffer: wgpu::Buffer,
+    buffers: Option<ForceBuffers>,
+}
+
+impl GpuForces {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let module = device.create_shader_module(&include_wgsl!("shaders/gravity.comp.wgsl"));
+
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStage::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Gravity Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_entry(1, true),
+                storage_entry(2, false),
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Gravity Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("Gravity Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &module,
+            entry_point: "main",
+        });
+
+        let params_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Gravity Params Buffer"),
+            size: mem::size_of::<GpuParams>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            params_buffer,
+            buffers: None,
+        }
+    }
+
+    /// Makes sure the storage buffers can hold at least `count` bodies. Buffers are only
+    /// reallocated (to the next power of two) when the current capacity is exceeded.
+    fn reserve(&mut self, device: &wgpu::Device, count: usize) {
+        if let Some(buffers) = &self.buffers {
+            if buffers.capacity >= count {
+                return;
+            }
+        }
+
+        let capacity = count.next_power_of_two();
+        let bodies = device.create_buffer(&BufferDescriptor {
+            label: Some("Gravity Bodies Buffer"),
+            size: (capacity * mem::size_of::<GpuBody>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let accel_size = (capacity * mem::size_of::<[f32; 2]>()) as wgpu::BufferAddress;
+        let accelerations = device.create_buffer(&BufferDescriptor {
+            label: Some("Gravity Accelerations Buffer"),
+            size: accel_size,
+            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback = device.create_buffer(&BufferDescriptor {
+            label: Some("Gravity Readback Buffer"),
+            size: accel_size,
+            usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Gravity Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: bodies.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: accelerations.as_entire_binding(),
+                },
+            ],
+        });
+
+        if let Some(old) = self.buffers.take() {
+            old.bodies.destroy();
+            old.accelerations.destroy();
+            old.readback.destroy();
+        }
+        self.buffers = Some(ForceBuffers {
+            capacity,
+            bodies,
+            accelerations,
+            readback,
+            bind_group,
+        });
+    }
+
+    /// Uploads the particles, evaluates the pairwise accelerations on the GPU and blocks
+    /// until they have been read back. The returned accelerations are in the same order as
+    /// `particles`.
+    pub fn compute(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        particles: &ParticleStore,
+        g: Scalar,
+        softening: Scalar,
+        period: Option<cgmath::Vector2<Scalar>>,
+    ) -> Vec<cgmath::Vector2<Scalar>> {
+        if particles.is_empty() {
+            return Vec::new();
+        }
+        let _span = tracing::info_span!("gpu forces").entered();
+        self.reserve(device, particles.len());
+        let buffers = self.buffers.as_ref().unwrap();
+
+        let bodies = GpuBody::from_store(particles);
+        let params = GpuParams {
+            num_bodies: bodies.len() as u32,
+            g: to_f32(g),
+            softening: to_f32(softening),
+            periodic: period.is_some() as u32,
+            period: period.map_or([0.0; 2], |period| [to_f32(period.x), to_f32(period.y)]),
+            _padding: [0; 2],
+        };
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&params));
+        queue.write_buffer(&buffers.bodies, 0, bytemuck::cast_slice(bodies.as_slice()));
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Gravity Encoder"),
+        });
+        {
+            let mut cpass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("Gravity Pass"),
+            });
+            cpass.set_pipeline(&self.pipeline);
+            cpass.set_bind_group(0, &buffers.bind_group, &[]);
+            let workgroups = (bodies.len() as u32).div_ceil(WORKGROUP_SIZE);
+            cpass.dispatch(workgroups, 1, 1);
+        }
+        let size = (bodies.len() * mem::size_of::<[f32; 2]>()) as wgpu::BufferAddress;
+        encoder.copy_buffer_to_buffer(&buffers.accelerations, 0, &buffers.readback, 0, size);
+        queue.submit(std::iter::once(encoder.finish()));
+
+        // Wait for the GPU to finish before reading the results back
+        let slice = buffers.readback.slice(..size);
+        let mapping = slice.map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        block_on(mapping).expect("Map acceleration readback buffer");
+
+        let accelerations = {
+            let data = slice.get_mapped_range();
+            bytemuck::cast_slice::<u8, [f32; 2]>(&data)
+                .iter()
+                .map(|a| cgmath::vec2(from_f32(a[0]), from_f32(a[1])))
+                .collect()
+        };
+        buffers.readback.unmap();
+
+        accelerations
+    }
+}
@@ -0,0 +1,169 @@
+use crate::keys::{Action, KeyBinding};
+use nbody::primitives::{color::ColorMapping, colormap::Colormap};
+use nbody::Scalar;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Name of the file the config is kept in, inside the config directory of the platform
+#[cfg(not(target_arch = "wasm32"))]
+const CONFIG_FILE: &str = "config.ron";
+
+/// Settings remembered from one session to the next. Each one is only a default: flags
+/// given on the command line win over it.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Inner size of the window in physical pixels
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub window_size: Option<(u32, u32)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub present_mode: Option<PresentMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color_mapping: Option<ColorMapping>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub colormap: Option<Colormap>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_step: Option<Scalar>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub theta: Option<Scalar>,
+    /// Scenario loaded last, loaded again unless particles or another scenario are given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scenario: Option<PathBuf>,
+    /// Keys bound to actions instead of their default keys, e.g.
+    /// `{ TogglePause: [(key: P)], Undo: [(key: Z, ctrl: true), (key: Back, alt: true)] }`.
+    /// Only ever edited by hand.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub keys: BTreeMap<Action, Vec<KeyBinding>>,
+}
+
+/// `wgpu::PresentMode` as written to the config file
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum PresentMode {
+    Fifo,
+    Mailbox,
+    Immediate,
+}
+
+impl From<wgpu::PresentMode> for PresentMode {
+    fn from(mode: wgpu::PresentMode) -> Self {
+        match mode {
+            wgpu::PresentMode::Fifo => PresentMode::Fifo,
+            wgpu::PresentMode::Mailbox => PresentMode::Mailbox,
+            wgpu::PresentMode::Immediate => PresentMode::Immediate,
+        }
+    }
+}
+
+impl From<PresentMode> for wgpu::PresentMode {
+    fn from(mode: PresentMode) -> Self {
+        match mode {
+            PresentMode::Fifo => wgpu::PresentMode::Fifo,
+            PresentMode::Mailbox => wgpu::PresentMode::Mailbox,
+            PresentMode::Immediate => wgpu::PresentMode::Immediate,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(ron::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "could not access config: {}", e),
+            ConfigError::Parse(e) => write!(f, "could not parse config: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+impl From<ron::Error> for ConfigError {
+    fn from(e: ron::Error) -> Self {
+        ConfigError::Parse(e)
+    }
+}
+
+impl Config {
+    /// Where the config is kept, e.g. `~/.config/nbody/config.ron` on Linux. `None` if the
+    /// platform has no config directory.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn path() -> Option<PathBuf> {
+        directories::ProjectDirs::from("", "", "nbody")
+            .map(|dirs| dirs.config_dir().join(CONFIG_FILE))
+    }
+
+    /// The browser has no files to keep a config in
+    #[cfg(target_arch = "wasm32")]
+    pub fn path() -> Option<PathBuf> {
+        None
+    }
+
+    /// Reads the config saved by the last session. Starts from the defaults if there is
+    /// none yet.
+    pub fn load() -> Result<Self, ConfigError> {
+        match Self::path() {
+            Some(path) if path.exists() => Self::read(&path),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    fn read(path: &Path) -> Result<Self, ConfigError> {
+        Ok(ron::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Writes the config for the next session, creating its directory if needed
+    pub fn save(&self) -> Result<(), ConfigError> {
+        let path = match Self::path() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let contents = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::new())?;
+        fs::write(path, contents)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn it_reads_key_bindings_and_fails_on_a_malformed_file() {
+        let path = std::env::temp_dir().join(format!("nbody-{}.ron", Uuid::new_v4()));
+        fs::write(
+            &path,
+            "(theta: Some(0.7), keys: { TogglePause: [(key: P)] })",
+        )
+        .unwrap();
+        let config = Config::read(&path).unwrap();
+        assert_eq!(config.theta, Some(0.7));
+        assert_eq!(config.keys[&Action::TogglePause].len(), 1);
+
+        fs::write(
+            &path,
+            "(theta: Some(0.7), keys: { TogglePause: [(key: P)] }",
+        )
+        .unwrap();
+        let malformed = Config::read(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(malformed, Err(ConfigError::Parse(_))));
+    }
+}
@@ -1,11 +1,25 @@
 use crate::primitives::scalar::Scalar;
 
-// 6.674 x 10^-11
+// Gravitational constant in SI units, 6.674 x 10^-11 m³ kg⁻¹ s⁻². Only used to relate
+// physical units to simulation units (see `units`).
 pub static G: Scalar = 6.674e-11;
 
+// Gravitational constant and softening length used by the application. World space is
+// measured in pixels rather than meters, so the SI value of G would make every force vanish.
+pub static SIM_G: Scalar = 1.0;
+pub static SOFTENING: Scalar = 1.0;
+
+// Range the Barnes-Hut opening angle can be adjusted in. 0 opens every node, which makes
+// the tree code exact, and anything much above 2 lumps far too much together.
+pub static MIN_THETA: Scalar = 0.0;
+pub static MAX_THETA: Scalar = 2.0;
+
 // bounding_box
 // World space axes constants
 pub static MIN_X: Scalar = 0.0;
 pub static MAX_X: Scalar = 1000.0;
 pub static MIN_Y: Scalar = 0.0;
 pub static MAX_Y: Scalar = 1000.0;
+// Depth axis of the 3D mode
+pub static MIN_Z: Scalar = 0.0;
+pub static MAX_Z: Scalar = 1000.0;
@@ -0,0 +1,152 @@
+use cgmath::InnerSpace;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::primitives::scalar::Scalar;
+
+/// How drag grows with the speed of a particle relative to the gas around it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DragLaw {
+    /// `-c u`, like a body creeping through a viscous fluid
+    #[default]
+    Linear,
+    /// `-c |u| u`, like a fast body pushing air out of its way
+    Quadratic,
+}
+
+/// Velocity the gas causing the drag moves with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DragFrame {
+    /// The gas is at rest, so every particle is slowed down until it stops
+    #[default]
+    Rest,
+    /// The gas moves with the mass weighted mean velocity of the particles nearby, so
+    /// that particles only lose the motion relative to their neighbors. Groups keep
+    /// moving and rotating together while their random motion dies out.
+    LocalMean,
+}
+
+impl DragFrame {
+    /// The frame to switch to when cycling through them
+    pub fn next(self) -> Self {
+        match self {
+            DragFrame::Rest => DragFrame::LocalMean,
+            DragFrame::LocalMean => DragFrame::Rest,
+        }
+    }
+}
+
+/// Friction slowing particles down relative to a surrounding gas, applied on top of the
+/// forces between particles. Drag does not depend on mass, so heavy and light particles
+/// slow down alike.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Drag {
+    pub law: DragLaw,
+    /// Strength `c` of the drag. Linear drag takes away about `c` times the relative
+    /// velocity per unit of time, so `c` times the time step should stay well below 1.
+    pub coefficient: Scalar,
+    pub frame: DragFrame,
+    /// Size of the square neighborhoods whose mean velocity `DragFrame::LocalMean` drags
+    /// toward
+    pub neighborhood: Scalar,
+}
+
+impl Drag {
+    pub fn new(law: DragLaw, coefficient: Scalar, frame: DragFrame, neighborhood: Scalar) -> Self {
+        Self {
+            law,
+            coefficient,
+            frame,
+            neighborhood,
+        }
+    }
+
+    /// Acceleration of a particle moving with `velocity` through gas moving with
+    /// `gas_velocity`
+    pub fn acceleration(
+        &self,
+        velocity: cgmath::Vector2<Scalar>,
+        gas_velocity: cgmath::Vector2<Scalar>,
+    ) -> cgmath::Vector2<Scalar> {
+        let u = velocity - gas_velocity;
+        match self.law {
+            DragLaw::Linear => -u * self.coefficient,
+            DragLaw::Quadratic => -u * (self.coefficient * u.magnitude()),
+        }
+    }
+
+    /// Velocity of the gas around each particle
+    pub fn gas_velocities(
+        &self,
+        positions: &[cgmath::Vector2<Scalar>],
+        velocities: &[cgmath::Vector2<Scalar>],
+        masses: &[Scalar],
+    ) -> Vec<cgmath::Vector2<Scalar>> {
+        match self.frame {
+            DragFrame::Rest => vec![cgmath::vec2(0.0, 0.0); positions.len()],
+            DragFrame::LocalMean => {
+                let size = self.neighborhood.max(Scalar::EPSILON);
+                let cell = |p: cgmath::Vector2<Scalar>| {
+                    ((p.x / size).floor() as i32, (p.y / size).floor() as i32)
+                };
+                // Momentum and mass of every occupied neighborhood
+                let mut cells: HashMap<(i32, i32), (cgmath::Vector2<Scalar>, Scalar)> =
+                    HashMap::new();
+                for ((&p, &v), &m) in positions.iter().zip(velocities).zip(masses) {
+                    let (momentum, mass) = cells
+                        .entry(cell(p))
+                        .or_insert((cgmath::vec2(0.0, 0.0), 0.0));
+                    *momentum += v * m;
+                    *mass += m;
+                }
+                positions
+                    .iter()
+                    .zip(velocities)
+                    .map(|(&p, &v)| match cells[&cell(p)] {
+                        (momentum, mass) if mass > 0.0 => momentum / mass,
+                        // Only massless particles around, which drag along with nothing
+                        _ => v,
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_drags_toward_the_mean_velocity_of_the_neighborhood() {
+        let drag = Drag::new(DragLaw::Linear, 0.5, DragFrame::LocalMean, 100.0);
+        let positions = [
+            cgmath::vec2(10.0, 10.0),
+            cgmath::vec2(20.0, 20.0),
+            cgmath::vec2(510.0, 10.0),
+        ];
+        let velocities = [
+            cgmath::vec2(4.0, 0.0),
+            cgmath::vec2(0.0, 0.0),
+            cgmath::vec2(0.0, 6.0),
+        ];
+        let gas = drag.gas_velocities(&positions, &velocities, &[1.0, 3.0, 2.0]);
+        assert_eq!(
+            gas,
+            vec![
+                cgmath::vec2(1.0, 0.0),
+                cgmath::vec2(1.0, 0.0),
+                velocities[2]
+            ]
+        );
+        assert_eq!(
+            drag.acceleration(velocities[0], gas[0]),
+            cgmath::vec2(-1.5, 0.0)
+        );
+        // Alone in its neighborhood, so nothing slows it down
+        assert_eq!(
+            drag.acceleration(velocities[2], gas[2]),
+            cgmath::vec2(0.0, 0.0)
+        );
+    }
+}
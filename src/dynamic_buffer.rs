@@ -0,0 +1,72 @@
+use bytemuck::Pod;
+use std::mem;
+
+/// Vertex (or other) buffer that is allocated once and then updated in place with
+/// `queue.write_buffer`. It is only reallocated, to the next power of two, when the data
+/// written to it no longer fits.
+pub struct DynamicBuffer<T> {
+    label: &'static str,
+    usage: wgpu::BufferUsage,
+    buffer: wgpu::Buffer,
+    /// Number of elements the buffer can hold
+    capacity: usize,
+    /// Number of elements written by the last `write`
+    len: usize,
+    _marker: std::marker::PhantomData<T>,
+}
+
+impl<T: Pod> DynamicBuffer<T> {
+    pub fn new(
+        device: &wgpu::Device,
+        label: &'static str,
+        usage: wgpu::BufferUsage,
+        capacity: usize,
+    ) -> Self {
+        let usage = usage | wgpu::BufferUsage::COPY_DST;
+        let capacity = capacity.max(1);
+        Self {
+            label,
+            usage,
+            buffer: Self::create_buffer(device, label, usage, capacity),
+            capacity,
+            len: 0,
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    fn create_buffer(
+        device: &wgpu::Device,
+        label: &'static str,
+        usage: wgpu::BufferUsage,
+        capacity: usize,
+    ) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: (capacity * mem::size_of::<T>()) as wgpu::BufferAddress,
+            usage,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Replaces the contents of the buffer with `data`, growing it first if needed
+    pub fn write(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, data: &[T]) {
+        if data.len() > self.capacity {
+            self.capacity = data.len().next_power_of_two();
+            self.buffer.destroy();
+            self.buffer = Self::create_buffer(device, self.label, self.usage, self.capacity);
+        }
+        if !data.is_empty() {
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(data));
+        }
+        self.len = data.len();
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    /// Number of elements written by the last `write`
+    pub fn len(&self) -> u32 {
+        self.len as u32
+    }
+}
@@ -0,0 +1,57 @@
+use serde::{Deserialize, Serialize};
+
+use crate::primitives::scalar::Scalar;
+
+/// Rules particles age by. Particles can lose mass at a steady rate and disappear once
+/// they get too light or too old, so that scenes that keep spawning particles stay
+/// bounded. Fixed particles age but never evaporate or expire.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Evaporation {
+    /// Age at which particles are removed. Never, if not set.
+    #[serde(default)]
+    pub lifetime: Option<Scalar>,
+    /// Fraction of its mass a particle loses per unit of time. Mass decays
+    /// exponentially and the area shrinks along with it, except for black holes which
+    /// keep their radius.
+    #[serde(default)]
+    pub mass_loss_rate: Scalar,
+    /// Particles lighter than this are removed
+    #[serde(default)]
+    pub min_mass: Scalar,
+}
+
+impl Evaporation {
+    pub fn new(lifetime: Option<Scalar>, mass_loss_rate: Scalar, min_mass: Scalar) -> Self {
+        Self {
+            lifetime,
+            mass_loss_rate,
+            min_mass,
+        }
+    }
+
+    /// Mass left of `mass` after evaporating for `dt`
+    pub fn mass_after(&self, mass: Scalar, dt: Scalar) -> Scalar {
+        mass * (-self.mass_loss_rate * dt).exp()
+    }
+
+    /// Whether a particle of `age` and `mass` should be removed
+    pub fn expired(&self, age: Scalar, mass: Scalar) -> bool {
+        mass < self.min_mass || self.lifetime.is_some_and(|lifetime| age >= lifetime)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_halves_the_mass_every_half_life() {
+        let evaporation = Evaporation::new(Some(10.0), (2.0 as Scalar).ln() / 5.0, 1.0);
+        let mass = evaporation.mass_after(8.0, 10.0);
+        assert!((mass - 2.0).abs() < 1e-4);
+        assert!(!evaporation.expired(9.0, mass));
+        assert!(evaporation.expired(10.0, mass));
+        assert!(evaporation.expired(0.0, 0.5));
+        assert!(!Evaporation::default().expired(1e9, 0.0));
+    }
+}
@@ -0,0 +1,47 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use crate::simulation::StepStats;
+use crate::store::ParticleId;
+
+/// Something that happened to a `Simulation`, sent to everyone who subscribed with
+/// `Simulation::subscribe`
+#[derive(Clone, Copy, Debug)]
+pub enum SimulationEvent {
+    /// A particle was added, either by the caller or as a fragment of a collision
+    ParticleAdded(ParticleId),
+    /// `absorbed` collided with `survivor` and was merged into it. `survivor` may be
+    /// absorbed in turn by a later event of the same step.
+    ParticlesMerged {
+        survivor: ParticleId,
+        absorbed: ParticleId,
+    },
+    /// A particle is gone for another reason than merging: removed by the caller,
+    /// shattered, evaporated or left an open world
+    ParticleRemoved(ParticleId),
+    /// A call to `advance` finished
+    StepCompleted(StepStats),
+}
+
+/// Senders of everyone subscribed to the events of a simulation. Subscribers that hung
+/// up are dropped the next time an event is sent.
+#[derive(Debug, Default)]
+pub(crate) struct Observers {
+    senders: Vec<Sender<SimulationEvent>>,
+}
+
+impl Observers {
+    pub fn subscribe(&mut self) -> Receiver<SimulationEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.senders.push(sender);
+        receiver
+    }
+
+    /// Whether anyone listens. Lets events that take work to put together be skipped.
+    pub fn is_empty(&self) -> bool {
+        self.senders.is_empty()
+    }
+
+    pub fn emit(&mut self, event: SimulationEvent) {
+        self.senders.retain(|sender| sender.send(event).is_ok());
+    }
+}
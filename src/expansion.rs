@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+
+/// How much of the mass distribution below a tree node is kept when the node stands in
+/// for all of its particles
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Expansion {
+    /// The total mass sitting at the center of mass
+    #[default]
+    Monopole,
+    /// Adds the quadrupole moment, which accounts for how the mass is spread around the
+    /// center of mass. A little more work per node, but far more accurate at the same
+    /// theta, so a larger theta can be used.
+    Quadrupole,
+}
+
+impl Expansion {
+    /// The expansion to switch to when cycling through them
+    pub fn next(self) -> Self {
+        match self {
+            Expansion::Monopole => Expansion::Quadrupole,
+            Expansion::Quadrupole => Expansion::Monopole,
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::primitives::scalar::Scalar;
+use crate::simulation::Simulation;
+use crate::store::ParticleId;
+
+/// Text formats particle states can be exported in for analysis with other tools (e.g.
+/// `pandas.read_csv` or `pandas.read_json(lines=True)`). Both write one row per particle
+/// with the columns `time`, `id`, `x`, `y`, `vx`, `vy` and `mass`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExportFormat {
+    /// Comma separated values with a header line
+    #[default]
+    Csv,
+    /// One JSON object per line
+    JsonLines,
+}
+
+impl ExportFormat {
+    /// Picks the format from the extension of `path`: `.json` and `.jsonl` are JSON lines,
+    /// anything else is CSV
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        match path.as_ref().extension().and_then(|e| e.to_str()) {
+            Some("json") | Some("jsonl") => ExportFormat::JsonLines,
+            _ => ExportFormat::Csv,
+        }
+    }
+
+    /// Writes what has to come before the first row
+    pub fn write_header<W: Write>(self, mut writer: W) -> io::Result<()> {
+        match self {
+            ExportFormat::Csv => writeln!(writer, "time,id,x,y,vx,vy,mass"),
+            ExportFormat::JsonLines => Ok(()),
+        }
+    }
+
+    /// Writes the state of a single particle
+    pub(crate) fn write_row<W: Write>(
+        self,
+        mut writer: W,
+        time: Scalar,
+        id: ParticleId,
+        position: cgmath::Vector2<Scalar>,
+        velocity: cgmath::Vector2<Scalar>,
+        mass: Scalar,
+    ) -> io::Result<()> {
+        match self {
+            ExportFormat::Csv => writeln!(
+                writer,
+                "{},{},{},{},{},{},{}",
+                time, id, position.x, position.y, velocity.x, velocity.y, mass
+            ),
+            ExportFormat::JsonLines => writeln!(
+                writer,
+                r#"{{"time":{},"id":"{}","x":{},"y":{},"vx":{},"vy":{},"mass":{}}}"#,
+                time, id, position.x, position.y, velocity.x, velocity.y, mass
+            ),
+        }
+    }
+}
+
+/// Appends the state of every particle after each step to a CSV or JSON lines file
+pub struct TrajectoryExporter {
+    writer: io::BufWriter<File>,
+    format: ExportFormat,
+    steps: usize,
+}
+
+impl TrajectoryExporter {
+    /// Creates (or truncates) the file at `path`
+    pub fn create<P: AsRef<Path>>(path: P, format: ExportFormat) -> io::Result<Self> {
+        let mut writer = io::BufWriter::new(File::create(path)?);
+        format.write_header(&mut writer)?;
+        Ok(Self {
+            writer,
+            format,
+            steps: 0,
+        })
+    }
+
+    /// Appends the current state of `sim`
+    pub fn record(&mut self, sim: &Simulation) -> io::Result<()> {
+        sim.export_snapshot(&mut self.writer, self.format)?;
+        self.steps += 1;
+        Ok(())
+    }
+
+    /// Number of steps exported so far
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// Writes any buffered rows to disk
+    pub fn finish(mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitives::{color, particle::Particle, particle::ParticleProperties};
+    use crate::Integrator;
+
+    fn two_particles() -> Simulation {
+        let mut sim = Simulation::new(0.05, 1.0, 1.0, 1.0, Integrator::Euler);
+        for &(x, vy) in [(400.0, 1.5), (600.0, -1.5)].iter() {
+            sim.add_particle(Particle::new(ParticleProperties {
+                position: cgmath::vec2(x, 500.0),
+                mass: 100.0,
+                radius: 2.0,
+                velocity: cgmath::vec2(0.0, vy),
+                acceleration: cgmath::vec2(0.0, 0.0),
+                color: color::WHITE,
+            }));
+        }
+        sim
+    }
+
+    #[test]
+    fn it_exports_csv() {
+        let sim = two_particles();
+        let mut out = Vec::new();
+        ExportFormat::Csv.write_header(&mut out).unwrap();
+        sim.export_snapshot(&mut out, ExportFormat::Csv).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "time,id,x,y,vx,vy,mass",
+                "0,0v1,400,500,0,1.5,100",
+                "0,1v1,600,500,0,-1.5,100",
+            ]
+        );
+    }
+
+    #[test]
+    fn it_exports_json_lines() {
+        let sim = two_particles();
+        let mut out = Vec::new();
+        ExportFormat::JsonLines.write_header(&mut out).unwrap();
+        sim.export_snapshot(&mut out, ExportFormat::JsonLines)
+            .unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(
+            text.lines().next(),
+            Some(r#"{"time":0,"id":"0v1","x":400,"y":500,"vx":0,"vy":1.5,"mass":100}"#)
+        );
+        assert_eq!(text.lines().count(), 2);
+    }
+
+    #[test]
+    fn it_picks_the_format_from_the_extension() {
+        assert_eq!(ExportFormat::from_path("run.csv"), ExportFormat::Csv);
+        assert_eq!(
+            ExportFormat::from_path("run.jsonl"),
+            ExportFormat::JsonLines
+        );
+        assert_eq!(ExportFormat::from_path("run.json"), ExportFormat::JsonLines);
+        assert_eq!(ExportFormat::from_path("run"), ExportFormat::Csv);
+    }
+}
@@ -0,0 +1,169 @@
+use std::fmt;
+
+use crate::boundary::minimum_image;
+use crate::forces::Sources;
+use crate::primitives::{particle::Particle, scalar::Scalar};
+
+/// How a mass pulls on a particle. The simulation uses `SoftenedGravity` with its own
+/// softening length unless `Simulation::set_force_law` hands it another law, which then
+/// drives both the tree walk and direct summation. Laws are given the total mass and
+/// center of mass of every tree node the walk accepts, just like a single particle.
+pub trait ForceLaw: fmt::Debug + Send + Sync {
+    /// Acceleration of `p` per unit of G due to `other_mass` at `other_pos`. Never called
+    /// with `other_pos` right at the position of `p`.
+    fn accel(
+        &self,
+        p: &Particle,
+        other_pos: cgmath::Vector2<Scalar>,
+        other_mass: Scalar,
+    ) -> cgmath::Vector2<Scalar>;
+
+    /// Sum of `accel` over every source, each measured to its nearest image in worlds
+    /// that repeat every `period`. Sources right at the position of `p`, such as `p`
+    /// itself, are skipped. Laws with a vectorized kernel override this.
+    fn accel_sum(
+        &self,
+        p: &Particle,
+        sources: &Sources,
+        period: Option<cgmath::Vector2<Scalar>>,
+    ) -> cgmath::Vector2<Scalar> {
+        sources
+            .iter()
+            .fold(cgmath::vec2(0.0, 0.0), |acceleration, (position, mass)| {
+                let d = minimum_image(position - p.position, period);
+                if d.x == 0.0 && d.y == 0.0 {
+                    acceleration
+                } else {
+                    acceleration + self.accel(p, p.position + d, mass)
+                }
+            })
+    }
+}
+
+/// Plain inverse-square gravity, `m / d²`. Close encounters get as violent as the time
+/// step allows.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Newtonian;
+
+impl ForceLaw for Newtonian {
+    fn accel(
+        &self,
+        p: &Particle,
+        other_pos: cgmath::Vector2<Scalar>,
+        other_mass: Scalar,
+    ) -> cgmath::Vector2<Scalar> {
+        SoftenedGravity { softening: 0.0 }.accel(p, other_pos, other_mass)
+    }
+
+    fn accel_sum(
+        &self,
+        p: &Particle,
+        sources: &Sources,
+        period: Option<cgmath::Vector2<Scalar>>,
+    ) -> cgmath::Vector2<Scalar> {
+        sources.acceleration(p.position, 0.0, period)
+    }
+}
+
+/// Inverse-square gravity softened by `softening` ε, `m / (d² + ε²)` along the softened
+/// direction
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SoftenedGravity {
+    pub softening: Scalar,
+}
+
+impl ForceLaw for SoftenedGravity {
+    fn accel(
+        &self,
+        p: &Particle,
+        other_pos: cgmath::Vector2<Scalar>,
+        other_mass: Scalar,
+    ) -> cgmath::Vector2<Scalar> {
+        let d = other_pos - p.position;
+        let d2 = d.x * d.x + d.y * d.y + self.softening * self.softening;
+        d * (other_mass / (d2 * d2.sqrt()))
+    }
+
+    fn accel_sum(
+        &self,
+        p: &Particle,
+        sources: &Sources,
+        period: Option<cgmath::Vector2<Scalar>>,
+    ) -> cgmath::Vector2<Scalar> {
+        sources.acceleration(p.position, self.softening * self.softening, period)
+    }
+}
+
+/// Toy gravity falling off as `m / d` (softened by `softening` ε like `SoftenedGravity`),
+/// which is how gravity behaves in a world that really only has two dimensions. Circular
+/// orbits around a point mass all have the same speed, whatever their radius.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct InverseLinear {
+    pub softening: Scalar,
+}
+
+impl ForceLaw for InverseLinear {
+    fn accel(
+        &self,
+        p: &Particle,
+        other_pos: cgmath::Vector2<Scalar>,
+        other_mass: Scalar,
+    ) -> cgmath::Vector2<Scalar> {
+        let d = other_pos - p.position;
+        let d2 = d.x * d.x + d.y * d.y + self.softening * self.softening;
+        d * (other_mass / d2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sources() -> Sources {
+        let mut sources = Sources::new();
+        for i in 0..20 {
+            let position = cgmath::vec2((i * 37 % 100) as Scalar, (i * 91 % 100) as Scalar);
+            sources.push(position, 1.0 + (i % 3) as Scalar);
+        }
+        sources
+    }
+
+    #[test]
+    fn its_default_sum_matches_the_gravity_kernel() {
+        // Summing pair by pair through `accel` instead of with the SIMD kernel
+        #[derive(Debug)]
+        struct PairByPair(SoftenedGravity);
+        impl ForceLaw for PairByPair {
+            fn accel(
+                &self,
+                p: &Particle,
+                other_pos: cgmath::Vector2<Scalar>,
+                other_mass: Scalar,
+            ) -> cgmath::Vector2<Scalar> {
+                self.0.accel(p, other_pos, other_mass)
+            }
+        }
+
+        let mut p = Particle::empty();
+        p.position = cgmath::vec2(42.5, 38.5);
+        let sources = sources();
+        for &period in &[None, Some(cgmath::vec2(100.0, 100.0))] {
+            let law = SoftenedGravity { softening: 2.0 };
+            let kernel = law.accel_sum(&p, &sources, period);
+            let summed = PairByPair(law).accel_sum(&p, &sources, period);
+            assert!((kernel - summed).x.abs() < 1e-4 && (kernel - summed).y.abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn inverse_linear_orbits_have_the_same_speed_at_every_radius() {
+        let p = Particle::empty();
+        let law = InverseLinear { softening: 0.0 };
+        for &r in &[1.0, 10.0, 100.0] {
+            let a = law.accel(&p, cgmath::vec2(r, 0.0), 5.0);
+            // v² = a r for a circular orbit
+            assert!((a.x * r - 5.0).abs() < 1e-4);
+            assert_eq!(a.y, 0.0);
+        }
+    }
+}
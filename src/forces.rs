@@ -0,0 +1,189 @@
+use std::convert::TryInto;
+use wide::CmpGt;
+
+use crate::boundary::minimum_image;
+use crate::primitives::scalar::Scalar;
+
+#[cfg(not(feature = "f64"))]
+type Lanes = wide::f32x8;
+#[cfg(feature = "f64")]
+type Lanes = wide::f64x4;
+
+/// Number of sources `Sources::acceleration` evaluates at once, filling a 256 bit vector
+pub const LANES: usize = std::mem::size_of::<Lanes>() / std::mem::size_of::<Scalar>();
+
+/// Point masses pulling on a particle, kept as separate arrays of x, y and mass so that
+/// every `LANES` of them load straight into vector registers. Direct summation fills it
+/// with every particle, the tree walk with the nodes and leaf particles it accepts.
+#[derive(Clone, Debug, Default)]
+pub struct Sources {
+    xs: Vec<Scalar>,
+    ys: Vec<Scalar>,
+    masses: Vec<Scalar>,
+}
+
+impl Sources {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sources for the particles at `positions` with `masses`
+    pub fn from_columns(positions: &[cgmath::Vector2<Scalar>], masses: &[Scalar]) -> Self {
+        Self {
+            xs: positions.iter().map(|p| p.x).collect(),
+            ys: positions.iter().map(|p| p.y).collect(),
+            masses: masses.to_vec(),
+        }
+    }
+
+    pub fn push(&mut self, position: cgmath::Vector2<Scalar>, mass: Scalar) {
+        self.xs.push(position.x);
+        self.ys.push(position.y);
+        self.masses.push(mass);
+    }
+
+    /// Removes every source while keeping the memory for the next particle
+    pub fn clear(&mut self) {
+        self.xs.clear();
+        self.ys.clear();
+        self.masses.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.masses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.masses.is_empty()
+    }
+
+    /// Position and mass of every source
+    pub fn iter(&self) -> impl Iterator<Item = (cgmath::Vector2<Scalar>, Scalar)> + '_ {
+        self.xs
+            .iter()
+            .zip(&self.ys)
+            .zip(&self.masses)
+            .map(|((&x, &y), &mass)| (cgmath::vec2(x, y), mass))
+    }
+
+    /// Acceleration at `position` per unit of G, summing `m / (d² + ε²)` along the
+    /// softened direction over every source. `LANES` sources are evaluated at once and
+    /// the remainder one by one. Sources right at `position`, such as the particle
+    /// itself, are skipped. Distances are measured to the nearest image in worlds that
+    /// repeat every `period`.
+    pub fn acceleration(
+        &self,
+        position: cgmath::Vector2<Scalar>,
+        softening2: Scalar,
+        period: Option<cgmath::Vector2<Scalar>>,
+    ) -> cgmath::Vector2<Scalar> {
+        let (x, y) = (Lanes::splat(position.x), Lanes::splat(position.y));
+        let softening2_lanes = Lanes::splat(softening2);
+        let (mut ax, mut ay) = (Lanes::ZERO, Lanes::ZERO);
+
+        let chunks = self
+            .xs
+            .chunks_exact(LANES)
+            .zip(self.ys.chunks_exact(LANES))
+            .zip(self.masses.chunks_exact(LANES));
+        for ((xs, ys), masses) in chunks {
+            let mut dx = lanes(xs) - x;
+            let mut dy = lanes(ys) - y;
+            if let Some(period) = period {
+                let (px, py) = (Lanes::splat(period.x), Lanes::splat(period.y));
+                dx -= px * (dx / px).round();
+                dy -= py * (dy / py).round();
+            }
+            let r2 = dx * dx + dy * dy;
+            let d2 = r2 + softening2_lanes;
+            let pull = lanes(masses) / (d2 * d2.sqrt());
+            // Without softening a source at the position itself would pull with 0 / 0
+            let pull = r2.cmp_gt(Lanes::ZERO).blend(pull, Lanes::ZERO);
+            ax += dx * pull;
+            ay += dy * pull;
+        }
+
+        let rest = self.len() - self.len() % LANES;
+        (rest..self.len()).fold(
+            cgmath::vec2(ax.reduce_add(), ay.reduce_add()),
+            |acceleration, i| acceleration + self.pull(i, position, softening2, period),
+        )
+    }
+
+    /// Same as `acceleration` but one source at a time. The reference the vectorized
+    /// version is checked and benchmarked against.
+    pub fn acceleration_scalar(
+        &self,
+        position: cgmath::Vector2<Scalar>,
+        softening2: Scalar,
+        period: Option<cgmath::Vector2<Scalar>>,
+    ) -> cgmath::Vector2<Scalar> {
+        (0..self.len()).fold(cgmath::vec2(0.0, 0.0), |acceleration, i| {
+            acceleration + self.pull(i, position, softening2, period)
+        })
+    }
+
+    /// Pull of source `i` on `position` per unit of G
+    fn pull(
+        &self,
+        i: usize,
+        position: cgmath::Vector2<Scalar>,
+        softening2: Scalar,
+        period: Option<cgmath::Vector2<Scalar>>,
+    ) -> cgmath::Vector2<Scalar> {
+        let d = minimum_image(cgmath::vec2(self.xs[i], self.ys[i]) - position, period);
+        let r2 = d.x * d.x + d.y * d.y;
+        if r2 == 0.0 {
+            return cgmath::vec2(0.0, 0.0);
+        }
+        let d2 = r2 + softening2;
+        d * (self.masses[i] / (d2 * d2.sqrt()))
+    }
+}
+
+/// Loads a chunk of exactly `LANES` values
+fn lanes(values: &[Scalar]) -> Lanes {
+    Lanes::new(values.try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scattered;
+
+    fn sources(n: usize) -> Sources {
+        let mut sources = Sources::new();
+        for (i, position) in scattered(n).enumerate() {
+            sources.push(position, 1.0 + (i % 7) as Scalar);
+        }
+        sources
+    }
+
+    #[test]
+    fn it_matches_the_scalar_sum() {
+        // Off the grid of the sources so that none sits exactly half a period away
+        let position = cgmath::vec2(420.5, 380.5);
+        // Counts that do and do not fill the last run of lanes
+        for &n in &[0, 1, LANES - 1, LANES, 3 * LANES + 2, 500] {
+            let sources = sources(n);
+            for &period in &[None, Some(cgmath::vec2(1000.0, 1000.0))] {
+                let simd = sources.acceleration(position, 4.0, period);
+                let scalar = sources.acceleration_scalar(position, 4.0, period);
+                let scale = scalar.x.abs().max(scalar.y.abs()).max(1e-6);
+                assert!((simd.x - scalar.x).abs() / scale < 1e-4, "{} sources", n);
+                assert!((simd.y - scalar.y).abs() / scale < 1e-4, "{} sources", n);
+            }
+        }
+    }
+
+    #[test]
+    fn it_skips_sources_at_the_position_without_softening() {
+        let mut sources = Sources::new();
+        for _ in 0..LANES {
+            sources.push(cgmath::vec2(0.0, 0.0), 1.0);
+        }
+        sources.push(cgmath::vec2(2.0, 0.0), 4.0);
+        let acceleration = sources.acceleration(cgmath::vec2(0.0, 0.0), 0.0, None);
+        assert_eq!(acceleration, cgmath::vec2(1.0, 0.0));
+    }
+}
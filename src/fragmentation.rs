@@ -0,0 +1,152 @@
+use cgmath::InnerSpace;
+use serde::{Deserialize, Serialize};
+
+use crate::primitives::{
+    particle::{BodyKind, Particle},
+    scalar::{consts::PI, Scalar},
+};
+
+/// Shatters particles that collide too fast instead of merging them. The fragments share
+/// the mass, area and charge of both particles equally and fly apart evenly spaced on a
+/// ring around their center of mass, so total mass, momentum and charge are conserved.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Fragmentation {
+    /// Relative speed above which colliding particles shatter
+    pub min_speed: Scalar,
+    /// Number of fragments a collision breaks into. Fewer if they would end up lighter
+    /// than `min_mass`.
+    pub fragments: usize,
+    /// Fraction of the kinetic energy of the impact the fragments fly apart with, the
+    /// rest being lost to heat. Above 1 collisions create energy.
+    pub dispersion: Scalar,
+    /// Lightest fragment a collision may produce. Collisions too light to break into
+    /// two such fragments merge instead, which keeps fragments from shattering forever.
+    pub min_mass: Scalar,
+}
+
+impl Fragmentation {
+    pub fn new(min_speed: Scalar, fragments: usize, dispersion: Scalar, min_mass: Scalar) -> Self {
+        Self {
+            min_speed,
+            fragments,
+            dispersion,
+            min_mass,
+        }
+    }
+
+    /// Fragments `a` and `b` break into, or `None` if they should merge. Fixed particles
+    /// and black holes never shatter. Fragments take the color and group of the heavier
+    /// particle.
+    pub fn shatter(&self, a: &Particle, b: &Particle) -> Option<Vec<Particle>> {
+        let unbreakable = |p: &Particle| p.fixed || p.kind == BodyKind::BlackHole;
+        let relative_velocity = b.velocity - a.velocity;
+        let speed = relative_velocity.magnitude();
+        if unbreakable(a) || unbreakable(b) || speed <= self.min_speed {
+            return None;
+        }
+        let mass = a.mass + b.mass;
+        let count = if self.min_mass > 0.0 {
+            self.fragments.min((mass / self.min_mass) as usize)
+        } else {
+            self.fragments
+        };
+        if count < 2 {
+            return None;
+        }
+
+        let center = (a.mass * a.position + b.mass * b.position) / mass;
+        let velocity = (a.mass * a.velocity + b.mass * b.velocity) / mass;
+        let n = count as Scalar;
+        let radius = ((a.radius.powi(2) + b.radius.powi(2)) / n).sqrt();
+        // Far enough out that neighbors on the ring do not touch and merge right away
+        let ring = 1.05 * radius / (PI / n).sin();
+        // The impact energy is ½ μ u² with the reduced mass μ. Every fragment flying out
+        // at `kick` carries ½ (m / n) kick² of it.
+        let reduced_mass = a.mass * b.mass / mass;
+        let kick = speed * (self.dispersion.max(0.0) * reduced_mass / mass).sqrt();
+        // Lined up with the impact, so that head-on collisions spray along their axis
+        let heading = relative_velocity.y.atan2(relative_velocity.x);
+        let heavier = if a.mass >= b.mass { a } else { b };
+        Some(
+            (0..count)
+                .map(|i| {
+                    let angle = heading + 2.0 * PI * i as Scalar / n;
+                    let direction = cgmath::vec2(angle.cos(), angle.sin());
+                    Particle {
+                        position: center + direction * ring,
+                        mass: mass / n,
+                        radius,
+                        velocity: velocity + direction * kick,
+                        charge: (a.charge + b.charge) / n,
+                        color: heavier.color,
+                        group: heavier.group,
+                        ..Particle::empty()
+                    }
+                })
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rock(x: Scalar, vx: Scalar, mass: Scalar) -> Particle {
+        let mut p = Particle::empty();
+        p.position = cgmath::vec2(x, 0.0);
+        p.velocity = cgmath::vec2(vx, 0.0);
+        p.mass = mass;
+        p.radius = 2.0;
+        p
+    }
+
+    #[test]
+    fn it_conserves_mass_momentum_and_area_when_shattering() {
+        let fragmentation = Fragmentation::new(5.0, 6, 0.5, 0.1);
+        let (a, b) = (rock(0.0, 10.0, 3.0), rock(3.0, -4.0, 1.0));
+        let fragments = fragmentation.shatter(&a, &b).unwrap();
+        assert_eq!(fragments.len(), 6);
+
+        let mass: Scalar = fragments.iter().map(|f| f.mass).sum();
+        let momentum = fragments
+            .iter()
+            .fold(cgmath::vec2(0.0, 0.0), |sum, f| sum + f.velocity * f.mass);
+        let center = fragments
+            .iter()
+            .fold(cgmath::vec2(0.0, 0.0), |sum, f| sum + f.position * f.mass)
+            / mass;
+        let area: Scalar = fragments.iter().map(|f| f.radius.powi(2)).sum();
+        assert!((mass - 4.0).abs() < 1e-4);
+        assert!((momentum - cgmath::vec2(26.0, 0.0)).magnitude() < 1e-3);
+        assert!((center - cgmath::vec2(0.75, 0.0)).magnitude() < 1e-3);
+        assert!((area - 8.0).abs() < 1e-3);
+
+        // Half of the impact energy ½ μ u² = ½ 0.75 14² goes into flying apart
+        let velocity = momentum / mass;
+        let energy: Scalar = fragments
+            .iter()
+            .map(|f| 0.5 * f.mass * (f.velocity - velocity).magnitude2())
+            .sum();
+        assert!((energy - 0.5 * 0.5 * 0.75 * 14.0 * 14.0).abs() < 1e-2);
+
+        // None of them touch each other
+        for (i, f) in fragments.iter().enumerate() {
+            assert!(fragments[i + 1..].iter().all(|g| !f.check_collision(g)));
+        }
+    }
+
+    #[test]
+    fn it_merges_slow_or_light_collisions() {
+        let fragmentation = Fragmentation::new(5.0, 6, 0.5, 1.0);
+        assert!(fragmentation
+            .shatter(&rock(0.0, 2.0, 3.0), &rock(3.0, -2.0, 1.0))
+            .is_none());
+        assert!(fragmentation
+            .shatter(&rock(0.0, 10.0, 1.0), &rock(3.0, -4.0, 0.5))
+            .is_none());
+        // Only heavy enough for three fragments
+        let fragments = fragmentation.shatter(&rock(0.0, 10.0, 2.0), &rock(3.0, -4.0, 1.5));
+        assert_eq!(fragments.map(|f| f.len()), Some(3));
+    }
+}
@@ -0,0 +1,98 @@
+use futures::executor::block_on;
+use std::{mem, time::Duration};
+
+/// Most timestamps written during one frame, the start included
+const MAX_TIMESTAMPS: u32 = 16;
+/// Bytes a timestamp is resolved into
+const TIMESTAMP_SIZE: u32 = mem::size_of::<u64>() as u32;
+
+/// Measures how long the GPU spends on the passes of a frame with timestamp queries.
+/// A timestamp is written before the first pass and after every pass, the time between
+/// two of them belongs to the pass in between. Reading them back waits for the GPU, so
+/// this is only created when asked for.
+pub struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    /// Resolved timestamps, mapped for reading after every frame
+    buffer: wgpu::Buffer,
+    /// Nanoseconds per tick
+    period: f32,
+    /// Pass that ends at each timestamp after the first one of the frame
+    labels: Vec<&'static str>,
+    /// Time taken by every pass of the last frame
+    last: Vec<(&'static str, Duration)>,
+}
+
+impl GpuTimer {
+    /// `period` is the timestamp period of the adapter
+    pub fn new(device: &wgpu::Device, period: f32) -> Self {
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            ty: wgpu::QueryType::Timestamp,
+            count: MAX_TIMESTAMPS,
+        });
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Timestamp Buffer"),
+            size: (MAX_TIMESTAMPS * TIMESTAMP_SIZE) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::MAP_READ,
+            mapped_at_creation: false,
+        });
+        Self {
+            query_set,
+            buffer,
+            period,
+            labels: Vec::new(),
+            last: Vec::new(),
+        }
+    }
+
+    /// Writes the timestamp the first pass of the frame starts at
+    pub fn start(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        self.labels.clear();
+        encoder.write_timestamp(&self.query_set, 0);
+    }
+
+    /// Writes the timestamp the pass encoded since the last one ends at. Passes beyond
+    /// `MAX_TIMESTAMPS` go unmeasured.
+    pub fn mark(&mut self, encoder: &mut wgpu::CommandEncoder, label: &'static str) {
+        let index = self.labels.len() as u32 + 1;
+        if index < MAX_TIMESTAMPS {
+            encoder.write_timestamp(&self.query_set, index);
+            self.labels.push(label);
+        }
+    }
+
+    /// Copies the timestamps of the frame into the readback buffer. Encoded last.
+    pub fn resolve(&self, encoder: &mut wgpu::CommandEncoder) {
+        let count = self.labels.len() as u32 + 1;
+        encoder.resolve_query_set(&self.query_set, 0..count, &self.buffer, 0);
+    }
+
+    /// Reads back the timestamps of the submitted frame. Blocks until the GPU is done.
+    pub fn read(&mut self, device: &wgpu::Device) {
+        let size = ((self.labels.len() + 1) * TIMESTAMP_SIZE as usize) as wgpu::BufferAddress;
+        let slice = self.buffer.slice(..size);
+        let mapping = slice.map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        if let Err(e) = block_on(mapping) {
+            eprintln!("could not read back timestamps: {}", e);
+            return;
+        }
+        let timestamps: Vec<u64> = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+        self.buffer.unmap();
+
+        let period = self.period as f64;
+        self.last = self
+            .labels
+            .iter()
+            .zip(timestamps.windows(2))
+            .map(|(&label, pair)| {
+                let ticks = pair[1].saturating_sub(pair[0]);
+                (label, Duration::from_nanos((ticks as f64 * period) as u64))
+            })
+            .collect();
+    }
+
+    /// Time taken by every pass of the last frame, for the F3 timings
+    pub fn spans(&self) -> &[(&'static str, Duration)] {
+        &self.last
+    }
+}
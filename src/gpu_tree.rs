@@ -0,0 +1,399 @@
+use bytemuck::{Pod, Zeroable};
+use futures::executor::block_on;
+use nbody::{
+    primitives::scalar::{from_f32, to_f32, Scalar},
+    ParticleStore, QuadBoundingBox,
+};
+use std::mem;
+use wgpu::{
+    BindGroupLayoutDescriptor, BufferDescriptor, CommandEncoderDescriptor, ComputePassDescriptor,
+    ComputePipelineDescriptor, PipelineLayoutDescriptor,
+};
+
+use crate::compute::GpuBody;
+
+/// Number of invocations per workgroup. Must match `workgroup_size` in `tree.comp.wgsl`
+const WORKGROUP_SIZE: u32 = 64;
+/// Bytes between the settings of consecutive dispatches in the steps buffer, which are
+/// picked with dynamic offsets
+const STEP_STRIDE: wgpu::BufferAddress = wgpu::BIND_BUFFER_ALIGNMENT;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct GpuTreeParams {
+    num_bodies: u32,
+    num_keys: u32,
+    g: f32,
+    softening: f32,
+    theta: f32,
+    min_x: f32,
+    min_y: f32,
+    span: f32,
+}
+
+unsafe impl Pod for GpuTreeParams {}
+unsafe impl Zeroable for GpuTreeParams {}
+
+/// Settings of a single bitonic sort or summing up dispatch
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+struct GpuStep {
+    j: u32,
+    k: u32,
+    level: u32,
+    _padding: u32,
+}
+
+unsafe impl Pod for GpuStep {}
+unsafe impl Zeroable for GpuStep {}
+
+/// Buffers sized for a given number of bodies along with the bind group referencing them
+struct TreeBuffers {
+    capacity: usize,
+    bodies: wgpu::Buffer,
+    links: wgpu::Buffer,
+    nodes: wgpu::Buffer,
+    accelerations: wgpu::Buffer,
+    readback: wgpu::Buffer,
+    steps: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    /// Number of keys the bitonic sort steps in `steps` were written for
+    num_keys: usize,
+}
+
+/// Evaluates Barnes-Hut gravity on the GPU. The tree is built there as well, over the
+/// Morton codes of the bodies, so nothing but the bodies and their accelerations cross
+/// the bus. See `tree.comp.wgsl` for how.
+pub struct GpuTreeForces {
+    morton: wgpu::ComputePipeline,
+    sort: wgpu::ComputePipeline,
+    build: wgpu::ComputePipeline,
+    sum_up: wgpu::ComputePipeline,
+    forces: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    params_buffer: wgpu::Buffer,
+    buffers: Option<TreeBuffers>,
+}
+
+/// Number of dispatches the bitonic sort of `num_keys` keys takes, a power of two
+fn sort_steps(num_keys: usize) -> usize {
+    let log = num_keys.trailing_zeros() as usize;
+    log * (log + 1) / 2
+}
+
+/// Number of summing up dispatches it takes to reach the root of a tree over
+/// `num_bodies` bodies. Every level of the tree shares a longer prefix of the 32 bit
+/// Morton code, extended by the index of the body where codes are equal.
+fn sum_up_steps(num_bodies: usize) -> usize {
+    32 + (usize::BITS - num_bodies.saturating_sub(1).leading_zeros()) as usize
+}
+
+/// Runs `invocations` invocations of the current pipeline with the settings of the
+/// `step`th entry of the steps buffer
+fn dispatch<'a>(
+    cpass: &mut wgpu::ComputePass<'a>,
+    bind_group: &'a wgpu::BindGroup,
+    step: usize,
+    invocations: usize,
+) {
+    let offset = step as wgpu::BufferAddress * STEP_STRIDE;
+    cpass.set_bind_group(0, bind_group, &[offset as wgpu::DynamicOffset]);
+    cpass.dispatch((invocations as u32).div_ceil(WORKGROUP_SIZE), 1, 1);
+}
+
+impl GpuTreeForces {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let module = device.create_shader_module(&include_wgsl!("shaders/tree.comp.wgsl"));
+
+        let uniform_entry = |binding, has_dynamic_offset| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStage::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStage::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Tree Bind Group Layout"),
+            entries: &[
+                uniform_entry(0, false),
+                uniform_entry(1, true),
+                storage_entry(2, true),
+                storage_entry(3, false),
+                storage_entry(4, false),
+                storage_entry(5, false),
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Tree Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |entry_point| {
+            device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: &module,
+                entry_point,
+            })
+        };
+
+        let params_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Tree Params Buffer"),
+            size: mem::size_of::<GpuTreeParams>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            morton: pipeline("morton"),
+            sort: pipeline("sort"),
+            build: pipeline("build"),
+            sum_up: pipeline("sum_up"),
+            forces: pipeline("forces"),
+            bind_group_layout,
+            params_buffer,
+            buffers: None,
+        }
+    }
+
+    /// Makes sure the buffers can hold at least `count` bodies and the tree over them.
+    /// Buffers are only reallocated (to the next power of two) when the current capacity
+    /// is exceeded.
+    fn reserve(&mut self, device: &wgpu::Device, count: usize) {
+        if let Some(buffers) = &self.buffers {
+            if buffers.capacity >= count {
+                return;
+            }
+        }
+
+        let capacity = count.next_power_of_two();
+        let storage = |label, size: usize, usage| {
+            device.create_buffer(&BufferDescriptor {
+                label: Some(label),
+                size: size as wgpu::BufferAddress,
+                usage: wgpu::BufferUsage::STORAGE | usage,
+                mapped_at_creation: false,
+            })
+        };
+        let vec4 = mem::size_of::<[f32; 4]>();
+        let bodies = storage(
+            "Tree Bodies Buffer",
+            capacity * mem::size_of::<GpuBody>(),
+            wgpu::BufferUsage::COPY_DST,
+        );
+        // Sort keys, then the links of the 2n - 1 nodes
+        let links = storage(
+            "Tree Links Buffer",
+            3 * capacity * vec4,
+            wgpu::BufferUsage::empty(),
+        );
+        // Centers of mass, then bounding boxes
+        let nodes = storage(
+            "Tree Nodes Buffer",
+            4 * capacity * vec4,
+            wgpu::BufferUsage::empty(),
+        );
+        let accel_size = (capacity * mem::size_of::<[f32; 2]>()) as wgpu::BufferAddress;
+        let accelerations = storage(
+            "Tree Accelerations Buffer",
+            accel_size as usize,
+            wgpu::BufferUsage::COPY_SRC,
+        );
+        let readback = device.create_buffer(&BufferDescriptor {
+            label: Some("Tree Readback Buffer"),
+            size: accel_size,
+            usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let max_steps = sort_steps(capacity) + sum_up_steps(capacity);
+        let steps = device.create_buffer(&BufferDescriptor {
+            label: Some("Tree Steps Buffer"),
+            size: max_steps as wgpu::BufferAddress * STEP_STRIDE,
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Tree Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: &steps,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(mem::size_of::<GpuStep>() as u64),
+                    },
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: bodies.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: links.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: nodes.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: accelerations.as_entire_binding(),
+                },
+            ],
+        });
+
+        if let Some(old) = self.buffers.take() {
+            old.bodies.destroy();
+            old.links.destroy();
+            old.nodes.destroy();
+            old.accelerations.destroy();
+            old.readback.destroy();
+            old.steps.destroy();
+        }
+        self.buffers = Some(TreeBuffers {
+            capacity,
+            bodies,
+            links,
+            nodes,
+            accelerations,
+            readback,
+            steps,
+            bind_group,
+            num_keys: 0,
+        });
+    }
+
+    /// Uploads the particles, builds the tree and evaluates the accelerations on the GPU
+    /// and blocks until they have been read back. The returned accelerations are in the
+    /// same order as `particles`.
+    pub fn compute(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        particles: &ParticleStore,
+        g: Scalar,
+        softening: Scalar,
+        theta: Scalar,
+    ) -> Vec<cgmath::Vector2<Scalar>> {
+        if particles.is_empty() {
+            return Vec::new();
+        }
+        let _span = tracing::info_span!("gpu tree forces").entered();
+        self.reserve(device, particles.len());
+        let buffers = self.buffers.as_mut().unwrap();
+
+        let num_bodies = particles.len();
+        let num_keys = num_bodies.next_power_of_two();
+        // The sort steps only change with the number of keys, the summing up steps are
+        // written for the most bodies that many keys can hold
+        if buffers.num_keys != num_keys {
+            let mut steps = Vec::new();
+            let mut k = 2;
+            while k <= num_keys {
+                let mut j = k / 2;
+                while j > 0 {
+                    steps.push(GpuStep {
+                        j: j as u32,
+                        k: k as u32,
+                        ..GpuStep::default()
+                    });
+                    j /= 2;
+                }
+                k *= 2;
+            }
+            steps.extend((1..=sum_up_steps(num_keys)).map(|level| GpuStep {
+                level: level as u32,
+                ..GpuStep::default()
+            }));
+            for (i, step) in steps.iter().enumerate() {
+                let offset = i as wgpu::BufferAddress * STEP_STRIDE;
+                queue.write_buffer(&buffers.steps, offset, bytemuck::bytes_of(step));
+            }
+            buffers.num_keys = num_keys;
+        }
+
+        let bounds = QuadBoundingBox::enclosing(particles.positions().iter().copied());
+        let params = GpuTreeParams {
+            num_bodies: num_bodies as u32,
+            num_keys: num_keys as u32,
+            g: to_f32(g),
+            softening: to_f32(softening),
+            theta: to_f32(theta),
+            min_x: to_f32(bounds.min_x),
+            min_y: to_f32(bounds.min_y),
+            span: to_f32(bounds.length()),
+        };
+        let bodies = GpuBody::from_store(particles);
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&params));
+        queue.write_buffer(&buffers.bodies, 0, bytemuck::cast_slice(bodies.as_slice()));
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Tree Encoder"),
+        });
+        {
+            let mut cpass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("Tree Pass"),
+            });
+            cpass.set_pipeline(&self.morton);
+            dispatch(&mut cpass, &buffers.bind_group, 0, num_keys);
+            cpass.set_pipeline(&self.sort);
+            for step in 0..sort_steps(num_keys) {
+                dispatch(&mut cpass, &buffers.bind_group, step, num_keys);
+            }
+            cpass.set_pipeline(&self.build);
+            dispatch(&mut cpass, &buffers.bind_group, 0, num_bodies);
+            if num_bodies > 1 {
+                cpass.set_pipeline(&self.sum_up);
+                for level in 0..sum_up_steps(num_bodies) {
+                    dispatch(
+                        &mut cpass,
+                        &buffers.bind_group,
+                        sort_steps(num_keys) + level,
+                        num_bodies - 1,
+                    );
+                }
+            }
+            cpass.set_pipeline(&self.forces);
+            dispatch(&mut cpass, &buffers.bind_group, 0, num_bodies);
+        }
+        let size = (num_bodies * mem::size_of::<[f32; 2]>()) as wgpu::BufferAddress;
+        encoder.copy_buffer_to_buffer(&buffers.accelerations, 0, &buffers.readback, 0, size);
+        queue.submit(std::iter::once(encoder.finish()));
+
+        // Wait for the GPU to finish before reading the results back
+        let slice = buffers.readback.slice(..size);
+        let mapping = slice.map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        block_on(mapping).expect("Map acceleration readback buffer");
+
+        let accelerations = {
+            let data = slice.get_mapped_range();
+            bytemuck::cast_slice::<u8, [f32; 2]>(&data)
+                .iter()
+                .map(|a| cgmath::vec2(from_f32(a[0]), from_f32(a[1])))
+                .collect()
+        };
+        buffers.readback.unmap();
+
+        accelerations
+    }
+}
@@ -0,0 +1,100 @@
+use serde::{Deserialize, Serialize};
+
+/// Which groups of particles (`Particle::group`) act on each other. By default every
+/// group feels every other one and collides with it. Turning entries off allows setups
+/// such as test particles that feel a massive disk without pulling on it, or dark matter
+/// that passes through stars. In a scenario file:
+///
+/// ```ron
+/// groups: (gravity: [[true, false], [true, true]], collisions: [[true, false], [false, true]]),
+/// ```
+///
+/// Entries missing from either matrix are true.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupMatrix {
+    /// `gravity[a][b]` is whether group `a` feels the forces of group `b`, gravity as
+    /// well as electrostatics
+    #[serde(default)]
+    pub gravity: Vec<Vec<bool>>,
+    /// `collisions[a][b]` is whether particles of groups `a` and `b` merge when they
+    /// touch. Pairs only collide if both `collisions[a][b]` and `collisions[b][a]` are
+    /// true.
+    #[serde(default)]
+    pub collisions: Vec<Vec<bool>>,
+}
+
+impl GroupMatrix {
+    /// Every group feels and collides with every other one
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether particles of group `group` feel the forces of particles of group `from`
+    pub fn feels(&self, group: u8, from: u8) -> bool {
+        entry(&self.gravity, group, from)
+    }
+
+    /// Whether particles of groups `a` and `b` collide
+    pub fn collide(&self, a: u8, b: u8) -> bool {
+        entry(&self.collisions, a, b) && entry(&self.collisions, b, a)
+    }
+
+    /// Makes group `group` feel the forces of group `from` or not
+    pub fn set_feels(&mut self, group: u8, from: u8, feels: bool) {
+        set_entry(&mut self.gravity, group, from, feels);
+    }
+
+    /// Makes particles of groups `a` and `b` collide or pass through each other
+    pub fn set_collide(&mut self, a: u8, b: u8, collide: bool) {
+        set_entry(&mut self.collisions, a, b, collide);
+        set_entry(&mut self.collisions, b, a, collide);
+    }
+
+    /// Whether every group feels every other one, in which case groups make no
+    /// difference to the forces
+    pub fn feels_all(&self) -> bool {
+        self.gravity.iter().flatten().all(|&feels| feels)
+    }
+
+    /// Whether every group collides with every other one
+    pub fn collides_all(&self) -> bool {
+        self.collisions.iter().flatten().all(|&collide| collide)
+    }
+}
+
+fn entry(matrix: &[Vec<bool>], row: u8, column: u8) -> bool {
+    matrix
+        .get(row as usize)
+        .and_then(|row| row.get(column as usize))
+        .copied()
+        .unwrap_or(true)
+}
+
+fn set_entry(matrix: &mut Vec<Vec<bool>>, row: u8, column: u8, value: bool) {
+    let (row, column) = (row as usize, column as usize);
+    if matrix.len() <= row {
+        matrix.resize(row + 1, Vec::new());
+    }
+    if matrix[row].len() <= column {
+        matrix[row].resize(column + 1, true);
+    }
+    matrix[row][column] = value;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_defaults_missing_entries_to_true() {
+        let mut groups = GroupMatrix::new();
+        assert!(groups.feels_all() && groups.collides_all());
+        groups.set_feels(2, 0, false);
+        groups.set_collide(1, 3, false);
+        assert!(!groups.feels(2, 0));
+        assert!(groups.feels(0, 2) && groups.feels(2, 1) && groups.feels(7, 0));
+        assert!(!groups.collide(1, 3) && !groups.collide(3, 1));
+        assert!(groups.collide(1, 2));
+        assert!(!groups.feels_all() && !groups.collides_all());
+    }
+}
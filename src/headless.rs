@@ -0,0 +1,186 @@
+use crate::metrics::{Metrics, MetricsServer};
+use nbody::primitives::{color, particle::ParticleProperties};
+use nbody::{
+    constants, AdaptiveTimeStep, BlockTimeSteps, Drag, Expansion, ExportFormat, ForceSolver,
+    Fragmentation, Integrator, MergeModel, Particle, Scalar, Simulation, StepStats,
+    TrajectoryExporter,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+/// Settings of a headless benchmark run
+pub struct HeadlessOptions {
+    /// Number of simulation steps to run
+    pub steps: usize,
+    /// Number of randomly placed particles to start with
+    pub particles: usize,
+    pub time_step: Scalar,
+    pub theta: Scalar,
+    pub expansion: Expansion,
+    pub incremental_tree: bool,
+    pub leaf_capacity: usize,
+    pub morton_sort: Option<usize>,
+    pub integrator: Integrator,
+    /// Picks the step size each step when set
+    pub adaptive_step: Option<AdaptiveTimeStep>,
+    /// Lets particles step by fractions of the time step when set
+    pub block_steps: Option<BlockTimeSteps>,
+    /// Slows particles down when set
+    pub drag: Option<Drag>,
+    /// Seed for placing the particles
+    pub seed: u64,
+    pub solver: ForceSolver,
+    pub merge_model: MergeModel,
+    /// Shatters fast collisions when set
+    pub fragmentation: Option<Fragmentation>,
+    /// File to export the particles into after every step
+    pub export: Option<PathBuf>,
+    /// Whether to print the Barnes-Hut acceleration error of the final state
+    pub compare: bool,
+    /// Address to serve metrics on while running
+    pub metrics: Option<String>,
+}
+
+/// Time between two updates of the served metrics
+const METRICS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Runs the simulation without a window or GPU and prints timing statistics once done.
+/// Useful for profiling how the simulation scales on machines without a display.
+pub fn run(options: HeadlessOptions) {
+    let mut sim = Simulation::new(
+        options.time_step,
+        options.theta,
+        constants::SIM_G,
+        constants::SOFTENING,
+        options.integrator,
+    );
+    sim.set_solver(options.solver);
+    sim.set_expansion(options.expansion);
+    sim.set_incremental_tree(options.incremental_tree);
+    sim.set_leaf_capacity(options.leaf_capacity);
+    sim.set_morton_sort(options.morton_sort);
+    sim.set_merge_model(options.merge_model);
+    sim.set_fragmentation(options.fragmentation);
+    sim.set_adaptive_step(options.adaptive_step);
+    sim.set_block_steps(options.block_steps);
+    sim.set_drag(options.drag);
+    let mut rng = StdRng::seed_from_u64(options.seed);
+    sim.add_particles((0..options.particles).map(|_| {
+        let radius = rng.gen_range(1..4) as Scalar;
+        Particle::new(ParticleProperties {
+            position: cgmath::vec2(
+                rng.gen_range(constants::MIN_X..constants::MAX_X),
+                rng.gen_range(constants::MIN_Y..constants::MAX_Y),
+            ),
+            radius,
+            mass: 50.0 * radius,
+            velocity: cgmath::vec2(0.0, 0.0),
+            acceleration: cgmath::vec2(0.0, 0.0),
+            color: color::WHITE,
+        })
+    }));
+    let initial = sim.get_particles().len();
+
+    let mut exporter = options.export.as_ref().and_then(|path| {
+        TrajectoryExporter::create(path, ExportFormat::from_path(path))
+            .map_err(|e| eprintln!("{}: {}", path.display(), e))
+            .ok()
+    });
+
+    let server = options.metrics.as_ref().and_then(|address| {
+        MetricsServer::start(address)
+            .map_err(|e| eprintln!("{}: {}", address, e))
+            .ok()
+    });
+    // Measuring the energy walks a tree of its own, so it is only done for the metrics
+    let initial_energy = server.as_ref().map(|_| sim.energy().total());
+    let mut last_update = (Instant::now(), 0);
+
+    let mut totals = StepStats::default();
+    let start = Instant::now();
+    for step in 1..=options.steps {
+        sim.advance();
+        let stats = sim.get_stats();
+        totals.tree_build += stats.tree_build;
+        totals.force_eval += stats.force_eval;
+        totals.collision_time += stats.collision_time;
+        totals.collisions += stats.collisions;
+        totals.fragmentations += stats.fragmentations;
+        if let Some(path) = &options.export {
+            if let Some(Err(e)) = exporter.as_mut().map(|exporter| exporter.record(&sim)) {
+                eprintln!("{}: {}", path.display(), e);
+                exporter = None;
+            }
+        }
+        if let Some(server) = &server {
+            let (time, steps) = last_update;
+            let elapsed = time.elapsed();
+            if elapsed >= METRICS_INTERVAL || step == options.steps {
+                server.update(Metrics {
+                    steps: step,
+                    step_rate: (step - steps) as f64 / elapsed.as_secs_f64(),
+                    particles: sim.get_particles().len(),
+                    simulated_time: sim.get_elapsed(),
+                    collisions: totals.collisions,
+                    energy_drift: initial_energy
+                        .filter(|&energy| energy != 0.0)
+                        .map(|energy| (sim.energy().total() - energy) / energy.abs()),
+                });
+                last_update = (Instant::now(), step);
+            }
+        }
+    }
+    if let (Some(path), Some(Err(e))) = (&options.export, exporter.map(TrajectoryExporter::finish))
+    {
+        eprintln!("{}: {}", path.display(), e);
+    }
+    let total = start.elapsed();
+
+    println!(
+        "{} steps, {} particles ({} requested, {} left), seed {}",
+        options.steps,
+        initial,
+        options.particles,
+        sim.get_particles().len(),
+        options.seed
+    );
+    print_phase("tree build", totals.tree_build, options.steps);
+    print_phase("force eval", totals.force_eval, options.steps);
+    print_phase("collisions", totals.collision_time, options.steps);
+    print_phase("total", total, options.steps);
+    println!("merged particles: {}", totals.collisions);
+    if options.fragmentation.is_some() {
+        println!("shattered collisions: {}", totals.fragmentations);
+    }
+    println!("simulated time: {:.2}", sim.get_elapsed());
+    if options.solver == ForceSolver::BarnesHut {
+        let tree = sim.get_tree_stats();
+        println!(
+            "final tree: {} nodes, {} leaves, depth {}, {:.1} particles per leaf",
+            tree.nodes,
+            tree.leaves,
+            tree.max_depth,
+            tree.mean_occupancy()
+        );
+    }
+    if options.compare {
+        println!(
+            "RMS acceleration error (theta {}): {:.3e}",
+            sim.get_theta(),
+            sim.acceleration_error()
+        );
+    }
+}
+
+fn print_phase(name: &str, time: Duration, steps: usize) {
+    let ms = time.as_secs_f64() * 1000.0;
+    println!(
+        "{:>12}: {:10.2} ms ({:.3} ms/step)",
+        name,
+        ms,
+        ms / steps.max(1) as f64
+    );
+}
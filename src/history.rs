@@ -0,0 +1,172 @@
+use std::collections::VecDeque;
+
+use crate::{
+    primitives::{particle::Particle, scalar::Scalar},
+    simulation::Simulation,
+    store::ParticleId,
+};
+
+/// Change made to a simulation by hand that can be taken back
+#[derive(Clone, Debug)]
+pub enum Edit {
+    /// These particles were added
+    Added(Vec<ParticleId>),
+    /// These particles were removed, as they were right before
+    Removed(Vec<Particle>),
+    /// `impulse` was added to the velocity of these particles
+    Pushed {
+        ids: Vec<ParticleId>,
+        impulse: cgmath::Vector2<Scalar>,
+    },
+}
+
+impl Edit {
+    /// Takes the edit back on `sim`, returning the edit that redoes it. Particles that
+    /// merged or left since are skipped.
+    pub fn revert(self, sim: &mut Simulation) -> Edit {
+        match self {
+            Edit::Added(ids) => Edit::Removed(
+                ids.into_iter()
+                    .filter_map(|id| sim.remove_particle(id))
+                    .collect(),
+            ),
+            Edit::Removed(particles) => Edit::Added(
+                particles
+                    .into_iter()
+                    .map(|p| sim.restore_particle(p))
+                    .collect(),
+            ),
+            Edit::Pushed { ids, impulse } => {
+                for &id in &ids {
+                    sim.edit_particle(id, |p| p.velocity -= impulse);
+                }
+                Edit::Pushed {
+                    ids,
+                    impulse: -impulse,
+                }
+            }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Edit::Added(ids) | Edit::Pushed { ids, .. } => ids.is_empty(),
+            Edit::Removed(particles) => particles.is_empty(),
+        }
+    }
+}
+
+/// Last edits made to a simulation, to undo and redo them in order. Holds up to
+/// `capacity` edits and forgets the oldest ones beyond that.
+#[derive(Debug)]
+pub struct EditHistory {
+    undo: VecDeque<Edit>,
+    redo: Vec<Edit>,
+    capacity: usize,
+}
+
+impl EditHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            undo: VecDeque::with_capacity(capacity),
+            redo: Vec::new(),
+            capacity,
+        }
+    }
+
+    /// Remembers an edit that was just made. Edits undone before can no longer be
+    /// redone afterwards.
+    pub fn record(&mut self, edit: Edit) {
+        if edit.is_empty() || self.capacity == 0 {
+            return;
+        }
+        self.redo.clear();
+        if self.undo.len() == self.capacity {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(edit);
+    }
+
+    /// Takes back the last edit. Returns false if there was nothing to undo.
+    pub fn undo(&mut self, sim: &mut Simulation) -> bool {
+        match self.undo.pop_back() {
+            Some(edit) => {
+                self.redo.push(edit.revert(sim));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Makes the last undone edit again. Returns false if there was nothing to redo.
+    pub fn redo(&mut self, sim: &mut Simulation) -> bool {
+        match self.redo.pop() {
+            Some(edit) => {
+                self.undo.push_back(edit.revert(sim));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Forgets every edit, e.g. once the particles they refer to are all gone
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrator::Integrator;
+
+    fn particle_at(x: Scalar) -> Particle {
+        Particle {
+            position: cgmath::vec2(x, 500.0),
+            mass: 10.0,
+            radius: 1.0,
+            ..Particle::empty()
+        }
+    }
+
+    #[test]
+    fn it_undoes_and_redoes_edits_in_order() {
+        let mut sim = Simulation::new(0.1, 1.0, 1.0, 0.0, Integrator::Leapfrog);
+        let mut history = EditHistory::new(2);
+        let first = sim.add_particle(particle_at(100.0));
+        history.record(Edit::Added(vec![first]));
+        let added = sim.add_particles(vec![particle_at(200.0), particle_at(300.0)]);
+        history.record(Edit::Added(added.clone()));
+        let removed = sim.remove_particle(first).unwrap();
+        history.record(Edit::Removed(vec![removed]));
+        let impulse = cgmath::vec2(1.0, 2.0);
+        sim.edit_particle(added[0], |p| p.velocity += impulse);
+        history.record(Edit::Pushed {
+            ids: vec![added[0]],
+            impulse,
+        });
+
+        assert!(history.undo(&mut sim));
+        assert_eq!(
+            sim.get_particle(added[0]).unwrap().velocity,
+            cgmath::vec2(0.0, 0.0)
+        );
+        assert!(history.undo(&mut sim));
+        assert_eq!(sim.get_particle(first).unwrap().position.x, 100.0);
+        // Only the last two edits are kept
+        assert!(!history.undo(&mut sim));
+        assert_eq!(sim.get_particles().len(), 3);
+
+        assert!(history.redo(&mut sim));
+        assert!(sim.get_particle(first).is_none());
+        assert!(history.redo(&mut sim));
+        assert_eq!(sim.get_particle(added[0]).unwrap().velocity, impulse);
+        assert!(!history.redo(&mut sim));
+
+        // A new edit drops what was undone
+        history.undo(&mut sim);
+        history.record(Edit::Added(vec![sim.add_particle(particle_at(400.0))]));
+        assert!(!history.redo(&mut sim));
+    }
+}
@@ -0,0 +1,27 @@
+use serde::{Deserialize, Serialize};
+
+/// Numerical scheme used to advance particles through time
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Integrator {
+    /// Semi-implicit Euler. Cheap (one force evaluation per step) but first order
+    /// accurate, so orbits slowly drift apart.
+    #[default]
+    Euler,
+    /// Kick-drift-kick leapfrog. Second order accurate and symplectic, which keeps
+    /// the energy of orbital setups bounded over long runs.
+    Leapfrog,
+    /// Classic fourth order Runge-Kutta. Four force evaluations per step buy far more
+    /// accurate short term trajectories, but energy is not conserved over long runs.
+    RungeKutta4,
+}
+
+impl Integrator {
+    /// The integrator to switch to when cycling through them
+    pub fn next(self) -> Self {
+        match self {
+            Integrator::Euler => Integrator::Leapfrog,
+            Integrator::Leapfrog => Integrator::RungeKutta4,
+            Integrator::RungeKutta4 => Integrator::Euler,
+        }
+    }
+}
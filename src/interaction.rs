@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+
+/// Forces the particles exert on each other
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Interaction {
+    /// Every mass pulls on every other one
+    #[default]
+    Gravity,
+    /// Only charges act on each other, like ones repelling and opposite ones attracting
+    /// with Coulomb's law. Mass only sets how hard a particle is to push around.
+    Electrostatic,
+    /// Gravity and electrostatics on top of each other
+    Both,
+}
+
+impl Interaction {
+    /// The interaction to switch to when cycling through them
+    pub fn next(self) -> Self {
+        match self {
+            Interaction::Gravity => Interaction::Electrostatic,
+            Interaction::Electrostatic => Interaction::Both,
+            Interaction::Both => Interaction::Gravity,
+        }
+    }
+
+    /// Whether masses attract each other
+    pub fn gravity(self) -> bool {
+        self != Interaction::Electrostatic
+    }
+
+    /// Whether charges act on each other
+    pub fn electrostatic(self) -> bool {
+        self != Interaction::Gravity
+    }
+}
@@ -0,0 +1,179 @@
+use cgmath::InnerSpace;
+
+use crate::{
+    integrator::Integrator,
+    primitives::{
+        particle::Particle,
+        scalar::{consts::PI, Scalar},
+    },
+    simulation::Simulation,
+    solver::ForceSolver,
+};
+
+/// Elliptic orbit of two bodies about each other under Newtonian gravity, which has an
+/// exact solution to check integrators against. Starts at periapsis along +x, moving
+/// counterclockwise.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeplerOrbit {
+    pub semi_major_axis: Scalar,
+    /// 0 for a circle, up to but excluding 1
+    pub eccentricity: Scalar,
+    /// Combined mass of both bodies
+    pub mass: Scalar,
+    /// Gravitational constant
+    pub g: Scalar,
+}
+
+impl KeplerOrbit {
+    pub fn period(&self) -> Scalar {
+        2.0 * PI * (self.semi_major_axis.powi(3) / (self.g * self.mass)).sqrt()
+    }
+
+    /// Offset and velocity of the lighter body relative to the heavier one `time` after
+    /// passing periapsis
+    pub fn state_at(&self, time: Scalar) -> (cgmath::Vector2<Scalar>, cgmath::Vector2<Scalar>) {
+        let (a, e) = (self.semi_major_axis, self.eccentricity);
+        let mean_motion = 2.0 * PI / self.period();
+        // Kepler's equation M = E - e sin E, solved for the eccentric anomaly E by
+        // Newton's method
+        let mean_anomaly = (mean_motion * time) % (2.0 * PI);
+        let mut anomaly = if e < 0.8 { mean_anomaly } else { PI };
+        for _ in 0..32 {
+            let step = (anomaly - e * anomaly.sin() - mean_anomaly) / (1.0 - e * anomaly.cos());
+            anomaly -= step;
+            if step.abs() < Scalar::EPSILON {
+                break;
+            }
+        }
+        let (sin, cos) = anomaly.sin_cos();
+        let minor = (1.0 - e * e).sqrt();
+        let rate = mean_motion / (1.0 - e * cos);
+        (
+            cgmath::vec2(a * (cos - e), a * minor * sin),
+            cgmath::vec2(-a * sin * rate, a * minor * cos * rate),
+        )
+    }
+}
+
+/// How far a simulated `KeplerOrbit` strays from the exact one
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeplerError {
+    /// Distance between the simulated and the exact relative position at the end, in
+    /// units of the semi-major axis
+    pub position: Scalar,
+    /// Change of the total energy from start to end, relative to the initial energy
+    pub energy_drift: Scalar,
+}
+
+/// Simulates `orbit` for `orbits` revolutions with `integrator` and fixed steps of
+/// `time_step`, summing forces directly without softening, and compares where it ends
+/// up to the exact solution. The heavier body is `mass_ratio` times as heavy as the
+/// lighter one.
+pub fn measure(
+    orbit: KeplerOrbit,
+    mass_ratio: Scalar,
+    integrator: Integrator,
+    time_step: Scalar,
+    orbits: Scalar,
+) -> KeplerError {
+    let mut sim = Simulation::new(time_step, 1.0, orbit.g, 0.0, integrator);
+    sim.set_solver(ForceSolver::Direct);
+    let light_mass = orbit.mass / (1.0 + mass_ratio);
+    let heavy_mass = orbit.mass - light_mass;
+    // Both bodies are placed about a resting center of mass, small enough to never touch
+    let center = cgmath::vec2(500.0, 500.0);
+    let (offset, velocity) = orbit.state_at(0.0);
+    let body = |mass: Scalar, share: Scalar| Particle {
+        position: center + offset * share,
+        velocity: velocity * share,
+        mass,
+        radius: orbit.semi_major_axis * (1.0 - orbit.eccentricity) / 10.0,
+        ..Particle::empty()
+    };
+    sim.add_particles(vec![
+        body(heavy_mass, -light_mass / orbit.mass),
+        body(light_mass, heavy_mass / orbit.mass),
+    ]);
+    let initial = sim.energy().total();
+
+    let steps = (orbits * orbit.period() / time_step).round() as usize;
+    for _ in 0..steps {
+        sim.advance();
+    }
+    let particles = sim.get_particles();
+    let (heavy, light) = match particles.positions() {
+        [heavy, light] => (*heavy, *light),
+        _ => panic!("the bodies of the orbit collided"),
+    };
+    let (exact, _) = orbit.state_at(steps as Scalar * time_step);
+    KeplerError {
+        position: (light - heavy - exact).magnitude() / orbit.semi_major_axis,
+        energy_drift: ((sim.energy().total() - initial) / initial).abs(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn orbit() -> KeplerOrbit {
+        KeplerOrbit {
+            semi_major_axis: 100.0,
+            eccentricity: 0.5,
+            mass: 1001.0,
+            g: 1.0,
+        }
+    }
+
+    #[test]
+    fn it_solves_keplers_equation() {
+        let orbit = orbit();
+        let (periapsis, fastest) = orbit.state_at(0.0);
+        let (apoapsis, slowest) = orbit.state_at(orbit.period() / 2.0);
+        assert!((periapsis - cgmath::vec2(50.0, 0.0)).magnitude() < 1e-3);
+        assert!((apoapsis - cgmath::vec2(-150.0, 0.0)).magnitude() < 1e-3);
+        // Angular momentum is the same everywhere, and so is the energy
+        assert!((50.0 * fastest.y + 150.0 * slowest.y).abs() < 1e-2);
+        let specific_energy = |time: Scalar| {
+            let (x, v) = orbit.state_at(time);
+            0.5 * v.magnitude2() - orbit.g * orbit.mass / x.magnitude()
+        };
+        let expected = -orbit.g * orbit.mass / (2.0 * orbit.semi_major_axis);
+        for &time in &[0.0, 31.0, 77.0, 150.0] {
+            assert!((specific_energy(time) - expected).abs() < 1e-3 * expected.abs());
+        }
+        let (back, _) = orbit.state_at(orbit.period());
+        assert!((back - periapsis).magnitude() < 1e-2);
+    }
+
+    #[test]
+    fn it_converges_at_the_order_of_each_integrator() {
+        let orbit = orbit();
+        // Half an orbit, since the errors of Euler partly cancel over whole ones
+        let error = |integrator: Integrator, steps: Scalar| {
+            measure(orbit, 1000.0, integrator, orbit.period() / steps, 0.5).position
+        };
+        // Halving the step cuts the error by 2^order
+        let gain = |integrator: Integrator| error(integrator, 50.0) / error(integrator, 100.0);
+        let (euler, leapfrog, rk4) = (
+            gain(Integrator::Euler),
+            gain(Integrator::Leapfrog),
+            gain(Integrator::RungeKutta4),
+        );
+        assert!(euler > 1.2 && euler < 3.0, "euler gains {}", euler);
+        assert!(
+            leapfrog > 3.0 && leapfrog < 6.0,
+            "leapfrog gains {}",
+            leapfrog
+        );
+        assert!(rk4 > 10.0, "rk4 gains {}", rk4);
+
+        let step = orbit.period() / 400.0;
+        let leapfrog = measure(orbit, 1000.0, Integrator::Leapfrog, step, 1.0);
+        assert!(
+            leapfrog.energy_drift < 1e-3,
+            "drift {}",
+            leapfrog.energy_drift
+        );
+    }
+}
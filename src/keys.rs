@@ -0,0 +1,459 @@
+use nbody::Preset;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+use winit::event::{ModifiersState, VirtualKeyCode};
+
+/// Something pressing a key does in the app
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Action {
+    /// Closes the help overlay if it is shown, and the app otherwise
+    Quit,
+    ToggleHelp,
+    TogglePause,
+    /// Pauses and takes a single step
+    StepOnce,
+    Reset,
+    IncreaseTimeStep,
+    DecreaseTimeStep,
+    /// More simulation steps per frame
+    SpeedUp,
+    SlowDown,
+    IncreaseTheta,
+    DecreaseTheta,
+    CycleIntegrator,
+    CycleSolver,
+    /// CPU, then brute force and Barnes-Hut on the GPU
+    CycleGpuSolver,
+    CycleExpansion,
+    CycleInteraction,
+    CycleBoundary,
+    ToggleAdaptiveStep,
+    ToggleBlockSteps,
+    /// Off, toward rest and toward the local mean velocity
+    CycleDrag,
+    ToggleFragmentation,
+    ToggleSolverComparison,
+    LoadPreset(Preset),
+    ReloadScenario,
+    SaveSnapshot,
+    LoadSnapshot,
+    ToggleOrbitPlacement,
+    ToggleMirrorPlacement,
+    GrowSpawn,
+    ShrinkSpawn,
+    ToggleCrosshair,
+    /// Moves the keyboard crosshair, or changes the velocity given there while Alt is
+    /// held. Only while the crosshair is on.
+    CrosshairUp,
+    CrosshairDown,
+    CrosshairLeft,
+    CrosshairRight,
+    PlaceAtCrosshair,
+    Undo,
+    Redo,
+    DeleteSelection,
+    NextGroup,
+    RecolorSelection,
+    SaveSelection,
+    /// Follows the selection or the center of mass
+    ToggleFollow,
+    /// Follows the selection with its velocity pointing up
+    ToggleFollowHeadingUp,
+    CycleColorMapping,
+    CycleColormap,
+    CycleVectorOverlay,
+    ToggleLabels,
+    ToggleLagrangePoints,
+    ToggleEnergyPlot,
+    ToggleStarfield,
+    ToggleBloom,
+    ToggleTimings,
+    CyclePresentMode,
+    ToggleReplayRecording,
+    ToggleExport,
+    TogglePlayback,
+    /// Steps through the replay being played back. Only while playing one back.
+    ReplayBack,
+    ReplayForward,
+    ToggleCapture,
+    SaveClip,
+    StopClip,
+}
+
+impl Action {
+    /// What the action does, as listed in the help overlay
+    pub fn description(self) -> String {
+        let description = match self {
+            Action::Quit => "close help or quit",
+            Action::ToggleHelp => "show or hide this help",
+            Action::TogglePause => "pause or resume",
+            Action::StepOnce => "take a single step",
+            Action::Reset => "remove every particle",
+            Action::IncreaseTimeStep => "longer time step",
+            Action::DecreaseTimeStep => "shorter time step",
+            Action::SpeedUp => "more steps per frame",
+            Action::SlowDown => "fewer steps per frame",
+            Action::IncreaseTheta => "larger theta (faster, less accurate)",
+            Action::DecreaseTheta => "smaller theta (slower, more accurate)",
+            Action::CycleIntegrator => "next integrator",
+            Action::CycleSolver => "Barnes-Hut or direct forces",
+            Action::CycleGpuSolver => "forces on the CPU or the GPU",
+            Action::CycleExpansion => "monopole or quadrupole tree",
+            Action::CycleInteraction => "gravity, electrostatics or both",
+            Action::CycleBoundary => "next boundary condition",
+            Action::ToggleAdaptiveStep => "adaptive time step",
+            Action::ToggleBlockSteps => "block time steps",
+            Action::CycleDrag => "drag off, toward rest, toward neighbors",
+            Action::ToggleFragmentation => "fragmentation",
+            Action::ToggleSolverComparison => "Barnes-Hut error against direct",
+            Action::LoadPreset(preset) => return format!("load the {:?} preset", preset),
+            Action::ReloadScenario => "reload the scenario",
+            Action::SaveSnapshot => "save a snapshot",
+            Action::LoadSnapshot => "load the snapshot",
+            Action::ToggleOrbitPlacement => "orbit placement",
+            Action::ToggleMirrorPlacement => "mirror placement",
+            Action::GrowSpawn => "bigger spawned particles",
+            Action::ShrinkSpawn => "smaller spawned particles",
+            Action::ToggleCrosshair => "keyboard placement crosshair",
+            Action::CrosshairUp => "crosshair up (Alt: velocity)",
+            Action::CrosshairDown => "crosshair down (Alt: velocity)",
+            Action::CrosshairLeft => "crosshair left (Alt: velocity)",
+            Action::CrosshairRight => "crosshair right (Alt: velocity)",
+            Action::PlaceAtCrosshair => "place a particle at the crosshair",
+            Action::Undo => "undo",
+            Action::Redo => "redo",
+            Action::DeleteSelection => "delete the selection",
+            Action::NextGroup => "move the selection to the next group",
+            Action::RecolorSelection => "recolor the selection",
+            Action::SaveSelection => "save the selection as a scenario",
+            Action::ToggleFollow => "follow the selection or center of mass",
+            Action::ToggleFollowHeadingUp => "follow the selection, heading up",
+            Action::CycleColorMapping => "color by nothing, mass, speed, pull",
+            Action::CycleColormap => "next colormap",
+            Action::CycleVectorOverlay => "velocity and acceleration arrows",
+            Action::ToggleLabels => "labels",
+            Action::ToggleLagrangePoints => "Lagrange points",
+            Action::ToggleEnergyPlot => "energy plot",
+            Action::ToggleStarfield => "starfield",
+            Action::ToggleBloom => "bloom",
+            Action::ToggleTimings => "frame timings",
+            Action::CyclePresentMode => "next present mode",
+            Action::ToggleReplayRecording => "record a replay",
+            Action::ToggleExport => "export every step",
+            Action::TogglePlayback => "play the replay back",
+            Action::ReplayBack => "replay one step back",
+            Action::ReplayForward => "replay one step forward",
+            Action::ToggleCapture => "record frames",
+            Action::SaveClip => "save the last seconds as a GIF",
+            Action::StopClip => "stop keeping frames for clips",
+        };
+        description.to_string()
+    }
+}
+
+/// Key press an action is bound to: a key along with the modifiers held with it. Written
+/// as e.g. `(key: S, ctrl: true)` in the config file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct KeyBinding {
+    pub key: VirtualKeyCode,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub ctrl: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub shift: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub alt: bool,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+impl KeyBinding {
+    /// `key` without modifiers
+    pub const fn key(key: VirtualKeyCode) -> Self {
+        Self {
+            key,
+            ctrl: false,
+            shift: false,
+            alt: false,
+        }
+    }
+
+    pub const fn ctrl(key: VirtualKeyCode) -> Self {
+        Self {
+            ctrl: true,
+            ..Self::key(key)
+        }
+    }
+
+    pub const fn shift(key: VirtualKeyCode) -> Self {
+        Self {
+            shift: true,
+            ..Self::key(key)
+        }
+    }
+
+    /// `key` pressed while `modifiers` are held
+    pub fn pressed(key: VirtualKeyCode, modifiers: ModifiersState) -> Self {
+        Self {
+            key,
+            ctrl: modifiers.ctrl(),
+            shift: modifiers.shift(),
+            alt: modifiers.alt(),
+        }
+    }
+}
+
+impl fmt::Display for KeyBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (held, name) in [
+            (self.ctrl, "Ctrl+"),
+            (self.shift, "Shift+"),
+            (self.alt, "Alt+"),
+        ] {
+            if held {
+                f.write_str(name)?;
+            }
+        }
+        let name = match self.key {
+            VirtualKeyCode::Key1 => "1",
+            VirtualKeyCode::Key2 => "2",
+            VirtualKeyCode::Key3 => "3",
+            VirtualKeyCode::Key4 => "4",
+            VirtualKeyCode::Key5 => "5",
+            VirtualKeyCode::Key6 => "6",
+            VirtualKeyCode::Key7 => "7",
+            VirtualKeyCode::Key8 => "8",
+            VirtualKeyCode::Key9 => "9",
+            VirtualKeyCode::Key0 => "0",
+            VirtualKeyCode::LBracket => "[",
+            VirtualKeyCode::RBracket => "]",
+            VirtualKeyCode::Equals => "=",
+            VirtualKeyCode::Plus | VirtualKeyCode::NumpadAdd => "+",
+            VirtualKeyCode::Minus | VirtualKeyCode::NumpadSubtract => "-",
+            VirtualKeyCode::Period => ".",
+            VirtualKeyCode::Comma => ",",
+            VirtualKeyCode::Slash => "/",
+            VirtualKeyCode::Back => "Backspace",
+            VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => "Enter",
+            key => return write!(f, "{:?}", key),
+        };
+        f.write_str(name)
+    }
+}
+
+/// Keys every action is bound to unless the config binds it to others. Where a key is
+/// bound to several actions, the first one that applies at the time wins.
+fn default_bindings() -> Vec<(Action, Vec<KeyBinding>)> {
+    use Action::*;
+    use VirtualKeyCode as Key;
+    let key = KeyBinding::key;
+    vec![
+        (Quit, vec![key(Key::Escape)]),
+        (
+            ToggleHelp,
+            vec![key(Key::F1), KeyBinding::shift(Key::Slash)],
+        ),
+        (TogglePause, vec![key(Key::Space)]),
+        (StepOnce, vec![key(Key::N)]),
+        (Reset, vec![key(Key::R)]),
+        // The crosshair and replays take the arrow keys over while they are on
+        (CrosshairUp, vec![key(Key::Up)]),
+        (CrosshairDown, vec![key(Key::Down)]),
+        (CrosshairLeft, vec![key(Key::Left)]),
+        (CrosshairRight, vec![key(Key::Right)]),
+        (ReplayBack, vec![key(Key::Left)]),
+        (ReplayForward, vec![key(Key::Right)]),
+        (IncreaseTimeStep, vec![key(Key::Up)]),
+        (DecreaseTimeStep, vec![key(Key::Down)]),
+        (
+            SpeedUp,
+            vec![
+                key(Key::Equals),
+                key(Key::Plus),
+                key(Key::NumpadAdd),
+                key(Key::Period),
+            ],
+        ),
+        (
+            SlowDown,
+            vec![key(Key::Minus), key(Key::NumpadSubtract), key(Key::Comma)],
+        ),
+        (IncreaseTheta, vec![key(Key::RBracket)]),
+        (DecreaseTheta, vec![key(Key::LBracket)]),
+        (CycleIntegrator, vec![key(Key::I)]),
+        (CycleSolver, vec![key(Key::B)]),
+        (CycleGpuSolver, vec![key(Key::G)]),
+        (CycleExpansion, vec![key(Key::Q)]),
+        (CycleInteraction, vec![key(Key::X)]),
+        (CycleBoundary, vec![key(Key::W)]),
+        (ToggleAdaptiveStep, vec![key(Key::A)]),
+        (ToggleBlockSteps, vec![key(Key::T)]),
+        (CycleDrag, vec![key(Key::D)]),
+        (ToggleFragmentation, vec![key(Key::F)]),
+        (ToggleSolverComparison, vec![key(Key::E)]),
+        (LoadPreset(Preset::GalaxyDisk), vec![key(Key::Key1)]),
+        (LoadPreset(Preset::BinaryStar), vec![key(Key::Key2)]),
+        (LoadPreset(Preset::SolarSystem), vec![key(Key::Key3)]),
+        (LoadPreset(Preset::RandomCloud), vec![key(Key::Key4)]),
+        (LoadPreset(Preset::Plasma), vec![key(Key::Key5)]),
+        (LoadPreset(Preset::Plummer), vec![key(Key::Key6)]),
+        (LoadPreset(Preset::King), vec![key(Key::Key7)]),
+        (LoadPreset(Preset::GalaxyCollision), vec![key(Key::Key8)]),
+        (ReloadScenario, vec![key(Key::S)]),
+        (SaveSnapshot, vec![key(Key::F5)]),
+        (LoadSnapshot, vec![key(Key::F9)]),
+        (ToggleOrbitPlacement, vec![key(Key::O)]),
+        (ToggleMirrorPlacement, vec![KeyBinding::shift(Key::O)]),
+        (GrowSpawn, vec![KeyBinding::ctrl(Key::RBracket)]),
+        (ShrinkSpawn, vec![KeyBinding::ctrl(Key::LBracket)]),
+        (ToggleCrosshair, vec![key(Key::Tab)]),
+        (
+            PlaceAtCrosshair,
+            vec![key(Key::Return), key(Key::NumpadEnter)],
+        ),
+        (Undo, vec![KeyBinding::ctrl(Key::Z)]),
+        (Redo, vec![KeyBinding::ctrl(Key::Y)]),
+        (DeleteSelection, vec![key(Key::Delete), key(Key::Back)]),
+        (NextGroup, vec![key(Key::U)]),
+        (RecolorSelection, vec![key(Key::Y)]),
+        (SaveSelection, vec![KeyBinding::ctrl(Key::S)]),
+        (ToggleFollow, vec![key(Key::H)]),
+        (ToggleFollowHeadingUp, vec![KeyBinding::shift(Key::H)]),
+        (CycleColorMapping, vec![key(Key::M)]),
+        (CycleColormap, vec![key(Key::C)]),
+        (CycleVectorOverlay, vec![key(Key::V)]),
+        (ToggleLabels, vec![key(Key::L)]),
+        (ToggleLagrangePoints, vec![key(Key::P)]),
+        (ToggleEnergyPlot, vec![key(Key::J)]),
+        (ToggleStarfield, vec![key(Key::K)]),
+        (ToggleBloom, vec![key(Key::F4)]),
+        (ToggleTimings, vec![key(Key::F3)]),
+        (CyclePresentMode, vec![key(Key::F2)]),
+        (ToggleReplayRecording, vec![key(Key::F6)]),
+        (ToggleExport, vec![key(Key::F7)]),
+        (TogglePlayback, vec![key(Key::F8)]),
+        (ToggleCapture, vec![key(Key::F12)]),
+        (SaveClip, vec![key(Key::F10)]),
+        (StopClip, vec![KeyBinding::shift(Key::F10)]),
+    ]
+}
+
+/// Which actions every key press triggers
+#[derive(Debug)]
+pub struct Keymap {
+    actions: HashMap<KeyBinding, Vec<Action>>,
+    /// Keys of every action, in the order of `default_bindings`
+    bindings: Vec<(Action, Vec<KeyBinding>)>,
+}
+
+impl Keymap {
+    /// Binds every action to its default keys, except for the actions `overrides` binds
+    /// to other keys. An action overridden with no keys at all is unbound.
+    pub fn new(overrides: &BTreeMap<Action, Vec<KeyBinding>>) -> Self {
+        let mut actions: HashMap<KeyBinding, Vec<Action>> = HashMap::new();
+        let mut bindings = default_bindings();
+        for (action, keys) in &mut bindings {
+            if let Some(overridden) = overrides.get(action) {
+                keys.clone_from(overridden);
+            }
+            for &binding in keys.iter() {
+                actions.entry(binding).or_default().push(*action);
+            }
+        }
+        Self { actions, bindings }
+    }
+
+    /// Every action along with the keys it is bound to, which may be none
+    pub fn bindings(&self) -> &[(Action, Vec<KeyBinding>)] {
+        &self.bindings
+    }
+
+    /// Actions bound to `key` pressed with `modifiers`, in the order they should be tried.
+    /// Falls back on the actions of the bare key if the combination is not bound and only
+    /// Shift or Alt are held, so that e.g. Shift + S still reloads the scenario. Ctrl
+    /// combinations never fall back, or Ctrl + R would wipe every particle.
+    pub fn actions(&self, key: VirtualKeyCode, modifiers: ModifiersState) -> &[Action] {
+        self.actions
+            .get(&KeyBinding::pressed(key, modifiers))
+            .or_else(|| {
+                if modifiers.ctrl() {
+                    None
+                } else {
+                    self.actions.get(&KeyBinding::key(key))
+                }
+            })
+            .map_or(&[], Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use VirtualKeyCode as Key;
+
+    fn keys(action: Action, keymap: &Keymap) -> &[KeyBinding] {
+        let (_, keys) = keymap
+            .bindings()
+            .iter()
+            .find(|(bound, _)| *bound == action)
+            .unwrap();
+        keys
+    }
+
+    #[test]
+    fn it_binds_overridden_actions_to_their_new_keys_only() {
+        let mut overrides = BTreeMap::new();
+        overrides.insert(Action::TogglePause, vec![KeyBinding::key(Key::P)]);
+        let keymap = Keymap::new(&overrides);
+        assert_eq!(
+            keys(Action::TogglePause, &keymap),
+            [KeyBinding::key(Key::P)]
+        );
+        assert_eq!(
+            keymap.actions(Key::P, ModifiersState::empty()),
+            [Action::TogglePause, Action::ToggleLagrangePoints]
+        );
+        assert!(keymap
+            .actions(Key::Space, ModifiersState::empty())
+            .is_empty());
+        assert_eq!(
+            keymap.actions(Key::R, ModifiersState::empty()),
+            [Action::Reset]
+        );
+    }
+
+    #[test]
+    fn it_unbinds_actions_overridden_with_no_keys() {
+        let mut overrides = BTreeMap::new();
+        overrides.insert(Action::Reset, Vec::new());
+        let keymap = Keymap::new(&overrides);
+        assert!(keys(Action::Reset, &keymap).is_empty());
+        assert!(keymap.actions(Key::R, ModifiersState::empty()).is_empty());
+    }
+
+    #[test]
+    fn it_falls_back_on_the_bare_key_for_shift_and_alt_only() {
+        let keymap = Keymap::new(&BTreeMap::new());
+        assert_eq!(
+            keymap.actions(Key::S, ModifiersState::SHIFT),
+            [Action::ReloadScenario]
+        );
+        assert_eq!(
+            keymap.actions(Key::Up, ModifiersState::ALT)[0],
+            Action::CrosshairUp
+        );
+        // Bound combinations win over the bare key
+        assert_eq!(
+            keymap.actions(Key::S, ModifiersState::CTRL),
+            [Action::SaveSelection]
+        );
+        assert!(keymap.actions(Key::R, ModifiersState::CTRL).is_empty());
+        assert!(keymap
+            .actions(Key::R, ModifiersState::CTRL | ModifiersState::SHIFT)
+            .is_empty());
+    }
+}
@@ -0,0 +1,108 @@
+//! Barnes-Hut n-body simulation core.
+//!
+//! Everything needed to run the physics lives in this library so it can be
+//! driven headlessly (no window or GPU surface required). The windowed
+//! application in `main.rs` is just one consumer of this API.
+//!
+//! ```
+//! use nbody::{Integrator, Particle, ParticleProperties, Simulation};
+//!
+//! let mut sim = Simulation::new(0.05, 1.0, 1.0, 1.0, Integrator::Leapfrog);
+//! sim.add_particle(Particle::new(ParticleProperties {
+//!     position: cgmath::vec2(250.0, 500.0),
+//!     mass: 100.0,
+//!     radius: 2.0,
+//!     velocity: cgmath::vec2(0.0, 0.0),
+//!     acceleration: cgmath::vec2(0.0, 0.0),
+//!     color: nbody::primitives::color::WHITE,
+//! }));
+//! sim.add_particle(Particle::new(ParticleProperties {
+//!     position: cgmath::vec2(750.0, 500.0),
+//!     mass: 100.0,
+//!     radius: 2.0,
+//!     velocity: cgmath::vec2(0.0, 0.0),
+//!     acceleration: cgmath::vec2(0.0, 0.0),
+//!     color: nbody::primitives::color::WHITE,
+//! }));
+//!
+//! for _ in 0..10 {
+//!     sim.advance();
+//! }
+//! assert_eq!(sim.get_particles().len(), 2);
+//! ```
+
+pub mod analysis;
+pub mod boundary;
+pub mod constants;
+pub mod drag;
+pub mod evaporation;
+pub mod events;
+pub mod expansion;
+pub mod export;
+pub mod force_law;
+pub mod forces;
+pub mod fragmentation;
+pub mod groups;
+pub mod history;
+pub mod integrator;
+pub mod interaction;
+pub mod kepler;
+pub mod merge;
+pub mod morton;
+pub mod octree;
+pub mod potential;
+pub mod presets;
+pub mod primitives;
+pub mod quadtree;
+pub mod replay;
+pub mod scenario;
+#[cfg(feature = "scripting")]
+pub mod scripting;
+pub mod simulation;
+pub mod simulation3d;
+pub mod solver;
+pub mod spatial_hash;
+pub mod star_cluster;
+pub mod store;
+#[cfg(test)]
+mod test_support;
+pub mod timestep;
+pub mod units;
+pub mod utils;
+
+pub use analysis::{Energy, LagrangePoints};
+pub use boundary::BoundaryCondition;
+pub use drag::{Drag, DragFrame, DragLaw};
+pub use evaporation::Evaporation;
+pub use events::SimulationEvent;
+pub use expansion::Expansion;
+pub use export::{ExportFormat, TrajectoryExporter};
+pub use force_law::{ForceLaw, InverseLinear, Newtonian, SoftenedGravity};
+pub use forces::Sources;
+pub use fragmentation::Fragmentation;
+pub use groups::GroupMatrix;
+pub use history::{Edit, EditHistory};
+pub use integrator::Integrator;
+pub use interaction::Interaction;
+pub use kepler::{KeplerError, KeplerOrbit};
+pub use merge::MergeModel;
+pub use octree::octree::{Octree, OctreeIter};
+pub use potential::ExternalPotential;
+pub use presets::{GalaxyCollision, Preset};
+pub use primitives::particle::{BodyKind, Particle, ParticleProperties};
+pub use primitives::particle3d::{Particle3D, Particle3DProperties};
+pub use primitives::scalar::Scalar;
+pub use quadtree::bounding_box::QuadBoundingBox;
+pub use quadtree::quadtree::{Attractor, NodeCharge, QuadNode, QuadTree, QuadTreeIter, TreeStats};
+pub use replay::{Replay, ReplayRecorder};
+pub use scenario::Scenario;
+#[cfg(feature = "scripting")]
+pub use scripting::{ScriptError, StepScript};
+pub use simulation::{Simulation, SnapshotError, StepStats};
+pub use simulation3d::Simulation3D;
+pub use solver::ForceSolver;
+pub use spatial_hash::SpatialHash;
+pub use star_cluster::ClusterModel;
+pub use store::{ParticleId, ParticleStore};
+pub use timestep::{AdaptiveTimeStep, BlockTimeSteps};
+pub use units::UnitSystem;
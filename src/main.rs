@@ -1,63 +1,235 @@
-use futures::executor::block_on;
 use wgpu::{self, SwapChainError};
 use winit::{
     event::*,
     event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
+    window::{Fullscreen, Window, WindowBuilder},
 };
 
-mod constants;
-mod primitives;
-mod quadtree;
-mod simulation;
+/// Builds a `wgpu::ShaderModuleDescriptor` for a WGSL file, relative to the current file.
+/// Stands in for the macro of the same name in later wgpu versions.
+macro_rules! include_wgsl {
+    ($path:literal) => {
+        wgpu::ShaderModuleDescriptor {
+            label: Some($path),
+            source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(include_str!($path))),
+            flags: wgpu::ShaderFlags::default(),
+        }
+    };
+}
+
+mod bloom;
+mod camera;
+mod capture;
+mod cli;
+mod compute;
+mod config;
+mod dynamic_buffer;
+mod gpu_timer;
+mod gpu_tree;
+#[cfg(not(target_arch = "wasm32"))]
+mod headless;
+mod keys;
+#[cfg(not(target_arch = "wasm32"))]
+mod metrics;
+mod physics;
+mod scene3d;
+mod starfield;
 mod state;
-mod utils;
+mod trace;
 use state::State;
+use tracing_chrome::FlushGuard;
 
+#[cfg(not(target_arch = "wasm32"))]
 fn main() {
     env_logger::init();
+    let options = cli::Options::parse();
+    let trace = options.trace.as_deref().map(trace::install);
+    if options.headless {
+        headless::run(headless::HeadlessOptions {
+            steps: options.steps,
+            particles: options.particles.unwrap_or(1000),
+            time_step: options.time_step,
+            theta: options.theta,
+            expansion: options.expansion,
+            incremental_tree: options.incremental_tree,
+            leaf_capacity: options.leaf_capacity,
+            morton_sort: options.morton_sort,
+            integrator: options.integrator,
+            adaptive_step: Some(options.adaptive_step).filter(|_| options.adaptive),
+            block_steps: Some(options.block_steps).filter(|_| options.block),
+            drag: Some(options.drag_settings).filter(|_| options.drag),
+            seed: options.seed,
+            merge_model: options.merge_model,
+            fragmentation: Some(options.fragmentation).filter(|_| options.fragment),
+            solver: if options.direct {
+                nbody::ForceSolver::Direct
+            } else {
+                nbody::ForceSolver::BarnesHut
+            },
+            compare: options.compare,
+            export: options.export.clone(),
+            metrics: options.metrics.clone(),
+        });
+        // The trace is only complete once the guard is dropped
+        drop(trace);
+        return;
+    }
+
+    if options.list_adapters {
+        state::list_adapters();
+        return;
+    }
+
+    println!("seed: {}", options.seed);
     let event_loop = EventLoop::new();
-    let window = WindowBuilder::new().build(&event_loop).unwrap();
-    let mut state = block_on(State::new(&window));
+    let window = build_window(&options, &event_loop);
+    match futures::executor::block_on(State::new(&window, &options)) {
+        Ok(state) => run(event_loop, window, state, trace),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// The browser build starts from `start` instead
+#[cfg(target_arch = "wasm32")]
+fn main() {}
+
+/// Entry point of the browser build. Options are read from the query string of the page
+/// (e.g. `?particles=500&seed=7`). The canvas is appended to the element with the id
+/// `nbody`, or to the body of the page if there is none.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen::prelude::wasm_bindgen(start)]
+pub fn start() {
+    use winit::platform::web::WindowExtWebSys;
 
+    std::panic::set_hook(Box::new(|info| {
+        web_sys::console::error_1(&info.to_string().into());
+    }));
+    let page = web_sys::window().expect("no browser window");
+    let query = page.location().search().unwrap_or_default();
+    let options = cli::Options::from_query(&query);
+
+    let event_loop = EventLoop::new();
+    let window = build_window(&options, &event_loop);
+    let document = page.document().expect("no document");
+    let parent = document
+        .get_element_by_id("nbody")
+        .or_else(|| document.body().map(Into::into))
+        .expect("no element to put the canvas in");
+    parent
+        .append_child(&window.canvas())
+        .expect("could not add the canvas to the page");
+
+    // The adapter and device can only be requested asynchronously in the browser
+    wasm_bindgen_futures::spawn_local(async move {
+        match State::new(&window, &options).await {
+            // There are no files to write a trace to in the browser
+            Ok(state) => run(event_loop, window, state, None),
+            Err(e) => web_sys::console::error_1(&e.to_string().into()),
+        }
+    });
+}
+
+/// Creates the window with the size and title given in `options`
+fn build_window(options: &cli::Options, event_loop: &EventLoop<()>) -> Window {
+    let mut window_builder = WindowBuilder::new().with_title(&options.title);
+    if let Some(size) = options.window_size {
+        window_builder = window_builder.with_inner_size(size);
+    }
+    let window = window_builder.build(event_loop).unwrap();
+    if options.fullscreen {
+        toggle_fullscreen(&window);
+    }
+    window
+}
+
+/// Switches between borderless fullscreen on the current monitor and a normal window.
+/// The window is resized either way, which reaches `State::resize` as a regular event.
+fn toggle_fullscreen(window: &Window) {
+    let fullscreen = match window.fullscreen() {
+        Some(_) => None,
+        None => Some(Fullscreen::Borderless(window.current_monitor())),
+    };
+    window.set_fullscreen(fullscreen);
+}
+
+/// Feeds window events to `state` and redraws continuously until the window is closed.
+/// The `--trace` file is finished once it is.
+fn run(
+    event_loop: EventLoop<()>,
+    window: Window,
+    mut state: State,
+    mut trace: Option<FlushGuard>,
+) -> ! {
     event_loop.run(move |event, _, control_flow| match event {
+        // F11 and Alt+Enter toggle fullscreen before `State` gets to see them
         Event::WindowEvent {
-            ref event,
-            window_id,
-        } if window_id == window.id() => {
-            // Give priority to `State`
-            if !state.input(event) {
-                match event {
-                    WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
-                    WindowEvent::KeyboardInput { input, .. } => match input {
+            event:
+                WindowEvent::KeyboardInput {
+                    input:
                         KeyboardInput {
                             state: ElementState::Pressed,
-                            virtual_keycode: Some(VirtualKeyCode::Escape),
+                            virtual_keycode: Some(key),
                             ..
-                        } => *control_flow = ControlFlow::Exit,
-                        _ => {}
-                    },
-                    WindowEvent::Resized(physical_size) => {
-                        state.resize(*physical_size);
-                    }
-                    WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                        state.resize(**new_inner_size);
-                    }
-                    _ => {}
+                        },
+                    ..
+                },
+            window_id,
+        } if window_id == window.id()
+            && (key == VirtualKeyCode::F11
+                || (key == VirtualKeyCode::Return && state.modifiers().alt())) =>
+        {
+            toggle_fullscreen(&window);
+        }
+        // Give priority to `State`
+        Event::WindowEvent {
+            ref event,
+            window_id,
+        } if window_id == window.id() && !state.input(event) => match event {
+            WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+            WindowEvent::Resized(physical_size) => {
+                state.resize(*physical_size);
+            }
+            WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                state.resize(**new_inner_size);
+            }
+            _ => {}
+        },
+        Event::MainEventsCleared if state.exit_requested() => *control_flow = ControlFlow::Exit,
+        Event::MainEventsCleared if *control_flow != ControlFlow::Exit => {
+            // Sleep until the next frame is due when the frame rate is capped
+            match state.next_frame_time() {
+                Some(time) if time > instant::Instant::now() => {
+                    *control_flow = ControlFlow::WaitUntil(time)
+                }
+                _ => {
+                    *control_flow = ControlFlow::Poll;
+                    window.request_redraw();
                 }
             }
         }
-        Event::MainEventsCleared => {
-            window.request_redraw();
+        Event::LoopDestroyed => {
+            state.save_config();
+            // The process exits without dropping the closure
+            trace.take();
         }
         Event::RedrawRequested(_) => {
             state.update();
             match state.render() {
                 Ok(_) => {}
-                Err(SwapChainError::Lost) => state.recreate_swap_chain(),
+                // The window changed in a way the swap chain no longer matches
+                Err(SwapChainError::Lost) | Err(SwapChainError::Outdated) => {
+                    state.recreate_swap_chain()
+                }
+                Err(SwapChainError::OutOfMemory) => {
+                    eprintln!("The GPU ran out of memory, try fewer particles or --msaa 1");
+                    *control_flow = ControlFlow::Exit;
+                }
                 Err(e) => eprintln!("{:?}", e),
             }
         }
         _ => {}
-    });
+    })
 }
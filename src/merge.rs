@@ -0,0 +1,77 @@
+use serde::{Deserialize, Serialize};
+
+use crate::primitives::particle::{BodyKind, Particle};
+
+/// How two colliding particles are combined into one
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MergeModel {
+    /// Perfectly inelastic merge. Total mass and momentum are conserved exactly, the
+    /// merged body sits at the center of mass and its area is the sum of both areas.
+    #[default]
+    Conservative,
+    /// The original merge rule. The radius grows by a tenth of the smaller radius and
+    /// the mass by the smaller mass times the new radius, so neither mass nor momentum
+    /// are conserved.
+    Legacy,
+}
+
+/// Moves `greater` to the center of mass of both particles and gives it their total
+/// mass and momentum
+fn absorb(greater: &mut Particle, lesser: &Particle) {
+    let mass = greater.mass + lesser.mass;
+    // Nothing to weigh the two by, so `greater` stays as it is
+    if mass <= 0.0 {
+        return;
+    }
+    greater.position = (greater.mass * greater.position + lesser.mass * lesser.position) / mass;
+    greater.velocity = (greater.mass * greater.velocity + lesser.mass * lesser.velocity) / mass;
+    greater.mass = mass;
+}
+
+impl MergeModel {
+    /// Merges `lesser` into `greater`. The caller is responsible for removing `lesser`.
+    /// If either particle is fixed, the merged one is fixed too and stays where the fixed
+    /// one was. The merged particle keeps the name of `greater`, or takes that of `lesser`
+    /// if it has none. Charge is always conserved. Anything touching a black hole ends up
+    /// in a black hole with the radius it had before.
+    pub fn merge(self, greater: &mut Particle, lesser: &Particle) {
+        let anchor = match (greater.fixed, lesser.fixed) {
+            (true, _) => Some((greater.position, greater.velocity)),
+            (false, true) => Some((lesser.position, lesser.velocity)),
+            (false, false) => None,
+        };
+        if greater.name.is_none() {
+            greater.name = lesser.name.clone();
+        }
+        greater.charge += lesser.charge;
+        let black_hole = |p: &Particle| p.kind == BodyKind::BlackHole;
+        if black_hole(greater) || black_hole(lesser) {
+            let radius = match (black_hole(greater), black_hole(lesser)) {
+                (true, true) => greater.radius.max(lesser.radius),
+                (true, false) => greater.radius,
+                (false, _) => lesser.radius,
+            };
+            absorb(greater, lesser);
+            greater.radius = radius;
+            greater.kind = BodyKind::BlackHole;
+        } else {
+            match self {
+                MergeModel::Conservative => {
+                    absorb(greater, lesser);
+                    greater.radius = (greater.radius.powi(2) + lesser.radius.powi(2)).sqrt();
+                }
+                MergeModel::Legacy => {
+                    let (mass, velocity) = (greater.mass, greater.velocity);
+                    greater.radius += lesser.radius / 10.0;
+                    greater.mass += lesser.mass * greater.radius;
+                    greater.velocity = (mass * velocity + lesser.mass * lesser.velocity) / mass;
+                }
+            }
+        }
+        if let Some((position, velocity)) = anchor {
+            greater.position = position;
+            greater.velocity = velocity;
+            greater.fixed = true;
+        }
+    }
+}
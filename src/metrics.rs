@@ -0,0 +1,233 @@
+use nbody::Scalar;
+use std::{
+    fmt::Write as _,
+    fs,
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+/// How long a client may take to send its request or read the answer, so that one that
+/// stalls does not keep the others waiting forever
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// State of a headless run as last reported by its loop
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Metrics {
+    pub steps: usize,
+    /// Steps per second, measured over the last interval between two updates
+    pub step_rate: f64,
+    pub particles: usize,
+    pub simulated_time: Scalar,
+    /// Particles merged into another one so far
+    pub collisions: usize,
+    /// `(E - E0) / |E0|` with `E0` the total energy at the start
+    pub energy_drift: Option<Scalar>,
+}
+
+/// Serves the metrics of a headless run over HTTP in the Prometheus text format, so that
+/// long runs on a server can be watched with the usual tools. Requests are answered on a
+/// thread of their own for as long as the process runs.
+pub struct MetricsServer {
+    metrics: Arc<Mutex<Metrics>>,
+}
+
+impl MetricsServer {
+    /// Starts listening on `address`, e.g. `127.0.0.1:9184`
+    pub fn start<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        println!(
+            "serving metrics on http://{}/metrics",
+            listener.local_addr()?
+        );
+        let metrics = Arc::new(Mutex::new(Metrics::default()));
+        let shared = Arc::clone(&metrics);
+        thread::Builder::new()
+            .name("metrics".to_string())
+            .spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let metrics = *shared.lock().unwrap();
+                    if let Err(e) = serve(stream, &metrics) {
+                        eprintln!("metrics: {}", e);
+                    }
+                }
+            })?;
+        Ok(Self { metrics })
+    }
+
+    /// Replaces the metrics served from now on
+    pub fn update(&self, metrics: Metrics) {
+        *self.metrics.lock().unwrap() = metrics;
+    }
+}
+
+/// Reads a request from `stream` and writes back the answer
+fn serve(mut stream: TcpStream, metrics: &Metrics) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    // Only the request line matters, which fits into the first read
+    let mut request = [0; 1024];
+    let read = stream.read(&mut request)?;
+    let request = String::from_utf8_lossy(&request[..read]);
+    stream.write_all(respond(&request, metrics).as_bytes())?;
+    stream.flush()
+}
+
+/// Answers a request for `/metrics` with `metrics` and anything else with a 404. Query
+/// parameters, which some scrapers add, are ignored.
+fn respond(request: &str, metrics: &Metrics) -> String {
+    let mut request_line = request.lines().next().unwrap_or("").split_whitespace();
+    let method = request_line.next();
+    let path = request_line
+        .next()
+        .map(|target| target.split('?').next().unwrap_or(target));
+    let (status, body) = if method == Some("GET") && path == Some("/metrics") {
+        ("200 OK", render(metrics))
+    } else {
+        ("404 Not Found", String::new())
+    };
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+/// `metrics` in the Prometheus text exposition format
+// Values are written as f64 whatever the precision of `Scalar` is
+#[allow(clippy::unnecessary_cast)]
+fn render(metrics: &Metrics) -> String {
+    let mut text = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: f64| {
+        let _ = write!(
+            text,
+            "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n",
+            name = name,
+            kind = kind,
+            help = help,
+            value = value
+        );
+    };
+    metric(
+        "nbody_steps_total",
+        "counter",
+        "Simulation steps taken",
+        metrics.steps as f64,
+    );
+    metric(
+        "nbody_step_rate",
+        "gauge",
+        "Simulation steps per second",
+        metrics.step_rate,
+    );
+    metric(
+        "nbody_particles",
+        "gauge",
+        "Particles in the simulation",
+        metrics.particles as f64,
+    );
+    metric(
+        "nbody_simulated_time",
+        "gauge",
+        "Simulated time since the start",
+        metrics.simulated_time as f64,
+    );
+    metric(
+        "nbody_merged_particles_total",
+        "counter",
+        "Particles merged into another one",
+        metrics.collisions as f64,
+    );
+    if let Some(drift) = metrics.energy_drift {
+        metric(
+            "nbody_energy_drift",
+            "gauge",
+            "Relative change of the total energy since the start",
+            drift as f64,
+        );
+    }
+    if let Some(bytes) = resident_memory() {
+        metric(
+            "process_resident_memory_bytes",
+            "gauge",
+            "Resident memory size in bytes",
+            bytes as f64,
+        );
+    }
+    text
+}
+
+/// Resident memory of the process in bytes, where the platform tells
+fn resident_memory() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kilobytes * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metrics() -> Metrics {
+        Metrics {
+            steps: 120,
+            step_rate: 60.0,
+            particles: 500,
+            simulated_time: 12.0,
+            collisions: 3,
+            energy_drift: None,
+        }
+    }
+
+    #[test]
+    fn it_renders_the_prometheus_text_format() {
+        let text = render(&metrics());
+        assert!(text.contains(
+            "# HELP nbody_steps_total Simulation steps taken\n# TYPE nbody_steps_total counter\nnbody_steps_total 120\n"
+        ));
+        assert!(text.contains("# TYPE nbody_step_rate gauge\nnbody_step_rate 60\n"));
+        assert!(text.contains("nbody_particles 500\n"));
+        assert!(text.contains("nbody_simulated_time 12\n"));
+        assert!(text.contains("nbody_merged_particles_total 3\n"));
+        assert!(!text.contains("nbody_energy_drift"));
+
+        let drifted = Metrics {
+            energy_drift: Some(-0.5),
+            ..metrics()
+        };
+        assert!(render(&drifted).contains("nbody_energy_drift -0.5\n"));
+    }
+
+    #[test]
+    fn it_answers_requests_for_metrics_only() {
+        let ok = respond(
+            "GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &metrics(),
+        );
+        assert!(ok.starts_with("HTTP/1.1 200 OK\r\n"));
+        // The resident memory may differ from one render to the next
+        let (_, body) = ok.split_at(ok.find("\r\n\r\n").unwrap() + 4);
+        assert!(body.starts_with("# HELP nbody_steps_total"));
+        assert!(ok.contains(&format!("Content-Length: {}\r\n", body.len())));
+
+        let query = respond(
+            "GET /metrics?name[]=nbody_steps_total HTTP/1.1\r\n\r\n",
+            &metrics(),
+        );
+        assert!(query.starts_with("HTTP/1.1 200 OK\r\n"));
+
+        for request in &[
+            "GET / HTTP/1.1\r\n\r\n",
+            "POST /metrics HTTP/1.1\r\n\r\n",
+            "",
+        ] {
+            let missing = respond(request, &metrics());
+            assert!(missing.starts_with("HTTP/1.1 404 Not Found\r\n"));
+            assert!(missing.ends_with("Content-Length: 0\r\nConnection: close\r\n\r\n"));
+        }
+    }
+}
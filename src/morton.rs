@@ -0,0 +1,73 @@
+use crate::primitives::scalar::Scalar;
+use crate::quadtree::bounding_box::QuadBoundingBox;
+
+/// Cells per side of the grid positions are snapped to before being encoded
+const GRID: Scalar = 65536.0;
+
+/// Spreads the lower 16 bits of `v` out to the even bits
+fn part1by1(v: u32) -> u32 {
+    let mut v = v & 0x0000_ffff;
+    v = (v | (v << 8)) & 0x00ff_00ff;
+    v = (v | (v << 4)) & 0x0f0f_0f0f;
+    v = (v | (v << 2)) & 0x3333_3333;
+    (v | (v << 1)) & 0x5555_5555
+}
+
+/// Interleaves the bits of `x` (even bits) and `y` (odd bits), so that sorting by the
+/// result walks the grid along a Z-order curve
+pub fn encode(x: u16, y: u16) -> u32 {
+    part1by1(x as u32) | (part1by1(y as u32) << 1)
+}
+
+/// Morton code of `position` on a 65536 x 65536 grid spanning `bounds`. Points closer to
+/// each other tend to have closer codes. Points outside `bounds` are clamped to its edge.
+pub fn code(position: cgmath::Vector2<Scalar>, bounds: &QuadBoundingBox) -> u32 {
+    let cell = |v: Scalar, min: Scalar, max: Scalar| {
+        let t = (v - min) / (max - min).max(Scalar::EPSILON);
+        (t * GRID).clamp(0.0, GRID - 1.0) as u16
+    };
+    encode(
+        cell(position.x, bounds.min_x, bounds.max_x),
+        cell(position.y, bounds.min_y, bounds.max_y),
+    )
+}
+
+/// Order in which to visit `positions` along the Z-order curve through their bounding
+/// box. Element `i` is the index of the `i`th point to visit.
+pub fn order(positions: &[cgmath::Vector2<Scalar>]) -> Vec<usize> {
+    let bounds = QuadBoundingBox::enclosing(positions.iter().copied());
+    let mut order: Vec<usize> = (0..positions.len()).collect();
+    order.sort_by_cached_key(|&i| code(positions[i], &bounds));
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_interleaves_bits() {
+        assert_eq!(encode(0, 0), 0);
+        assert_eq!(encode(1, 0), 0b01);
+        assert_eq!(encode(0, 1), 0b10);
+        assert_eq!(encode(3, 5), 0b10_01_11);
+        assert_eq!(encode(u16::MAX, u16::MAX), u32::MAX);
+    }
+
+    #[test]
+    fn it_visits_quadrants_one_after_the_other() {
+        // Two points in each quadrant, given in an order that jumps between them
+        let positions = [
+            (10.0, 10.0),
+            (900.0, 900.0),
+            (900.0, 10.0),
+            (10.0, 900.0),
+            (20.0, 20.0),
+            (950.0, 950.0),
+            (950.0, 20.0),
+            (20.0, 950.0),
+        ];
+        let positions: Vec<_> = positions.iter().map(|&(x, y)| cgmath::vec2(x, y)).collect();
+        assert_eq!(order(&positions), vec![0, 4, 2, 6, 3, 7, 1, 5]);
+    }
+}
@@ -0,0 +1,157 @@
+use crate::constants::{MAX_X, MAX_Y, MAX_Z, MIN_X, MIN_Y, MIN_Z};
+use crate::primitives::scalar::Scalar;
+
+/// Axis aligned cube used by the octree. The 3D counterpart of `QuadBoundingBox`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OctBoundingBox {
+    pub min_x: Scalar,
+    pub max_x: Scalar,
+    pub min_y: Scalar,
+    pub max_y: Scalar,
+    pub min_z: Scalar,
+    pub max_z: Scalar,
+}
+
+impl OctBoundingBox {
+    /// Smallest cube (plus a little padding) containing every point, or the default
+    /// world cube if there are none. See `QuadBoundingBox::enclosing`.
+    pub fn enclosing<I: IntoIterator<Item = cgmath::Vector3<Scalar>>>(points: I) -> Self {
+        let inf = Scalar::INFINITY;
+        let (min, max) = points.into_iter().fold(
+            (cgmath::vec3(inf, inf, inf), cgmath::vec3(-inf, -inf, -inf)),
+            |(min, max), p| {
+                (
+                    cgmath::vec3(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z)),
+                    cgmath::vec3(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z)),
+                )
+            },
+        );
+        if min.x > max.x || min.y > max.y || min.z > max.z {
+            return Self::default();
+        }
+
+        let extent = max - min;
+        let half = extent.x.max(extent.y).max(extent.z).max(1.0) * 1.01 / 2.0;
+        let c = (min + max) / 2.0;
+        Self {
+            min_x: c.x - half,
+            max_x: c.x + half,
+            min_y: c.y - half,
+            max_y: c.y + half,
+            min_z: c.z - half,
+            max_z: c.z + half,
+        }
+    }
+
+    /// Gets the center of the bounding box
+    pub fn center(&self) -> cgmath::Vector3<Scalar> {
+        cgmath::vec3(
+            (self.min_x + self.max_x) / 2.0,
+            (self.min_y + self.max_y) / 2.0,
+            (self.min_z + self.max_z) / 2.0,
+        )
+    }
+
+    /// Gets the length of the bounding box
+    pub fn length(&self) -> Scalar {
+        self.max_x - self.min_x
+    }
+
+    /// If the point does not lie in this bounding box, `contains` will return false
+    pub fn contains(&self, p: cgmath::Vector3<Scalar>) -> bool {
+        (self.min_x..=self.max_x).contains(&p.x)
+            && (self.min_y..=self.max_y).contains(&p.y)
+            && (self.min_z..=self.max_z).contains(&p.z)
+    }
+
+    /// Given a point, determine which octant it lies in. Bit 0 is set for the upper
+    /// half of the x axis, bit 1 for the upper half of y and bit 2 for the upper half of z.
+    pub fn get_point_octant(&self, p: cgmath::Vector3<Scalar>) -> usize {
+        let c = self.center();
+        let x_bit = (p.x >= c.x) as usize;
+        let y_bit = (p.y >= c.y) as usize;
+        let z_bit = (p.z >= c.z) as usize;
+        x_bit | (y_bit << 1) | (z_bit << 2)
+    }
+
+    /// Gets the child bounding box given an octant index (0 -> 7), see `get_point_octant`
+    pub fn get_child_bb(&self, octant: usize) -> Self {
+        let c = self.center();
+        let (min_x, max_x) = if octant & 1 == 0 {
+            (self.min_x, c.x)
+        } else {
+            (c.x, self.max_x)
+        };
+        let (min_y, max_y) = if octant & 2 == 0 {
+            (self.min_y, c.y)
+        } else {
+            (c.y, self.max_y)
+        };
+        let (min_z, max_z) = if octant & 4 == 0 {
+            (self.min_z, c.z)
+        } else {
+            (c.z, self.max_z)
+        };
+        Self {
+            min_x,
+            max_x,
+            min_y,
+            max_y,
+            min_z,
+            max_z,
+        }
+    }
+}
+
+impl Default for OctBoundingBox {
+    fn default() -> Self {
+        Self {
+            min_x: MIN_X,
+            max_x: MAX_X,
+            min_y: MIN_Y,
+            max_y: MAX_Y,
+            min_z: MIN_Z,
+            max_z: MAX_Z,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static BB: OctBoundingBox = OctBoundingBox {
+        min_x: 0.0,
+        max_x: 1000.0,
+        min_y: 0.0,
+        max_y: 1000.0,
+        min_z: 0.0,
+        max_z: 1000.0,
+    };
+
+    #[test]
+    fn it_gets_octant_given_a_point() {
+        assert_eq!(BB.get_point_octant(cgmath::vec3(100.0, 100.0, 100.0)), 0);
+        assert_eq!(BB.get_point_octant(cgmath::vec3(900.0, 100.0, 100.0)), 1);
+        assert_eq!(BB.get_point_octant(cgmath::vec3(100.0, 900.0, 100.0)), 2);
+        assert_eq!(BB.get_point_octant(cgmath::vec3(900.0, 900.0, 900.0)), 7);
+    }
+
+    #[test]
+    fn it_computes_child_octant() {
+        let child = BB.get_child_bb(5);
+        assert_eq!(
+            child,
+            OctBoundingBox {
+                min_x: 500.0,
+                max_x: 1000.0,
+                min_y: 0.0,
+                max_y: 500.0,
+                min_z: 500.0,
+                max_z: 1000.0,
+            }
+        );
+        assert!(child.contains(cgmath::vec3(700.0, 200.0, 800.0)));
+        assert!(!child.contains(cgmath::vec3(700.0, 600.0, 800.0)));
+    }
+}
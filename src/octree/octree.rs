@@ -0,0 +1,274 @@
+use crate::octree::bounding_box::OctBoundingBox;
+use crate::primitives::{particle3d::Particle3D, scalar::Scalar};
+use cgmath::InnerSpace;
+
+/// Index of a node inside the `Octree` arena
+pub type NodeId = usize;
+
+/// Index of the root node. The root always exists, even in an empty tree.
+pub const ROOT: NodeId = 0;
+
+/// Depth at which particles too close to fall into different octants share a leaf
+/// rather than splitting it any further
+pub const MAX_DEPTH: usize = 32;
+
+#[derive(Debug, Clone)]
+pub struct OctNode {
+    pub bounding_box: OctBoundingBox,
+    /// The particle itself for leaf nodes. For internal nodes this holds the total mass
+    /// and center of mass of all the particles below it.
+    pub particle: Particle3D,
+    pub children: [Option<NodeId>; 8],
+}
+
+impl OctNode {
+    fn new(particle: Particle3D, bounding_box: OctBoundingBox) -> Self {
+        Self {
+            bounding_box,
+            particle,
+            children: [None; 8],
+        }
+    }
+
+    /// Update center of mass
+    pub fn update_cm(&mut self, position: cgmath::Vector3<Scalar>, m: Scalar) {
+        let p = &mut self.particle;
+        let total_mass = p.mass + m;
+        p.position = (p.position * p.mass + position * m) / total_mass;
+        p.mass = total_mass;
+    }
+
+    pub fn is_subdivided(&self) -> bool {
+        self.children.iter().any(|child| child.is_some())
+    }
+}
+
+/// Barnes-Hut octree, the 3D counterpart of `QuadTree`. Nodes live in a flat arena so
+/// that `rebuild` reuses the previous allocation.
+#[derive(Debug, Clone)]
+pub struct Octree {
+    nodes: Vec<OctNode>,
+}
+
+impl Default for Octree {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl Octree {
+    pub fn empty() -> Self {
+        let mut tree = Self { nodes: Vec::new() };
+        tree.clear();
+        tree
+    }
+
+    pub fn from_points(points: &[Particle3D]) -> Self {
+        let mut tree = Self::empty();
+        tree.rebuild(points);
+        tree
+    }
+
+    /// Removes every node except for an empty root while keeping the allocation around
+    pub fn clear(&mut self) {
+        self.clear_with_bounds(OctBoundingBox::default());
+    }
+
+    fn clear_with_bounds(&mut self, bounding_box: OctBoundingBox) {
+        self.nodes.clear();
+        self.nodes
+            .push(OctNode::new(Particle3D::empty(), bounding_box));
+    }
+
+    /// Clears the tree and inserts `points` into it. The root is sized to fit all of them.
+    pub fn rebuild(&mut self, points: &[Particle3D]) {
+        self.clear_with_bounds(OctBoundingBox::enclosing(points.iter().map(|p| p.position)));
+        for p in points {
+            self.insert_particle(*p);
+        }
+    }
+
+    pub fn root(&self) -> &OctNode {
+        &self.nodes[ROOT]
+    }
+
+    pub fn node(&self, id: NodeId) -> &OctNode {
+        &self.nodes[id]
+    }
+
+    /// Number of nodes (internal and leaves) in the tree
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Returns true if no particle has been inserted into the tree
+    pub fn is_empty(&self) -> bool {
+        self.root().particle.mass == 0.0
+    }
+
+    /// Adds a new child to `parent` in the given octant and returns its id
+    fn add_child(&mut self, parent: NodeId, octant: usize, particle: Particle3D) -> NodeId {
+        let bb = self.nodes[parent].bounding_box;
+        let id = self.nodes.len();
+        self.nodes
+            .push(OctNode::new(particle, bb.get_child_bb(octant)));
+        self.nodes[parent].children[octant] = Some(id);
+        id
+    }
+
+    /// Inserts a particle the same way `QuadTree::insert_particle` does. Particles
+    /// outside of the root bounding box are ignored, use `rebuild` to fit the root
+    /// around them. A leaf at `MAX_DEPTH` takes in the particle as part of its own mass.
+    pub fn insert_particle(&mut self, particle: Particle3D) {
+        if !self.nodes[ROOT].bounding_box.contains(particle.position) {
+            return;
+        }
+        if self.nodes[ROOT].particle.mass == 0.0 {
+            self.nodes[ROOT].particle = particle;
+            return;
+        }
+
+        let (position, m) = (particle.position, particle.mass);
+        let mut parent = ROOT;
+        let mut octant = self.nodes[parent].bounding_box.get_point_octant(position);
+        let mut depth = 0;
+
+        while let Some(child) = self.nodes[parent].children[octant] {
+            self.nodes[parent].update_cm(position, m);
+            parent = child;
+            depth += 1;
+            octant = self.nodes[parent].bounding_box.get_point_octant(position);
+        }
+
+        if self.nodes[parent].is_subdivided() {
+            // The particle lands in an empty octant of an internal node
+            self.nodes[parent].update_cm(position, m);
+        } else {
+            // The parent is a leaf. Subdivide until the particle already living in it and
+            // the new particle end up in different octants.
+            let parent_particle = self.nodes[parent].particle;
+            self.nodes[parent].update_cm(position, m);
+            let cm_parent_particle = self.nodes[parent].particle;
+            if depth == MAX_DEPTH {
+                return;
+            }
+            let mut po = self.nodes[parent]
+                .bounding_box
+                .get_point_octant(parent_particle.position);
+
+            while octant == po {
+                parent = self.add_child(parent, octant, cm_parent_particle);
+                depth += 1;
+                if depth == MAX_DEPTH {
+                    // Both particles are left in the new leaf
+                    return;
+                }
+                let bb = self.nodes[parent].bounding_box;
+                po = bb.get_point_octant(parent_particle.position);
+                octant = bb.get_point_octant(position);
+            }
+
+            self.add_child(parent, po, parent_particle);
+        }
+
+        self.add_child(parent, octant, particle);
+    }
+}
+
+/// Yields the nodes that act on a point under the Barnes-Hut opening criterion
+pub struct OctreeIter<'a> {
+    p: cgmath::Vector3<Scalar>,
+    theta: Scalar,
+    tree: &'a Octree,
+    stack: Vec<NodeId>,
+}
+
+impl<'a> OctreeIter<'a> {
+    pub fn new(p: cgmath::Vector3<Scalar>, theta: Scalar, tree: &'a Octree) -> Self {
+        Self {
+            p,
+            theta,
+            tree,
+            stack: vec![ROOT],
+        }
+    }
+}
+
+impl<'a> Iterator for OctreeIter<'a> {
+    type Item = &'a OctNode;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(id) = self.stack.pop() {
+            let node = self.tree.node(id);
+            let d = (node.particle.position - self.p).magnitude();
+            if d == 0.0 {
+                continue;
+            }
+
+            if !node.is_subdivided() || (node.bounding_box.length() / d) < self.theta {
+                return Some(node);
+            }
+
+            self.stack.extend(node.children.iter().flatten());
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitives::particle3d::Particle3DProperties;
+
+    fn particle_at(x: Scalar, y: Scalar, z: Scalar, mass: Scalar) -> Particle3D {
+        Particle3D::new(Particle3DProperties {
+            position: cgmath::vec3(x, y, z),
+            mass,
+            radius: 1.0,
+            velocity: cgmath::vec3(0.0, 0.0, 0.0),
+            color: crate::primitives::color::WHITE,
+        })
+    }
+
+    #[test]
+    fn it_aggregates_mass_at_the_root() {
+        let points = vec![
+            particle_at(100.0, 100.0, 100.0, 1.0),
+            particle_at(900.0, 100.0, 500.0, 2.0),
+            particle_at(120.0, 110.0, 105.0, 3.0),
+            particle_at(500.0, 900.0, 900.0, 4.0),
+        ];
+        let tree = Octree::from_points(&points);
+
+        let root = tree.root();
+        assert_eq!(root.particle.mass, 10.0);
+        let cz = (100.0 + 1000.0 + 315.0 + 3600.0) / 10.0;
+        assert!((root.particle.position.z - cz).abs() < 1e-3);
+    }
+
+    #[test]
+    fn it_visits_every_leaf_when_theta_is_zero() {
+        let points = vec![
+            particle_at(100.0, 100.0, 100.0, 1.0),
+            particle_at(900.0, 100.0, 500.0, 1.0),
+            particle_at(120.0, 110.0, 105.0, 1.0),
+            particle_at(500.0, 900.0, 900.0, 1.0),
+        ];
+        let tree = Octree::from_points(&points);
+        let visited = OctreeIter::new(cgmath::vec3(0.0, 0.0, 0.0), 0.0, &tree).count();
+        assert_eq!(visited, points.len());
+    }
+
+    #[test]
+    fn it_stops_splitting_coincident_particles_at_the_max_depth() {
+        let mut points = vec![particle_at(900.0, 900.0, 900.0, 1.0)];
+        points.extend((0..20).map(|_| particle_at(250.0, 250.0, 250.0, 1.0)));
+        let tree = Octree::from_points(&points);
+        assert_eq!(tree.root().particle.mass, 21.0);
+        assert!(tree.len() <= 8 * MAX_DEPTH + 1);
+        let mass: Scalar = OctreeIter::new(cgmath::vec3(0.0, 0.0, 0.0), 0.0, &tree)
+            .map(|node| node.particle.mass)
+            .sum();
+        assert_eq!(mass, 21.0);
+    }
+}
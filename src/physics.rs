@@ -0,0 +1,561 @@
+use crate::compute::GpuForces;
+use crate::gpu_tree::GpuTreeForces;
+use instant::{Duration, Instant};
+use nbody::primitives::color::ColorMapping;
+use nbody::primitives::colormap::Colormap;
+use nbody::primitives::instance::Instance;
+use nbody::replay::ReplayRecorder;
+use nbody::{
+    analysis, simulation::Simulation, BoundaryCondition, Drag, Energy, Expansion, ExportFormat,
+    ExternalPotential, ForceSolver, Fragmentation, Integrator, Interaction, ParticleStore, Scalar,
+    TrajectoryExporter, TreeStats,
+};
+use std::{
+    collections::VecDeque,
+    mem,
+    path::PathBuf,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+/// Log F6 records particle trajectories into and F8 plays back
+pub const REPLAY_PATH: &str = "nbody.replay";
+/// How often the acceleration error is printed while comparing solvers
+const ERROR_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+/// Number of steps the energy plot reaches back
+pub const ENERGY_HISTORY: usize = 2000;
+
+/// Settings and measurements of the simulation shown in the HUD
+#[derive(Clone, Copy, Debug)]
+pub struct Status {
+    /// Simulated time since the start, the sum of every step taken
+    pub elapsed: Scalar,
+    pub particles: usize,
+    /// Time step, or the size of the last step taken if it is adaptive
+    pub time_step: Scalar,
+    /// Whether the step size is picked from the accelerations
+    pub adaptive: bool,
+    pub theta: Scalar,
+    pub integrator: Integrator,
+    /// Deepest level of the block time steps during the last step, if they are on
+    pub block_level: Option<u32>,
+    pub solver: ForceSolver,
+    pub gpu_solver: Option<ForceSolver>,
+    pub expansion: Expansion,
+    pub interaction: Interaction,
+    pub drag: Option<Drag>,
+    pub fragmentation: Option<Fragmentation>,
+    pub boundary: BoundaryCondition,
+    pub color_mapping: ColorMapping,
+    pub colormap: Colormap,
+    /// Time taken by the last force evaluation
+    pub force_time: Duration,
+    /// Time taken by the steps of the last tick, tree builds included
+    pub physics_time: Duration,
+    /// Building Barnes-Hut trees during the last tick
+    pub tree_build_time: Duration,
+    /// RMS acceleration error of Barnes-Hut after the last tick while comparing solvers
+    pub acceleration_error: Option<Scalar>,
+    /// Steps recorded into `REPLAY_PATH` so far, while recording
+    pub replay_frames: Option<usize>,
+    /// Steps exported so far, while exporting
+    pub exported_steps: Option<usize>,
+    /// Angular momentum of the particles about their center of mass
+    pub angular_momentum: Scalar,
+    /// `2T / |U|` after the last step, while the energy is measured for the energy plot
+    pub virial_ratio: Option<Scalar>,
+}
+
+/// Everything the render thread needs to draw the simulation, as it was after a tick
+pub struct Snapshot {
+    pub instances: Vec<Instance>,
+    /// Range of the quantity mapped to colors
+    pub color_range: Option<(Scalar, Scalar)>,
+    /// Copy of the particles for the overlays, labels and inspector
+    pub particles: ParticleStore,
+    pub status: Status,
+    /// Energy after each of the last `ENERGY_HISTORY` steps, oldest first. Empty unless
+    /// it is plotted.
+    pub energy: Vec<Energy>,
+    /// Shape of the Barnes-Hut tree after the last tick, while it is built on the CPU
+    pub tree: Option<TreeStats>,
+}
+
+/// The simulation together with everything taking a step needs. Owned by the physics
+/// thread between steps, and locked by the render thread to change it.
+pub struct Stepper {
+    pub sim: Simulation,
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    gpu_forces: GpuForces,
+    gpu_tree: GpuTreeForces,
+    /// Solver forces are evaluated with on the GPU, if they are not evaluated on the CPU
+    pub gpu_solver: Option<ForceSolver>,
+    /// Whether steps are taken every tick. Only queued steps are taken otherwise.
+    pub running: bool,
+    /// Simulation steps taken per tick. Below 1 the simulation runs in slow motion,
+    /// taking a step only every few ticks.
+    pub steps_per_tick: f32,
+    /// Fraction of a step left over from previous ticks
+    step_budget: f32,
+    /// Steps to take on the next tick while not running
+    pub queued_steps: usize,
+    /// Whether every tick is also checked against the exact pairwise forces
+    pub compare_solvers: bool,
+    /// Pull of the mouse cursor on every particle while the right button is held. Added
+    /// on top of the forces of the solver rather than kept with the simulation, so that
+    /// it never ends up in snapshots.
+    pub attractor: Option<ExternalPotential>,
+    acceleration_error: Option<Scalar>,
+    /// When the acceleration error was last printed
+    last_error_report: Instant,
+    /// Records every simulation step into `REPLAY_PATH` while set
+    recorder: Option<ReplayRecorder>,
+    /// Exports the particles after every simulation step into `export_path` while set
+    exporter: Option<TrajectoryExporter>,
+    export_path: PathBuf,
+    /// How particle colors are chosen when building the instances
+    pub color_mapping: ColorMapping,
+    pub colormap: Colormap,
+    force_time: Duration,
+    physics_time: Duration,
+    tree_build_time: Duration,
+    /// Energy after each of the last steps, while it is plotted
+    energy: Option<VecDeque<Energy>>,
+    /// Snapshot the render thread is done with, reused for the next one
+    spare: Option<Snapshot>,
+}
+
+impl Stepper {
+    pub fn new(
+        sim: Simulation,
+        device: Arc<wgpu::Device>,
+        queue: Arc<wgpu::Queue>,
+        export_path: PathBuf,
+    ) -> Self {
+        Self {
+            sim,
+            gpu_forces: GpuForces::new(&device),
+            gpu_tree: GpuTreeForces::new(&device),
+            device,
+            queue,
+            gpu_solver: None,
+            running: false,
+            steps_per_tick: 1.0,
+            step_budget: 0.0,
+            queued_steps: 0,
+            compare_solvers: false,
+            attractor: None,
+            acceleration_error: None,
+            last_error_report: Instant::now(),
+            recorder: None,
+            exporter: None,
+            export_path,
+            color_mapping: ColorMapping::default(),
+            colormap: Colormap::default(),
+            force_time: Duration::default(),
+            physics_time: Duration::default(),
+            tree_build_time: Duration::default(),
+            energy: None,
+            spare: None,
+        }
+    }
+
+    /// Changes the number of steps per tick, dropping any fraction of a step left over
+    pub fn set_steps_per_tick(&mut self, steps_per_tick: f32) {
+        self.steps_per_tick = steps_per_tick;
+        self.step_budget = 0.0;
+    }
+
+    /// Switches comparing every tick against the exact pairwise forces on or off
+    pub fn toggle_compare_solvers(&mut self) {
+        self.compare_solvers = !self.compare_solvers;
+        self.acceleration_error = None;
+    }
+
+    /// Starts or stops measuring the energy after every step for the energy plot
+    pub fn toggle_energy_plot(&mut self) {
+        self.energy = match self.energy {
+            Some(_) => None,
+            None => Some(VecDeque::with_capacity(ENERGY_HISTORY)),
+        };
+    }
+
+    /// Forgets the energy measured so far, e.g. after the particles were replaced
+    pub fn clear_energy_history(&mut self) {
+        if let Some(energy) = &mut self.energy {
+            energy.clear();
+        }
+    }
+
+    /// Starts recording trajectories into `REPLAY_PATH` or finishes an ongoing recording
+    pub fn toggle_replay_recording(&mut self) {
+        let result = match self.recorder.take() {
+            Some(recorder) => recorder.finish(),
+            None => ReplayRecorder::create(REPLAY_PATH).map(|recorder| {
+                self.recorder = Some(recorder);
+            }),
+        };
+        if let Err(e) = result {
+            eprintln!("{}: {}", REPLAY_PATH, e);
+        }
+    }
+
+    /// Starts exporting particle states into `export_path` or finishes an ongoing export
+    pub fn toggle_export(&mut self) {
+        let result = match self.exporter.take() {
+            Some(exporter) => exporter.finish(),
+            None => {
+                let format = ExportFormat::from_path(&self.export_path);
+                TrajectoryExporter::create(&self.export_path, format).map(|exporter| {
+                    self.exporter = Some(exporter);
+                })
+            }
+        };
+        if let Err(e) = result {
+            eprintln!("{}: {}", self.export_path.display(), e);
+        }
+    }
+
+    /// Number of steps to take this tick. Fractions of a step are carried over, so
+    /// below one step per tick the simulation only advances every few ticks.
+    fn take_steps(&mut self) -> usize {
+        if !self.running {
+            return mem::take(&mut self.queued_steps);
+        }
+        self.step_budget += self.steps_per_tick;
+        let steps = self.step_budget.floor();
+        self.step_budget -= steps;
+        steps as usize
+    }
+
+    /// GPU solver forces are computed with, if `runs_on_gpu` allows it
+    fn gpu_solver(&self) -> Option<ForceSolver> {
+        self.gpu_solver
+            .filter(|&solver| runs_on_gpu(&self.sim, solver))
+    }
+
+    /// Advances the simulation by a single step and records it if requested
+    fn advance(&mut self) {
+        let gpu_solver = self.gpu_solver();
+        let (gpu_forces, gpu_tree) = (&mut self.gpu_forces, &mut self.gpu_tree);
+        let (device, queue) = (&*self.device, &*self.queue);
+        let force_time = &mut self.force_time;
+        let attractor = self.attractor;
+        self.sim.advance_with(|sim| {
+            let start = Instant::now();
+            match gpu_solver {
+                Some(ForceSolver::Direct) => {
+                    let accelerations = gpu_forces.compute(
+                        device,
+                        queue,
+                        sim.get_particles(),
+                        sim.get_g(),
+                        sim.get_softening(),
+                        sim.get_boundary().period(),
+                    );
+                    sim.set_accelerations(&accelerations);
+                }
+                Some(ForceSolver::BarnesHut) => {
+                    let accelerations = gpu_tree.compute(
+                        device,
+                        queue,
+                        sim.get_particles(),
+                        sim.get_g(),
+                        sim.get_softening(),
+                        sim.get_theta(),
+                    );
+                    sim.set_accelerations(&accelerations);
+                }
+                None => sim.step(),
+            }
+            if let Some(attractor) = attractor {
+                let (particles, g) = (sim.get_particles(), sim.get_g());
+                let accelerations: Vec<_> = particles
+                    .positions()
+                    .iter()
+                    .zip(particles.accelerations())
+                    .map(|(&x, &a)| a + attractor.acceleration(x, g, 0.0))
+                    .collect();
+                sim.set_accelerations(&accelerations);
+            }
+            *force_time = start.elapsed();
+        });
+        #[cfg(feature = "scripting")]
+        if let Some(e) = self.sim.take_script_error() {
+            eprintln!("{}", e);
+        }
+        if let Some(energy) = &mut self.energy {
+            if energy.len() == ENERGY_HISTORY {
+                energy.pop_front();
+            }
+            energy.push_back(self.sim.energy());
+        }
+        if let Some(recorder) = &mut self.recorder {
+            if let Err(e) = recorder.record(&self.sim) {
+                eprintln!("{}: {}", REPLAY_PATH, e);
+                self.recorder = None;
+            }
+        }
+        if let Some(exporter) = &mut self.exporter {
+            if let Err(e) = exporter.record(&self.sim) {
+                eprintln!("{}: {}", self.export_path.display(), e);
+                self.exporter = None;
+            }
+        }
+    }
+
+    /// Measures the Barnes-Hut error after a tick while comparing solvers
+    fn measure_error(&mut self) {
+        if !self.compare_solvers {
+            return;
+        }
+        let error = self.sim.acceleration_error();
+        self.acceleration_error = Some(error);
+        // Once a second is plenty for watching the error while tuning theta
+        if self.last_error_report.elapsed() >= ERROR_REPORT_INTERVAL {
+            println!(
+                "theta {:.2}: RMS acceleration error {:.3e}",
+                self.sim.get_theta(),
+                error
+            );
+            self.last_error_report = Instant::now();
+        }
+    }
+
+    /// Captures the current state of the simulation for the render thread
+    fn snapshot(&mut self) -> Snapshot {
+        let sim = &self.sim;
+        let mut instances = self
+            .spare
+            .take()
+            .map(|snapshot| snapshot.instances)
+            .unwrap_or_default();
+        let color_range = sim.get_instances(self.color_mapping, self.colormap, &mut instances);
+        let energy = self
+            .energy
+            .as_ref()
+            .map(|energy| energy.iter().copied().collect())
+            .unwrap_or_default();
+        let tree = Some(sim.get_tree_stats()).filter(|_| {
+            sim.get_solver() == ForceSolver::BarnesHut
+                && self.gpu_solver().is_none()
+                && sim.get_groups().feels_all()
+        });
+        Snapshot {
+            instances,
+            color_range,
+            particles: sim.get_particles().clone(),
+            status: Status {
+                elapsed: sim.get_elapsed(),
+                particles: sim.get_particles().len(),
+                time_step: match sim.get_adaptive_step() {
+                    Some(_) => sim.get_effective_time_step(),
+                    None => sim.get_time_step(),
+                },
+                adaptive: sim.get_adaptive_step().is_some(),
+                theta: sim.get_theta(),
+                integrator: sim.get_integrator(),
+                block_level: sim.get_block_steps().map(|_| sim.get_stats().deepest_level),
+                solver: sim.get_solver(),
+                gpu_solver: self.gpu_solver(),
+                expansion: sim.get_expansion(),
+                interaction: sim.get_interaction(),
+                drag: sim.get_drag(),
+                fragmentation: sim.get_fragmentation(),
+                boundary: sim.get_boundary(),
+                color_mapping: self.color_mapping,
+                colormap: self.colormap,
+                force_time: self.force_time,
+                physics_time: self.physics_time,
+                tree_build_time: self.tree_build_time,
+                acceleration_error: self.acceleration_error.filter(|_| self.compare_solvers),
+                replay_frames: self.recorder.as_ref().map(ReplayRecorder::frames),
+                exported_steps: self.exporter.as_ref().map(TrajectoryExporter::steps),
+                angular_momentum: analysis::angular_momentum(sim.get_particles()),
+                virial_ratio: self
+                    .energy
+                    .as_ref()
+                    .and_then(VecDeque::back)
+                    .and_then(Energy::virial_ratio),
+            },
+            energy,
+            tree,
+        }
+    }
+}
+
+/// State shared between the render thread and the physics thread
+struct Shared {
+    stepper: Mutex<Stepper>,
+    /// Snapshot taken after the last tick or change
+    latest: Mutex<Arc<Snapshot>>,
+    /// Tells the physics thread to finish
+    #[cfg(not(target_arch = "wasm32"))]
+    stop: std::sync::atomic::AtomicBool,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Stepper> {
+        self.stepper.lock().expect("the physics thread panicked")
+    }
+
+    /// Takes the steps due this tick and publishes a snapshot if any were taken. The
+    /// stepper is only locked for one step at a time, so the render thread gets to
+    /// change the simulation between any two steps.
+    fn tick(&self) {
+        let steps = self.lock().take_steps();
+        if steps == 0 {
+            return;
+        }
+        let _span = tracing::info_span!("physics tick").entered();
+        let start = Instant::now();
+        let mut tree_build = Duration::default();
+        for _ in 0..steps {
+            let mut stepper = self.lock();
+            if stepper.sim.get_particles().is_empty() {
+                break;
+            }
+            stepper.advance();
+            tree_build += stepper.sim.get_stats().tree_build;
+        }
+        let mut stepper = self.lock();
+        stepper.physics_time = start.elapsed();
+        stepper.tree_build_time = tree_build;
+        stepper.measure_error();
+        self.publish(&mut stepper);
+    }
+
+    /// Replaces the latest snapshot with one of the current state of `stepper`. The one
+    /// it replaces is reused for the next snapshot if the render thread is done with it,
+    /// so that no more than three exist at a time: one being drawn, the latest and the
+    /// one being taken.
+    fn publish(&self, stepper: &mut Stepper) {
+        let _span = tracing::info_span!("snapshot").entered();
+        let snapshot = Arc::new(stepper.snapshot());
+        let previous = mem::replace(&mut *self.latest.lock().unwrap(), snapshot);
+        stepper.spare = Arc::try_unwrap(previous).ok();
+    }
+}
+
+/// Whether `solver` can compute the forces of `sim` on the GPU. The GPU only knows about
+/// gravity between every pair of particles, so charges and groups that do not feel each
+/// other fall back to the CPU. The brute force shader pulls toward the nearest periodic
+/// copy of every particle, but the GPU tree is built around the particles and knows
+/// nothing of periodic copies, so periodic boundaries keep Barnes-Hut on the CPU.
+fn runs_on_gpu(sim: &Simulation, solver: ForceSolver) -> bool {
+    sim.get_interaction() == Interaction::Gravity
+        && sim.get_groups().feels_all()
+        && (solver == ForceSolver::Direct || sim.get_boundary().period().is_none())
+}
+
+/// Runs a `Stepper` on a thread of its own, which takes its steps at a fixed number of
+/// ticks per second and publishes a `Snapshot` after every tick. The render thread only
+/// ever waits for a single step when it changes the simulation, so heavy physics slows
+/// down the simulation but not input handling and drawing.
+///
+/// The browser has no threads, there the steps are taken by `update` once per frame.
+pub struct Physics {
+    shared: Arc<Shared>,
+    #[cfg(not(target_arch = "wasm32"))]
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl Physics {
+    /// Starts stepping `stepper` `tick_rate` times per second
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn start(mut stepper: Stepper, tick_rate: f32) -> Self {
+        let snapshot = stepper.snapshot();
+        let shared = Arc::new(Shared {
+            stepper: Mutex::new(stepper),
+            latest: Mutex::new(Arc::new(snapshot)),
+            stop: Default::default(),
+        });
+        let interval = Duration::from_secs_f32(1.0 / tick_rate.max(1.0));
+        let thread = {
+            let shared = Arc::clone(&shared);
+            std::thread::Builder::new()
+                .name("physics".to_string())
+                .spawn(move || run(&shared, interval))
+                .expect("could not start the physics thread")
+        };
+        Self {
+            shared,
+            thread: Some(thread),
+        }
+    }
+
+    /// Steps `stepper` from `update`, once per rendered frame
+    #[cfg(target_arch = "wasm32")]
+    pub fn start(mut stepper: Stepper, _tick_rate: f32) -> Self {
+        let snapshot = stepper.snapshot();
+        Self {
+            shared: Arc::new(Shared {
+                stepper: Mutex::new(stepper),
+                latest: Mutex::new(Arc::new(snapshot)),
+            }),
+        }
+    }
+
+    /// Takes the steps of this frame where there is no physics thread
+    #[cfg(target_arch = "wasm32")]
+    pub fn update(&self) {
+        self.shared.tick();
+    }
+
+    /// Waits for the step in progress, if any, and holds the physics thread off until
+    /// the guard is dropped. Call `publish` afterwards to show the changes right away.
+    pub fn lock(&self) -> MutexGuard<'_, Stepper> {
+        self.shared.lock()
+    }
+
+    /// Takes a snapshot of the current state without waiting for the next tick
+    pub fn publish(&self) {
+        self.shared.publish(&mut self.lock());
+    }
+
+    /// The snapshot published last
+    pub fn latest(&self) -> Arc<Snapshot> {
+        Arc::clone(&self.shared.latest.lock().unwrap())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for Physics {
+    fn drop(&mut self) {
+        self.shared
+            .stop
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Body of the physics thread. Ticks every `interval` until told to stop. A tick that
+/// takes longer than that is followed by the next one right away, without trying to
+/// catch up on the ticks that were missed.
+#[cfg(not(target_arch = "wasm32"))]
+fn run(shared: &Shared, interval: Duration) {
+    let mut next_tick = Instant::now();
+    while !shared.stop.load(std::sync::atomic::Ordering::Relaxed) {
+        shared.tick();
+        next_tick = (next_tick + interval).max(Instant::now());
+        std::thread::sleep(next_tick.saturating_duration_since(Instant::now()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_keeps_periodic_barnes_hut_on_the_cpu() {
+        let mut sim = Simulation::new(0.1, 0.5, 1.0, 1.0, Integrator::Leapfrog);
+        assert!(runs_on_gpu(&sim, ForceSolver::BarnesHut));
+        sim.set_boundary(BoundaryCondition::Periodic);
+        assert!(!runs_on_gpu(&sim, ForceSolver::BarnesHut));
+        assert!(runs_on_gpu(&sim, ForceSolver::Direct));
+        sim.set_interaction(Interaction::Electrostatic);
+        assert!(!runs_on_gpu(&sim, ForceSolver::Direct));
+    }
+}
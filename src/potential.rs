@@ -0,0 +1,145 @@
+use cgmath::InnerSpace;
+use serde::{Deserialize, Serialize};
+
+use crate::primitives::scalar::Scalar;
+
+/// Fixed background field that pulls on every particle without being made of particles
+/// itself, e.g. the dark matter halo of a galaxy. Positions are in world coordinates and
+/// masses in the same units as particle masses. In a scenario file a halo reads
+///
+/// ```ron
+/// Nfw(center: (500.0, 500.0), mass: 1000000.0, scale_radius: 100.0)
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ExternalPotential {
+    /// A point mass pinned in place. Softened like the pull between particles.
+    PointMass {
+        position: (Scalar, Scalar),
+        mass: Scalar,
+    },
+    /// The same acceleration everywhere, e.g. the pull of something very far away
+    Uniform { acceleration: (Scalar, Scalar) },
+    /// Plummer sphere `Φ = -G M / sqrt(r² + a²)` with total mass `M` and scale radius `a`
+    Plummer {
+        center: (Scalar, Scalar),
+        mass: Scalar,
+        scale_radius: Scalar,
+    },
+    /// Navarro-Frenk-White halo with density `ρ0 / ((r / rs) (1 + r / rs)²)`. Its total
+    /// mass diverges, so `mass` is the characteristic mass `4π ρ0 rs³` instead.
+    Nfw {
+        center: (Scalar, Scalar),
+        mass: Scalar,
+        scale_radius: Scalar,
+    },
+}
+
+impl ExternalPotential {
+    /// Acceleration of a particle at `position`, for gravitational constant `g` and
+    /// squared softening length `softening2`
+    pub fn acceleration(
+        &self,
+        position: cgmath::Vector2<Scalar>,
+        g: Scalar,
+        softening2: Scalar,
+    ) -> cgmath::Vector2<Scalar> {
+        match *self {
+            ExternalPotential::PointMass {
+                position: (x, y),
+                mass,
+            } => {
+                let d = cgmath::vec2(x, y) - position;
+                let d2 = d.magnitude2() + softening2;
+                d * (g * mass / (d2 * d2.sqrt()))
+            }
+            ExternalPotential::Uniform {
+                acceleration: (x, y),
+            } => cgmath::vec2(x, y),
+            ExternalPotential::Plummer {
+                center: (x, y),
+                mass,
+                scale_radius,
+            } => {
+                let d = cgmath::vec2(x, y) - position;
+                let d2 = d.magnitude2() + scale_radius * scale_radius;
+                d * (g * mass / (d2 * d2.sqrt()))
+            }
+            ExternalPotential::Nfw {
+                center: (x, y),
+                mass,
+                scale_radius,
+            } => {
+                let d = cgmath::vec2(x, y) - position;
+                let r = d.magnitude();
+                if r == 0.0 {
+                    return cgmath::vec2(0.0, 0.0);
+                }
+                // Mass enclosed within r
+                let s = r / scale_radius;
+                let enclosed = mass * ((1.0 + s).ln() - s / (1.0 + s));
+                d * (g * enclosed / (r * r * r))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_pulls_towards_the_center() {
+        let position = cgmath::vec2(700.0, 500.0);
+        for potential in &[
+            ExternalPotential::PointMass {
+                position: (500.0, 500.0),
+                mass: 1000.0,
+            },
+            ExternalPotential::Plummer {
+                center: (500.0, 500.0),
+                mass: 1000.0,
+                scale_radius: 50.0,
+            },
+            ExternalPotential::Nfw {
+                center: (500.0, 500.0),
+                mass: 1000.0,
+                scale_radius: 50.0,
+            },
+        ] {
+            let a = potential.acceleration(position, 1.0, 1.0);
+            assert!(a.x < 0.0 && a.y == 0.0, "{:?}: {:?}", potential, a);
+        }
+    }
+
+    #[test]
+    fn it_looks_like_a_point_mass_from_far_away() {
+        let point = ExternalPotential::PointMass {
+            position: (0.0, 0.0),
+            mass: 1000.0,
+        };
+        let plummer = ExternalPotential::Plummer {
+            center: (0.0, 0.0),
+            mass: 1000.0,
+            scale_radius: 1.0,
+        };
+        let far = cgmath::vec2(0.0, 1000.0);
+        let (a, b) = (
+            point.acceleration(far, 1.0, 0.0),
+            plummer.acceleration(far, 1.0, 0.0),
+        );
+        assert!((a - b).magnitude() < a.magnitude() * 1e-4);
+    }
+
+    #[test]
+    fn it_has_no_pull_at_the_center_of_a_halo() {
+        let nfw = ExternalPotential::Nfw {
+            center: (500.0, 500.0),
+            mass: 1000.0,
+            scale_radius: 50.0,
+        };
+        assert_eq!(
+            nfw.acceleration(cgmath::vec2(500.0, 500.0), 1.0, 0.0),
+            cgmath::vec2(0.0, 0.0)
+        );
+    }
+}
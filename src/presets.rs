@@ -0,0 +1,685 @@
+use cgmath::InnerSpace;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::constants;
+use crate::primitives::{
+    color::{self, Color},
+    particle::{BodyKind, Particle, ParticleProperties},
+    particle3d::{Particle3D, Particle3DProperties},
+    scalar::{consts::PI, Scalar},
+};
+use crate::star_cluster::ClusterModel;
+
+/// Ready made initial conditions. Bodies on orbits are given circular velocities
+/// computed from the mass enclosed by their orbit so that the presets stay stable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Preset {
+    /// Light stars orbiting a heavy core in a rotating disk
+    GalaxyDisk,
+    /// Two equally heavy stars orbiting their common center of mass
+    BinaryStar,
+    /// A sun with a handful of planets on circular orbits
+    SolarSystem,
+    /// Bodies scattered uniformly over the world with small random velocities
+    RandomCloud,
+    /// Light particles of opposite charges in equal numbers, jostling around. Only
+    /// interesting with electrostatics turned on.
+    Plasma,
+    /// Star cluster sampled from a Plummer sphere
+    Plummer,
+    /// Star cluster sampled from a moderately concentrated King model
+    King,
+    /// Two disk galaxies of unequal mass on a parabolic encounter
+    GalaxyCollision,
+}
+
+impl Preset {
+    /// Generates the particles of this preset centered in the world. `g` must be the
+    /// gravitational constant of the simulation the particles are added to.
+    pub fn generate<R: Rng>(self, rng: &mut R, g: Scalar) -> Vec<Particle> {
+        let center = world_center();
+        match self {
+            Preset::GalaxyDisk => galaxy_disk(rng, center, 300, 400.0, 20000.0, g),
+            Preset::BinaryStar => binary_star(center, 200.0, 5000.0, g),
+            Preset::SolarSystem => solar_system(rng, center, 10000.0, g),
+            Preset::RandomCloud => random_cloud(rng, 500),
+            Preset::Plasma => plasma(rng, center, 400, 300.0, 10.0),
+            Preset::Plummer => {
+                star_cluster(rng, center, 1000, 20000.0, 100.0, ClusterModel::Plummer, g)
+            }
+            Preset::King => star_cluster(
+                rng,
+                center,
+                1000,
+                20000.0,
+                100.0,
+                ClusterModel::King { w0: 6.0 },
+                g,
+            ),
+            Preset::GalaxyCollision => galaxy_collision(rng, center, GalaxyCollision::default(), g),
+        }
+    }
+}
+
+fn world_center() -> cgmath::Vector2<Scalar> {
+    cgmath::vec2(
+        (constants::MIN_X + constants::MAX_X) / 2.0,
+        (constants::MIN_Y + constants::MAX_Y) / 2.0,
+    )
+}
+
+fn body(
+    position: cgmath::Vector2<Scalar>,
+    velocity: cgmath::Vector2<Scalar>,
+    mass: Scalar,
+    radius: Scalar,
+    color: Color,
+) -> Particle {
+    Particle::new(ParticleProperties {
+        position,
+        mass,
+        radius,
+        velocity,
+        acceleration: cgmath::vec2(0.0, 0.0),
+        color,
+    })
+}
+
+/// Velocity of a circular, counter-clockwise orbit around `center` at `offset` from it,
+/// given the mass enclosed by the orbit
+pub fn circular_velocity(
+    offset: cgmath::Vector2<Scalar>,
+    enclosed_mass: Scalar,
+    g: Scalar,
+) -> cgmath::Vector2<Scalar> {
+    let r = offset.magnitude();
+    let speed = (g * enclosed_mass / r).sqrt();
+    cgmath::vec2(-offset.y, offset.x) / r * speed
+}
+
+/// `count` light stars spread over a disk of the given radius around a core of mass
+/// `core_mass`
+pub fn galaxy_disk<R: Rng>(
+    rng: &mut R,
+    center: cgmath::Vector2<Scalar>,
+    count: usize,
+    radius: Scalar,
+    core_mass: Scalar,
+    g: Scalar,
+) -> Vec<Particle> {
+    // Keep stars away from the core so they don't merge into it right away
+    let inner = radius / 8.0;
+    let mut orbits: Vec<(Scalar, Scalar, Scalar)> = (0..count)
+        .map(|_| {
+            let r = rng.gen_range(inner..radius);
+            let angle = rng.gen_range(0.0..2.0 * PI);
+            let mass = rng.gen_range(1.0..5.0);
+            (r, angle, mass)
+        })
+        .collect();
+    // Sort by radius so the enclosed mass can be accumulated from the inside out
+    orbits.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+    // A black hole, so that the core keeps its size while it swallows stars
+    let mut particles = vec![Particle {
+        kind: BodyKind::BlackHole,
+        ..body(
+            center,
+            cgmath::vec2(0.0, 0.0),
+            core_mass,
+            8.0,
+            [1.0, 0.9, 0.6, 1.0],
+        )
+    }];
+    let mut enclosed_mass = core_mass;
+    for (r, angle, mass) in orbits {
+        let offset = cgmath::vec2(angle.cos(), angle.sin()) * r;
+        let velocity = circular_velocity(offset, enclosed_mass, g);
+        particles.push(body(
+            center + offset,
+            velocity,
+            mass,
+            1.0,
+            [0.7, 0.8, 1.0, 1.0],
+        ));
+        enclosed_mass += mass;
+    }
+    particles
+}
+
+/// Setup of two disk galaxies heading for each other, see `galaxy_collision`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GalaxyCollision {
+    /// Stars of the heavier galaxy. The lighter one gets `mass_ratio` times as many.
+    pub stars: usize,
+    /// Radius of the heavier disk. The lighter one is `√mass_ratio` times as large.
+    pub radius: Scalar,
+    /// Mass of the central body of the heavier galaxy
+    pub core_mass: Scalar,
+    /// Mass of the lighter galaxy relative to the heavier one, up to 1
+    pub mass_ratio: Scalar,
+    /// Distance between the two cores to start with
+    pub separation: Scalar,
+    /// How close the cores would pass if they flew by on straight lines. 0 is head-on.
+    pub impact_parameter: Scalar,
+    /// Whether the lighter disk spins against the direction it orbits the heavier one
+    pub retrograde: bool,
+}
+
+impl Default for GalaxyCollision {
+    fn default() -> Self {
+        Self {
+            stars: 400,
+            radius: 150.0,
+            core_mass: 20000.0,
+            mass_ratio: 0.5,
+            separation: 550.0,
+            impact_parameter: 150.0,
+            retrograde: false,
+        }
+    }
+}
+
+/// Two `galaxy_disk`s around `center` falling towards each other on a parabolic orbit,
+/// the classic Toomre & Toomre encounter. The galaxies approach at the speed at which
+/// they are just bound, aimed to miss each other by `impact_parameter`. Stars of the
+/// lighter galaxy are tinted orange to follow where they end up.
+pub fn galaxy_collision<R: Rng>(
+    rng: &mut R,
+    center: cgmath::Vector2<Scalar>,
+    setup: GalaxyCollision,
+    g: Scalar,
+) -> Vec<Particle> {
+    let ratio = setup.mass_ratio.clamp(Scalar::EPSILON, 1.0);
+    let light_stars = ((setup.stars as Scalar * ratio).round() as usize).max(1);
+    let heavy = galaxy_disk(rng, center, setup.stars, setup.radius, setup.core_mass, g);
+    let light = galaxy_disk(
+        rng,
+        center,
+        light_stars,
+        setup.radius * ratio.sqrt(),
+        setup.core_mass * ratio,
+        g,
+    );
+    // The stars are scattered unevenly around the core, so each galaxy is placed by its
+    // own center of mass and momentum
+    let barycenter = |galaxy: &[Particle]| {
+        let mass: Scalar = galaxy.iter().map(|p| p.mass).sum();
+        let (position, velocity) = galaxy.iter().fold(
+            (cgmath::vec2(0.0, 0.0), cgmath::vec2(0.0, 0.0)),
+            |(x, v), p| (x + p.position * p.mass, v + p.velocity * p.mass),
+        );
+        (position / mass, velocity / mass, mass)
+    };
+    let (heavy_center, heavy_velocity, heavy_mass) = barycenter(&heavy);
+    let (light_center, light_velocity, light_mass) = barycenter(&light);
+    let total = heavy_mass + light_mass;
+
+    // The light galaxy starts out along +x and comes in towards -x, offset along y by
+    // the impact parameter
+    let separation = setup.separation.max(Scalar::EPSILON);
+    let impact = setup.impact_parameter.clamp(-separation, separation);
+    let offset = cgmath::vec2((separation * separation - impact * impact).sqrt(), impact);
+    let speed = (2.0 * g * total / separation).sqrt();
+    let velocity = cgmath::vec2(-speed, 0.0);
+
+    // Both move about their common center of mass
+    let heavy_target = (
+        center - offset * light_mass / total,
+        -velocity * light_mass / total,
+    );
+    let light_target = (
+        center + offset * heavy_mass / total,
+        velocity * heavy_mass / total,
+    );
+    // Passing above the heavy galaxy towards -x it goes around counterclockwise, the
+    // same way the disks spin. A retrograde disk spins against that.
+    let prograde = (impact >= 0.0) != setup.retrograde;
+    let light_spin: Scalar = if prograde { 1.0 } else { -1.0 };
+    let moved = |p: Particle, (from, from_velocity), (to, to_velocity), spin: Scalar| Particle {
+        position: p.position - from + to,
+        velocity: (p.velocity - from_velocity) * spin + to_velocity,
+        ..p
+    };
+    heavy
+        .into_iter()
+        .map(|p| moved(p, (heavy_center, heavy_velocity), heavy_target, 1.0))
+        .chain(light.into_iter().map(|p| {
+            let p = moved(p, (light_center, light_velocity), light_target, light_spin);
+            match p.kind {
+                BodyKind::Normal => Particle {
+                    color: [1.0, 0.75, 0.55, 1.0],
+                    ..p
+                },
+                _ => p,
+            }
+        }))
+        .collect()
+}
+
+/// Two stars of equal `mass`, `separation` apart, orbiting their center of mass
+pub fn binary_star(
+    center: cgmath::Vector2<Scalar>,
+    separation: Scalar,
+    mass: Scalar,
+    g: Scalar,
+) -> Vec<Particle> {
+    let offset = cgmath::vec2(separation / 2.0, 0.0);
+    // Each star is pulled by the other one from twice its orbital radius, which is the
+    // same as orbiting a quarter of its mass sitting at the center of mass
+    let velocity = circular_velocity(offset, mass / 4.0, g);
+    vec![
+        body(center + offset, velocity, mass, 6.0, [1.0, 0.8, 0.4, 1.0]),
+        body(center - offset, -velocity, mass, 6.0, [0.5, 0.7, 1.0, 1.0]),
+    ]
+}
+
+/// A sun of `sun_mass` with six planets at increasing distances and random phases
+pub fn solar_system<R: Rng>(
+    rng: &mut R,
+    center: cgmath::Vector2<Scalar>,
+    sun_mass: Scalar,
+    g: Scalar,
+) -> Vec<Particle> {
+    // (orbital radius, mass, radius) of each planet, scaled to fit the world
+    let planets = [
+        (60.0, 2.0, 1.0),
+        (100.0, 5.0, 2.0),
+        (150.0, 6.0, 2.0),
+        (220.0, 3.0, 1.5),
+        (300.0, 40.0, 4.0),
+        (380.0, 30.0, 3.5),
+    ];
+
+    let mut particles = vec![body(
+        center,
+        cgmath::vec2(0.0, 0.0),
+        sun_mass,
+        10.0,
+        [1.0, 0.85, 0.3, 1.0],
+    )];
+    let mut enclosed_mass = sun_mass;
+    for &(r, mass, radius) in planets.iter() {
+        let angle: Scalar = rng.gen_range(0.0..2.0 * PI);
+        let offset = cgmath::vec2(angle.cos(), angle.sin()) * r;
+        let velocity = circular_velocity(offset, enclosed_mass, g);
+        particles.push(body(
+            center + offset,
+            velocity,
+            mass,
+            radius,
+            [0.6, 0.8, 1.0, 1.0],
+        ));
+        enclosed_mass += mass;
+    }
+    particles
+}
+
+/// 3D version of `galaxy_disk`. Stars are spread over a disk of the given `radius` in
+/// the xz plane, `thickness` thick along y, orbiting a heavy core at `center`.
+pub fn galaxy_disk_3d<R: Rng>(
+    rng: &mut R,
+    center: cgmath::Vector3<Scalar>,
+    count: usize,
+    radius: Scalar,
+    thickness: Scalar,
+    core_mass: Scalar,
+    g: Scalar,
+) -> Vec<Particle3D> {
+    let inner = radius / 8.0;
+    let mut orbits: Vec<(Scalar, Scalar, Scalar, Scalar)> = (0..count)
+        .map(|_| {
+            let r = rng.gen_range(inner..radius);
+            let angle = rng.gen_range(0.0..2.0 * PI);
+            let height = rng.gen_range(-thickness / 2.0..thickness / 2.0);
+            let mass = rng.gen_range(1.0..5.0);
+            (r, angle, height, mass)
+        })
+        .collect();
+    orbits.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+    let mut particles = vec![Particle3D::new(Particle3DProperties {
+        position: center,
+        mass: core_mass,
+        radius: 8.0,
+        velocity: cgmath::vec3(0.0, 0.0, 0.0),
+        color: [1.0, 0.9, 0.6, 1.0],
+    })];
+    let mut enclosed_mass = core_mass;
+    for (r, angle, height, mass) in orbits {
+        // Orbits are computed in the disk plane, the height only thickens the disk
+        let offset = cgmath::vec2(angle.cos(), angle.sin()) * r;
+        let velocity = circular_velocity(offset, enclosed_mass, g);
+        particles.push(Particle3D::new(Particle3DProperties {
+            position: center + cgmath::vec3(offset.x, height, offset.y),
+            mass,
+            radius: 1.0,
+            velocity: cgmath::vec3(velocity.x, 0.0, velocity.y),
+            color: [0.7, 0.8, 1.0, 1.0],
+        }));
+        enclosed_mass += mass;
+    }
+    particles
+}
+
+/// `count` light bodies spread uniformly over a disk of the given `radius` around
+/// `center`. If `rotating`, every body is put on a circular orbit around the center
+/// given the mass of the bodies inside its orbit, otherwise they start at rest.
+pub fn cluster<R: Rng>(
+    rng: &mut R,
+    center: cgmath::Vector2<Scalar>,
+    count: usize,
+    radius: Scalar,
+    rotating: bool,
+    g: Scalar,
+) -> Vec<Particle> {
+    let mut bodies: Vec<(Scalar, Scalar, Scalar)> = (0..count)
+        .map(|_| {
+            // The square root spreads the bodies evenly over the area of the disk
+            let r = radius * rng.gen_range(0.0..1.0 as Scalar).sqrt();
+            let angle = rng.gen_range(0.0..2.0 * PI);
+            let body_radius = rng.gen_range(0.5..1.5);
+            (r, angle, body_radius)
+        })
+        .collect();
+    bodies.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+    let mut enclosed_mass = 0.0;
+    bodies
+        .into_iter()
+        .map(|(r, angle, body_radius)| {
+            let offset = cgmath::vec2(angle.cos(), angle.sin()) * r;
+            let mass = 50.0 * body_radius;
+            let velocity = if rotating && enclosed_mass > 0.0 {
+                circular_velocity(offset, enclosed_mass, g)
+            } else {
+                cgmath::vec2(0.0, 0.0)
+            };
+            enclosed_mass += mass;
+            body(center + offset, velocity, mass, body_radius, color::WHITE)
+        })
+        .collect()
+}
+
+/// `count` stars of total `mass` sampled from `model` with a half-mass radius of `radius`
+/// around `center`. The simulation is flat, so the sphere is flattened onto the plane and
+/// the velocities are then scaled to bring it back into virial equilibrium (`2T = |U|`,
+/// ignoring softening) in the plane.
+pub fn star_cluster<R: Rng>(
+    rng: &mut R,
+    center: cgmath::Vector2<Scalar>,
+    count: usize,
+    mass: Scalar,
+    radius: Scalar,
+    model: ClusterModel,
+    g: Scalar,
+) -> Vec<Particle> {
+    let sample = model.sample(rng, count, mass, radius, g);
+    let positions: Vec<_> = sample.positions.iter().map(|x| x.truncate()).collect();
+    let velocities: Vec<_> = sample.velocities.iter().map(|v| v.truncate()).collect();
+    let star_mass = mass / count.max(1) as Scalar;
+
+    let kinetic: Scalar = velocities.iter().map(|v| v.magnitude2()).sum::<Scalar>() / 2.0;
+    let potential: Scalar = positions
+        .iter()
+        .enumerate()
+        .flat_map(|(i, a)| positions[i + 1..].iter().map(move |b| (a - b).magnitude()))
+        .filter(|&d| d > 0.0)
+        .map(|d| g * star_mass / d)
+        .sum();
+    let speed = if kinetic > 0.0 {
+        (potential / (2.0 * kinetic)).sqrt()
+    } else {
+        1.0
+    };
+    positions
+        .into_iter()
+        .zip(velocities)
+        .map(|(x, v)| body(center + x, v * speed, star_mass, 0.5, color::WHITE))
+        .collect()
+}
+
+/// 3D version of `star_cluster`, which keeps the sampled sphere as it is
+pub fn star_cluster_3d<R: Rng>(
+    rng: &mut R,
+    center: cgmath::Vector3<Scalar>,
+    count: usize,
+    mass: Scalar,
+    radius: Scalar,
+    model: ClusterModel,
+    g: Scalar,
+) -> Vec<Particle3D> {
+    let sample = model.sample(rng, count, mass, radius, g);
+    let star_mass = mass / count.max(1) as Scalar;
+    sample
+        .positions
+        .into_iter()
+        .zip(sample.velocities)
+        .map(|(x, v)| {
+            Particle3D::new(Particle3DProperties {
+                position: center + x,
+                mass: star_mass,
+                radius: 0.5,
+                velocity: v,
+                color: [1.0, 0.95, 0.85, 1.0],
+            })
+        })
+        .collect()
+}
+
+/// `count` tracers at rest spread uniformly over a disk of the given `radius` around
+/// `center`
+pub fn tracers<R: Rng>(
+    rng: &mut R,
+    center: cgmath::Vector2<Scalar>,
+    count: usize,
+    radius: Scalar,
+) -> Vec<Particle> {
+    (0..count)
+        .map(|_| {
+            let r = radius * rng.gen_range(0.0..1.0 as Scalar).sqrt();
+            let angle = rng.gen_range(0.0..2.0 * PI);
+            let offset = cgmath::vec2(angle.cos(), angle.sin()) * r;
+            Particle {
+                kind: BodyKind::Tracer,
+                ..body(
+                    center + offset,
+                    cgmath::vec2(0.0, 0.0),
+                    0.0,
+                    0.5,
+                    color::WHITE,
+                )
+            }
+        })
+        .collect()
+}
+
+/// `count` bodies scattered uniformly over the whole world
+pub fn random_cloud<R: Rng>(rng: &mut R, count: usize) -> Vec<Particle> {
+    (0..count)
+        .map(|_| {
+            let radius = rng.gen_range(1..4) as Scalar;
+            body(
+                cgmath::vec2(
+                    rng.gen_range(constants::MIN_X..constants::MAX_X),
+                    rng.gen_range(constants::MIN_Y..constants::MAX_Y),
+                ),
+                cgmath::vec2(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)),
+                50.0 * radius,
+                radius,
+                color::WHITE,
+            )
+        })
+        .collect()
+}
+
+/// `count` particles of unit mass scattered over a square `size` across around `center`,
+/// alternately carrying `charge` and `-charge`, with random velocities. Positive
+/// particles are red, negative ones blue.
+pub fn plasma<R: Rng>(
+    rng: &mut R,
+    center: cgmath::Vector2<Scalar>,
+    count: usize,
+    size: Scalar,
+    charge: Scalar,
+) -> Vec<Particle> {
+    (0..count)
+        .map(|i| {
+            let offset = cgmath::vec2(
+                rng.gen_range(-size / 2.0..size / 2.0),
+                rng.gen_range(-size / 2.0..size / 2.0),
+            );
+            let velocity = cgmath::vec2(rng.gen_range(-2.0..2.0), rng.gen_range(-2.0..2.0));
+            let (charge, color) = if i % 2 == 0 {
+                (charge, [1.0, 0.45, 0.35, 1.0])
+            } else {
+                (-charge, [0.4, 0.6, 1.0, 1.0])
+            };
+            Particle {
+                charge,
+                ..body(center + offset, velocity, 1.0, 1.5, color)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Integrator, ParticleStore, Simulation};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn it_gives_disk_stars_circular_velocities() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let center = cgmath::vec2(500.0, 500.0);
+        let particles = galaxy_disk(&mut rng, center, 50, 400.0, 20000.0, 1.0);
+        assert_eq!(particles.len(), 51);
+
+        // The innermost star only feels the core
+        let star = &particles[1];
+        let r = (star.position - center).magnitude();
+        let expected = (20000.0 / r).sqrt();
+        assert!((star.velocity.magnitude() - expected).abs() < 1e-3);
+        assert!((star.position - center).dot(star.velocity).abs() < 1e-2);
+    }
+
+    #[test]
+    fn it_keeps_the_binary_separation() {
+        let mut sim = Simulation::new(0.01, 0.5, 1.0, 0.0, Integrator::Leapfrog);
+        sim.add_particles(binary_star(cgmath::vec2(500.0, 500.0), 200.0, 5000.0, 1.0));
+        for _ in 0..1000 {
+            sim.advance();
+        }
+        let particles = sim.get_particles();
+        let d = (particles.positions()[0] - particles.positions()[1]).magnitude();
+        assert!((d - 200.0).abs() < 2.0, "separation {}", d);
+    }
+
+    #[test]
+    fn it_repeats_presets_for_the_same_seed() {
+        let generate = |seed| Preset::GalaxyDisk.generate(&mut StdRng::seed_from_u64(seed), 1.0);
+        let (a, b) = (generate(7), generate(7));
+        assert!(a
+            .iter()
+            .zip(&b)
+            .all(|(a, b)| a.position == b.position && a.velocity == b.velocity));
+        assert_ne!(a[1].position, generate(8)[1].position);
+    }
+
+    #[test]
+    fn it_spawns_clusters_inside_the_disk() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let center = cgmath::vec2(200.0, 300.0);
+        let still = cluster(&mut rng, center, 100, 50.0, false, 1.0);
+        assert_eq!(still.len(), 100);
+        assert!(still
+            .iter()
+            .all(|p| (p.position - center).magnitude() <= 50.0 && p.velocity.magnitude() == 0.0));
+
+        let spinning = cluster(&mut rng, center, 100, 50.0, true, 1.0);
+        // Everything but the innermost body goes around counterclockwise
+        assert!(spinning[1..].iter().all(|p| {
+            let offset = p.position - center;
+            offset.x * p.velocity.y - offset.y * p.velocity.x > 0.0
+        }));
+    }
+
+    #[test]
+    fn it_flattens_star_clusters_into_virial_equilibrium() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let center = cgmath::vec2(500.0, 500.0);
+        let g = 2.0;
+        let stars = star_cluster(
+            &mut rng,
+            center,
+            300,
+            3000.0,
+            50.0,
+            ClusterModel::Plummer,
+            g,
+        );
+        assert_eq!(stars.len(), 300);
+
+        let (mut kinetic, mut potential) = (0.0, 0.0);
+        for (i, a) in stars.iter().enumerate() {
+            kinetic += 0.5 * a.mass * a.velocity.magnitude2();
+            for b in &stars[i + 1..] {
+                potential -= g * a.mass * b.mass / (a.position - b.position).magnitude();
+            }
+        }
+        assert!((2.0 * kinetic / -potential - 1.0).abs() < 1e-3);
+        let stars: ParticleStore = stars.into_iter().collect();
+        let center_of_mass = stars.center_of_mass().unwrap();
+        assert!((center_of_mass - center).magnitude() < 1e-2);
+    }
+
+    #[test]
+    fn it_sends_galaxies_towards_each_other_around_their_center_of_mass() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let center = cgmath::vec2(500.0, 500.0);
+        let setup = GalaxyCollision {
+            stars: 100,
+            ..GalaxyCollision::default()
+        };
+        let particles = galaxy_collision(&mut rng, center, setup, 1.0);
+        assert_eq!(particles.len(), 1 + 100 + 1 + 50);
+        let (heavy, light) = particles.split_at(101);
+        assert_eq!(light[0].kind, BodyKind::BlackHole);
+        let barycenter = |galaxy: &[Particle]| {
+            let store: ParticleStore = galaxy.iter().cloned().collect();
+            let mass: Scalar = store.masses().iter().sum();
+            let momentum = galaxy
+                .iter()
+                .fold(cgmath::vec2(0.0, 0.0), |sum, p| sum + p.velocity * p.mass);
+            (store.center_of_mass().unwrap(), momentum / mass)
+        };
+        let (everything, at_rest) = barycenter(&particles);
+        assert!((everything - center).magnitude() < 1e-2);
+        assert!(at_rest.magnitude() < 1e-3);
+
+        // The galaxies are just bound and would miss by the impact parameter
+        let ((heavy_center, heavy_velocity), (light_center, light_velocity)) =
+            (barycenter(heavy), barycenter(light));
+        let (offset, velocity) = (light_center - heavy_center, light_velocity - heavy_velocity);
+        assert!((offset.magnitude() - setup.separation).abs() < 1e-2);
+        let mass: Scalar = particles.iter().map(|p| p.mass).sum();
+        let escape_speed = (2.0 * mass / setup.separation).sqrt();
+        assert!((velocity.magnitude() - escape_speed).abs() < 1e-3);
+        let miss = (offset.x * velocity.y - offset.y * velocity.x).abs() / velocity.magnitude();
+        assert!((miss - setup.impact_parameter).abs() < 1e-2);
+        assert!(velocity.dot(offset) < 0.0);
+    }
+
+    #[test]
+    fn it_fills_the_world_with_a_random_cloud() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let particles = random_cloud(&mut rng, 100);
+        assert_eq!(particles.len(), 100);
+        assert!(particles.iter().all(|p| {
+            (constants::MIN_X..constants::MAX_X).contains(&p.position.x)
+                && (constants::MIN_Y..constants::MAX_Y).contains(&p.position.y)
+        }));
+    }
+}
@@ -0,0 +1,47 @@
+use crate::primitives::scalar::{to_f32, Scalar};
+use serde::{Deserialize, Serialize};
+
+/// RGBA color with components in 0..1
+pub type Color = [f32; 4];
+
+pub const WHITE: Color = [1.0, 1.0, 1.0, 1.0];
+
+/// Colors used for the lowest and highest values of a mapped quantity
+const COLD: Color = [0.2, 0.4, 1.0, 1.0];
+const HOT: Color = [1.0, 0.35, 0.1, 1.0];
+
+/// Determines how the color of a particle instance is chosen
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ColorMapping {
+    /// Use the color stored on each particle
+    #[default]
+    Fixed,
+    /// Blend from cold to hot as mass goes from the lightest to the heaviest particle
+    Mass,
+    /// Blend from cold to hot as speed goes from the slowest to the fastest particle
+    Speed,
+    /// Blend from cold to hot as acceleration goes from the weakest to the strongest pull
+    Acceleration,
+}
+
+impl ColorMapping {
+    /// Cycles through the available mappings
+    pub fn next(self) -> Self {
+        match self {
+            ColorMapping::Fixed => ColorMapping::Mass,
+            ColorMapping::Mass => ColorMapping::Speed,
+            ColorMapping::Speed => ColorMapping::Acceleration,
+            ColorMapping::Acceleration => ColorMapping::Fixed,
+        }
+    }
+}
+
+/// Linearly interpolates between the cold and hot colors. `t` is clamped to 0..1.
+pub fn gradient(t: Scalar) -> Color {
+    let t = t.clamp(0.0, 1.0);
+    let mut color = COLD;
+    for (c, hot) in color.iter_mut().zip(HOT.iter()) {
+        *c += (hot - *c) * to_f32(t);
+    }
+    color
+}
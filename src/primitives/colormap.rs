@@ -0,0 +1,113 @@
+use crate::primitives::color::{self, Color};
+use crate::primitives::scalar::{to_f32, Scalar};
+use serde::{Deserialize, Serialize};
+
+/// Evenly spaced samples of matplotlib's viridis, from low to high
+const VIRIDIS: [u32; 10] = [
+    0x440154, 0x482878, 0x3e4989, 0x31688e, 0x26828e, 0x1f9e89, 0x35b779, 0x6ece58, 0xb5de2b,
+    0xfde725,
+];
+/// Evenly spaced samples of matplotlib's plasma, from low to high
+const PLASMA: [u32; 10] = [
+    0x0d0887, 0x46039f, 0x7201a8, 0x9c179e, 0xbd3786, 0xd8576b, 0xed7953, 0xfb9f3a, 0xfdca26,
+    0xf0f921,
+];
+
+/// Color scales mapped quantities are drawn with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Colormap {
+    /// Blend from blue to orange. The original gradient.
+    #[default]
+    Heat,
+    /// Perceptually uniform, dark purple to yellow. Readable with color blindness.
+    Viridis,
+    /// Perceptually uniform, dark blue to yellow through magenta
+    Plasma,
+}
+
+impl Colormap {
+    /// Cycles through the available colormaps
+    pub fn next(self) -> Self {
+        match self {
+            Colormap::Heat => Colormap::Viridis,
+            Colormap::Viridis => Colormap::Plasma,
+            Colormap::Plasma => Colormap::Heat,
+        }
+    }
+
+    /// Color at `t` along the scale. `t` is clamped to 0..1.
+    pub fn sample(self, t: Scalar) -> Color {
+        match self {
+            Colormap::Heat => color::gradient(t),
+            Colormap::Viridis => interpolate(&VIRIDIS, t),
+            Colormap::Plasma => interpolate(&PLASMA, t),
+        }
+    }
+}
+
+/// Linearly interpolates between the two samples of `table` around `t`
+fn interpolate(table: &[u32], t: Scalar) -> Color {
+    let x = to_f32(t.clamp(0.0, 1.0)) * (table.len() - 1) as f32;
+    let i = (x as usize).min(table.len() - 2);
+    let (a, b) = (rgb(table[i]), rgb(table[i + 1]));
+    let f = x - i as f32;
+    [
+        a[0] + (b[0] - a[0]) * f,
+        a[1] + (b[1] - a[1]) * f,
+        a[2] + (b[2] - a[2]) * f,
+        1.0,
+    ]
+}
+
+fn rgb(hex: u32) -> [f32; 3] {
+    [
+        ((hex >> 16) & 0xff) as f32 / 255.0,
+        ((hex >> 8) & 0xff) as f32 / 255.0,
+        (hex & 0xff) as f32 / 255.0,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_hits_the_samples_at_the_ends() {
+        assert_eq!(
+            Colormap::Viridis.sample(0.0),
+            [
+                0x44 as f32 / 255.0,
+                0x01 as f32 / 255.0,
+                0x54 as f32 / 255.0,
+                1.0
+            ]
+        );
+        assert_eq!(
+            Colormap::Plasma.sample(1.0),
+            [
+                0xf0 as f32 / 255.0,
+                0xf9 as f32 / 255.0,
+                0x21 as f32 / 255.0,
+                1.0
+            ]
+        );
+        assert_eq!(Colormap::Heat.sample(0.5), color::gradient(0.5));
+    }
+
+    #[test]
+    fn it_clamps_and_blends() {
+        for &map in &[Colormap::Viridis, Colormap::Plasma] {
+            assert_eq!(map.sample(-1.0), map.sample(0.0));
+            assert_eq!(map.sample(2.0), map.sample(1.0));
+            // Halfway between the first two samples
+            let (a, b, mid) = (
+                map.sample(0.0),
+                map.sample(1.0 / 9.0),
+                map.sample(0.5 / 9.0),
+            );
+            for c in 0..3 {
+                assert!((mid[c] - (a[c] + b[c]) / 2.0).abs() < 1e-5);
+            }
+        }
+    }
+}
@@ -1,57 +1,305 @@
+use crate::primitives::colormap::Colormap;
+use crate::primitives::scalar::{consts, to_f32, Scalar};
 use crate::primitives::vertex::Vertex;
 use cgmath;
-use std::f32::consts::PI;
-use winit::dpi::PhysicalSize;
 
 pub struct DrawBuffers {
     pub vertices: Vec<Vertex>,
     pub indices: Option<Vec<u16>>,
 }
 
-/// Creates vertices and indices describing a unit circle. The first value of the tuple
-/// are the vertices and the second value are the indices
-pub fn create_unit_circle(
+/// Standard "over" alpha blending. Used for the anti-aliased edges of circles.
+pub const ALPHA_BLEND: wgpu::BlendState = wgpu::BlendState {
+    src_factor: wgpu::BlendFactor::SrcAlpha,
+    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+    operation: wgpu::BlendOperation::Add,
+};
+
+/// Creates vertices and indices describing a quad spanning -1..1 on both axes. Circles
+/// are cut out of it in `circle.frag.wgsl` using the distance of each fragment from the
+/// center, so only 4 vertices are needed per particle.
+pub fn create_unit_quad(color: cgmath::Vector3<f32>) -> DrawBuffers {
+    let color: [f32; 3] = [color.x, color.y, color.z];
+    let vbuf = [[-1.0, -1.0], [1.0, -1.0], [1.0, 1.0], [-1.0, 1.0]]
+        .iter()
+        .map(|&position| Vertex { position, color })
+        .collect();
+
+    DrawBuffers {
+        vertices: vbuf,
+        indices: Some(vec![0, 1, 2, 0, 2, 3]),
+    }
+}
+
+/// Creates a line list describing an arrow pointing from `from` to `to`. The head is
+/// made of two short strokes angled back from the tip.
+pub fn create_arrow(
+    from: cgmath::Vector2<Scalar>,
+    to: cgmath::Vector2<Scalar>,
     color: cgmath::Vector3<f32>,
-    window_size: PhysicalSize<u32>,
 ) -> DrawBuffers {
+    use cgmath::InnerSpace;
+
     let color: [f32; 3] = [color.x, color.y, color.z];
-    let wx = window_size.width as f32;
-    let wy = window_size.height as f32;
-    let aspect_ratio = wx / wy;
-
-    // 360 vertices circumscribing the circle.
-    // One center vertex.
-    let num_vertices = 361;
-    let mut vbuf = Vec::with_capacity(num_vertices);
-    let mut ibuf: Vec<u16> = vec![0, 360, 1];
-
-    // Center vertex
-    vbuf.push(Vertex {
-        position: [0.0, 0.0],
+    let vertex = |p: cgmath::Vector2<Scalar>| Vertex {
+        position: [to_f32(p.x), to_f32(p.y)],
         color,
-    });
-
-    for i in 0..(num_vertices - 1) {
-        let rad = cgmath::Rad((i as f32) * (PI / 180.0));
-        let x_comp = cgmath::Angle::cos(rad);
-        let y_comp = cgmath::Angle::sin(rad);
-        let pos = cgmath::Vector2::new(x_comp, y_comp * aspect_ratio);
-        vbuf.push(Vertex {
-            position: [pos.x, pos.y],
+    };
+    let mut vbuf = vec![vertex(from), vertex(to)];
+
+    let shaft = to - from;
+    if shaft.magnitude2() > 0.0 {
+        // Head strokes are a fifth of the shaft and spread 25° to either side
+        let back = -shaft * 0.2;
+        for angle in [25.0 as Scalar, -25.0].iter() {
+            let (sin, cos) = angle.to_radians().sin_cos();
+            let stroke = cgmath::vec2(back.x * cos - back.y * sin, back.x * sin + back.y * cos);
+            vbuf.push(vertex(to));
+            vbuf.push(vertex(to + stroke));
+        }
+    }
+
+    DrawBuffers {
+        vertices: vbuf,
+        indices: None,
+    }
+}
+
+/// Which per particle vectors are drawn as arrows on top of the particles
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VectorOverlay {
+    #[default]
+    Off,
+    Velocity,
+    /// Velocity and acceleration
+    Both,
+}
+
+impl VectorOverlay {
+    /// Cycles through the available overlays
+    pub fn next(self) -> Self {
+        match self {
+            VectorOverlay::Off => VectorOverlay::Velocity,
+            VectorOverlay::Velocity => VectorOverlay::Both,
+            VectorOverlay::Both => VectorOverlay::Off,
+        }
+    }
+}
+
+/// Creates a line list with an arrow from every position along its vector multiplied by
+/// `scale`. Zero vectors are left out.
+pub fn create_vector_arrows(
+    positions: &[cgmath::Vector2<Scalar>],
+    vectors: &[cgmath::Vector2<Scalar>],
+    scale: Scalar,
+    color: cgmath::Vector3<f32>,
+) -> DrawBuffers {
+    let vbuf = positions
+        .iter()
+        .zip(vectors)
+        .filter(|(_, v)| v.x != 0.0 || v.y != 0.0)
+        .flat_map(|(&p, &v)| create_arrow(p, p + v * scale, color).vertices)
+        .collect();
+
+    DrawBuffers {
+        vertices: vbuf,
+        indices: None,
+    }
+}
+
+/// Creates a line list filling the rectangle between the corners `min` and `max` with
+/// `columns` vertical lines, colored along `colormap` from left to right. Used for the
+/// legend of mapped colors.
+pub fn create_color_bar(
+    min: cgmath::Vector2<Scalar>,
+    max: cgmath::Vector2<Scalar>,
+    columns: usize,
+    colormap: Colormap,
+) -> DrawBuffers {
+    let vbuf = (0..columns)
+        .flat_map(|i| {
+            let t = i as Scalar / (columns - 1).max(1) as Scalar;
+            let x = to_f32(min.x + (max.x - min.x) * t);
+            let [r, g, b, _] = colormap.sample(t);
+            [to_f32(min.y), to_f32(max.y)].map(|y| Vertex {
+                position: [x, y],
+                color: [r, g, b],
+            })
+        })
+        .collect();
+
+    DrawBuffers {
+        vertices: vbuf,
+        indices: None,
+    }
+}
+
+/// Creates a line list plotting `values` from left to right in the rectangle between the
+/// corners `min` and `max`, one every `1 / (slots - 1)` of its width with the last one on
+/// the right edge. `range` is mapped onto its height.
+pub fn create_line_graph(
+    values: &[Scalar],
+    min: cgmath::Vector2<Scalar>,
+    max: cgmath::Vector2<Scalar>,
+    slots: usize,
+    range: (Scalar, Scalar),
+    color: cgmath::Vector3<f32>,
+) -> DrawBuffers {
+    let color: [f32; 3] = [color.x, color.y, color.z];
+    let (low, high) = range;
+    let spacing = (max.x - min.x) / (slots.max(2) - 1) as Scalar;
+    let start = max.x - spacing * values.len().saturating_sub(1) as Scalar;
+    let point = |(i, &value): (usize, &Scalar)| {
+        let t = if high > low {
+            (value - low) / (high - low)
+        } else {
+            0.5
+        };
+        Vertex {
+            position: [
+                to_f32(start + spacing * i as Scalar),
+                to_f32(min.y + (max.y - min.y) * t),
+            ],
             color,
-        });
+        }
+    };
+    let points: Vec<_> = values.iter().enumerate().map(point).collect();
+    let vbuf = points
+        .windows(2)
+        .flat_map(|segment| [segment[0], segment[1]])
+        .collect();
+
+    DrawBuffers {
+        vertices: vbuf,
+        indices: None,
+    }
+}
+
+/// Creates a line list outlining the rectangle between the corners `min` and `max`
+pub fn create_rectangle(
+    min: cgmath::Vector2<Scalar>,
+    max: cgmath::Vector2<Scalar>,
+    color: cgmath::Vector3<f32>,
+) -> DrawBuffers {
+    let color: [f32; 3] = [color.x, color.y, color.z];
+    let corners = [
+        (min.x, min.y),
+        (max.x, min.y),
+        (max.x, max.y),
+        (min.x, max.y),
+    ];
+    let vbuf = (0..4)
+        .flat_map(|i| [corners[i], corners[(i + 1) % 4]])
+        .map(|(x, y)| Vertex {
+            position: [to_f32(x), to_f32(y)],
+            color,
+        })
+        .collect();
+
+    DrawBuffers {
+        vertices: vbuf,
+        indices: None,
+    }
+}
+
+/// Number of straight segments `create_ring` approximates a circle with
+pub const RING_SEGMENTS: usize = 32;
+
+/// Creates a line list describing a circle of `radius` around `center`, made of
+/// `RING_SEGMENTS` segments
+pub fn create_ring(
+    center: cgmath::Vector2<Scalar>,
+    radius: Scalar,
+    color: cgmath::Vector3<f32>,
+) -> DrawBuffers {
+    let color: [f32; 3] = [color.x, color.y, color.z];
+    let point = |i: usize| {
+        let angle = i as Scalar / RING_SEGMENTS as Scalar * consts::TAU;
+        let (sin, cos) = angle.sin_cos();
+        Vertex {
+            position: [
+                to_f32(center.x + radius * cos),
+                to_f32(center.y + radius * sin),
+            ],
+            color,
+        }
+    };
+    let vbuf = (0..RING_SEGMENTS)
+        .flat_map(|i| [point(i), point(i + 1)])
+        .collect();
+
+    DrawBuffers {
+        vertices: vbuf,
+        indices: None,
+    }
+}
+
+/// Creates a line list describing a cross of a horizontal and a vertical stroke, both
+/// `size` long, centered on `center`
+pub fn create_crosshair(
+    center: cgmath::Vector2<Scalar>,
+    size: Scalar,
+    color: cgmath::Vector3<f32>,
+) -> DrawBuffers {
+    let color: [f32; 3] = [color.x, color.y, color.z];
+    let half = size / 2.0;
+    let vbuf = [(-half, 0.0), (half, 0.0), (0.0, -half), (0.0, half)]
+        .iter()
+        .map(|&(x, y)| Vertex {
+            position: [to_f32(center.x + x), to_f32(center.y + y)],
+            color,
+        })
+        .collect();
+
+    DrawBuffers {
+        vertices: vbuf,
+        indices: None,
     }
+}
 
-    // Connect each vertex v[i] with v[0] (center vertex) and v[i-1] which produces
-    // a fan of triangles resulting in a 2D circle
-    for i in 1..num_vertices as u16 {
-        ibuf.push(0);
-        ibuf.push(i);
-        ibuf.push(i + 1);
+/// Creates a line list following `points` in dashes `dash` long, separated by gaps of the
+/// same length
+pub fn create_dashed_path(
+    points: &[cgmath::Vector2<Scalar>],
+    dash: Scalar,
+    color: cgmath::Vector3<f32>,
+) -> DrawBuffers {
+    use cgmath::InnerSpace;
+
+    let color: [f32; 3] = [color.x, color.y, color.z];
+    let vertex = |p: cgmath::Vector2<Scalar>| Vertex {
+        position: [to_f32(p.x), to_f32(p.y)],
+        color,
+    };
+    let mut vbuf = Vec::new();
+    // Whether the current stretch is a dash (or a gap) and how much of it is left
+    let (mut drawing, mut left) = (true, dash);
+    for segment in points.windows(2) {
+        let (mut from, to) = (segment[0], segment[1]);
+        let mut length = (to - from).magnitude();
+        if length == 0.0 {
+            continue;
+        }
+        let direction = (to - from) / length;
+        while length > 0.0 {
+            let step = left.min(length);
+            let next = from + direction * step;
+            if drawing {
+                vbuf.push(vertex(from));
+                vbuf.push(vertex(next));
+            }
+            from = next;
+            length -= step;
+            left -= step;
+            if left <= 0.0 {
+                drawing = !drawing;
+                left = dash;
+            }
+        }
     }
 
     DrawBuffers {
         vertices: vbuf,
-        indices: Some(ibuf),
+        indices: None,
     }
 }
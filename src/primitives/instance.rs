@@ -1,15 +1,53 @@
 use bytemuck::{Pod, Zeroable};
 use std::mem;
 
+use crate::primitives::{color::Color, particle::BodyKind};
+
+/// Color black holes are filled with, inside a ring of their own color
+pub const BLACK_HOLE_FILL: Color = [0.02, 0.02, 0.03, 1.0];
+/// Color tracers are drawn in, whatever their own color
+pub const TRACER_COLOR: Color = [0.3, 0.9, 1.0, 0.8];
+/// Radius of the dark fill of black holes relative to their own radius, which leaves
+/// the outer part as a ring
+pub const BLACK_HOLE_FILL_RADIUS: f32 = 0.7;
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 pub struct Instance {
     /// Denotes the center of the circle instance
     pub position: [f32; 2],
     pub radius: f32,
+    pub color: [f32; 4],
 }
 
 impl Instance {
+    /// Appends the instances a body of `kind` is drawn with: a single disk, for black
+    /// holes a disk of `color` with a smaller dark one on top that leaves only a ring, and
+    /// for tracers a disk of `TRACER_COLOR`
+    pub fn push_body(
+        instances: &mut Vec<Instance>,
+        kind: BodyKind,
+        position: [f32; 2],
+        radius: f32,
+        color: Color,
+    ) {
+        instances.push(Instance {
+            position,
+            radius,
+            color: match kind {
+                BodyKind::Tracer => TRACER_COLOR,
+                _ => color,
+            },
+        });
+        if kind == BodyKind::BlackHole {
+            instances.push(Instance {
+                position,
+                radius: radius * BLACK_HOLE_FILL_RADIUS,
+                color: BLACK_HOLE_FILL,
+            });
+        }
+    }
+
     pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
         wgpu::VertexBufferLayout {
             array_stride: mem::size_of::<Instance>() as wgpu::BufferAddress,
@@ -25,6 +63,11 @@ impl Instance {
                     offset: mem::size_of::<[f32; 2]>() as wgpu::BufferAddress,
                     format: wgpu::VertexFormat::Float,
                 },
+                wgpu::VertexAttribute {
+                    shader_location: 4,
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    format: wgpu::VertexFormat::Float4,
+                },
             ],
         }
     }
@@ -32,3 +75,42 @@ impl Instance {
 
 unsafe impl Pod for Instance {}
 unsafe impl Zeroable for Instance {}
+
+/// Instance of the 3D mode. Spheres are drawn as circles facing the camera.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Instance3D {
+    /// Denotes the center of the sphere instance
+    pub position: [f32; 3],
+    pub radius: f32,
+    pub color: [f32; 4],
+}
+
+impl Instance3D {
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<Instance3D>() as wgpu::BufferAddress,
+            step_mode: wgpu::InputStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    shader_location: 2,
+                    offset: 0,
+                    format: wgpu::VertexFormat::Float3,
+                },
+                wgpu::VertexAttribute {
+                    shader_location: 3,
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    format: wgpu::VertexFormat::Float,
+                },
+                wgpu::VertexAttribute {
+                    shader_location: 4,
+                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    format: wgpu::VertexFormat::Float4,
+                },
+            ],
+        }
+    }
+}
+
+unsafe impl Pod for Instance3D {}
+unsafe impl Zeroable for Instance3D {}
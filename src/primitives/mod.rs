@@ -1,5 +1,8 @@
+pub mod color;
+pub mod colormap;
 pub mod draw;
 pub mod instance;
 pub mod particle;
+pub mod particle3d;
 pub mod scalar;
 pub mod vertex;
@@ -1,49 +1,107 @@
+use crate::primitives::color::{self, Color};
 use crate::primitives::instance::Instance;
-use crate::utils;
-use crate::{constants, utils::generate_new_uuid};
+use crate::primitives::scalar::{to_f32, Scalar};
+use crate::store::ParticleId;
 use cgmath::num_traits::Pow;
-use uuid::Uuid;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 
 // Not too happy about the copy paste of properties but this will have to do
 // for now
 pub struct ParticleProperties {
-    pub position: cgmath::Vector2<f32>,
-    pub mass: f32,
-    pub radius: f32,
-    pub velocity: cgmath::Vector2<f32>,
-    pub acceleration: cgmath::Vector2<f32>,
+    pub position: cgmath::Vector2<Scalar>,
+    pub mass: Scalar,
+    pub radius: Scalar,
+    pub velocity: cgmath::Vector2<Scalar>,
+    pub acceleration: cgmath::Vector2<Scalar>,
+    pub color: Color,
 }
 
-#[derive(Clone, Copy, Debug)]
+/// What kind of body a particle stands for
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BodyKind {
+    /// Grows when it absorbs other particles and is drawn as a disk of its color
+    #[default]
+    Normal,
+    /// Swallows whatever it touches without ever changing its radius, conserving mass
+    /// and momentum whatever the merge model. Drawn as a dark disk inside a ring of its
+    /// color.
+    BlackHole,
+    /// Massless test particle that follows the field without pulling on anything or
+    /// colliding with anything, to show how the particles around it flow. Its mass and
+    /// charge are zeroed when it is added to a simulation. Drawn in `TRACER_COLOR`
+    /// whatever the color mapping.
+    Tracer,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Particle {
-    pub id: Uuid,
-    pub position: cgmath::Vector2<f32>,
-    pub mass: f32,
-    pub radius: f32,
-    pub velocity: cgmath::Vector2<f32>,
-    pub acceleration: cgmath::Vector2<f32>,
+    /// Handle of the particle in the store it was added to. Refers to nothing until
+    /// then.
+    pub id: ParticleId,
+    pub position: cgmath::Vector2<Scalar>,
+    pub mass: Scalar,
+    pub radius: Scalar,
+    pub velocity: cgmath::Vector2<Scalar>,
+    pub acceleration: cgmath::Vector2<Scalar>,
+    pub color: Color,
+    /// Pinned in place. A fixed particle still pulls on every other one but is never
+    /// moved, neither by forces nor by the world edges. False for new particles.
+    pub fixed: bool,
+    /// Label drawn next to the particle. None for new particles. Shared so that copies
+    /// of the particle, e.g. in the leaves of the tree, do not copy the text.
+    #[serde(default)]
+    pub name: Option<Arc<str>>,
+    /// Electric charge, only felt while the simulation includes electrostatics. Zero for
+    /// new particles.
+    #[serde(default)]
+    pub charge: Scalar,
+    #[serde(default)]
+    pub kind: BodyKind,
+    /// Simulated time since the particle was added or created by a collision. Zero for
+    /// new particles.
+    #[serde(default)]
+    pub age: Scalar,
+    /// Group the particle belongs to, which decides what it feels and collides with
+    /// (see `GroupMatrix`). 0 for new particles.
+    #[serde(default)]
+    pub group: u8,
 }
 
 impl Particle {
     pub fn empty() -> Self {
         Self {
-            id: generate_new_uuid(),
+            id: ParticleId::default(),
             position: cgmath::vec2(0.0, 0.0),
             mass: 0.0,
             radius: 0.0,
             velocity: cgmath::vec2(0.0, 0.0),
             acceleration: cgmath::vec2(0.0, 0.0),
+            color: color::WHITE,
+            fixed: false,
+            name: None,
+            charge: 0.0,
+            kind: BodyKind::Normal,
+            age: 0.0,
+            group: 0,
         }
     }
 
     pub fn new(properties: ParticleProperties) -> Self {
         Self {
-            id: generate_new_uuid(),
+            id: ParticleId::default(),
             position: properties.position,
             mass: properties.mass,
             radius: properties.radius,
             velocity: properties.velocity,
             acceleration: properties.acceleration,
+            color: properties.color,
+            fixed: false,
+            name: None,
+            charge: 0.0,
+            kind: BodyKind::Normal,
+            age: 0.0,
+            group: 0,
         }
     }
 
@@ -55,8 +113,8 @@ impl Particle {
 
         // Applying a square root and then comparing with radii_sum is
         // slightly more expensive
-        let dist = f32::pow(x2 - x1, 2) + f32::pow(y2 - y1, 2);
-        let radii_sum = f32::pow(self.radius + p2.radius, 2);
+        let dist = Scalar::pow(x2 - x1, 2) + Scalar::pow(y2 - y1, 2);
+        let radii_sum = Scalar::pow(self.radius + p2.radius, 2);
         dist <= radii_sum
     }
 
@@ -72,35 +130,12 @@ impl Particle {
     }
 
     /// Converts a particle into an `Instance` to be fed into
-    /// the instance buffer for the GPU
-    pub fn to_instance(self) -> Instance {
-        let mut inst = Instance {
-            position: [self.position.x, self.position.y],
-            radius: self.radius,
-        };
-        let (x, y) = (inst.position[0], inst.position[1]);
-        let ndc = utils::normalize_window_coordinates(&utils::ViewportTransformOptions {
-            window_pos: cgmath::Vector2::new(x as f64, y as f64),
-            xw: utils::MinMax::<f64> {
-                min: constants::MIN_X as f64,
-                max: constants::MAX_X as f64,
-            },
-            yw: utils::MinMax::<f64> {
-                min: constants::MIN_Y as f64,
-                max: constants::MAX_Y as f64,
-            },
-            xv: utils::MinMax::<f64> {
-                min: -1.0,
-                max: 1.0,
-            },
-            yv: utils::MinMax::<f64> {
-                min: -1.0,
-                max: 1.0,
-            },
-        });
-        inst.position = [ndc.x, ndc.y];
-        inst.radius /= constants::MAX_X / 2.0;
-
-        inst
+    /// the instance buffer for the GPU. Instances stay in world space.
+    pub fn to_instance(self, color: Color) -> Instance {
+        Instance {
+            position: [to_f32(self.position.x), to_f32(self.position.y)],
+            radius: to_f32(self.radius),
+            color,
+        }
     }
 }
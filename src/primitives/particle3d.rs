@@ -0,0 +1,65 @@
+use crate::primitives::color::{self, Color};
+use crate::primitives::instance::Instance3D;
+use crate::primitives::scalar::{to_f32, Scalar};
+use crate::utils::generate_new_uuid;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+pub struct Particle3DProperties {
+    pub position: cgmath::Vector3<Scalar>,
+    pub mass: Scalar,
+    pub radius: Scalar,
+    pub velocity: cgmath::Vector3<Scalar>,
+    pub color: Color,
+}
+
+/// A body of the 3D simulation mode. See `Particle` for the 2D counterpart.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Particle3D {
+    pub id: Uuid,
+    pub position: cgmath::Vector3<Scalar>,
+    pub mass: Scalar,
+    pub radius: Scalar,
+    pub velocity: cgmath::Vector3<Scalar>,
+    pub acceleration: cgmath::Vector3<Scalar>,
+    pub color: Color,
+}
+
+impl Particle3D {
+    pub fn empty() -> Self {
+        Self {
+            id: generate_new_uuid(),
+            position: cgmath::vec3(0.0, 0.0, 0.0),
+            mass: 0.0,
+            radius: 0.0,
+            velocity: cgmath::vec3(0.0, 0.0, 0.0),
+            acceleration: cgmath::vec3(0.0, 0.0, 0.0),
+            color: color::WHITE,
+        }
+    }
+
+    pub fn new(properties: Particle3DProperties) -> Self {
+        Self {
+            id: generate_new_uuid(),
+            position: properties.position,
+            mass: properties.mass,
+            radius: properties.radius,
+            velocity: properties.velocity,
+            acceleration: cgmath::vec3(0.0, 0.0, 0.0),
+            color: properties.color,
+        }
+    }
+
+    /// Converts a particle into an `Instance3D` to be fed into the instance buffer
+    pub fn to_instance(self) -> Instance3D {
+        Instance3D {
+            position: [
+                to_f32(self.position.x),
+                to_f32(self.position.y),
+                to_f32(self.position.z),
+            ],
+            radius: to_f32(self.radius),
+            color: self.color,
+        }
+    }
+}
@@ -1,2 +1,24 @@
-// Floating point precision
+// Floating point precision of the physics. Everything handed to the GPU stays f32, so
+// the `f64` feature only costs memory and a conversion when instances are built.
+#[cfg(not(feature = "f64"))]
 pub type Scalar = f32;
+#[cfg(feature = "f64")]
+pub type Scalar = f64;
+
+// Mathematical constants of the same precision
+#[cfg(not(feature = "f64"))]
+pub use std::f32::consts;
+#[cfg(feature = "f64")]
+pub use std::f64::consts;
+
+/// Narrows a physics value to the f32 everything on the GPU works with
+#[allow(clippy::unnecessary_cast)]
+pub fn to_f32(value: Scalar) -> f32 {
+    value as f32
+}
+
+/// Widens a value coming from the GPU to the precision of the physics
+#[allow(clippy::unnecessary_cast)]
+pub fn from_f32(value: f32) -> Scalar {
+    value as Scalar
+}
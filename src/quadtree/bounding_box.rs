@@ -12,6 +12,38 @@ pub struct QuadBoundingBox {
 }
 
 impl QuadBoundingBox {
+    /// Smallest square (plus a little padding) containing every point, or the default
+    /// world box if there are none. Used as the root of the quadtree so that particles
+    /// are never left out of it, however far they drift.
+    pub fn enclosing<I: IntoIterator<Item = cgmath::Vector2<Scalar>>>(points: I) -> Self {
+        let (min, max) = points.into_iter().fold(
+            (
+                cgmath::vec2(Scalar::INFINITY, Scalar::INFINITY),
+                cgmath::vec2(Scalar::NEG_INFINITY, Scalar::NEG_INFINITY),
+            ),
+            |(min, max), p| {
+                (
+                    cgmath::vec2(min.x.min(p.x), min.y.min(p.y)),
+                    cgmath::vec2(max.x.max(p.x), max.y.max(p.y)),
+                )
+            },
+        );
+        if min.x > max.x || min.y > max.y {
+            return Self::default();
+        }
+
+        // Nodes have to be square since the opening criterion only looks at their width.
+        // The padding keeps points on the far edges inside despite rounding.
+        let length = (max.x - min.x).max(max.y - min.y).max(1.0) * 1.01;
+        let (cx, cy) = ((min.x + max.x) / 2.0, (min.y + max.y) / 2.0);
+        Self {
+            min_x: cx - length / 2.0,
+            max_x: cx + length / 2.0,
+            min_y: cy - length / 2.0,
+            max_y: cy + length / 2.0,
+        }
+    }
+
     /// Gets the center of the x axis of the bounding box
     pub fn cx(&self) -> Scalar {
         (self.min_x + self.max_x) / 2.0
@@ -27,6 +59,14 @@ impl QuadBoundingBox {
         self.max_x - self.min_x
     }
 
+    /// Squared distance from `p` to the closest point of the bounding box. Zero if the
+    /// point lies inside.
+    pub fn distance2(&self, p: cgmath::Vector2<Scalar>) -> Scalar {
+        let dx = (self.min_x - p.x).max(0.0).max(p.x - self.max_x);
+        let dy = (self.min_y - p.y).max(0.0).max(p.y - self.max_y);
+        dx * dx + dy * dy
+    }
+
     /// If the point does not lie in this bounding box, `contains` will return false
     pub fn contains(&self, p: cgmath::Vector2<Scalar>) -> bool {
         let (px, py) = (p.x, p.y);
@@ -75,7 +115,7 @@ impl QuadBoundingBox {
                 min_y: self.min_y,
                 max_y: self.cy(),
             },
-            _ => self.clone(),
+            _ => *self,
         }
     }
 }
@@ -152,6 +192,19 @@ mod tests {
     }
 
     #[test]
+    fn it_encloses_points_in_a_square() {
+        let points = vec![cgmath::vec2(-500.0, 100.0), cgmath::vec2(2500.0, 400.0)];
+        let bb = QuadBoundingBox::enclosing(points.clone());
+        assert!(points.into_iter().all(|p| bb.contains(p)));
+        assert_eq!(bb.length(), bb.max_y - bb.min_y);
+        assert_eq!(
+            QuadBoundingBox::enclosing(vec![]),
+            QuadBoundingBox::default()
+        );
+    }
+
+    #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn it_checks_if_bb_contains_point() {
         assert_eq!(BB.contains(cgmath::vec2(1200.0, 600.0)), false);
         assert_eq!(BB.contains(cgmath::vec2(0.0, 600.0)), true);
@@ -1,2 +1,3 @@
 pub mod bounding_box;
+#[allow(clippy::module_inception)]
 pub mod quadtree;
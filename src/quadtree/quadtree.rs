@@ -1,171 +1,1177 @@
+use cgmath::InnerSpace;
+use instant::{Duration, Instant};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use crate::boundary::minimum_image;
 use crate::primitives::{particle::Particle, scalar::Scalar};
 use crate::quadtree::bounding_box::QuadBoundingBox;
+use crate::store::{ParticleId, ParticleStore};
 
-#[derive(Debug)]
-pub struct QuadTree {
+/// Index of a node inside the `QuadTree` arena
+pub type NodeId = usize;
+
+/// Index of the root node. The root always exists, even in an empty tree.
+pub const ROOT: NodeId = 0;
+
+/// Number of particles a leaf holds before it is split, unless set otherwise with
+/// `QuadTree::set_leaf_capacity`
+pub const DEFAULT_LEAF_CAPACITY: usize = 8;
+/// Depth past which leaves are no longer split, however many particles they hold. Keeps
+/// nearly coincident particles from subdividing the tree without end.
+pub const MAX_DEPTH: usize = 32;
+
+/// Marks particle slots that have no leaf in the tree
+const NO_NODE: NodeId = usize::MAX;
+/// Fraction of particles that may leave their leaf before `update_from` builds the tree
+/// from scratch rather than moving them one by one
+const MAX_MOVED_FRACTION: Scalar = 0.25;
+
+#[derive(Debug, Clone)]
+pub struct QuadNode {
     pub bounding_box: QuadBoundingBox,
+    /// Total mass and center of mass of all the particles below this node, or held by it
+    /// for leaves
     pub particle: Particle,
-    pub children: Vec<Option<Self>>,
+    /// Quadrupole moment `(Qxx, Qxy, Qyy)` of the particles below this node about its
+    /// center of mass, where `Qij = Σ m (3 si sj - |s|² δij)`. Zero until
+    /// `compute_quadrupoles` is called.
+    pub quadrupole: [Scalar; 3],
+    /// Charge of the particles below this node. Zero until `compute_charges` is called.
+    pub charge: NodeCharge,
+    pub children: [Option<NodeId>; 4],
+    /// Particles held by a leaf. No more than the leaf capacity of the tree, unless the
+    /// leaf lies at `MAX_DEPTH`. Empty for internal nodes.
+    pub bodies: Vec<Particle>,
 }
 
-impl QuadTree {
-    pub fn empty() -> Self {
+impl QuadNode {
+    fn new(particle: Particle, bounding_box: QuadBoundingBox) -> Self {
         Self {
-            particle: Particle::empty(),
-            bounding_box: QuadBoundingBox::default(),
-            children: vec![None, None, None, None],
-        }
-    }
-
-    pub fn from_points(points: Vec<Particle>) -> Self {
-        let mut qt = Self::empty();
-        for p in points {
-            qt.insert_particle(p);
+            bounding_box,
+            particle,
+            quadrupole: [0.0; 3],
+            charge: NodeCharge::ZERO,
+            children: [None; 4],
+            bodies: Vec::new(),
         }
-
-        qt
     }
 
-    /// Update center of mass.
+    /// Update center of mass. Massless particles leave it where it is.
     /// See https://en.wikipedia.org/wiki/Center_of_mass#A_system_of_particles
     pub fn update_cm(&mut self, x: Scalar, y: Scalar, m: Scalar) {
         let p = &mut self.particle;
         let total_mass = p.mass + m;
-        p.position.x = (p.mass * p.position.x + x * m) / total_mass;
-        p.position.y = (p.mass * p.position.y + y * m) / total_mass;
+        if total_mass > 0.0 {
+            p.position.x = (p.mass * p.position.x + x * m) / total_mass;
+            p.position.y = (p.mass * p.position.y + y * m) / total_mass;
+        }
         p.mass = total_mass;
     }
 
-    /// Adds a new child to the quad tree. Quadrant and child bounding box is determined based on x and y.
-    pub fn add_child(&mut self, particle: Particle) {
-        let quadrant = self.bounding_box.get_point_quadrant(particle.position);
-        self.children[quadrant] = Some(Self {
-            particle,
-            bounding_box: self.bounding_box.get_child_bb(quadrant),
-            children: vec![None, None, None, None],
-        });
+    /// Whether this node holds particles itself (rather than children)
+    pub fn is_leaf(&self) -> bool {
+        !self.is_subdivided() && !self.bodies.is_empty()
     }
 
-    // is_subdivided checks to see if the current quadtree has any child nodes. If it does, then it is already
+    // is_subdivided checks to see if the current node has any child nodes. If it does, then it is already
     // subdivided. If it doesn't, then it needs to be subdivided.
     pub fn is_subdivided(&self) -> bool {
-        for child in self.children.as_slice() {
-            if child.is_some() {
-                return true
+        self.children.iter().any(|child| child.is_some())
+    }
+}
+
+/// Barnes-Hut quadtree. Nodes live in a flat arena and refer to their children by index,
+/// so rebuilding the tree every frame with `rebuild` reuses the previous allocation.
+/// Leaves hold up to `leaf_capacity` particles before they are split.
+/// A tree built from a store with `rebuild_from` can also be brought up to date in place
+/// with `update_from` after the particles moved.
+#[derive(Debug, Clone)]
+pub struct QuadTree {
+    nodes: Vec<QuadNode>,
+    /// Parent of every node. The root is its own parent.
+    parents: Vec<NodeId>,
+    /// Leaf holding the particle of every slot of the store the tree was built from,
+    /// indexed by `ParticleId::slot`
+    leaves: Vec<NodeId>,
+    /// Whether `leaves` is kept up to date, which is what allows `update_from` to keep
+    /// the tree. Only trees built from a store are tracked.
+    tracked: bool,
+    /// Nodes `update_from` cut off from the tree. Their slots are only reclaimed by the
+    /// next full rebuild.
+    detached: usize,
+    leaf_capacity: usize,
+    /// Time the last `rebuild`, `rebuild_from` or `update_from` took
+    build_time: Duration,
+}
+
+/// Shape of a `QuadTree`, to tell whether it degenerates, e.g. into long chains of nodes
+/// around nearly coincident particles
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TreeStats {
+    /// Nodes in the tree, internal ones and leaves
+    pub nodes: usize,
+    pub leaves: usize,
+    /// Depth of the deepest node, the root being at depth 0
+    pub max_depth: usize,
+    /// Number of leaves holding each number of particles, indexed by that number. Only
+    /// leaves at `MAX_DEPTH` hold more than the leaf capacity.
+    pub occupancy: Vec<usize>,
+    /// Time the tree took to build or update the last time
+    pub build_time: Duration,
+}
+
+impl TreeStats {
+    /// Average number of particles per leaf
+    pub fn mean_occupancy(&self) -> Scalar {
+        let particles: usize = self.occupancy.iter().enumerate().map(|(n, l)| n * l).sum();
+        particles as Scalar / self.leaves.max(1) as Scalar
+    }
+}
+
+impl Default for QuadTree {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl QuadTree {
+    pub fn empty() -> Self {
+        let mut qt = Self {
+            nodes: Vec::new(),
+            parents: Vec::new(),
+            leaves: Vec::new(),
+            tracked: false,
+            detached: 0,
+            leaf_capacity: DEFAULT_LEAF_CAPACITY,
+            build_time: Duration::default(),
+        };
+        qt.clear();
+        qt
+    }
+
+    pub fn from_points(points: &[Particle]) -> Self {
+        let mut qt = Self::empty();
+        qt.rebuild(points);
+        qt
+    }
+
+    pub fn leaf_capacity(&self) -> usize {
+        self.leaf_capacity
+    }
+
+    /// Sets how many particles a leaf holds before it is split (at least 1). Larger
+    /// leaves make the tree shallower and cheaper to build, at the cost of summing more
+    /// particles directly during the walk. Takes effect when the tree is next built.
+    pub fn set_leaf_capacity(&mut self, capacity: usize) {
+        self.leaf_capacity = capacity.max(1);
+        // Leaves of an updated tree would keep their old size
+        self.tracked = false;
+    }
+
+    /// Removes every node except for an empty root while keeping the allocation around
+    pub fn clear(&mut self) {
+        self.clear_with_bounds(QuadBoundingBox::default());
+    }
+
+    fn clear_with_bounds(&mut self, bounding_box: QuadBoundingBox) {
+        self.nodes.clear();
+        self.nodes
+            .push(QuadNode::new(Particle::empty(), bounding_box));
+        self.parents.clear();
+        self.parents.push(ROOT);
+        self.leaves.clear();
+        self.tracked = false;
+        self.detached = 0;
+    }
+
+    /// Clears the tree and inserts `points` into it. The root is sized to fit all of
+    /// them, so the tree is not limited to the world box.
+    pub fn rebuild(&mut self, points: &[Particle]) {
+        let start = Instant::now();
+        self.clear_with_bounds(QuadBoundingBox::enclosing(
+            points.iter().map(|p| p.position),
+        ));
+        for p in points {
+            self.insert(p.clone());
+        }
+        self.build_time = start.elapsed();
+    }
+
+    /// Same as `rebuild` for the particles in a store
+    pub fn rebuild_from(&mut self, particles: &ParticleStore) {
+        let start = Instant::now();
+        self.clear_with_bounds(QuadBoundingBox::enclosing(
+            particles.positions().iter().copied(),
+        ));
+        self.tracked = true;
+        for p in particles.iter() {
+            self.insert(p);
+        }
+        self.build_time = start.elapsed();
+    }
+
+    /// Brings a tree built by `rebuild_from` up to date after the particles of the store
+    /// moved. Particles still inside their leaf only have it refreshed, the others are
+    /// taken out and inserted again, after which the mass and center of mass of every
+    /// node are summed up again. Most steps move few particles out of their leaf, so
+    /// this is cheaper than building the tree from scratch. Particles that were removed
+    /// from the store are taken out and new ones inserted the same way. Falls back to
+    /// `rebuild_from` when particles left the root or too many of them left their leaf.
+    pub fn update_from(&mut self, particles: &ParticleStore) {
+        let start = Instant::now();
+        self.update(particles);
+        self.build_time = start.elapsed();
+    }
+
+    fn update(&mut self, particles: &ParticleStore) {
+        let positions = particles.positions();
+        let root = self.nodes[ROOT].bounding_box;
+        if !self.tracked
+            || self.detached > self.nodes.len() / 2
+            || !positions.iter().all(|&p| root.contains(p))
+        {
+            self.rebuild_from(particles);
+            return;
+        }
+
+        // Particles that merged or were deleted since the last update
+        for slot in 0..self.leaves.len() {
+            let leaf = self.leaves[slot];
+            if leaf == NO_NODE {
+                continue;
+            }
+            let gone = self.nodes[leaf]
+                .bodies
+                .iter()
+                .position(|b| b.id.slot() == slot && !particles.contains(b.id));
+            if let Some(index) = gone {
+                self.remove(leaf, index);
+            }
+        }
+        let mut moved = Vec::new();
+        for (i, &id) in particles.ids().iter().enumerate() {
+            match self.find(id) {
+                Some((leaf, index)) if self.nodes[leaf].bounding_box.contains(positions[i]) => {
+                    let p = &mut self.nodes[leaf].bodies[index];
+                    p.position = positions[i];
+                    p.mass = particles.masses()[i];
+                    p.radius = particles.radii()[i];
+                    p.velocity = particles.velocities()[i];
+                    p.charge = particles.charges()[i];
+                }
+                _ => moved.push(i),
+            }
+        }
+        if moved.len() as Scalar > positions.len() as Scalar * MAX_MOVED_FRACTION {
+            self.rebuild_from(particles);
+            return;
+        }
+
+        for &i in &moved {
+            if let Some((leaf, index)) = self.find(particles.ids()[i]) {
+                self.remove(leaf, index);
+            }
+        }
+        for &i in &moved {
+            self.insert(particles.particle(i));
+        }
+        self.sum_up();
+    }
+
+    /// Leaf holding the particle behind `id` and its index among the particles of that
+    /// leaf, if the tree tracks it
+    fn find(&self, id: ParticleId) -> Option<(NodeId, usize)> {
+        let leaf = self
+            .leaves
+            .get(id.slot())
+            .copied()
+            .filter(|&l| l != NO_NODE)?;
+        let index = self.nodes[leaf].bodies.iter().position(|b| b.id == id)?;
+        Some((leaf, index))
+    }
+
+    /// Records that `leaf` now holds the particle behind `id`
+    fn track(&mut self, id: ParticleId, leaf: NodeId) {
+        if !self.tracked {
+            return;
+        }
+        let slot = id.slot();
+        if slot >= self.leaves.len() {
+            self.leaves.resize(slot + 1, NO_NODE);
+        }
+        self.leaves[slot] = leaf;
+    }
+
+    /// Takes the particle at `index` out of `leaf`, and the leaf out of the tree if it
+    /// was the last one in it
+    fn remove(&mut self, leaf: NodeId, index: usize) {
+        let slot = self.nodes[leaf].bodies.swap_remove(index).id.slot();
+        if self.leaves.get(slot) == Some(&leaf) {
+            self.leaves[slot] = NO_NODE;
+        }
+        if self.nodes[leaf].bodies.is_empty() {
+            self.detach(leaf);
+        }
+    }
+
+    /// Cuts an empty leaf off the tree, along with every ancestor left without children.
+    /// An empty root is reset so that the next insertion fills it again.
+    fn detach(&mut self, mut id: NodeId) {
+        while id != ROOT {
+            let parent = self.parents[id];
+            for child in self.nodes[parent].children.iter_mut() {
+                if *child == Some(id) {
+                    *child = None;
+                }
+            }
+            self.detached += 1;
+            if self.nodes[parent].is_subdivided() {
+                break;
+            }
+            id = parent;
+        }
+        if id == ROOT && !self.nodes[ROOT].is_subdivided() {
+            self.nodes[ROOT].particle = Particle::empty();
+        }
+    }
+
+    /// Recomputes the mass and center of mass of every node from its children, or from
+    /// its particles for leaves. Children always come after their parent in the arena,
+    /// so walking it backwards visits them first.
+    fn sum_up(&mut self) {
+        for id in (0..self.nodes.len()).rev() {
+            let node = &self.nodes[id];
+            let (mut mass, mut moment) = (0.0, cgmath::vec2(0.0, 0.0));
+            let children = node
+                .children
+                .iter()
+                .flatten()
+                .map(|&c| &self.nodes[c].particle);
+            for p in children.chain(&node.bodies) {
+                mass += p.mass;
+                moment += p.position * p.mass;
+            }
+            let p = &mut self.nodes[id].particle;
+            p.mass = mass;
+            if mass > 0.0 {
+                p.position = moment / mass;
+            }
+        }
+    }
+
+    /// Fills in the quadrupole moment of every node from those of its children (parallel
+    /// axis theorem), or from its particles for leaves. Children always come after their
+    /// parent in the arena, so walking it backwards visits them first.
+    pub fn compute_quadrupoles(&mut self) {
+        for id in (0..self.nodes.len()).rev() {
+            let node = &self.nodes[id];
+            let center = node.particle.position;
+            let children = node
+                .children
+                .iter()
+                .flatten()
+                .map(|&c| (&self.nodes[c].particle, self.nodes[c].quadrupole));
+            let bodies = node.bodies.iter().map(|p| (p, [0.0; 3]));
+            let mut q = [0.0; 3];
+            for (p, quadrupole) in children.chain(bodies) {
+                let s = p.position - center;
+                let m = p.mass;
+                q[0] += quadrupole[0] + m * (2.0 * s.x * s.x - s.y * s.y);
+                q[1] += quadrupole[1] + m * 3.0 * s.x * s.y;
+                q[2] += quadrupole[2] + m * (2.0 * s.y * s.y - s.x * s.x);
+            }
+            self.nodes[id].quadrupole = q;
+        }
+    }
+
+    /// Fills in the charge of every node from those of its children, or from its
+    /// particles for leaves. Children always come after their parent in the arena, so
+    /// walking it backwards visits them first.
+    pub fn compute_charges(&mut self) {
+        for id in (0..self.nodes.len()).rev() {
+            let node = &self.nodes[id];
+            let children = node
+                .children
+                .iter()
+                .flatten()
+                .map(|&c| self.nodes[c].charge);
+            let bodies = node.bodies.iter().map(NodeCharge::of);
+            let charge = children.chain(bodies).fold(NodeCharge::ZERO, |a, b| a + b);
+            self.nodes[id].charge = charge;
+        }
+    }
+
+    pub fn root(&self) -> &QuadNode {
+        &self.nodes[ROOT]
+    }
+
+    pub fn node(&self, id: NodeId) -> &QuadNode {
+        &self.nodes[id]
+    }
+
+    /// Number of nodes (internal and leaves) in the tree
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Returns true if no particle has been inserted into the tree
+    pub fn is_empty(&self) -> bool {
+        !self.root().is_subdivided() && self.root().bodies.is_empty()
+    }
+
+    /// Counts the nodes and leaves still in the tree and how deep and full they are.
+    /// Walks the whole tree, so it costs about as much as a single tree walk.
+    pub fn stats(&self) -> TreeStats {
+        let mut stats = TreeStats {
+            build_time: self.build_time,
+            ..TreeStats::default()
+        };
+        let mut stack = vec![(ROOT, 0)];
+        while let Some((id, depth)) = stack.pop() {
+            let node = &self.nodes[id];
+            stats.nodes += 1;
+            stats.max_depth = stats.max_depth.max(depth);
+            if node.is_leaf() {
+                let held = node.bodies.len();
+                if stats.occupancy.len() <= held {
+                    stats.occupancy.resize(held + 1, 0);
+                }
+                stats.occupancy[held] += 1;
+                stats.leaves += 1;
+            }
+            stack.extend(node.children.iter().flatten().map(|&c| (c, depth + 1)));
+        }
+        stats
+    }
+
+    /// Returns every particle whose center lies within `radius` of `center`, in no
+    /// particular order. Only nodes whose bounding box reaches into the circle are visited.
+    /// The results are copies, but their `id` leads back to the stored particle.
+    pub fn query_radius(&self, center: cgmath::Vector2<Scalar>, radius: Scalar) -> Vec<&Particle> {
+        let radius2 = radius * radius;
+        let mut found = Vec::new();
+        let mut stack = vec![ROOT];
+        while let Some(id) = stack.pop() {
+            let node = &self.nodes[id];
+            if node.bounding_box.distance2(center) > radius2 {
+                continue;
+            }
+            found.extend(
+                node.bodies
+                    .iter()
+                    .filter(|p| (p.position - center).magnitude2() <= radius2),
+            );
+            stack.extend(node.children.iter().flatten());
+        }
+        found
+    }
+
+    /// Returns the `k` particles closest to `point`, nearest first. Nodes are visited in
+    /// order of their distance to `point`, so the search stops as soon as no unvisited
+    /// node can hold anything closer than the particles found so far.
+    pub fn k_nearest(&self, point: cgmath::Vector2<Scalar>, k: usize) -> Vec<&Particle> {
+        let mut found = Vec::with_capacity(k);
+        if k == 0 {
+            return found;
+        }
+        let mut queue = BinaryHeap::new();
+        queue.push(Nearest {
+            distance2: self.nodes[ROOT].bounding_box.distance2(point),
+            id: ROOT,
+            body: None,
+        });
+        while let Some(Nearest { id, body, .. }) = queue.pop() {
+            let node = &self.nodes[id];
+            if let Some(index) = body {
+                // Nothing left in the queue can be closer than this particle
+                found.push(&node.bodies[index]);
+                if found.len() == k {
+                    break;
+                }
+                continue;
+            }
+            for (index, p) in node.bodies.iter().enumerate() {
+                queue.push(Nearest {
+                    distance2: (p.position - point).magnitude2(),
+                    id,
+                    body: Some(index),
+                });
+            }
+            for &child in node.children.iter().flatten() {
+                queue.push(Nearest {
+                    distance2: self.nodes[child].bounding_box.distance2(point),
+                    id: child,
+                    body: None,
+                });
             }
         }
-        false
+        found
+    }
+
+    /// Adds a new leaf holding `particle` to `parent` in the given quadrant and returns
+    /// its id
+    fn add_child(&mut self, parent: NodeId, quadrant: usize, particle: Particle) -> NodeId {
+        let bb = self.nodes[parent].bounding_box;
+        let id = self.nodes.len();
+        let mut node = QuadNode::new(particle.clone(), bb.get_child_bb(quadrant));
+        node.bodies.push(particle);
+        self.nodes.push(node);
+        self.parents.push(parent);
+        self.nodes[parent].children[quadrant] = Some(id);
+        id
     }
 
+    /// Inserts a single particle. Particles outside of the root bounding box are ignored,
+    /// use `rebuild` to fit the root around them.
     pub fn insert_particle(&mut self, particle: Particle) {
+        // The tree no longer matches the store it was built from
+        self.tracked = false;
+        self.insert(particle);
+    }
+
+    fn insert(&mut self, particle: Particle) {
         // In case we get a point that does not lie in our boundary
-        if !self.bounding_box.contains(particle.position) {
+        if !self.nodes[ROOT].bounding_box.contains(particle.position) {
             return;
         }
-        let p = &mut self.particle;
-        if p.mass == 0.0 {
-            *p = particle;
-            return;
+
+        let (x, y, m) = (particle.position.x, particle.position.y, particle.mass);
+        let (mut parent, mut depth) = (ROOT, 0);
+        while self.nodes[parent].is_subdivided() {
+            // First, update the center of mass of the parent node
+            self.nodes[parent].update_cm(x, y, m);
+            let quadrant = self.nodes[parent]
+                .bounding_box
+                .get_point_quadrant(particle.position);
+            depth += 1;
+            match self.nodes[parent].children[quadrant] {
+                Some(child) => parent = child,
+                None => {
+                    // The particle lands in an empty quadrant of an internal node
+                    let id = particle.id;
+                    let leaf = self.add_child(parent, quadrant, particle);
+                    self.track(id, leaf);
+                    return;
+                }
+            }
         }
 
-        let mut parent = self;
-        let mut bounding_box = parent.bounding_box;
-        let mut quadrant = bounding_box.get_point_quadrant(particle.position);
-
-        while let Some(_) = &mut parent.children[quadrant] {
-            // First, update the center of mass of the parent quadtree
-            parent.update_cm(particle.position.x, particle.position.y, particle.mass);
-            // Assign child as parent then update the bounding box and quadrant
-            parent = parent.children[quadrant].as_mut().unwrap();
-            bounding_box = parent.bounding_box;
-            quadrant = bounding_box.get_point_quadrant(particle.position);
-        }
-
-        // We're on a node that has had no subdivisions. However, this node already has a body inserted into it which means it is a
-        // leaf node. Each section must contain at most 1 body, hence we have to subdivide such that this invariant holds true.
-        if !parent.is_subdivided() {
-            // We're going to subdivide until the particle currently in this node and the particle to be inserted are in different
-            // sections.
-
-            // Parent properties before center of mass is updated. This data needs to be used
-            // when we reinsert the parent after the subdivison is complete.
-            let parent_particle = parent.particle;
-            let (_x, _y, _m) = (
-                parent_particle.position.x,
-                parent_particle.position.y,
-                parent_particle.mass,
-            );
-            parent.update_cm(particle.position.x, particle.position.y, particle.mass);
-            // Parent properties after center of mass is updated
-            let cm_parent_particle = parent.particle;
-            let bb = parent.bounding_box;
-            let mut pq = bb.get_point_quadrant(parent_particle.position);
+        // We're on a leaf (or the empty root). It takes the particle in and is split once
+        // it holds more than it may.
+        let node = &mut self.nodes[parent];
+        if node.bodies.is_empty() {
+            node.particle = particle.clone();
+        } else {
+            node.update_cm(x, y, m);
+        }
+        self.track(particle.id, parent);
+        self.nodes[parent].bodies.push(particle);
+        if self.nodes[parent].bodies.len() > self.leaf_capacity && depth < MAX_DEPTH {
+            self.split(parent, depth);
+        }
+    }
+
+    /// Moves the particles of an overfull leaf at `depth` down into new leaves, splitting
+    /// those again while they still hold too many (i.e. all of them landed in the same
+    /// quadrant) and `MAX_DEPTH` is not reached
+    fn split(&mut self, id: NodeId, depth: usize) {
+        let bodies = std::mem::take(&mut self.nodes[id].bodies);
+        let bb = self.nodes[id].bounding_box;
+        for p in bodies {
+            let quadrant = bb.get_point_quadrant(p.position);
+            let particle_id = p.id;
+            let leaf = match self.nodes[id].children[quadrant] {
+                Some(child) => {
+                    let node = &mut self.nodes[child];
+                    node.update_cm(p.position.x, p.position.y, p.mass);
+                    node.bodies.push(p);
+                    child
+                }
+                None => self.add_child(id, quadrant, p),
+            };
+            self.track(particle_id, leaf);
+        }
 
-            // While point quadrant and parent quadrant are the same, we keep subdividing until the sections are small enough
-            // such that they separate
-            while quadrant == pq {
-                parent.add_child(cm_parent_particle);
-                parent = parent.children[quadrant].as_mut().unwrap();
-                pq = parent
-                    .bounding_box
-                    .get_point_quadrant(parent_particle.position);
-                quadrant = parent.bounding_box.get_point_quadrant(particle.position);
+        if depth + 1 < MAX_DEPTH {
+            let children = self.nodes[id].children;
+            for &child in children.iter().flatten() {
+                if self.nodes[child].bodies.len() > self.leaf_capacity {
+                    self.split(child, depth + 1);
+                }
             }
+        }
+    }
+}
+
+/// Entry of the `k_nearest` search queue. Either a node that still has to be opened
+/// (ordered by the distance to its bounding box) or one of the particles of a leaf
+/// (ordered by its own distance). Ordered so that `BinaryHeap` pops the closest entry
+/// first.
+struct Nearest {
+    distance2: Scalar,
+    id: NodeId,
+    /// Index of the particle among those of leaf `id`
+    body: Option<usize>,
+}
+
+impl PartialEq for Nearest {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Nearest {}
 
-            parent.add_child(parent_particle);
+impl PartialOrd for Nearest {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Nearest {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.distance2.total_cmp(&self.distance2)
+    }
+}
+
+/// Charge of the particles below a tree node, split by sign. Each sign has its own center
+/// of charge, so a node holding both still pushes and pulls like its particles do from
+/// afar, even where they cancel out overall.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NodeCharge {
+    /// Sum of the positive charges and where they are centered
+    pub positive: (Scalar, cgmath::Vector2<Scalar>),
+    /// Sum of the negative charges (at most zero) and where they are centered
+    pub negative: (Scalar, cgmath::Vector2<Scalar>),
+}
+
+impl NodeCharge {
+    pub const ZERO: Self = Self {
+        positive: (0.0, cgmath::Vector2::new(0.0, 0.0)),
+        negative: (0.0, cgmath::Vector2::new(0.0, 0.0)),
+    };
+
+    /// Charge of a single particle
+    pub fn of(p: &Particle) -> Self {
+        let mut charge = Self::ZERO;
+        if p.charge > 0.0 {
+            charge.positive = (p.charge, p.position);
+        } else if p.charge < 0.0 {
+            charge.negative = (p.charge, p.position);
         }
+        charge
+    }
+}
+
+impl std::ops::Add for NodeCharge {
+    type Output = Self;
 
-        // We have reached our desired cell. Add a new subcell with this point.
-        parent.add_child(particle);
+    /// Both charges together
+    fn add(self, other: Self) -> Self {
+        fn combine(
+            (a, center_a): (Scalar, cgmath::Vector2<Scalar>),
+            (b, center_b): (Scalar, cgmath::Vector2<Scalar>),
+        ) -> (Scalar, cgmath::Vector2<Scalar>) {
+            let total = a + b;
+            if total == 0.0 {
+                (0.0, center_a)
+            } else {
+                (total, (center_a * a + center_b * b) / total)
+            }
+        }
+        Self {
+            positive: combine(self.positive, other.positive),
+            negative: combine(self.negative, other.negative),
+        }
     }
 }
 
+/// Mass returned by `QuadTreeIter`. Either a node standing in for every particle below
+/// it or a single particle of a leaf that was opened.
+#[derive(Debug, Clone, Copy)]
+pub struct Attractor<'a> {
+    /// The particle itself, or the total mass and center of mass of the node
+    pub particle: &'a Particle,
+    /// Quadrupole moment of the node. Zero for single particles.
+    pub quadrupole: &'a [Scalar; 3],
+    /// Charge of the node, as filled in by `QuadTree::compute_charges`, or of the
+    /// particle itself
+    pub charge: NodeCharge,
+}
+
+thread_local! {
+    /// Stacks of finished walks, handed on to the next walk on the same thread. Every
+    /// particle walks the tree every step, so this keeps the walks from allocating.
+    /// Walks nested inside each other each take their own.
+    static SPARE_STACKS: RefCell<Vec<Vec<NodeId>>> = const { RefCell::new(Vec::new()) };
+}
+
 pub struct QuadTreeIter<'a> {
     /// The point for which net force is being calculated
     p: cgmath::Vector2<Scalar>,
     /// Accuracy metric. If theta is zero, then this degenerates into a brute force sum (quadratic complexity)
     theta: Scalar,
-    stack: Vec<&'a QuadTree>,
+    tree: &'a QuadTree,
+    stack: Vec<NodeId>,
+    /// Particles of the last opened leaf that have not been returned yet
+    bodies: std::slice::Iter<'a, Particle>,
+    /// Size of the repeating cell when distances are measured to the nearest image
+    period: Option<cgmath::Vector2<Scalar>>,
 }
 
 impl<'a> QuadTreeIter<'a> {
-    pub fn new(p: cgmath::Vector2<Scalar>, theta: Scalar, root: &'a QuadTree) -> Self {
+    pub fn new(p: cgmath::Vector2<Scalar>, theta: Scalar, tree: &'a QuadTree) -> Self {
+        let mut stack = SPARE_STACKS
+            .with(|spare| spare.borrow_mut().pop())
+            .unwrap_or_default();
+        stack.push(ROOT);
         Self {
             p,
             theta,
-            stack: vec![root],
+            tree,
+            stack,
+            bodies: [].iter(),
+            period: None,
         }
     }
+
+    /// Same as `new` but nodes are opened based on their distance to the nearest periodic
+    /// image of `p`, for worlds that repeat every `period`
+    pub fn with_period(
+        p: cgmath::Vector2<Scalar>,
+        theta: Scalar,
+        tree: &'a QuadTree,
+        period: Option<cgmath::Vector2<Scalar>>,
+    ) -> Self {
+        let mut walk = Self::new(p, theta, tree);
+        walk.period = period;
+        walk
+    }
 }
 
 impl<'a> Iterator for QuadTreeIter<'a> {
-    type Item = &'a QuadTree;
+    type Item = Attractor<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        while !self.stack.is_empty() {
-            let node = self.stack.pop().unwrap();
-            let (x, y) = (node.particle.position.x, node.particle.position.y);
-            let s = node.bounding_box.length();
-            let d = f32::sqrt(f32::powi(x - self.p.x, 2) + f32::powi(y - self.p.y, 2));
-            if d == 0.0 {
-                continue;
+        loop {
+            // Particles of an opened leaf are summed one by one, except for the one at `p`
+            for particle in &mut self.bodies {
+                if minimum_image(particle.position - self.p, self.period).magnitude2() > 0.0 {
+                    return Some(Attractor {
+                        particle,
+                        quadrupole: &[0.0; 3],
+                        charge: NodeCharge::of(particle),
+                    });
+                }
             }
 
-            // If node is a leaf node or if the distance ratio between point and node is low enough
-            // (lower the distance ratio, the farther away the two points are in space), then approximate
-            // that particle by returning the internal node
-            if !node.is_subdivided() || (s / d) < self.theta {
-                return Some(node);
+            let node = self.tree.node(self.stack.pop()?);
+            let offset = minimum_image(node.particle.position - self.p, self.period);
+            let s = node.bounding_box.length();
+            let d = Scalar::sqrt(Scalar::powi(offset.x, 2) + Scalar::powi(offset.y, 2));
+
+            // If the distance ratio between point and node is low enough (lower the distance
+            // ratio, the farther away the two points are in space), then approximate the
+            // particles below it by returning the node
+            if d > 0.0 && (s / d) < self.theta {
+                return Some(Attractor {
+                    particle: &node.particle,
+                    quadrupole: &node.quadrupole,
+                    charge: node.charge,
+                });
             }
 
             // If node is not sufficiently far away (i.e s/d >= θ), then recurse into
-            // the node's children
-            for child in &node.children {
-                if let Some(node) = child {
-                    self.stack.push(node);
-                }
-            }
+            // the node's children or the particles of the leaf
+            self.stack.extend(node.children.iter().flatten());
+            self.bodies = node.bodies.iter();
+        }
+    }
+}
+
+impl Drop for QuadTreeIter<'_> {
+    fn drop(&mut self) {
+        let mut stack = std::mem::take(&mut self.stack);
+        stack.clear();
+        // Nothing to hand the stack on to while the thread shuts down
+        let _ = SPARE_STACKS.try_with(|spare| spare.borrow_mut().push(stack));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scattered;
+
+    fn particle_at(x: Scalar, y: Scalar, mass: Scalar) -> Particle {
+        Particle {
+            position: cgmath::vec2(x, y),
+            mass,
+            radius: 1.0,
+            ..Particle::empty()
+        }
+    }
+
+    #[test]
+    fn it_aggregates_mass_at_the_root() {
+        let points = vec![
+            particle_at(100.0, 100.0, 1.0),
+            particle_at(900.0, 100.0, 2.0),
+            particle_at(120.0, 110.0, 3.0),
+            particle_at(500.0, 900.0, 4.0),
+        ];
+        let qt = QuadTree::from_points(&points);
+
+        let root = qt.root();
+        assert_eq!(root.particle.mass, 10.0);
+        let cx = (100.0 + 1800.0 + 360.0 + 2000.0) / 10.0;
+        assert!((root.particle.position.x - cx).abs() < 1e-3);
+    }
+
+    #[test]
+    fn it_sums_quadrupole_moments_about_the_center_of_mass() {
+        let points = vec![
+            particle_at(100.0, 100.0, 1.0),
+            particle_at(900.0, 100.0, 2.0),
+            particle_at(120.0, 110.0, 3.0),
+            particle_at(500.0, 900.0, 4.0),
+            particle_at(510.0, 880.0, 5.0),
+        ];
+        let mut qt = QuadTree::from_points(&points);
+        qt.compute_quadrupoles();
+
+        let center = qt.root().particle.position;
+        let mut expected = [0.0; 3];
+        for p in &points {
+            let s = p.position - center;
+            expected[0] += p.mass * (2.0 * s.x * s.x - s.y * s.y);
+            expected[1] += p.mass * 3.0 * s.x * s.y;
+            expected[2] += p.mass * (2.0 * s.y * s.y - s.x * s.x);
+        }
+        for (q, e) in qt.root().quadrupole.iter().zip(&expected) {
+            assert!((q - e).abs() <= e.abs() * 1e-4, "{} != {}", q, e);
+        }
+    }
+
+    #[test]
+    fn it_keeps_charges_of_either_sign_apart() {
+        let charged = |x, y, charge| Particle {
+            charge,
+            ..particle_at(x, y, 1.0)
+        };
+        let points = vec![
+            charged(100.0, 100.0, 2.0),
+            charged(300.0, 100.0, 2.0),
+            charged(900.0, 900.0, -1.0),
+            charged(500.0, 500.0, 0.0),
+        ];
+        let mut qt = QuadTree::from_points(&points);
+        qt.compute_charges();
+
+        let charge = qt.root().charge;
+        assert_eq!(charge.positive, (4.0, cgmath::vec2(200.0, 100.0)));
+        assert_eq!(charge.negative, (-1.0, cgmath::vec2(900.0, 900.0)));
+    }
+
+    #[test]
+    fn it_includes_particles_outside_the_world() {
+        let points = vec![
+            particle_at(-5000.0, 100.0, 1.0),
+            particle_at(500.0, 500.0, 2.0),
+            particle_at(500.0, 25000.0, 3.0),
+        ];
+        let qt = QuadTree::from_points(&points);
+        assert_eq!(qt.root().particle.mass, 6.0);
+        let visited = QuadTreeIter::new(cgmath::vec2(0.0, 0.0), 0.0, &qt).count();
+        assert_eq!(visited, points.len());
+    }
+
+    #[test]
+    fn it_keeps_the_center_of_mass_finite_with_massless_particles() {
+        // Enough to split the leaves, with massless ones inserted first
+        let mut points: Vec<_> = (0..20)
+            .map(|i| particle_at(100.0 + 10.0 * i as Scalar, 100.0, 0.0))
+            .collect();
+        points.push(particle_at(300.0, 300.0, 4.0));
+        let qt = QuadTree::from_points(&points);
+        assert!(!qt.is_empty());
+        assert_eq!(qt.root().particle.mass, 4.0);
+        assert_eq!(qt.root().particle.position, cgmath::vec2(300.0, 300.0));
+        for node in QuadTreeIter::new(cgmath::vec2(900.0, 900.0), 0.5, &qt) {
+            assert!(node.particle.position.x.is_finite() && node.particle.position.y.is_finite());
         }
-        None
+
+        let massless = QuadTree::from_points(&points[..3]);
+        assert!(!massless.is_empty());
+        assert_eq!(massless.root().particle.mass, 0.0);
+    }
+
+    #[test]
+    fn it_queries_particles_within_a_radius() {
+        let points = vec![
+            particle_at(100.0, 100.0, 1.0),
+            particle_at(110.0, 100.0, 2.0),
+            particle_at(100.0, 125.0, 3.0),
+            particle_at(900.0, 900.0, 4.0),
+        ];
+        let qt = QuadTree::from_points(&points);
+        let mut masses: Vec<_> = qt
+            .query_radius(cgmath::vec2(100.0, 100.0), 20.0)
+            .iter()
+            .map(|p| p.mass)
+            .collect();
+        masses.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(masses, vec![1.0, 2.0]);
+        assert!(qt.query_radius(cgmath::vec2(500.0, 500.0), 10.0).is_empty());
+    }
+
+    #[test]
+    fn it_finds_the_k_nearest_particles_in_order() {
+        let points: Vec<_> = scattered(50).map(|p| particle_at(p.x, p.y, 1.0)).collect();
+        let qt = QuadTree::from_points(&points);
+        let point = cgmath::vec2(420.0, 380.0);
+
+        let mut expected: Vec<_> = points
+            .iter()
+            .map(|p| (p.position - point).magnitude2())
+            .collect();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let nearest: Vec<_> = qt
+            .k_nearest(point, 5)
+            .iter()
+            .map(|p| (p.position - point).magnitude2())
+            .collect();
+        assert_eq!(nearest, expected[..5].to_vec());
+        assert_eq!(qt.k_nearest(point, 100).len(), points.len());
+    }
+
+    #[test]
+    fn it_keeps_particle_handles_in_leaves() {
+        let store: ParticleStore = (0..10)
+            .map(|i| particle_at(100.0 * i as Scalar, 500.0, 1.0 + i as Scalar))
+            .collect();
+        let mut qt = QuadTree::empty();
+        qt.rebuild_from(&store);
+
+        let nearest = qt.k_nearest(cgmath::vec2(310.0, 500.0), 1)[0];
+        assert_eq!(store.get(nearest.id).map(|p| p.mass), Some(4.0));
+    }
+
+    #[test]
+    fn it_reuses_the_arena_on_rebuild() {
+        let points = vec![
+            particle_at(100.0, 100.0, 1.0),
+            particle_at(900.0, 900.0, 1.0),
+        ];
+        let mut qt = QuadTree::from_points(&points);
+        let len = qt.len();
+        qt.rebuild(&points);
+        assert_eq!(qt.len(), len);
+
+        qt.clear();
+        assert!(qt.is_empty());
+        assert_eq!(qt.len(), 1);
+    }
+
+    /// Root mass, center of mass and exact accelerations (theta 0) at a few points
+    fn summary(qt: &QuadTree) -> (Scalar, cgmath::Vector2<Scalar>, Vec<Scalar>) {
+        let probes = [(0.0, 0.0), (333.0, 777.0), (1200.0, -40.0)];
+        let pulls = probes
+            .iter()
+            .map(|&(x, y)| {
+                let p = cgmath::vec2(x, y);
+                QuadTreeIter::new(p, 0.0, qt)
+                    .map(|node| node.particle.mass / (node.particle.position - p).magnitude2())
+                    .sum()
+            })
+            .collect();
+        (qt.root().particle.mass, qt.root().particle.position, pulls)
+    }
+
+    fn assert_close(
+        a: (Scalar, cgmath::Vector2<Scalar>, Vec<Scalar>),
+        b: (Scalar, cgmath::Vector2<Scalar>, Vec<Scalar>),
+    ) {
+        assert!((a.0 - b.0).abs() < 1e-3, "{} != {}", a.0, b.0);
+        assert!((a.1 - b.1).magnitude() < 1e-2, "{:?} != {:?}", a.1, b.1);
+        for (x, y) in a.2.iter().zip(&b.2) {
+            assert!((x - y).abs() <= y.abs() * 1e-4, "{} != {}", x, y);
+        }
+    }
+
+    #[test]
+    fn it_updates_in_place_after_particles_moved() {
+        let mut store: ParticleStore = scattered(40)
+            .enumerate()
+            .map(|(i, p)| particle_at(p.x, p.y, 1.0 + (i % 3) as Scalar))
+            .collect();
+        let mut qt = QuadTree::empty();
+        // Every particle in a leaf of its own, so that moving one empties its leaf
+        qt.set_leaf_capacity(1);
+        qt.rebuild_from(&store);
+
+        // A few particles jump across the box, the rest barely move
+        for i in 0..store.len() {
+            let mut p = store.particle(i);
+            p.position += if i % 10 == 0 {
+                cgmath::vec2(400.0, -300.0)
+            } else {
+                cgmath::vec2(0.01, 0.02)
+            };
+            p.position.x = p.position.x.min(990.0);
+            p.position.y = p.position.y.max(10.0);
+            store.set(i, &p);
+        }
+        qt.update_from(&store);
+        assert!(qt.detached > 0, "the tree was rebuilt instead of updated");
+
+        let mut fresh = QuadTree::empty();
+        fresh.rebuild_from(&store);
+        assert_close(summary(&qt), summary(&fresh));
+        assert_eq!(
+            QuadTreeIter::new(cgmath::vec2(0.0, 0.0), 0.0, &qt).count(),
+            store.len()
+        );
+        let nearest = qt.k_nearest(store.positions()[10], 1)[0];
+        assert_eq!(nearest.id, store.ids()[10]);
+    }
+
+    #[test]
+    fn it_follows_particles_that_come_and_go() {
+        let mut store: ParticleStore = (0..10)
+            .map(|i| particle_at(100.0 * i as Scalar, 500.0, 1.0))
+            .collect();
+        let mut qt = QuadTree::empty();
+        qt.set_leaf_capacity(1);
+        qt.rebuild_from(&store);
+        let id = store.ids()[3];
+        store.remove(id);
+        // Reuses the slot of the removed particle
+        let added = store.insert(particle_at(310.0, 520.0, 2.0));
+        qt.update_from(&store);
+        assert!(qt.detached > 0, "the tree was rebuilt instead of updated");
+        assert_eq!(qt.root().particle.mass, 11.0);
+        let found = qt.k_nearest(cgmath::vec2(300.0, 500.0), 10);
+        assert_eq!(found.len(), 10);
+        assert_eq!(found[0].id, added);
+        assert!(found.iter().all(|p| p.id != id));
+
+        let mut fresh = QuadTree::empty();
+        fresh.rebuild_from(&store);
+        assert_close(summary(&qt), summary(&fresh));
+    }
+
+    #[test]
+    fn it_empties_the_root_when_the_last_particle_goes() {
+        let mut store: ParticleStore = vec![particle_at(500.0, 500.0, 1.0)].into_iter().collect();
+        let mut qt = QuadTree::empty();
+        qt.rebuild_from(&store);
+        store.remove(store.ids()[0]);
+        qt.update_from(&store);
+        assert!(qt.is_empty());
+    }
+
+    #[test]
+    fn it_stops_splitting_coincident_particles_at_the_max_depth() {
+        let mut points = vec![particle_at(900.0, 900.0, 1.0)];
+        points.extend((0..20).map(|_| particle_at(250.0, 250.0, 1.0)));
+        let qt = QuadTree::from_points(&points);
+        assert_eq!(qt.root().particle.mass, 21.0);
+        assert!(qt.len() <= 4 * MAX_DEPTH + 1);
+        let visited = QuadTreeIter::new(cgmath::vec2(0.0, 0.0), 0.0, &qt).count();
+        assert_eq!(visited, points.len());
+    }
+
+    #[test]
+    fn it_holds_several_particles_per_leaf() {
+        let mut store: ParticleStore = scattered(200).map(|p| particle_at(p.x, p.y, 1.0)).collect();
+        let mut qt = QuadTree::empty();
+        qt.set_leaf_capacity(4);
+        qt.rebuild_from(&store);
+        let mut single = QuadTree::empty();
+        single.set_leaf_capacity(1);
+        single.rebuild_from(&store);
+        assert!(qt.len() < single.len());
+        assert!((0..qt.len()).all(|id| qt.node(id).bodies.len() <= 4));
+        assert_close(summary(&qt), summary(&single));
+
+        for i in 0..store.len() {
+            let mut p = store.particle(i);
+            p.position += cgmath::vec2(0.5, -0.5);
+            store.set(i, &p);
+        }
+        qt.update_from(&store);
+        single.rebuild_from(&store);
+        assert_close(summary(&qt), summary(&single));
+        let nearest = qt.k_nearest(store.positions()[7], 1)[0];
+        assert_eq!(nearest.id, store.ids()[7]);
+    }
+
+    #[test]
+    fn it_counts_nodes_depth_and_leaf_occupancy() {
+        let points = vec![
+            particle_at(100.0, 100.0, 1.0),
+            particle_at(900.0, 100.0, 1.0),
+            particle_at(120.0, 110.0, 1.0),
+            particle_at(500.0, 900.0, 1.0),
+        ];
+        let mut qt = QuadTree::empty();
+        assert_eq!(qt.stats().nodes, 1);
+        assert_eq!(qt.stats().leaves, 0);
+        qt.set_leaf_capacity(1);
+        qt.rebuild(&points);
+        let stats = qt.stats();
+        assert_eq!(stats.leaves, 4);
+        assert_eq!(stats.occupancy, vec![0, 4]);
+        assert_eq!(stats.nodes, qt.len());
+        assert!(stats.max_depth >= 2);
+        assert_eq!(stats.mean_occupancy(), 1.0);
+
+        // Coincident particles pile up in a single leaf at the bottom of a long chain
+        let mut points = vec![particle_at(900.0, 900.0, 1.0)];
+        points.extend((0..20).map(|_| particle_at(250.0, 250.0, 1.0)));
+        qt.rebuild(&points);
+        let stats = qt.stats();
+        assert_eq!(stats.max_depth, MAX_DEPTH);
+        assert_eq!(stats.occupancy[20], 1);
+        assert_eq!(stats.leaves, 2);
+    }
+
+    #[test]
+    fn it_reuses_the_stacks_of_finished_walks() {
+        let points: Vec<Particle> = scattered(100).map(|p| particle_at(p.x, p.y, 1.0)).collect();
+        let qt = QuadTree::from_points(&points);
+        let walk = |p| QuadTreeIter::new(p, 0.5, &qt);
+        assert_eq!(
+            walk(cgmath::vec2(0.0, 0.0)).count(),
+            walk(cgmath::vec2(0.0, 0.0)).count()
+        );
+
+        let first = walk(cgmath::vec2(500.0, 500.0));
+        let capacity = first.stack.capacity();
+        let address = first.stack.as_ptr();
+        drop(first);
+        let second = walk(cgmath::vec2(100.0, 900.0));
+        assert_eq!(second.stack.as_ptr(), address);
+        assert_eq!(second.stack.capacity(), capacity);
+        // A walk started while another is still going gets a stack of its own
+        let nested = walk(cgmath::vec2(100.0, 900.0));
+        assert_ne!(nested.stack.as_ptr(), address);
+        assert_eq!(nested.count(), second.count());
+    }
+
+    #[test]
+    fn it_visits_every_leaf_when_theta_is_zero() {
+        let points = vec![
+            particle_at(100.0, 100.0, 1.0),
+            particle_at(900.0, 100.0, 1.0),
+            particle_at(120.0, 110.0, 1.0),
+            particle_at(500.0, 900.0, 1.0),
+        ];
+        let qt = QuadTree::from_points(&points);
+        let visited = QuadTreeIter::new(cgmath::vec2(0.0, 0.0), 0.0, &qt).count();
+        assert_eq!(visited, points.len());
     }
 }
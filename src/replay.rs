@@ -0,0 +1,167 @@
+use serde::{Deserialize, Serialize};
+use std::{fs::File, io, path::Path};
+
+use crate::primitives::{
+    color::Color,
+    instance::Instance,
+    particle::BodyKind,
+    scalar::{to_f32, Scalar},
+};
+use crate::simulation::{Simulation, SnapshotError};
+
+/// The part of a particle needed to draw it again later
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReplayParticle {
+    pub position: [f32; 2],
+    pub radius: f32,
+    pub color: Color,
+    pub kind: BodyKind,
+}
+
+/// State of every particle after a single simulation step
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReplayFrame {
+    /// Simulated time at which the frame was recorded
+    pub elapsed: Scalar,
+    pub particles: Vec<ReplayParticle>,
+}
+
+impl ReplayFrame {
+    pub fn from_simulation(sim: &Simulation) -> Self {
+        Self {
+            elapsed: sim.get_elapsed(),
+            particles: sim
+                .get_particles()
+                .iter()
+                .map(|p| ReplayParticle {
+                    position: [to_f32(p.position.x), to_f32(p.position.y)],
+                    radius: to_f32(p.radius),
+                    color: p.color,
+                    kind: p.kind,
+                })
+                .collect(),
+        }
+    }
+
+    /// Instances to render this frame with
+    pub fn get_instances(&self) -> Vec<Instance> {
+        let mut instances = Vec::with_capacity(self.particles.len());
+        for p in &self.particles {
+            Instance::push_body(&mut instances, p.kind, p.position, p.radius, p.color);
+        }
+        instances
+    }
+}
+
+/// Appends frames to a replay log. The log is a plain sequence of bincode encoded
+/// `ReplayFrame`s so recording never has to rewrite what was already written.
+pub struct ReplayRecorder {
+    writer: io::BufWriter<File>,
+    frames: usize,
+}
+
+impl ReplayRecorder {
+    /// Creates (or truncates) the log at `path`
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, SnapshotError> {
+        Ok(Self {
+            writer: io::BufWriter::new(File::create(path)?),
+            frames: 0,
+        })
+    }
+
+    /// Appends the current state of `sim` to the log
+    pub fn record(&mut self, sim: &Simulation) -> Result<(), SnapshotError> {
+        bincode::serialize_into(&mut self.writer, &ReplayFrame::from_simulation(sim))?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Number of frames recorded so far
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Writes any buffered frames to disk
+    pub fn finish(mut self) -> Result<(), SnapshotError> {
+        io::Write::flush(&mut self.writer)?;
+        Ok(())
+    }
+}
+
+/// A replay log loaded into memory so that it can be scrubbed through freely
+pub struct Replay {
+    frames: Vec<ReplayFrame>,
+}
+
+impl Replay {
+    /// Reads every frame of the log at `path`
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SnapshotError> {
+        let mut reader = io::BufReader::new(File::open(path)?);
+        let mut frames = Vec::new();
+        loop {
+            match bincode::deserialize_from(&mut reader) {
+                Ok(frame) => frames.push(frame),
+                // The log simply ends after the last complete frame
+                Err(e) => match *e {
+                    bincode::ErrorKind::Io(ref io) if io.kind() == io::ErrorKind::UnexpectedEof => {
+                        break
+                    }
+                    _ => return Err(e.into()),
+                },
+            }
+        }
+        Ok(Self { frames })
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn frame(&self, index: usize) -> Option<&ReplayFrame> {
+        self.frames.get(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitives::{color, particle::Particle, particle::ParticleProperties};
+    use crate::Integrator;
+    use uuid::Uuid;
+
+    #[test]
+    fn it_plays_back_recorded_frames() {
+        let mut sim = Simulation::new(0.05, 1.0, 1.0, 0.0, Integrator::Euler);
+        for &x in [400.0, 600.0].iter() {
+            sim.add_particle(Particle::new(ParticleProperties {
+                position: cgmath::vec2(x, 500.0),
+                mass: 100.0,
+                radius: 2.0,
+                velocity: cgmath::vec2(0.0, 0.0),
+                acceleration: cgmath::vec2(0.0, 0.0),
+                color: color::WHITE,
+            }));
+        }
+
+        let path = std::env::temp_dir().join(format!("nbody-{}.replay", Uuid::new_v4()));
+        let mut recorder = ReplayRecorder::create(&path).unwrap();
+        let mut expected = Vec::new();
+        for _ in 0..5 {
+            sim.advance();
+            recorder.record(&sim).unwrap();
+            expected.push(ReplayFrame::from_simulation(&sim));
+        }
+        assert_eq!(recorder.frames(), 5);
+        recorder.finish().unwrap();
+
+        let replay = Replay::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(replay.len(), 5);
+        assert_eq!(replay.frame(4), expected.last());
+        assert!(replay.frame(5).is_none());
+    }
+}
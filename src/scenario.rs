@@ -0,0 +1,514 @@
+use serde::{Deserialize, Serialize};
+use std::{fmt, fs, io, path::Path, sync::Arc};
+
+use crate::constants;
+use crate::evaporation::Evaporation;
+use crate::groups::GroupMatrix;
+use crate::potential::ExternalPotential;
+use crate::primitives::{
+    color::{self, Color},
+    particle::{BodyKind, Particle, ParticleProperties},
+    scalar::Scalar,
+};
+use crate::units::{Length, Mass, Time, UnitSystem};
+
+/// Initial conditions of a simulation, usually loaded from a RON file such as
+///
+/// ```ron
+/// Scenario(
+///     particles: [
+///         (position: (500.0, 500.0), mass: 10000.0, radius: 8.0),
+///         (position: (600.0, 500.0), velocity: (0.0, 10.0), mass: 10.0, radius: 2.0),
+///     ],
+///     potentials: [
+///         Plummer(center: (500.0, 500.0), mass: 50000.0, scale_radius: 80.0),
+///     ],
+/// )
+/// ```
+///
+/// Quantities are in simulation units unless `units` says otherwise.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Scenario {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub units: Option<ScenarioUnits>,
+    pub particles: Vec<ParticleDescription>,
+    /// Fixed fields the particles move in. None unless given.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub potentials: Vec<ExternalPotential>,
+    /// How particles lose mass and expire as they age, e.g.
+    /// `evaporation: Some((lifetime: Some(50.0), mass_loss_rate: 0.01, min_mass: 0.5))`.
+    /// Particles last forever unless given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub evaporation: Option<Evaporation>,
+    /// Which groups of particles feel and collide with which. Every group feels every
+    /// other one unless given.
+    #[serde(default)]
+    pub groups: GroupMatrix,
+    /// Rhai script run at the start of every step, e.g. to push on particles or spawn new
+    /// ones (see `StepScript`). Works in simulation units even when `units` is given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script: Option<String>,
+}
+
+/// Physical units the positions, velocities, masses and potentials of a scenario are
+/// given in, along with how large they appear in the simulation. Radii stay in world
+/// units since they are mostly there to be seen. For the solar system:
+///
+/// ```ron
+/// units: Some((length: Au, world_per_length: 250.0, time: Year, sim_per_time: 100.0, mass: SolarMass)),
+/// ```
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct ScenarioUnits {
+    pub length: Length,
+    /// World units one `length` spans
+    pub world_per_length: Scalar,
+    pub time: Time,
+    /// Units of simulated time one `time` lasts
+    pub sim_per_time: Scalar,
+    pub mass: Mass,
+    /// World position of the origin of the scenario's coordinates
+    #[serde(default = "default_origin")]
+    pub origin: (Scalar, Scalar),
+}
+
+fn default_origin() -> (Scalar, Scalar) {
+    (
+        (constants::MIN_X + constants::MAX_X) / 2.0,
+        (constants::MIN_Y + constants::MAX_Y) / 2.0,
+    )
+}
+
+impl ScenarioUnits {
+    pub fn unit_system(&self) -> UnitSystem {
+        UnitSystem::from_scales(
+            self.length,
+            self.world_per_length,
+            self.time,
+            self.sim_per_time,
+        )
+    }
+
+    fn position(&self, (x, y): (Scalar, Scalar)) -> (Scalar, Scalar) {
+        let units = self.unit_system();
+        (
+            self.origin.0 + units.to_sim_length(x, self.length),
+            self.origin.1 + units.to_sim_length(y, self.length),
+        )
+    }
+
+    fn length(&self, value: Scalar) -> Scalar {
+        self.unit_system().to_sim_length(value, self.length)
+    }
+
+    fn velocity(&self, (x, y): (Scalar, Scalar)) -> (Scalar, Scalar) {
+        let units = self.unit_system();
+        (
+            units.to_sim_velocity(x, self.length, self.time),
+            units.to_sim_velocity(y, self.length, self.time),
+        )
+    }
+
+    fn acceleration(&self, (x, y): (Scalar, Scalar)) -> (Scalar, Scalar) {
+        let units = self.unit_system();
+        (
+            units.to_sim_acceleration(x, self.length, self.time),
+            units.to_sim_acceleration(y, self.length, self.time),
+        )
+    }
+
+    fn mass(&self, value: Scalar) -> Scalar {
+        self.unit_system().to_sim_mass(value, self.mass)
+    }
+
+    /// Same evaporation rules in simulation units
+    fn evaporation(&self, evaporation: Evaporation) -> Evaporation {
+        let units = self.unit_system();
+        Evaporation {
+            lifetime: evaporation
+                .lifetime
+                .map(|lifetime| units.to_sim_time(lifetime, self.time)),
+            // A rate per unit of time
+            mass_loss_rate: evaporation.mass_loss_rate / units.to_sim_time(1.0, self.time),
+            min_mass: self.mass(evaporation.min_mass),
+        }
+    }
+
+    /// Same potential in simulation units
+    fn potential(&self, potential: ExternalPotential) -> ExternalPotential {
+        match potential {
+            ExternalPotential::PointMass { position, mass } => ExternalPotential::PointMass {
+                position: self.position(position),
+                mass: self.mass(mass),
+            },
+            ExternalPotential::Uniform { acceleration } => ExternalPotential::Uniform {
+                acceleration: self.acceleration(acceleration),
+            },
+            ExternalPotential::Plummer {
+                center,
+                mass,
+                scale_radius,
+            } => ExternalPotential::Plummer {
+                center: self.position(center),
+                mass: self.mass(mass),
+                scale_radius: self.length(scale_radius),
+            },
+            ExternalPotential::Nfw {
+                center,
+                mass,
+                scale_radius,
+            } => ExternalPotential::Nfw {
+                center: self.position(center),
+                mass: self.mass(mass),
+                scale_radius: self.length(scale_radius),
+            },
+        }
+    }
+}
+
+/// Initial state of a single particle. Velocity defaults to zero, color to white and
+/// particles are free to move unless `fixed` is set. A `name` is drawn next to the
+/// particle.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ParticleDescription {
+    pub position: (Scalar, Scalar),
+    #[serde(default)]
+    pub velocity: (Scalar, Scalar),
+    pub mass: Scalar,
+    pub radius: Scalar,
+    #[serde(default = "default_color")]
+    pub color: Color,
+    #[serde(default)]
+    pub fixed: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Electric charge, in simulation units even if the scenario has `units`
+    #[serde(default)]
+    pub charge: Scalar,
+    /// `BlackHole` swallows whatever it touches without growing
+    #[serde(default)]
+    pub kind: BodyKind,
+    /// Group the particle belongs to in `Scenario::groups`
+    #[serde(default)]
+    pub group: u8,
+}
+
+fn default_color() -> Color {
+    color::WHITE
+}
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(io::Error),
+    Parse(ron::Error),
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioError::Io(e) => write!(f, "could not read scenario: {}", e),
+            ScenarioError::Parse(e) => write!(f, "could not parse scenario: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+impl From<io::Error> for ScenarioError {
+    fn from(e: io::Error) -> Self {
+        ScenarioError::Io(e)
+    }
+}
+
+impl From<ron::Error> for ScenarioError {
+    fn from(e: ron::Error) -> Self {
+        ScenarioError::Parse(e)
+    }
+}
+
+impl Scenario {
+    /// Reads and parses a RON scenario file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ScenarioError> {
+        let contents = fs::read_to_string(path)?;
+        Self::from_ron_str(&contents)
+    }
+
+    pub fn from_ron_str(s: &str) -> Result<Self, ScenarioError> {
+        Ok(ron::de::from_str(s)?)
+    }
+
+    /// Scenario that starts out with `particles` as they are, in simulation units. Only
+    /// the particles are kept, not the potentials or rules of the simulation they come
+    /// from.
+    pub fn from_particles<I: IntoIterator<Item = Particle>>(particles: I) -> Self {
+        Self {
+            units: None,
+            particles: particles
+                .into_iter()
+                .map(|p| ParticleDescription {
+                    position: (p.position.x, p.position.y),
+                    velocity: (p.velocity.x, p.velocity.y),
+                    mass: p.mass,
+                    radius: p.radius,
+                    color: p.color,
+                    fixed: p.fixed,
+                    name: p.name.as_deref().map(str::to_string),
+                    charge: p.charge,
+                    kind: p.kind,
+                    group: p.group,
+                })
+                .collect(),
+            potentials: Vec::new(),
+            evaporation: None,
+            groups: GroupMatrix::default(),
+            script: None,
+        }
+    }
+
+    /// Formats this scenario the way `from_ron_str` reads it
+    pub fn to_ron_string(&self) -> Result<String, ScenarioError> {
+        Ok(ron::ser::to_string_pretty(
+            self,
+            ron::ser::PrettyConfig::new(),
+        )?)
+    }
+
+    /// Writes this scenario to a RON file that `load` reads back
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ScenarioError> {
+        fs::write(path, self.to_ron_string()?)?;
+        Ok(())
+    }
+
+    /// Creates the particles described by this scenario, in simulation units
+    pub fn to_particles(&self) -> Vec<Particle> {
+        self.particles
+            .iter()
+            .map(|d| {
+                let (position, velocity, mass) = match &self.units {
+                    Some(units) => (
+                        units.position(d.position),
+                        units.velocity(d.velocity),
+                        units.mass(d.mass),
+                    ),
+                    None => (d.position, d.velocity, d.mass),
+                };
+                Particle {
+                    fixed: d.fixed,
+                    name: d.name.as_deref().map(Arc::from),
+                    charge: d.charge,
+                    kind: d.kind,
+                    group: d.group,
+                    ..Particle::new(ParticleProperties {
+                        position: cgmath::vec2(position.0, position.1),
+                        mass,
+                        radius: d.radius,
+                        velocity: cgmath::vec2(velocity.0, velocity.1),
+                        acceleration: cgmath::vec2(0.0, 0.0),
+                        color: d.color,
+                    })
+                }
+            })
+            .collect()
+    }
+
+    /// The evaporation rules of this scenario, in simulation units
+    pub fn to_evaporation(&self) -> Option<Evaporation> {
+        match &self.units {
+            Some(units) => self.evaporation.map(|e| units.evaporation(e)),
+            None => self.evaporation,
+        }
+    }
+
+    /// The external potentials of this scenario, in simulation units
+    pub fn to_potentials(&self) -> Vec<ExternalPotential> {
+        match &self.units {
+            Some(units) => self
+                .potentials
+                .iter()
+                .map(|&potential| units.potential(potential))
+                .collect(),
+            None => self.potentials.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_a_scenario() {
+        let scenario = Scenario::from_ron_str(
+            "Scenario(particles: [
+                (position: (500.0, 500.0), mass: 1000.0, radius: 5.0, name: Some(\"Sun\")),
+                (position: (600.0, 500.0), velocity: (0.0, 3.0), mass: 1.0, radius: 1.0),
+                (position: (700.0, 500.0), mass: 1.0, radius: 1.0, fixed: true),
+            ])",
+        )
+        .unwrap();
+
+        let particles = scenario.to_particles();
+        assert_eq!(particles.len(), 3);
+        assert_eq!(particles[0].velocity, cgmath::vec2(0.0, 0.0));
+        assert_eq!(particles[1].velocity, cgmath::vec2(0.0, 3.0));
+        assert_eq!(particles[1].mass, 1.0);
+        assert!(!particles[1].fixed && particles[2].fixed);
+        assert_eq!(particles[0].name.as_deref(), Some("Sun"));
+        assert_eq!(particles[1].name, None);
+        assert!(scenario.potentials.is_empty());
+    }
+
+    #[test]
+    fn it_writes_particles_back_out() {
+        let mut sun = Particle::new(ParticleProperties {
+            position: cgmath::vec2(500.0, 500.0),
+            mass: 1000.0,
+            radius: 5.0,
+            velocity: cgmath::vec2(0.0, 0.0),
+            acceleration: cgmath::vec2(0.0, 0.0),
+            color: [1.0, 0.9, 0.2, 1.0],
+        });
+        sun.fixed = true;
+        sun.name = Some("Sun".into());
+        let mut planet = sun.clone();
+        planet.position = cgmath::vec2(600.0, 500.0);
+        planet.velocity = cgmath::vec2(0.0, 3.0);
+        planet.name = None;
+        planet.group = 1;
+
+        let text = Scenario::from_particles(vec![sun, planet])
+            .to_ron_string()
+            .unwrap();
+        let particles = Scenario::from_ron_str(&text).unwrap().to_particles();
+        assert_eq!(particles.len(), 2);
+        assert_eq!(particles[0].name.as_deref(), Some("Sun"));
+        assert!(particles[0].fixed);
+        assert_eq!(particles[0].color, [1.0, 0.9, 0.2, 1.0]);
+        assert_eq!(particles[1].velocity, cgmath::vec2(0.0, 3.0));
+        assert_eq!(particles[1].group, 1);
+    }
+
+    #[test]
+    fn it_parses_a_script() {
+        let scenario = Scenario::from_ron_str(
+            "Scenario(particles: [], script: Some(r#\"sim.add_particle(1.0, 2.0, 0.0, 0.0, 1.0, 1.0);\"#))",
+        )
+        .unwrap();
+        assert_eq!(
+            scenario.script.as_deref(),
+            Some("sim.add_particle(1.0, 2.0, 0.0, 0.0, 1.0, 1.0);")
+        );
+        let fountain = Scenario::load("scenarios/fountain.ron").unwrap();
+        assert!(fountain.script.is_some());
+        #[cfg(feature = "scripting")]
+        {
+            let script = crate::scripting::StepScript::new(fountain.script.as_ref().unwrap());
+            let particles = fountain.to_particles().into_iter().collect();
+            let effects = script.unwrap().run(0.0, &particles).unwrap();
+            assert_eq!(effects.spawned.len(), 1);
+        }
+    }
+
+    #[test]
+    fn it_parses_external_potentials() {
+        let scenario = Scenario::from_ron_str(
+            "Scenario(
+                particles: [],
+                potentials: [
+                    PointMass(position: (500.0, 500.0), mass: 1000.0),
+                    Uniform(acceleration: (0.0, -1.0)),
+                ],
+            )",
+        )
+        .unwrap();
+        assert_eq!(
+            scenario.potentials,
+            vec![
+                ExternalPotential::PointMass {
+                    position: (500.0, 500.0),
+                    mass: 1000.0,
+                },
+                ExternalPotential::Uniform {
+                    acceleration: (0.0, -1.0),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn it_parses_groups() {
+        let scenario = Scenario::from_ron_str(
+            "Scenario(
+                particles: [
+                    (position: (500.0, 500.0), mass: 1000.0, radius: 5.0),
+                    (position: (600.0, 500.0), mass: 1.0, radius: 1.0, group: 1),
+                ],
+                groups: (gravity: [[true, false], [true, false]], collisions: [[], [true, false]]),
+            )",
+        )
+        .unwrap();
+        let particles = scenario.to_particles();
+        assert_eq!((particles[0].group, particles[1].group), (0, 1));
+        assert!(scenario.groups.feels(1, 0) && !scenario.groups.feels(0, 1));
+        assert!(!scenario.groups.collide(1, 1) && scenario.groups.collide(0, 1));
+    }
+
+    #[test]
+    fn it_parses_bundled_scenarios() {
+        Scenario::from_ron_str(include_str!("../scenarios/binary.ron")).unwrap();
+        Scenario::from_ron_str(include_str!("../scenarios/halo.ron")).unwrap();
+        Scenario::from_ron_str(include_str!("../scenarios/solar_system.ron")).unwrap();
+    }
+
+    #[test]
+    fn it_converts_physical_units() {
+        let scenario = Scenario::from_ron_str(
+            "Scenario(
+                units: Some((
+                    length: Au, world_per_length: 100.0,
+                    time: Year, sim_per_time: 100.0,
+                    mass: SolarMass,
+                )),
+                particles: [
+                    (position: (0.0, 0.0), mass: 1.0, radius: 5.0),
+                    (position: (1.0, 0.0), velocity: (0.0, 6.2832), mass: 0.000003, radius: 1.0),
+                ],
+                potentials: [Plummer(center: (0.0, 0.0), mass: 1.0, scale_radius: 0.5)],
+                evaporation: Some((lifetime: Some(2.0), mass_loss_rate: 0.5, min_mass: 0.5)),
+            )",
+        )
+        .unwrap();
+
+        let particles = scenario.to_particles();
+        let (sun, earth) = (&particles[0], &particles[1]);
+        assert_eq!(sun.position, cgmath::vec2(500.0, 500.0));
+        assert!((earth.position.x - 600.0).abs() < 1e-3);
+        assert_eq!(earth.radius, 1.0);
+        // On a circular orbit once converted
+        let circular = (constants::SIM_G * sun.mass / 100.0).sqrt();
+        assert!((earth.velocity.y - circular).abs() < circular * 1e-3);
+
+        match scenario.to_potentials()[0] {
+            ExternalPotential::Plummer {
+                center,
+                mass,
+                scale_radius,
+            } => {
+                assert_eq!(center, (500.0, 500.0));
+                assert!((mass - sun.mass).abs() < sun.mass * 1e-6);
+                assert!((scale_radius - 50.0).abs() < 1e-3);
+            }
+            potential => panic!("{:?}", potential),
+        }
+
+        // Two years last 200 units of time, half a sun is half its mass
+        let evaporation = scenario.to_evaporation().unwrap();
+        assert!((evaporation.lifetime.unwrap() - 200.0).abs() < 1e-3);
+        assert!((evaporation.mass_loss_rate - 0.005).abs() < 1e-6);
+        assert!((evaporation.min_mass - sun.mass / 2.0).abs() < sun.mass * 1e-6);
+    }
+
+    #[test]
+    fn it_rejects_malformed_scenarios() {
+        assert!(Scenario::from_ron_str("Scenario(particles: [(mass: 1.0)])").is_err());
+    }
+}
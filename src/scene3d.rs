@@ -0,0 +1,270 @@
+use crate::camera::{OrbitCamera, OrbitCameraUniform};
+use crate::dynamic_buffer::DynamicBuffer;
+use nbody::{
+    constants,
+    integrator::Integrator,
+    presets,
+    primitives::scalar::{to_f32, Scalar},
+    primitives::{draw, draw::DrawBuffers, instance::Instance3D, vertex::Vertex},
+    Simulation3D,
+};
+use rand::{rngs::StdRng, SeedableRng};
+use wgpu::{
+    util::DeviceExt, PipelineLayoutDescriptor, RenderPassColorAttachmentDescriptor,
+    RenderPassDepthStencilAttachmentDescriptor, RenderPassDescriptor, RenderPipelineDescriptor,
+};
+use winit::dpi::PhysicalSize;
+
+const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+fn world_center() -> cgmath::Vector3<Scalar> {
+    cgmath::vec3(
+        (constants::MIN_X + constants::MAX_X) / 2.0,
+        (constants::MIN_Y + constants::MAX_Y) / 2.0,
+        (constants::MIN_Z + constants::MAX_Z) / 2.0,
+    )
+}
+
+/// 3D simulation mode. Owns a `Simulation3D` together with everything needed to draw it
+/// with a perspective orbit camera and a depth buffer.
+pub struct Scene3D {
+    pub sim: Simulation3D,
+    pub camera: OrbitCamera,
+    pipeline: wgpu::RenderPipeline,
+    camera_buffer: wgpu::Buffer,
+    camera_bind_group: wgpu::BindGroup,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    num_indices: u32,
+    instance_buffer: DynamicBuffer<Instance3D>,
+    depth_view: wgpu::TextureView,
+    /// Samples per pixel of the target and depth buffer
+    sample_count: u32,
+    rng: StdRng,
+}
+
+impl Scene3D {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        circle_module: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        window_size: PhysicalSize<u32>,
+        seed: u64,
+    ) -> Self {
+        let vx_module = device.create_shader_module(&include_wgsl!("shaders/sphere.vert.wgsl"));
+
+        let camera = OrbitCamera::new(world_center().map(to_f32), 1200.0, window_size);
+        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Orbit Camera Buffer"),
+            contents: bytemuck::bytes_of(&OrbitCameraUniform::from_camera(&camera)),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+        let camera_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Orbit Camera Bind Group Layout"),
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Orbit Camera Bind Group"),
+            layout: &camera_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            }],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Sphere Pipeline Layout"),
+            bind_group_layouts: &[&camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Sphere Pipeline"),
+            layout: Some(&pipeline_layout),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+                clamp_depth: false,
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..wgpu::MultisampleState::default()
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            fragment: Some(wgpu::FragmentState {
+                entry_point: "main",
+                module: circle_module,
+                targets: &[wgpu::ColorTargetState {
+                    alpha_blend: draw::ALPHA_BLEND,
+                    color_blend: draw::ALPHA_BLEND,
+                    write_mask: wgpu::ColorWrite::ALL,
+                    format,
+                }],
+            }),
+            vertex: wgpu::VertexState {
+                entry_point: "main",
+                module: &vx_module,
+                buffers: &[Vertex::desc(), Instance3D::desc()],
+            },
+        });
+
+        let DrawBuffers { vertices, indices } =
+            draw::create_unit_quad(cgmath::Vector3::new(1.0, 1.0, 1.0));
+        let indices = indices.unwrap();
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Sphere Vertex Buffer"),
+            contents: bytemuck::cast_slice(vertices.as_slice()),
+            usage: wgpu::BufferUsage::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Sphere Index Buffer"),
+            contents: bytemuck::cast_slice(indices.as_slice()),
+            usage: wgpu::BufferUsage::INDEX,
+        });
+
+        let mut sim = Simulation3D::new(
+            0.05,
+            1.0,
+            constants::SIM_G,
+            constants::SOFTENING,
+            Integrator::Leapfrog,
+        );
+        let mut rng = StdRng::seed_from_u64(seed);
+        sim.add_particles(Self::initial_particles(&mut rng, sim.get_g()));
+        let mut instance_buffer = DynamicBuffer::new(
+            device,
+            "Sphere Instance Buffer",
+            wgpu::BufferUsage::VERTEX,
+            sim.get_particles().len(),
+        );
+        instance_buffer.write(device, queue, &sim.get_instances());
+
+        Self {
+            sim,
+            camera,
+            pipeline,
+            camera_buffer,
+            camera_bind_group,
+            vertex_buffer,
+            index_buffer,
+            num_indices: indices.len() as u32,
+            instance_buffer,
+            depth_view: Self::create_depth_view(device, window_size, sample_count),
+            sample_count,
+            rng,
+        }
+    }
+
+    /// A thick rotating disk galaxy in the middle of the world cube
+    fn initial_particles(rng: &mut StdRng, g: Scalar) -> Vec<nbody::Particle3D> {
+        presets::galaxy_disk_3d(rng, world_center(), 1000, 400.0, 40.0, 20000.0, g)
+    }
+
+    fn create_depth_view(
+        device: &wgpu::Device,
+        window_size: PhysicalSize<u32>,
+        sample_count: u32,
+    ) -> wgpu::TextureView {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Depth Texture"),
+            size: wgpu::Extent3d {
+                width: window_size.width,
+                height: window_size.height,
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format: DEPTH_FORMAT,
+            usage: wgpu::TextureUsage::RENDER_ATTACHMENT,
+        });
+        texture.create_view(&wgpu::TextureViewDescriptor::default())
+    }
+
+    /// The depth buffer always has to match the size of the render target
+    pub fn resize(&mut self, device: &wgpu::Device, window_size: PhysicalSize<u32>) {
+        self.camera.resize(window_size);
+        self.depth_view = Self::create_depth_view(device, window_size, self.sample_count);
+    }
+
+    /// Uploads the current camera matrices. Must be called whenever the camera changes.
+    pub fn update_camera_buffer(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytemuck::bytes_of(&OrbitCameraUniform::from_camera(&self.camera)),
+        );
+    }
+
+    /// Starts over with a fresh disk galaxy
+    pub fn reset(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        self.sim.reset();
+        let particles = Self::initial_particles(&mut self.rng, self.sim.get_g());
+        self.sim.add_particles(particles);
+        self.update_instance_buffer(device, queue);
+    }
+
+    /// Advances the simulation by `steps` steps and uploads the new positions
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, steps: usize) {
+        for _ in 0..steps {
+            self.sim.advance();
+        }
+        self.update_instance_buffer(device, queue);
+    }
+
+    fn update_instance_buffer(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        self.instance_buffer
+            .write(device, queue, &self.sim.get_instances());
+    }
+
+    /// Clears `view` and draws every body as a camera facing circle. Multisampled views
+    /// are resolved into `resolve_target`.
+    pub fn draw(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        resolve_target: Option<&wgpu::TextureView>,
+        clear_color: wgpu::Color,
+    ) {
+        let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Sphere Pass"),
+            color_attachments: &[RenderPassColorAttachmentDescriptor {
+                attachment: view,
+                resolve_target,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(clear_color),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachmentDescriptor {
+                attachment: &self.depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, &self.camera_bind_group, &[]);
+        rpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        rpass.set_vertex_buffer(1, self.instance_buffer.buffer().slice(..));
+        rpass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        rpass.draw_indexed(0..self.num_indices, 0, 0..self.instance_buffer.len());
+    }
+}
@@ -0,0 +1,290 @@
+use rhai::{Engine, EvalAltResult, ParseError, Scope, AST, FLOAT, INT};
+use std::{convert::TryFrom, fmt};
+
+use crate::{
+    primitives::{
+        color,
+        particle::{Particle, ParticleProperties},
+        scalar::Scalar,
+    },
+    store::ParticleStore,
+};
+
+/// Operations a script may take in a single step before it is stopped. Scripts run while
+/// the physics is locked, so one that never ends would freeze the window. Enough for a
+/// few dozen operations on each of 100k particles.
+const MAX_OPERATIONS: u64 = 10_000_000;
+/// Deepest nesting of function calls a script may reach
+const MAX_CALL_LEVELS: usize = 32;
+
+/// Rhai script run once at the start of every step, after collisions and before the
+/// forces are computed. It sees the simulation as `sim`, with particles numbered from 0
+/// to `sim.count - 1`, and can push on them or spawn new ones:
+///
+/// ```rhai
+/// // Wind blowing everything to the right after the first 10 time units
+/// if sim.time > 10.0 {
+///     for i in 0..sim.count {
+///         sim.apply_force(i, 0.5 * sim.mass(i), 0.0);
+///     }
+/// }
+/// ```
+///
+/// `sim.x(i)`, `sim.y(i)`, `sim.vx(i)`, `sim.vy(i)` and `sim.mass(i)` read a particle,
+/// `sim.apply_force(i, fx, fy)` pushes on it for the coming step and
+/// `sim.add_particle(x, y, vx, vy, mass, radius)` adds a particle. Everything is in simulation
+/// units, and numbers passed to these functions must be floats. A script that takes more
+/// than `MAX_OPERATIONS` operations in a step fails like any other.
+pub struct StepScript {
+    engine: Engine,
+    ast: AST,
+}
+
+/// What a script did in a step
+#[derive(Clone, Debug, Default)]
+pub struct ScriptEffects {
+    /// Acceleration from the forces the script applied to each particle, by dense index
+    pub accelerations: Vec<cgmath::Vector2<Scalar>>,
+    /// Particles the script spawned
+    pub spawned: Vec<Particle>,
+}
+
+#[derive(Debug)]
+pub enum ScriptError {
+    Parse(ParseError),
+    Run(Box<EvalAltResult>),
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptError::Parse(e) => write!(f, "could not parse script: {}", e),
+            ScriptError::Run(e) => write!(f, "script failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+/// The simulation as a script sees it. Holds a copy of the particles, since scripts
+/// can only hold on to values they own.
+#[derive(Clone)]
+struct Context {
+    time: Scalar,
+    positions: Vec<cgmath::Vector2<Scalar>>,
+    velocities: Vec<cgmath::Vector2<Scalar>>,
+    masses: Vec<Scalar>,
+    effects: ScriptEffects,
+}
+
+/// What functions called by scripts return, so that they can fail the script
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+impl Context {
+    fn index(&self, i: INT) -> ScriptResult<usize> {
+        usize::try_from(i)
+            .ok()
+            .filter(|&i| i < self.masses.len())
+            .ok_or_else(|| format!("there is no particle {}", i).into())
+    }
+}
+
+impl StepScript {
+    pub fn new(source: &str) -> Result<Self, ScriptError> {
+        let mut engine = Engine::new();
+        engine
+            .set_max_operations(MAX_OPERATIONS)
+            .set_max_call_levels(MAX_CALL_LEVELS)
+            .register_type_with_name::<Context>("Simulation")
+            .register_get("time", |c: &mut Context| to_float(c.time))
+            .register_get("count", |c: &mut Context| c.masses.len() as INT)
+            .register_fn("x", |c: &mut Context, i: INT| -> ScriptResult<FLOAT> {
+                Ok(to_float(c.positions[c.index(i)?].x))
+            })
+            .register_fn("y", |c: &mut Context, i: INT| -> ScriptResult<FLOAT> {
+                Ok(to_float(c.positions[c.index(i)?].y))
+            })
+            .register_fn("vx", |c: &mut Context, i: INT| -> ScriptResult<FLOAT> {
+                Ok(to_float(c.velocities[c.index(i)?].x))
+            })
+            .register_fn("vy", |c: &mut Context, i: INT| -> ScriptResult<FLOAT> {
+                Ok(to_float(c.velocities[c.index(i)?].y))
+            })
+            .register_fn("mass", |c: &mut Context, i: INT| -> ScriptResult<FLOAT> {
+                Ok(to_float(c.masses[c.index(i)?]))
+            })
+            .register_fn(
+                "apply_force",
+                |c: &mut Context, i: INT, fx: FLOAT, fy: FLOAT| -> ScriptResult<()> {
+                    let i = c.index(i)?;
+                    // Massless particles are test particles, which forces do not move
+                    if c.masses[i] > 0.0 {
+                        let force = cgmath::vec2(from_float(fx), from_float(fy));
+                        c.effects.accelerations[i] += force / c.masses[i];
+                    }
+                    Ok(())
+                },
+            )
+            .register_fn(
+                "add_particle",
+                |c: &mut Context,
+                 x: FLOAT,
+                 y: FLOAT,
+                 vx: FLOAT,
+                 vy: FLOAT,
+                 m: FLOAT,
+                 r: FLOAT|
+                 -> ScriptResult<()> {
+                    if !(x.is_finite() && y.is_finite() && vx.is_finite() && vy.is_finite()) {
+                        return Err("particles need a finite position and velocity".into());
+                    }
+                    // Also rules out NaN, which fails every comparison
+                    if !(m > 0.0 && m.is_finite() && r > 0.0 && r.is_finite()) {
+                        return Err("particles need a positive, finite mass and radius".into());
+                    }
+                    c.effects.spawned.push(Particle::new(ParticleProperties {
+                        position: cgmath::vec2(from_float(x), from_float(y)),
+                        mass: from_float(m),
+                        radius: from_float(r),
+                        velocity: cgmath::vec2(from_float(vx), from_float(vy)),
+                        acceleration: cgmath::vec2(0.0, 0.0),
+                        color: color::WHITE,
+                    }));
+                    Ok(())
+                },
+            );
+        let ast = engine.compile(source).map_err(ScriptError::Parse)?;
+        Ok(Self { engine, ast })
+    }
+
+    /// Runs the script on `particles` at simulated time `time`
+    pub fn run(
+        &self,
+        time: Scalar,
+        particles: &ParticleStore,
+    ) -> Result<ScriptEffects, ScriptError> {
+        let context = Context {
+            time,
+            positions: particles.positions().to_vec(),
+            velocities: particles.velocities().to_vec(),
+            masses: particles.masses().to_vec(),
+            effects: ScriptEffects {
+                accelerations: vec![cgmath::vec2(0.0, 0.0); particles.len()],
+                spawned: Vec::new(),
+            },
+        };
+        let mut scope = Scope::new();
+        scope.push("sim", context);
+        self.engine
+            .run_ast_with_scope(&mut scope, &self.ast)
+            .map_err(ScriptError::Run)?;
+        let context: Context = scope
+            .remove("sim")
+            .ok_or_else(|| ScriptError::Run("the script replaced `sim`".into()))?;
+        Ok(context.effects)
+    }
+}
+
+impl fmt::Debug for StepScript {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StepScript").finish_non_exhaustive()
+    }
+}
+
+/// Widens a physics value to the floats of Rhai
+#[allow(clippy::unnecessary_cast)]
+fn to_float(value: Scalar) -> FLOAT {
+    value as FLOAT
+}
+
+/// Narrows a float coming from Rhai to the precision of the physics
+#[allow(clippy::unnecessary_cast)]
+fn from_float(value: FLOAT) -> Scalar {
+    value as Scalar
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn particles() -> ParticleStore {
+        vec![
+            Particle {
+                position: cgmath::vec2(100.0, 200.0),
+                mass: 2.0,
+                ..Particle::empty()
+            },
+            Particle {
+                position: cgmath::vec2(300.0, 400.0),
+                mass: 4.0,
+                ..Particle::empty()
+            },
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    #[test]
+    fn it_lets_scripts_push_on_and_spawn_particles() {
+        let script = StepScript::new(
+            "for i in 0..sim.count {
+                 sim.apply_force(i, sim.time * sim.mass(i), 0.0);
+             }
+             if sim.x(1) > 250.0 {
+                 sim.add_particle(sim.x(0), sim.y(0), 0.0, 1.0, 3.0, 1.5);
+             }",
+        )
+        .unwrap();
+        let effects = script.run(2.0, &particles()).unwrap();
+        assert_eq!(
+            effects.accelerations,
+            vec![cgmath::vec2(2.0, 0.0), cgmath::vec2(2.0, 0.0)]
+        );
+        assert_eq!(effects.spawned.len(), 1);
+        let spawned = &effects.spawned[0];
+        assert_eq!(spawned.position, cgmath::vec2(100.0, 200.0));
+        assert_eq!(spawned.velocity, cgmath::vec2(0.0, 1.0));
+        assert_eq!((spawned.mass, spawned.radius), (3.0, 1.5));
+    }
+
+    #[test]
+    fn it_reports_broken_scripts() {
+        assert!(matches!(
+            StepScript::new("sim.add_particle(("),
+            Err(ScriptError::Parse(_))
+        ));
+        let script = StepScript::new("sim.mass(2);").unwrap();
+        assert!(matches!(
+            script.run(0.0, &particles()),
+            Err(ScriptError::Run(_))
+        ));
+    }
+
+    #[test]
+    fn it_stops_runaway_scripts() {
+        for source in &["loop {}", "fn f(n) { f(n + 1) } f(0);"] {
+            let script = StepScript::new(source).unwrap();
+            assert!(matches!(
+                script.run(0.0, &particles()),
+                Err(ScriptError::Run(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn it_refuses_to_spawn_invalid_particles() {
+        for args in &[
+            "1.0 / 0.0, 0.0, 0.0, 0.0, 1.0, 1.0",
+            "0.0, 0.0, 0.0, 0.0, 0.0, 1.0",
+            "0.0, 0.0, 0.0, 0.0, 1.0, -1.0",
+            "0.0, 0.0, 0.0, 0.0, 0.0 / 0.0, 1.0",
+        ] {
+            let script = StepScript::new(&format!("sim.add_particle({});", args)).unwrap();
+            assert!(
+                matches!(script.run(0.0, &particles()), Err(ScriptError::Run(_))),
+                "{}",
+                args
+            );
+        }
+    }
+}
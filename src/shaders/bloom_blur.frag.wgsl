@@ -0,0 +1,27 @@
+[[location(0)]] var<in> v_uv: vec2<f32>;
+[[location(0)]] var<out> f_color: vec4<f32>;
+
+// Laid out like `BloomParams` in bloom.rs
+[[block]]
+struct Params {
+  step: vec2<f32>;
+  threshold: f32;
+  intensity: f32;
+};
+[[group(0), binding(0)]] var t_source: texture_2d<f32>;
+[[group(0), binding(1)]] var s_source: sampler;
+[[group(0), binding(2)]] var<uniform> params: Params;
+
+// One direction of a 9 tap gaussian blur. Sampling between two texels lets the
+// linear filter blend them, so 5 samples are enough.
+[[stage(fragment)]]
+fn main() {
+  const near: vec2<f32> = params.step * 1.3846153846;
+  const far: vec2<f32> = params.step * 3.2307692308;
+  var color: vec4<f32> = textureSample(t_source, s_source, v_uv) * 0.2270270270;
+  color = color + textureSample(t_source, s_source, v_uv + near) * 0.3162162162;
+  color = color + textureSample(t_source, s_source, v_uv - near) * 0.3162162162;
+  color = color + textureSample(t_source, s_source, v_uv + far) * 0.0702702703;
+  color = color + textureSample(t_source, s_source, v_uv - far) * 0.0702702703;
+  f_color = vec4<f32>(color.xyz, 1.0);
+}
@@ -0,0 +1,21 @@
+[[location(0)]] var<in> v_uv: vec2<f32>;
+[[location(0)]] var<out> f_color: vec4<f32>;
+
+// Laid out like `BloomParams` in bloom.rs
+[[block]]
+struct Params {
+  step: vec2<f32>;
+  threshold: f32;
+  intensity: f32;
+};
+[[group(0), binding(0)]] var t_source: texture_2d<f32>;
+[[group(0), binding(1)]] var s_source: sampler;
+[[group(0), binding(2)]] var<uniform> params: Params;
+[[group(0), binding(3)]] var t_bloom: texture_2d<f32>;
+
+[[stage(fragment)]]
+fn main() {
+  const scene: vec4<f32> = textureSample(t_source, s_source, v_uv);
+  const bloom: vec4<f32> = textureSample(t_bloom, s_source, v_uv);
+  f_color = vec4<f32>(scene.xyz + bloom.xyz * params.intensity, 1.0);
+}
@@ -0,0 +1,23 @@
+[[location(0)]] var<in> v_uv: vec2<f32>;
+[[location(0)]] var<out> f_color: vec4<f32>;
+
+// Laid out like `BloomParams` in bloom.rs
+[[block]]
+struct Params {
+  step: vec2<f32>;
+  threshold: f32;
+  intensity: f32;
+};
+[[group(0), binding(0)]] var t_source: texture_2d<f32>;
+[[group(0), binding(1)]] var s_source: sampler;
+[[group(0), binding(2)]] var<uniform> params: Params;
+
+[[stage(fragment)]]
+fn main() {
+  const color: vec4<f32> = textureSample(t_source, s_source, v_uv);
+  // Keep only the part of the brightest channel above the threshold, without
+  // changing the hue
+  const brightness: f32 = max(max(color.x, color.y), color.z);
+  const excess: f32 = max(brightness - params.threshold, 0.0);
+  f_color = vec4<f32>(color.xyz * (excess / max(brightness, 0.0001)), 1.0);
+}
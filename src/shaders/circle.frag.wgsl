@@ -0,0 +1,17 @@
+[[location(0)]] var<in> v_color: vec4<f32>;
+// Position within the unit quad. The circle is everything within a distance of 1.
+[[location(1)]] var<in> v_local: vec2<f32>;
+// Size of a pixel in the same units as v_local. Passed in by the vertex shader
+// since naga cannot translate derivatives such as fwidth yet.
+[[location(2)]] var<in> v_pixel: f32;
+[[location(0)]] var<out> f_color: vec4<f32>;
+
+[[stage(fragment)]]
+fn main() {
+  // Fade out over roughly one pixel around the edge
+  const coverage: f32 = clamp((1.0 - length(v_local)) / v_pixel + 0.5, 0.0, 1.0);
+  if (coverage <= 0.0) {
+    discard;
+  }
+  f_color = vec4<f32>(v_color.xyz, v_color.w * coverage);
+}
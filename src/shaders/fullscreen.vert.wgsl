@@ -0,0 +1,10 @@
+[[location(0)]] var<in> a_position: vec2<f32>;
+[[location(0)]] var<out> v_uv: vec2<f32>;
+[[builtin(position)]] var<out> out_position: vec4<f32>;
+
+[[stage(vertex)]]
+fn main() {
+  // A single triangle covering the whole screen. Texture coordinates grow downwards.
+  v_uv = vec2<f32>(0.5, -0.5) * a_position + vec2<f32>(0.5, 0.5);
+  out_position = vec4<f32>(a_position, 0.0, 1.0);
+}
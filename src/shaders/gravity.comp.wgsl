@@ -0,0 +1,63 @@
+// Brute force pairwise gravity. Naga does not support workgroup shared memory
+// yet, so every invocation reads all bodies straight from the storage buffer.
+
+[[block]]
+struct Params {
+  num_bodies: u32;
+  g: f32;
+  softening: f32;
+  // 1 if the world wraps around with `period`, laid out like `GpuParams` in compute.rs
+  periodic: u32;
+  period: vec2<f32>;
+};
+
+// Position in xy and mass in z, laid out like `GpuBody` in compute.rs
+[[block]]
+struct Bodies {
+  bodies: [[stride(16)]] array<vec4<f32> >;
+};
+
+[[block]]
+struct Accelerations {
+  accelerations: [[stride(8)]] array<vec2<f32> >;
+};
+
+[[group(0), binding(0)]] var<uniform> params: Params;
+[[group(0), binding(1)]] var<storage> bodies: [[access(read)]] Bodies;
+[[group(0), binding(2)]] var<storage> accelerations: [[access(read_write)]] Accelerations;
+
+[[builtin(global_invocation_id)]] var global_id: vec3<u32>;
+
+// Must match WORKGROUP_SIZE in compute.rs
+[[stage(compute), workgroup_size(64)]]
+fn main() {
+  const i: u32 = global_id.x;
+  if (i >= params.num_bodies) {
+    return;
+  }
+  const position: vec2<f32> = bodies.bodies[i].xy;
+  var acceleration: vec2<f32> = vec2<f32>(0.0, 0.0);
+
+  var j: u32 = 0u;
+  loop {
+    if (j >= params.num_bodies) {
+      break;
+    }
+    const body: vec4<f32> = bodies.bodies[j];
+    var d: vec2<f32> = body.xy - position;
+    // Pull toward the nearest periodic copy of the body
+    if (params.periodic != 0u) {
+      d = d - params.period * round(d / params.period);
+    }
+    const d2: f32 = dot(d, d) + params.softening * params.softening;
+    // Skip the body itself
+    if (d2 > 0.0) {
+      acceleration = acceleration + body.z * d * inverseSqrt(d2 * d2 * d2);
+    }
+    continuing {
+      j = j + 1u;
+    }
+  }
+
+  accelerations.accelerations[i] = params.g * acceleration;
+}
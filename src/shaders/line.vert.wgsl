@@ -0,0 +1,17 @@
+[[location(0)]] var<in> a_position: vec2<f32>;
+[[location(1)]] var<in> a_color: vec3<f32>;
+[[location(0)]] var<out> v_color: vec4<f32>;
+[[builtin(position)]] var<out> out_position: vec4<f32>;
+
+[[block]]
+struct Camera {
+  u_view_proj: mat4x4<f32>;
+};
+[[group(0), binding(0)]] var<uniform> camera: Camera;
+
+[[stage(vertex)]]
+fn main() {
+  v_color = vec4<f32>(a_color, 1.0);
+  // Line vertices are already in world space
+  out_position = camera.u_view_proj * vec4<f32>(a_position, 0.0, 1.0);
+}
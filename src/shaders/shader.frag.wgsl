@@ -0,0 +1,7 @@
+[[location(0)]] var<in> v_color: vec4<f32>;
+[[location(0)]] var<out> f_color: vec4<f32>;
+
+[[stage(fragment)]]
+fn main() {
+  f_color = v_color;
+}
@@ -0,0 +1,27 @@
+[[location(0)]] var<in> a_position: vec2<f32>;
+[[location(1)]] var<in> a_color: vec3<f32>;
+[[location(2)]] var<in> center: vec2<f32>;
+[[location(3)]] var<in> radius: f32;
+[[location(4)]] var<in> i_color: vec4<f32>;
+[[location(0)]] var<out> v_color: vec4<f32>;
+[[location(1)]] var<out> v_local: vec2<f32>;
+[[location(2)]] var<out> v_pixel: f32;
+[[builtin(position)]] var<out> out_position: vec4<f32>;
+
+[[block]]
+struct Camera {
+  u_view_proj: mat4x4<f32>;
+  // Size of a pixel in world units
+  u_pixel_size: f32;
+};
+[[group(0), binding(0)]] var<uniform> camera: Camera;
+
+[[stage(vertex)]]
+fn main() {
+  v_color = vec4<f32>(a_color, 1.0) * i_color;
+  v_local = a_position;
+  v_pixel = camera.u_pixel_size / radius;
+  // Instances are in world space. The camera maps them to clip space.
+  const i_position: vec2<f32> = (radius * a_position) + center;
+  out_position = camera.u_view_proj * vec4<f32>(i_position, 0.0, 1.0);
+}
@@ -0,0 +1,29 @@
+[[location(0)]] var<in> a_position: vec2<f32>;
+[[location(1)]] var<in> a_color: vec3<f32>;
+[[location(2)]] var<in> center: vec3<f32>;
+[[location(3)]] var<in> radius: f32;
+[[location(4)]] var<in> i_color: vec4<f32>;
+[[location(0)]] var<out> v_color: vec4<f32>;
+[[location(1)]] var<out> v_local: vec2<f32>;
+[[location(2)]] var<out> v_pixel: f32;
+[[builtin(position)]] var<out> out_position: vec4<f32>;
+
+[[block]]
+struct Camera {
+  u_view: mat4x4<f32>;
+  u_proj: mat4x4<f32>;
+  // Size of a pixel at a distance of one unit in front of the camera
+  u_pixel_size: f32;
+};
+[[group(0), binding(0)]] var<uniform> camera: Camera;
+
+[[stage(vertex)]]
+fn main() {
+  v_color = vec4<f32>(a_color, 1.0) * i_color;
+  v_local = a_position;
+  // Offsetting the unit circle in view space keeps it facing the camera
+  const view_center: vec4<f32> = camera.u_view * vec4<f32>(center, 1.0);
+  // The camera looks down -z, and pixels grow linearly with the distance
+  v_pixel = camera.u_pixel_size * -view_center.z / radius;
+  out_position = camera.u_proj * (view_center + vec4<f32>(radius * a_position, 0.0, 0.0));
+}
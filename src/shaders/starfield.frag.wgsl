@@ -0,0 +1,63 @@
+[[location(0)]] var<in> v_uv: vec2<f32>;
+[[location(0)]] var<out> f_color: vec4<f32>;
+
+// Laid out like `StarfieldUniform` in starfield.rs
+[[block]]
+struct View {
+  // Camera center in window heights at zoom 1
+  u_center: vec2<f32>;
+  u_zoom: f32;
+  // Window width / window height
+  u_aspect: f32;
+  // Size of a pixel in window heights
+  u_pixel: f32;
+  // Picks one of many star layouts
+  u_seed: f32;
+};
+[[group(0), binding(0)]] var<uniform> view: View;
+
+fn hash(cell: vec2<f32>, salt: f32) -> f32 {
+  return fract(sin(dot(cell, vec2<f32>(12.9898, 78.233)) + view.u_seed + salt) * 43758.5453);
+}
+
+// Brightness of the star in the grid cell around `q`, if the cell has one. Every cell
+// holds at most one star at a random spot.
+fn star(q: vec2<f32>, cells_per_pixel: f32) -> f32 {
+  const cell: vec2<f32> = floor(q);
+  if (hash(cell, 0.0) > 0.3) {
+    return 0.0;
+  }
+  const x: f32 = hash(cell, 1.0);
+  const y: f32 = hash(cell, 2.0);
+  const spot: vec2<f32> = vec2<f32>(x, y) * 0.8 + vec2<f32>(0.1, 0.1);
+  // Dim enough to never glow
+  const brightness: f32 = 0.15 + 0.45 * hash(cell, 3.0);
+  // Stars stay about three pixels across whatever the zoom
+  const offset: f32 = length(fract(q) - spot) / cells_per_pixel;
+  return brightness * clamp(1.5 - offset, 0.0, 1.0);
+}
+
+[[stage(fragment)]]
+fn main() {
+  // Window heights from the center of the window, y up
+  const p: vec2<f32> = (v_uv - vec2<f32>(0.5, 0.5)) * vec2<f32>(view.u_aspect, -1.0);
+  var light: f32 = 0.0;
+  // Three layers from far to near. Farther layers have smaller cells and follow the
+  // camera less, both when panning and zooming.
+  var parallax: f32 = 0.15;
+  loop {
+    if (parallax > 0.5) {
+      break;
+    }
+    const scale: f32 = pow(view.u_zoom, parallax) * 6.0 / parallax;
+    const q: vec2<f32> = (p + view.u_center * parallax) * scale;
+    light = max(light, star(q, view.u_pixel * scale));
+    continuing {
+      parallax = parallax + 0.15;
+    }
+  }
+  if (light <= 0.0) {
+    discard;
+  }
+  f_color = vec4<f32>(0.9, 0.95, 1.0, light);
+}
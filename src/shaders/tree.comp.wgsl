@@ -0,0 +1,328 @@
+// Barnes-Hut on the GPU. Every step the bodies are sorted along a Z-order curve, a
+// binary radix tree (Karras 2012) is built over the sorted Morton codes, the mass and
+// extent of its nodes are summed up level by level and every body walks the tree
+// without a stack by following the rope to the next subtree. Naga does not support
+// atomics or workgroup shared memory yet, so the codes are sorted with a bitonic
+// network and nodes are summed up in one dispatch per level.
+//
+// Internal nodes are numbered 0..n-2 with the root at 0, the leaf of the kth sorted
+// body is node n-1+k.
+
+[[block]]
+struct Params {
+  num_bodies: u32;
+  // Number of keys, the number of bodies rounded up to a power of two
+  num_keys: u32;
+  g: f32;
+  softening: f32;
+  theta: f32;
+  // Square the Morton codes are computed in
+  min_x: f32;
+  min_y: f32;
+  span: f32;
+};
+
+// Settings of a single dispatch, picked with a dynamic offset
+[[block]]
+struct Step {
+  // Distance between the keys compared by a bitonic sort step
+  j: u32;
+  // Size of the sequences a bitonic sort step merges
+  k: u32;
+  // Number of the summing up dispatch, starting at 1
+  level: u32;
+};
+
+// Position in xy and mass in z, laid out like `GpuBody` in compute.rs
+[[block]]
+struct Bodies {
+  bodies: [[stride(16)]] array<vec4<f32> >;
+};
+
+// Morton code and index of every body in xy, padded with codes that sort last to
+// `num_keys`. Followed by the left child, right child, rope and (for leaves) body of
+// every node.
+[[block]]
+struct Links {
+  links: [[stride(16)]] array<vec4<u32> >;
+};
+
+// Per node center of mass in xy, mass in z and in w the level it was summed up at
+// (1 for leaves, 0 if it is not yet). Followed by the bounding box of the bodies
+// below every node as (min x, min y, max x, max y), starting at `2n - 1`.
+[[block]]
+struct Nodes {
+  nodes: [[stride(16)]] array<vec4<f32> >;
+};
+
+[[block]]
+struct Accelerations {
+  accelerations: [[stride(8)]] array<vec2<f32> >;
+};
+
+[[group(0), binding(0)]] var<uniform> params: Params;
+[[group(0), binding(1)]] var<uniform> current: Step;
+[[group(0), binding(2)]] var<storage> bodies: [[access(read)]] Bodies;
+[[group(0), binding(3)]] var<storage> links: [[access(read_write)]] Links;
+[[group(0), binding(4)]] var<storage> nodes: [[access(read_write)]] Nodes;
+[[group(0), binding(5)]] var<storage> accelerations: [[access(read_write)]] Accelerations;
+
+[[builtin(global_invocation_id)]] var global_id: vec3<u32>;
+
+// Marks missing children and the rope of the last subtree
+const NONE: u32 = 4294967295u;
+
+// Spreads the lower 16 bits of `v` out to the even bits
+fn part1by1(x: u32) -> u32 {
+  var v: u32 = x & 65535u;
+  v = (v | (v << 8u)) & 16711935u;
+  v = (v | (v << 4u)) & 252645135u;
+  v = (v | (v << 2u)) & 858993459u;
+  v = (v | (v << 1u)) & 1431655765u;
+  return v;
+}
+
+// Number of leading zero bits of `x`, found by binary search since the SPIR-V backend
+// cannot count bits yet
+fn clz(x: u32) -> i32 {
+  if (x == 0u) {
+    return 32;
+  }
+  var v: u32 = x;
+  var zeros: i32 = 0;
+  if (v <= 65535u) {
+    zeros = zeros + 16;
+    v = v << 16u;
+  }
+  if (v <= 16777215u) {
+    zeros = zeros + 8;
+    v = v << 8u;
+  }
+  if (v <= 268435455u) {
+    zeros = zeros + 4;
+    v = v << 4u;
+  }
+  if (v <= 1073741823u) {
+    zeros = zeros + 2;
+    v = v << 2u;
+  }
+  if (v <= 2147483647u) {
+    zeros = zeros + 1;
+  }
+  return zeros;
+}
+
+// Length of the common prefix of sorted keys `i` and `j`, or -1 if `j` is out of range.
+// Equal codes are told apart by their index.
+fn delta(i: i32, j: i32) -> i32 {
+  if (j < 0 || j >= i32(params.num_bodies)) {
+    return -1;
+  }
+  const a: u32 = links.links[i].x;
+  const b: u32 = links.links[j].x;
+  if (a == b) {
+    return 32 + clz(u32(i) ^ u32(j));
+  }
+  return clz(a ^ b);
+}
+
+// 1 if the range of internal node `i` extends to its right, -1 if to its left
+fn direction(i: i32) -> i32 {
+  if (delta(i, i + 1) > delta(i, i - 1)) {
+    return 1;
+  }
+  return -1;
+}
+
+// Node to continue with after the subtree whose range of sorted bodies ends at `last`.
+// That is the right child of the node splitting after `last`, which is internal node
+// `last + 1` if that one extends to the right and leaf `last + 1` otherwise.
+fn rope(last: i32) -> u32 {
+  const n: i32 = i32(params.num_bodies);
+  if (last == n - 1) {
+    return NONE;
+  }
+  if (last + 1 < n - 1) {
+    if (direction(last + 1) > 0) {
+      return u32(last + 1);
+    }
+  }
+  return u32(n + last);
+}
+
+[[stage(compute), workgroup_size(64)]]
+fn morton() {
+  const i: u32 = global_id.x;
+  if (i >= params.num_keys) {
+    return;
+  }
+  if (i >= params.num_bodies) {
+    links.links[i] = vec4<u32>(NONE, i, 0u, 0u);
+    return;
+  }
+  const t: vec2<f32> = (bodies.bodies[i].xy - vec2<f32>(params.min_x, params.min_y)) / params.span;
+  const cell: vec2<f32> = clamp(t * 65536.0, vec2<f32>(0.0, 0.0), vec2<f32>(65535.0, 65535.0));
+  const code: u32 = part1by1(u32(cell.x)) | (part1by1(u32(cell.y)) << 1u);
+  links.links[i] = vec4<u32>(code, i, 0u, 0u);
+}
+
+// One compare and swap step of a bitonic sort of the keys by code, then index
+[[stage(compute), workgroup_size(64)]]
+fn sort() {
+  const i: u32 = global_id.x;
+  const l: u32 = i ^ current.j;
+  if (i >= params.num_keys || l <= i) {
+    return;
+  }
+  const a: vec4<u32> = links.links[i];
+  const b: vec4<u32> = links.links[l];
+  // Sequences whose bit k is clear are sorted in ascending order, the others descending
+  if ((a.x > b.x || (a.x == b.x && a.y > b.y)) == ((i & current.k) == 0u)) {
+    links.links[i] = b;
+    links.links[l] = a;
+  }
+}
+
+// Fills in leaf `i` and the children of internal node `i`
+[[stage(compute), workgroup_size(64)]]
+fn build() {
+  const n: i32 = i32(params.num_bodies);
+  const i: i32 = i32(global_id.x);
+  if (i >= n) {
+    return;
+  }
+  const index: u32 = links.links[i].y;
+  const offset: i32 = i32(params.num_keys);
+  const body: vec4<f32> = bodies.bodies[index];
+  const leaf: i32 = n - 1 + i;
+  links.links[offset + leaf] = vec4<u32>(NONE, NONE, rope(i), index);
+  nodes.nodes[leaf] = vec4<f32>(body.x, body.y, body.z, 1.0);
+  nodes.nodes[2 * n - 1 + leaf] = vec4<f32>(body.x, body.y, body.x, body.y);
+  if (i >= n - 1) {
+    return;
+  }
+
+  // Direction and other end of the range of keys covered by this node
+  const d: i32 = direction(i);
+  const delta_min: i32 = delta(i, i - d);
+  var max_span: i32 = 2;
+  loop {
+    if (delta(i, i + max_span * d) <= delta_min) {
+      break;
+    }
+    max_span = max_span * 2;
+  }
+  var span: i32 = 0;
+  var t: i32 = max_span / 2;
+  loop {
+    if (t < 1) {
+      break;
+    }
+    if (delta(i, i + (span + t) * d) > delta_min) {
+      span = span + t;
+    }
+    t = t / 2;
+  }
+  const j: i32 = i + span * d;
+
+  // Last key that shares more than the common prefix of the range with key `i`
+  const delta_node: i32 = delta(i, j);
+  var split: i32 = 0;
+  t = span;
+  loop {
+    t = (t + 1) / 2;
+    if (delta(i, i + (split + t) * d) > delta_node) {
+      split = split + t;
+    }
+    if (t <= 1) {
+      break;
+    }
+  }
+  var gamma: i32 = i + split * d;
+  if (d < 0) {
+    gamma = gamma - 1;
+  }
+
+  const first: i32 = min(i, j);
+  const last: i32 = max(i, j);
+  var left: u32 = u32(gamma);
+  if (first == gamma) {
+    left = u32(n - 1 + gamma);
+  }
+  var right: u32 = u32(gamma + 1);
+  if (last == gamma + 1) {
+    right = u32(n + gamma);
+  }
+  links.links[offset + i] = vec4<u32>(left, right, rope(last), NONE);
+  nodes.nodes[i] = vec4<f32>(0.0, 0.0, 0.0, 0.0);
+}
+
+// Sums up internal node `i` if both of its children were summed up by an earlier
+// dispatch
+[[stage(compute), workgroup_size(64)]]
+fn sum_up() {
+  const n: u32 = params.num_bodies;
+  const i: u32 = global_id.x;
+  if (i + 1u >= n) {
+    return;
+  }
+  if (nodes.nodes[i].w != 0.0) {
+    return;
+  }
+  const children: vec4<u32> = links.links[params.num_keys + i];
+  const a: vec4<f32> = nodes.nodes[children.x];
+  const b: vec4<f32> = nodes.nodes[children.y];
+  const level: f32 = f32(current.level);
+  if (a.w == 0.0 || a.w > level || b.w == 0.0 || b.w > level) {
+    return;
+  }
+  const mass: f32 = a.z + b.z;
+  var center: vec2<f32> = (a.xy + b.xy) * 0.5;
+  if (mass > 0.0) {
+    center = (a.xy * a.z + b.xy * b.z) / mass;
+  }
+  nodes.nodes[i] = vec4<f32>(center.x, center.y, mass, level + 1.0);
+  const box_a: vec4<f32> = nodes.nodes[2u * n - 1u + children.x];
+  const box_b: vec4<f32> = nodes.nodes[2u * n - 1u + children.y];
+  nodes.nodes[2u * n - 1u + i] = vec4<f32>(min(box_a.xy, box_b.xy), max(box_a.zw, box_b.zw));
+}
+
+// Acceleration of body `i`, walking the tree depth first. A node is opened if the
+// bounding box of its bodies is not small enough compared to its distance.
+[[stage(compute), workgroup_size(64)]]
+fn forces() {
+  const n: u32 = params.num_bodies;
+  const i: u32 = global_id.x;
+  if (i >= n) {
+    return;
+  }
+  const position: vec2<f32> = bodies.bodies[i].xy;
+  const softening2: f32 = params.softening * params.softening;
+  const theta2: f32 = params.theta * params.theta;
+  var acceleration: vec2<f32> = vec2<f32>(0.0, 0.0);
+
+  var node: u32 = 0u;
+  loop {
+    if (node == NONE) {
+      break;
+    }
+    const link: vec4<u32> = links.links[params.num_keys + node];
+    const center: vec4<f32> = nodes.nodes[node];
+    const box: vec4<f32> = nodes.nodes[2u * n - 1u + node];
+    const d: vec2<f32> = center.xy - position;
+    const size: f32 = max(box.z - box.x, box.w - box.y);
+    // Leaves come after the n - 1 internal nodes
+    if (node + 1u >= n || size * size < theta2 * dot(d, d)) {
+      // Skip the body itself
+      if (link.w != i) {
+        const d2: f32 = dot(d, d) + softening2;
+        acceleration = acceleration + center.z * d * inverseSqrt(d2 * d2 * d2);
+      }
+      node = link.z;
+    } else {
+      node = link.x;
+    }
+  }
+
+  accelerations.accelerations[i] = params.g * acceleration;
+}
@@ -1,151 +1,1077 @@
 use cgmath::InnerSpace;
-use uuid::Uuid;
+use instant::{Duration, Instant};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{cell::RefCell, fmt, fs::File, io, io::Write, path::Path, sync::mpsc::Receiver};
 
-use crate::quadtree::quadtree::QuadTree;
+use crate::analysis::Energy;
+use crate::boundary::{minimum_image, BoundaryCondition};
+use crate::drag::Drag;
+use crate::evaporation::Evaporation;
+use crate::events::{Observers, SimulationEvent};
+use crate::expansion::Expansion;
+use crate::export::ExportFormat;
+use crate::force_law::ForceLaw;
+use crate::forces::Sources;
+use crate::fragmentation::Fragmentation;
+use crate::groups::GroupMatrix;
+use crate::integrator::Integrator;
+use crate::interaction::Interaction;
+use crate::merge::MergeModel;
+use crate::morton;
+use crate::potential::ExternalPotential;
+use crate::quadtree::quadtree::{QuadTree, TreeStats};
+#[cfg(feature = "scripting")]
+use crate::scripting::{ScriptError, StepScript};
+use crate::solver::ForceSolver;
+use crate::spatial_hash::SpatialHash;
+use crate::store::{ParticleId, ParticleStore};
+use crate::timestep::{AdaptiveTimeStep, BlockTimeSteps};
 use crate::{
-    primitives::{instance::Instance, particle::Particle, scalar::Scalar},
+    constants,
+    primitives::{
+        color::ColorMapping,
+        colormap::Colormap,
+        instance::Instance,
+        particle::{BodyKind, Particle},
+        scalar::{to_f32, Scalar},
+    },
     quadtree::quadtree::QuadTreeIter,
 };
 
 /// Simulation handles all core aspects of simulating the particle system
+#[derive(Serialize, Deserialize)]
 pub struct Simulation {
-    particles: Vec<Particle>,
+    particles: ParticleStore,
     time_step: Scalar,
     base_step: Scalar,
+    /// Picks the step size from the accelerations each step instead of `time_step`
+    adaptive_step: Option<AdaptiveTimeStep>,
+    /// Size of the last step taken
+    effective_step: Scalar,
+    /// Lets particles take fractions of the time step when set
+    block_steps: Option<BlockTimeSteps>,
     theta: Scalar,
+    /// Gravitational constant
+    g: Scalar,
+    /// Softening length. Keeps accelerations finite when two bodies get very close.
+    softening: Scalar,
+    integrator: Integrator,
+    /// How accelerations are computed by `step`
+    solver: ForceSolver,
+    /// What the Barnes-Hut tree nodes keep of the mass distribution below them
+    expansion: Expansion,
+    /// What happens to particles at the edges of the world
+    boundary: BoundaryCondition,
+    /// How colliding particles are combined
+    merge_model: MergeModel,
+    /// Shatters particles colliding faster than its threshold instead of merging them,
+    /// if set
+    fragmentation: Option<Fragmentation>,
+    /// Fixed fields pulling on every particle on top of their mutual gravity
+    potentials: Vec<ExternalPotential>,
+    /// Friction slowing the particles down, if any
+    drag: Option<Drag>,
+    /// How particles lose mass and expire as they age, if they do
+    evaporation: Option<Evaporation>,
+    /// Whether masses, charges or both act on each other
+    interaction: Interaction,
+    /// Which groups of particles feel and collide with which
+    groups: GroupMatrix,
+    /// Coulomb constant k in `k * q1 * q2 / (d² + ε²)`
+    coulomb_constant: Scalar,
+    /// Law the particles pull on each other with instead of softened gravity, if any.
+    /// Not saved with snapshots.
+    #[serde(skip)]
+    force_law: Option<Box<dyn ForceLaw>>,
+    /// Script run at the start of every step, if any. Not saved with snapshots.
+    #[cfg(feature = "scripting")]
+    #[serde(skip)]
+    script: Option<StepScript>,
+    /// Why the script stopped running, until it is taken
+    #[cfg(feature = "scripting")]
+    #[serde(skip)]
+    script_error: Option<ScriptError>,
+    /// Accelerations the script gave each particle this step, by dense index. Dropped
+    /// when particles are removed in the middle of the step.
+    #[serde(skip)]
+    script_accelerations: Vec<cgmath::Vector2<Scalar>>,
+    /// Simulated time that has passed so far
+    elapsed: Scalar,
+    /// Rebuilt from the particles whenever it is needed. Kept around to reuse its
+    /// allocation and, with `incremental_tree`, its structure.
+    #[serde(skip)]
+    quadtree: QuadTree,
+    /// One tree for every set of groups some group feels, while not every group feels
+    /// every other one
+    #[serde(skip)]
+    group_trees: Vec<QuadTree>,
+    /// Whether the quadtree is updated in place rather than rebuilt from scratch
+    #[serde(skip)]
+    incremental_tree: bool,
+    /// Number of steps between sorting the particles along a Z-order curve, if they are
+    /// sorted at all
+    #[serde(skip)]
+    morton_sort: Option<usize>,
+    /// Steps taken since the particles were last sorted
+    #[serde(skip)]
+    steps_since_sort: usize,
+    /// Collision broad phase. Also kept around to reuse its allocation.
+    #[serde(skip)]
+    grid: SpatialHash,
+    #[serde(skip)]
+    stats: StepStats,
+    /// Everyone listening to the events of the simulation
+    #[serde(skip)]
+    observers: Observers,
+}
+
+/// Time spent in each phase of the last call to `advance`. Integrators that evaluate
+/// forces more than once per step report the sum of all evaluations.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StepStats {
+    /// Building the Barnes-Hut tree for force evaluation, including sorting the
+    /// particles in Morton order
+    pub tree_build: Duration,
+    /// Walking the tree to compute accelerations
+    pub force_eval: Duration,
+    /// Detecting and merging colliding particles
+    pub collision_time: Duration,
+    /// Number of particles that were merged into another one
+    pub collisions: usize,
+    /// Number of collisions that shattered into fragments
+    pub fragmentations: usize,
+    /// Number of particles removed for getting too light or too old
+    pub evaporated: usize,
+    /// Deepest level any particle stepped at with block time steps
+    pub deepest_level: u32,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    Encoding(bincode::Error),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "could not access snapshot: {}", e),
+            SnapshotError::Encoding(e) => write!(f, "could not encode snapshot: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
+
+impl From<bincode::Error> for SnapshotError {
+    fn from(e: bincode::Error) -> Self {
+        SnapshotError::Encoding(e)
+    }
 }
 
 impl Simulation {
-    pub fn new(time_step: Scalar, theta: Scalar) -> Self {
+    /// Creates an empty simulation. `time_step` is the base step size (it can only be
+    /// increased from here on), `theta` is the Barnes-Hut opening angle, `g` is the
+    /// gravitational constant, `softening` is the softening length ε used in
+    /// `G * m / (d² + ε²)` and `integrator` is the scheme used to advance particles
+    /// through time.
+    pub fn new(
+        time_step: Scalar,
+        theta: Scalar,
+        g: Scalar,
+        softening: Scalar,
+        integrator: Integrator,
+    ) -> Self {
         Self {
-            particles: Vec::new(),
+            particles: ParticleStore::new(),
             time_step,
             base_step: time_step,
+            adaptive_step: None,
+            effective_step: time_step,
+            block_steps: None,
             theta,
+            g,
+            softening,
+            integrator,
+            solver: ForceSolver::default(),
+            expansion: Expansion::default(),
+            boundary: BoundaryCondition::default(),
+            merge_model: MergeModel::default(),
+            fragmentation: None,
+            evaporation: None,
+            groups: GroupMatrix::default(),
+            potentials: Vec::new(),
+            drag: None,
+            interaction: Interaction::default(),
+            coulomb_constant: 1.0,
+            force_law: None,
+            #[cfg(feature = "scripting")]
+            script: None,
+            #[cfg(feature = "scripting")]
+            script_error: None,
+            script_accelerations: Vec::new(),
+            elapsed: 0.0,
+            quadtree: QuadTree::empty(),
+            group_trees: Vec::new(),
+            incremental_tree: false,
+            morton_sort: None,
+            steps_since_sort: 0,
+            grid: SpatialHash::default(),
+            stats: StepStats::default(),
+            observers: Observers::default(),
         }
     }
 
-    pub fn step(&mut self) {
-        let quadtree: QuadTree = QuadTree::from_points(self.particles.clone());
-        let theta = self.theta;
+    /// Writes the complete state of the simulation (particles, time step, theta and
+    /// elapsed time) to `path` so that it can be resumed later with `load`
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SnapshotError> {
+        let file = io::BufWriter::new(File::create(path)?);
+        bincode::serialize_into(file, self)?;
+        Ok(())
+    }
+
+    /// Writes the id, position, velocity and mass of every particle to `writer` as rows of
+    /// `format`, e.g. to analyze a run with other tools. A CSV header is only written by
+    /// `ExportFormat::write_header`, so that rows of many steps can follow one header.
+    pub fn export_snapshot<W: Write>(&self, mut writer: W, format: ExportFormat) -> io::Result<()> {
+        let particles = &self.particles;
+        let columns = particles
+            .ids()
+            .iter()
+            .zip(particles.positions())
+            .zip(particles.velocities())
+            .zip(particles.masses());
+        for (((&id, &position), &velocity), &mass) in columns {
+            format.write_row(&mut writer, self.elapsed, id, position, velocity, mass)?;
+        }
+        Ok(())
+    }
 
-        for p in &mut self.particles {
-            let tree_iter = QuadTreeIter::new(p.position, theta, &quadtree);
+    /// Restores a simulation previously written with `save`
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SnapshotError> {
+        let file = io::BufReader::new(File::open(path)?);
+        Ok(bincode::deserialize_from(file)?)
+    }
+
+    /// Advances the simulation by a single step. This resolves collisions, computes
+    /// the forces acting on each particle and finally integrates them.
+    pub fn advance(&mut self) {
+        self.advance_with(Self::step);
+    }
 
-            for node in tree_iter {
-                let node_particle = node.particle;
-                let (x, y) = (node_particle.position.x, node_particle.position.y);
-                let d = cgmath::vec2(x - p.position.x, y - p.position.y);
-                let mass = node_particle.mass;
-                p.acceleration = (mass / d.magnitude2()) * d.normalize();
+    /// Same as `advance` except that accelerations are computed by `compute_forces`
+    /// instead of `step`. `compute_forces` must update the acceleration of
+    /// every particle (e.g. through `set_accelerations`).
+    pub fn advance_with<F: FnMut(&mut Self)>(&mut self, mut compute_forces: F) {
+        let _span = tracing::info_span!("step").entered();
+        self.stats = StepStats::default();
+        if let Some(interval) = self.morton_sort {
+            self.steps_since_sort += 1;
+            if self.steps_since_sort >= interval {
+                let start = Instant::now();
+                let _span = tracing::info_span!("morton sort").entered();
+                self.sort_particles();
+                self.stats.tree_build += start.elapsed();
             }
         }
+        let start = Instant::now();
+        let span = tracing::info_span!("collisions").entered();
+        let (collisions, fragmentations) = self.collide();
+        span.exit();
+        self.stats.collision_time = start.elapsed();
+        self.stats.collisions = collisions;
+        self.stats.fragmentations = fragmentations;
+        #[cfg(feature = "scripting")]
+        self.run_script();
+
+        compute_forces(self);
+        self.integrate_with(compute_forces);
+        let stats = self.stats;
+        self.observers.emit(SimulationEvent::StepCompleted(stats));
     }
 
-    pub fn resolve_collisions(&mut self) {
-        let quadtree: QuadTree = QuadTree::from_points(self.particles.clone());
-
-        // Collision detection using quadtree to figure out a particle's nearby siblings
-        for p in self.particles.clone() {
-            let mut stack = vec![&quadtree];
-            let mut parent = &quadtree;
-            let mut nearby_particles = Vec::new();
-
-            // Broad phase (figuring out all the nearby particles to check collision for)
-            while !stack.is_empty() {
-                let node = stack.pop().unwrap();
-                if !node.is_subdivided() && node.particle.id == p.id {
-                    let mut p_stack = vec![parent];
-                    while !p_stack.is_empty() {
-                        let node = p_stack.pop().unwrap();
-                        if !node.is_subdivided() && node.particle.id != p.id {
-                            nearby_particles.push(node.particle);
-                        }
+    /// Receives every `SimulationEvent` from now on, e.g. to log merges or play a sound
+    /// for them. Events queue up until they are received, so drop the receiver once
+    /// done listening.
+    pub fn subscribe(&mut self) -> Receiver<SimulationEvent> {
+        self.observers.subscribe()
+    }
 
-                        for child in &node.children {
-                            match child {
-                                Some(n) => p_stack.push(n),
-                                _ => (),
-                            }
-                        }
+    /// Computes the acceleration of each particle due to all others with the selected
+    /// `ForceSolver`. External potentials are added on top by `integrate`.
+    pub fn step(&mut self) {
+        match self.solver {
+            ForceSolver::BarnesHut => self.step_tree(None),
+            ForceSolver::Direct => self.step_direct(None),
+        }
+    }
+
+    /// Sorts the particles by the Morton code of their position, so that particles close
+    /// to each other in space are also close in memory. Every loop over the particles
+    /// then walks the same parts of the tree one after the other, which keeps them in
+    /// the cache. Changes the dense index of every particle, but not their handles.
+    pub fn sort_particles(&mut self) {
+        let order = morton::order(self.particles.positions());
+        self.particles.reorder(&order);
+        self.steps_since_sort = 0;
+    }
+
+    /// Brings the quadtree up to date with the particles
+    fn update_quadtree(&mut self) {
+        if self.incremental_tree {
+            self.quadtree.update_from(&self.particles);
+        } else {
+            self.quadtree.rebuild_from(&self.particles);
+        }
+    }
+
+    /// Computes the acceleration of each particle by walking an up to date quadtree and
+    /// summing the pull (and with electrostatics the push) of every node accepted by the
+    /// opening criterion. Particles are
+    /// processed in parallel when the `parallel` feature is enabled since the tree is
+    /// read-only during traversal. Only the particles flagged in `active` are updated if
+    /// it is given.
+    fn step_tree(&mut self, active: Option<&[bool]>) {
+        let views = self.group_views();
+        let start = Instant::now();
+        let span = tracing::info_span!("tree build").entered();
+        self.build_trees(views.as_ref());
+        span.exit();
+        self.stats.tree_build += start.elapsed();
+
+        let start = Instant::now();
+        let _span = tracing::info_span!("forces").entered();
+        self.walk_trees(views.as_ref(), active);
+        self.stats.force_eval += start.elapsed();
+    }
+
+    /// Brings the quadtree, or with groups that do not feel each other the tree of
+    /// every view, up to date with the particles
+    fn build_trees(&mut self, views: Option<&GroupViews>) {
+        let walk = self.tree_walk();
+        let (quadrupole, coulomb) = (walk.quadrupole, walk.coulomb.is_some());
+        match views {
+            None => {
+                self.update_quadtree();
+                if quadrupole {
+                    self.quadtree.compute_quadrupoles();
+                }
+                if coulomb {
+                    self.quadtree.compute_charges();
+                }
+            }
+            Some(views) => {
+                let capacity = self.quadtree.leaf_capacity();
+                self.group_trees
+                    .resize_with(views.sources.len(), QuadTree::empty);
+                for (tree, sources) in self.group_trees.iter_mut().zip(&views.sources) {
+                    let points: Vec<Particle> = self
+                        .particles
+                        .iter()
+                        .filter(|p| sources[p.group as usize])
+                        .collect();
+                    tree.set_leaf_capacity(capacity);
+                    tree.rebuild(&points);
+                    if quadrupole {
+                        tree.compute_quadrupoles();
                     }
+                    if coulomb {
+                        tree.compute_charges();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Updates the accelerations from the trees built by `build_trees` for `views`
+    fn walk_trees(&mut self, views: Option<&GroupViews>, active: Option<&[bool]>) {
+        let walk = self.tree_walk();
+        let particles = &self.particles;
+        let tree_of = |p: &Particle| match views {
+            None => &self.quadtree,
+            Some(views) => &self.group_trees[views.view_of[p.group as usize]],
+        };
+        #[cfg(feature = "parallel")]
+        let indices = (0..particles.len()).into_par_iter();
+        #[cfg(not(feature = "parallel"))]
+        let indices = 0..particles.len();
+
+        let accelerations: Vec<_> = indices
+            .map(|i| {
+                active.is_none_or(|active| active[i]).then(|| {
+                    let p = particles.particle(i);
+                    walk.acceleration(tree_of(&p), &p)
+                })
+            })
+            .collect();
+        self.update_accelerations(accelerations);
+    }
+
+    /// Builds the Barnes-Hut tree of the particles as it is built at the start of `step`,
+    /// to time it on its own
+    #[cfg(feature = "bench")]
+    pub fn build_tree(&mut self) {
+        let views = self.group_views();
+        self.build_trees(views.as_ref());
+    }
+
+    /// Updates every acceleration by walking the tree built last by `build_tree` or
+    /// `step`, without rebuilding it. `build_tree` has to be called after the particles
+    /// change for the result to be meaningful.
+    #[cfg(feature = "bench")]
+    pub fn evaluate_forces(&mut self) {
+        let views = self.group_views();
+        self.walk_trees(views.as_ref(), None);
+    }
+
+    /// Settings `step_tree` walks the tree with. The quadrupole expansion assumes
+    /// softened gravity, so nodes only stand in with their total mass under any other law.
+    fn tree_walk(&self) -> TreeWalk<'_> {
+        let gravity = self.interaction.gravity();
+        TreeWalk {
+            theta: self.theta,
+            g: self.g,
+            softening2: self.softening * self.softening,
+            period: self.boundary.period(),
+            gravity,
+            quadrupole: gravity
+                && self.expansion == Expansion::Quadrupole
+                && self.force_law.is_none(),
+            law: self.force_law.as_deref(),
+            coulomb: Some(self.coulomb_constant).filter(|_| self.interaction.electrostatic()),
+        }
+    }
+
+    /// Overwrites the acceleration of every particle that has a new one
+    fn update_accelerations(&mut self, accelerations: Vec<Option<cgmath::Vector2<Scalar>>>) {
+        let columns = self.particles.columns_mut();
+        for (a, new) in columns.accelerations.iter_mut().zip(accelerations) {
+            if let Some(new) = new {
+                *a = new;
+            }
+        }
+    }
+
+    /// Path a test particle starting at `position` with `velocity` would follow over the
+    /// next `steps` steps if every other particle stayed where it is. The test particle
+    /// is pulled by the particles and external potentials but pulls on nothing itself.
+    /// The path ends early where it runs into a particle. World edges are ignored.
+    pub fn predict_trajectory(
+        &mut self,
+        position: cgmath::Vector2<Scalar>,
+        velocity: cgmath::Vector2<Scalar>,
+        steps: usize,
+    ) -> Vec<cgmath::Vector2<Scalar>> {
+        self.update_quadtree();
+        if self.tree_walk().quadrupole {
+            self.quadtree.compute_quadrupoles();
+        }
+        let walk = self.tree_walk();
+        let (quadtree, potentials) = (&self.quadtree, &self.potentials);
+        let acceleration = |position| {
+            let mut p = Particle::empty();
+            p.position = position;
+            potentials
+                .iter()
+                .fold(walk.acceleration(quadtree, &p), |a, potential| {
+                    a + potential.acceleration(position, walk.g, walk.softening2)
+                })
+        };
+        let max_radius = self
+            .particles
+            .radii()
+            .iter()
+            .copied()
+            .fold(0.0, Scalar::max);
+        let dt = self.time_step;
+
+        let (mut position, mut velocity) = (position, velocity);
+        let mut path = Vec::with_capacity(steps + 1);
+        path.push(position);
+        for _ in 0..steps {
+            match self.integrator {
+                Integrator::Euler => {
+                    velocity += acceleration(position) * dt;
+                    position += velocity * dt;
+                }
+                Integrator::Leapfrog => {
+                    velocity += acceleration(position) * (dt / 2.0);
+                    position += velocity * dt;
+                    velocity += acceleration(position) * (dt / 2.0);
+                }
+                Integrator::RungeKutta4 => {
+                    let (x1, v1) = (position, velocity);
+                    let a1 = acceleration(x1);
+                    let (x2, v2) = (position + v1 * (dt / 2.0), velocity + a1 * (dt / 2.0));
+                    let a2 = acceleration(x2);
+                    let (x3, v3) = (position + v2 * (dt / 2.0), velocity + a2 * (dt / 2.0));
+                    let a3 = acceleration(x3);
+                    let (x4, v4) = (position + v3 * dt, velocity + a3 * dt);
+                    let a4 = acceleration(x4);
+                    position += (v1 + v2 * 2.0 + v3 * 2.0 + v4) * (dt / 6.0);
+                    velocity += (a1 + a2 * 2.0 + a3 * 2.0 + a4) * (dt / 6.0);
                 }
+            }
+            path.push(position);
+            let hit = quadtree
+                .query_radius(position, max_radius)
+                .iter()
+                .any(|p| (p.position - position).magnitude2() <= p.radius * p.radius);
+            if hit {
+                break;
+            }
+        }
+        path
+    }
 
-                if node.is_subdivided() {
-                    for child in &node.children {
-                        match child {
-                            Some(n) => stack.push(n),
-                            _ => (),
+    /// Computes the acceleration of each particle by summing the pull (and with
+    /// electrostatics the push) of every other particle, several at a time with SIMD under
+    /// softened gravity. Only the particles flagged in `active` are updated if it is given.
+    fn step_direct(&mut self, active: Option<&[bool]>) {
+        let start = Instant::now();
+        let _span = tracing::info_span!("forces").entered();
+        let walk = self.tree_walk();
+        let particles = &self.particles;
+        let views = self.group_views();
+        // Masses and charges pulling on every particle, or on the particles of each view
+        let sources: Vec<(Sources, Sources)> = match &views {
+            None => {
+                let masses = if walk.gravity {
+                    Sources::from_columns(particles.positions(), particles.masses())
+                } else {
+                    Sources::new()
+                };
+                let charges = if walk.coulomb.is_some() {
+                    Sources::from_columns(particles.positions(), particles.charges())
+                } else {
+                    Sources::new()
+                };
+                vec![(masses, charges)]
+            }
+            Some(views) => views
+                .sources
+                .iter()
+                .map(|sources| {
+                    let (mut masses, mut charges) = (Sources::new(), Sources::new());
+                    for i in 0..particles.len() {
+                        if !sources[particles.groups()[i] as usize] {
+                            continue;
+                        }
+                        let position = particles.positions()[i];
+                        if walk.gravity {
+                            masses.push(position, particles.masses()[i]);
+                        }
+                        if walk.coulomb.is_some() {
+                            charges.push(position, particles.charges()[i]);
                         }
                     }
-                }
-                parent = node;
+                    (masses, charges)
+                })
+                .collect(),
+        };
+        #[cfg(feature = "parallel")]
+        let indices = (0..particles.len()).into_par_iter();
+        #[cfg(not(feature = "parallel"))]
+        let indices = 0..particles.len();
+
+        let accelerations: Vec<_> = indices
+            .map(|i| {
+                active.is_none_or(|active| active[i]).then(|| {
+                    let p = particles.particle(i);
+                    let (masses, charges) = match &views {
+                        None => &sources[0],
+                        Some(views) => &sources[views.view_of[p.group as usize]],
+                    };
+                    walk.sum(&p, masses) * walk.g + walk.electric(&p, charges)
+                })
+            })
+            .collect();
+        self.update_accelerations(accelerations);
+        self.stats.force_eval += start.elapsed();
+    }
+
+    /// Relative RMS error of the Barnes-Hut accelerations at the current theta, measured
+    /// against the exact pairwise sum: `sqrt(Σ|a_tree - a_direct|² / Σ|a_direct|²)`.
+    /// The particles are left untouched, but this costs a full O(n²) evaluation.
+    pub fn acceleration_error(&mut self) -> Scalar {
+        let accelerations = self.particles.accelerations().to_vec();
+        let stats = self.stats;
+
+        self.step_tree(None);
+        let tree = self.particles.accelerations().to_vec();
+        self.step_direct(None);
+        let (error, total) = self.particles.accelerations().iter().zip(tree).fold(
+            (0.0, 0.0),
+            |(error, total), (direct, a)| {
+                (
+                    error + (a - direct).magnitude2(),
+                    total + direct.magnitude2(),
+                )
+            },
+        );
+
+        self.set_accelerations(&accelerations);
+        self.stats = stats;
+        if total > 0.0 {
+            (error / total).sqrt()
+        } else {
+            0.0
+        }
+    }
+
+    /// Kinetic energy of the particles and the potential energy of their gravity and
+    /// electrostatics, the latter estimated from a Barnes-Hut tree at the current theta.
+    /// The potential assumes softened gravity whatever the force law and leaves groups
+    /// and external potentials out.
+    pub fn energy(&self) -> Energy {
+        let walk = self.tree_walk();
+        let mut quadtree = QuadTree::empty();
+        quadtree.set_leaf_capacity(self.quadtree.leaf_capacity());
+        quadtree.rebuild_from(&self.particles);
+        if walk.coulomb.is_some() {
+            quadtree.compute_charges();
+        }
+
+        let particles = &self.particles;
+        #[cfg(feature = "parallel")]
+        let indices = (0..particles.len()).into_par_iter();
+        #[cfg(not(feature = "parallel"))]
+        let indices = 0..particles.len();
+        let potential: Scalar = indices
+            .map(|i| walk.potential(&quadtree, &particles.particle(i)))
+            .sum();
+        let kinetic = particles
+            .masses()
+            .iter()
+            .zip(particles.velocities())
+            .map(|(&m, v)| 0.5 * m * v.magnitude2())
+            .sum();
+        Energy {
+            kinetic,
+            // Every pair was counted from both ends
+            potential: potential / 2.0,
+        }
+    }
+
+    /// Overwrites the acceleration of each particle with accelerations computed
+    /// elsewhere (e.g. on the GPU). `accelerations` must be in the same order as the
+    /// particles returned by `get_particles`.
+    pub fn set_accelerations(&mut self, accelerations: &[cgmath::Vector2<Scalar>]) {
+        let columns = self.particles.columns_mut();
+        for (a, new) in columns.accelerations.iter_mut().zip(accelerations) {
+            *a = *new;
+        }
+    }
+
+    /// Merges every pair of overlapping particles. Candidate pairs are collected first
+    /// (each pair once), then merged in a single pass. A particle that collides with
+    /// several others absorbs all of them, or is absorbed together with everything it
+    /// already absorbed. With fragmentation, pairs colliding fast enough shatter instead
+    /// and their fragments take no part in any other collision until the next call.
+    pub fn resolve_collisions(&mut self) {
+        self.collide();
+    }
+
+    /// `resolve_collisions`, returning the number of particles merged into another one
+    /// and the number of collisions that shattered
+    fn collide(&mut self) -> (usize, usize) {
+        self.grid.rebuild(&self.particles);
+        let mut pairs = Vec::new();
+        let kinds = self.particles.kinds();
+        self.grid.for_each_collision(&self.particles, |i, j| {
+            if kinds[i] != BodyKind::Tracer && kinds[j] != BodyKind::Tracer {
+                pairs.push((i, j));
+            }
+        });
+        if pairs.is_empty() {
+            return (0, 0);
+        }
+
+        // Index of the particle each particle was merged into (itself while it exists)
+        let mut merged_into: Vec<usize> = (0..self.particles.len()).collect();
+        let mut shattered = vec![false; self.particles.len()];
+        let mut fragments = Vec::new();
+        let mut fragmentations = 0;
+        for (i, j) in pairs {
+            let (i, j) = (root(&mut merged_into, i), root(&mut merged_into, j));
+            if i == j || shattered[i] || shattered[j] {
+                continue;
             }
+            let groups = self.particles.groups();
+            if !self.groups.collide(groups[i], groups[j]) {
+                continue;
+            }
+            if let Some(pieces) = self.fragmentation.and_then(|fragmentation| {
+                fragmentation.shatter(&self.particles.particle(i), &self.particles.particle(j))
+            }) {
+                shattered[i] = true;
+                shattered[j] = true;
+                fragments.extend(pieces);
+                fragmentations += 1;
+                continue;
+            }
+            // A fixed particle survives, otherwise the heavier one. On a tie the first
+            // one does.
+            let (masses, fixed) = (self.particles.masses(), self.particles.fixed());
+            let (greater, lesser) = if (fixed[i], masses[i]) >= (fixed[j], masses[j]) {
+                (i, j)
+            } else {
+                (j, i)
+            };
+            let mut survivor = self.particles.particle(greater);
+            self.merge_model
+                .merge(&mut survivor, &self.particles.particle(lesser));
+            self.particles.set(greater, &survivor);
+            merged_into[lesser] = greater;
+            self.observers.emit(SimulationEvent::ParticlesMerged {
+                survivor: survivor.id,
+                absorbed: self.particles.ids()[lesser],
+            });
+        }
 
-            // Narrow phase
-            for p2 in nearby_particles {
-                if p.check_collision(&p2) {
-                    self.merge_particle(p, p2);
+        let absorbed: Vec<ParticleId> = merged_into
+            .iter()
+            .enumerate()
+            .filter(|&(i, &into)| into != i)
+            .map(|(i, _)| self.particles.ids()[i])
+            .collect();
+        let broken: Vec<ParticleId> = shattered
+            .iter()
+            .enumerate()
+            .filter(|&(_, &shattered)| shattered)
+            .map(|(i, _)| self.particles.ids()[i])
+            .collect();
+        let merged = absorbed.len();
+        for id in absorbed {
+            self.particles.remove(id);
+        }
+        for id in broken {
+            self.particles.remove(id);
+            self.observers.emit(SimulationEvent::ParticleRemoved(id));
+        }
+        for fragment in fragments {
+            let id = self.particles.insert(fragment);
+            self.observers.emit(SimulationEvent::ParticleAdded(id));
+        }
+        (merged, fragmentations)
+    }
+
+    /// Runs the script, if any, keeping the accelerations it gave the particles for this
+    /// step and adding the particles it spawned. A failing script is stopped.
+    #[cfg(feature = "scripting")]
+    fn run_script(&mut self) {
+        self.script_accelerations.clear();
+        let script = match &self.script {
+            Some(script) => script,
+            None => return,
+        };
+        match script.run(self.elapsed, &self.particles) {
+            Ok(effects) => {
+                self.script_accelerations = effects.accelerations;
+                for particle in effects.spawned {
+                    let id = self.particles.insert(particle);
+                    self.observers.emit(SimulationEvent::ParticleAdded(id));
                 }
             }
+            Err(e) => {
+                self.script = None;
+                self.script_error = Some(e);
+            }
         }
     }
 
-    fn merge_particle(&mut self, p1: Particle, p2: Particle) {
-        let (lesser, greater) = p1.compare(p2);
-        let greater_idx = self
-            .particles
-            .clone()
-            .into_iter()
-            .position(|p| p.id == greater.id);
+    /// Sums up the forces acting on each particle in the system. Accelerations must
+    /// have been computed with `step` beforehand.
+    pub fn integrate(&mut self) {
+        self.integrate_with(Self::step);
+    }
 
-        if let Some(idx) = greater_idx {
-            let p = self.particles.get_mut(idx).unwrap();
-            p.radius += lesser.radius / 10.0;
-            p.mass += lesser.mass * p.radius;
-            p.velocity =
-                (greater.mass * greater.velocity + lesser.mass * lesser.velocity) / greater.mass;
+    /// Integrates the particles with the selected integrator. Integrators that need
+    /// accelerations at intermediate positions recompute them with `compute_forces`.
+    /// The pull of the external potentials and drag are added to every acceleration
+    /// computed.
+    pub fn integrate_with<F: FnMut(&mut Self)>(&mut self, mut compute_forces: F) {
+        self.apply_external_forces(None);
+        let dt = match self.adaptive_step {
+            Some(adaptive) => adaptive.time_step(self.softening, self.max_acceleration()),
+            None => self.time_step,
+        };
+        self.effective_step = dt;
+        if let Some(blocks) = self.block_steps {
+            self.block_step(dt, blocks);
+            self.grow_older(dt);
+            self.elapsed += dt;
+            return;
+        }
+        match self.integrator {
+            Integrator::Euler => {
+                self.kick(dt);
+                self.drift(dt);
+            }
+            Integrator::Leapfrog => {
+                self.kick(dt / 2.0);
+                self.drift(dt);
+                compute_forces(self);
+                self.apply_external_forces(None);
+                self.kick(dt / 2.0);
+            }
+            Integrator::RungeKutta4 => self.runge_kutta4(dt, &mut compute_forces),
+        }
+        self.grow_older(dt);
+        self.elapsed += dt;
+    }
 
-            self.remove_particle(lesser.id);
+    /// Ages every particle by `dt`, then lets them evaporate and removes the ones that
+    /// expired
+    fn grow_older(&mut self, dt: Scalar) {
+        let columns = self.particles.columns_mut();
+        for age in columns.ages.iter_mut() {
+            *age += dt;
+        }
+        let evaporation = match self.evaporation {
+            Some(evaporation) => evaporation,
+            None => return,
+        };
+        if evaporation.mass_loss_rate > 0.0 {
+            for i in 0..columns.masses.len() {
+                if columns.fixed[i] {
+                    continue;
+                }
+                let mass = evaporation.mass_after(columns.masses[i], dt);
+                // Shrinks along with the mass, keeping the density the same
+                if columns.kinds[i] != BodyKind::BlackHole && columns.masses[i] > 0.0 {
+                    columns.radii[i] *= (mass / columns.masses[i]).sqrt();
+                }
+                columns.masses[i] = mass;
+            }
+        }
+        let expired: Vec<ParticleId> = (0..self.particles.len())
+            .filter(|&i| {
+                !self.particles.fixed()[i]
+                    && evaporation.expired(self.particles.ages()[i], self.particles.masses()[i])
+            })
+            .map(|i| self.particles.ids()[i])
+            .collect();
+        self.stats.evaporated += expired.len();
+        for id in expired {
+            self.particles.remove(id);
+            self.observers.emit(SimulationEvent::ParticleRemoved(id));
         }
     }
 
-    /// Removes a particle with the given id.
-    fn remove_particle(&mut self, id: Uuid) {
-        self.particles = self
+    /// Kick-drift-kick leapfrog in which every particle steps by its own power-of-two
+    /// fraction of `dt`. All particles drift together on the smallest sub-step, but
+    /// accelerations are only recomputed (always on the CPU) for the particles at the end
+    /// of one of their own steps. The accelerations of every particle must be current.
+    fn block_step(&mut self, dt: Scalar, blocks: BlockTimeSteps) {
+        let substeps = 1usize << blocks.max_level;
+        let h = dt / substeps as Scalar;
+        let softening = self.softening;
+        // Sub-steps per step of each particle, for the acceleration it has now
+        let stride = |a: cgmath::Vector2<Scalar>, fixed: bool| {
+            if fixed {
+                substeps
+            } else {
+                substeps >> blocks.level(dt, softening, a.magnitude())
+            }
+        };
+        let mut strides: Vec<usize> = self
             .particles
-            .clone()
-            .into_iter()
-            .filter(|p| p.id != id)
+            .accelerations()
+            .iter()
+            .zip(self.particles.fixed())
+            .map(|(&a, &fixed)| stride(a, fixed))
             .collect();
+        let mut active = vec![false; strides.len()];
+        let mut smallest = substeps;
+
+        for s in 0..substeps {
+            let columns = self.particles.columns_mut();
+            for i in 0..strides.len() {
+                smallest = smallest.min(strides[i]);
+                if columns.fixed[i] {
+                    continue;
+                }
+                if s % strides[i] == 0 {
+                    let half = h * strides[i] as Scalar / 2.0;
+                    columns.velocities[i] += columns.accelerations[i] * half;
+                }
+                columns.positions[i] += columns.velocities[i] * h;
+                active[i] = (s + 1) % strides[i] == 0;
+            }
+
+            match self.solver {
+                ForceSolver::BarnesHut => self.step_tree(Some(&active)),
+                ForceSolver::Direct => self.step_direct(Some(&active)),
+            }
+            self.apply_external_forces(Some(&active));
+
+            let columns = self.particles.columns_mut();
+            for i in 0..strides.len() {
+                if !active[i] {
+                    continue;
+                }
+                let half = h * strides[i] as Scalar / 2.0;
+                columns.velocities[i] += columns.accelerations[i] * half;
+                // Smaller steps always line up with the current one, larger ones only
+                // where the sub-step is a multiple of their length
+                let wanted = stride(columns.accelerations[i], columns.fixed[i]);
+                let mut next = strides[i].min(wanted);
+                while next < wanted && (s + 1) % (next * 2) == 0 {
+                    next *= 2;
+                }
+                strides[i] = next;
+            }
+        }
+        self.stats.deepest_level = blocks.max_level - smallest.trailing_zeros();
+        self.apply_boundary();
     }
 
-    /// Sums up the forces acting on each particle in the system
-    pub fn integrate(&mut self) {
-        let particles = &mut self.particles;
+    /// Classic fourth order Runge-Kutta step. The accelerations of the first stage must
+    /// be set already, the other three stages are evaluated with `compute_forces` at
+    /// trial positions. Fixed particles are skipped.
+    fn runge_kutta4<F: FnMut(&mut Self)>(&mut self, dt: Scalar, compute_forces: &mut F) {
+        let x0 = self.particles.positions().to_vec();
+        let v0 = self.particles.velocities().to_vec();
+        // Weighted sums of the velocities and accelerations of all stages
+        let mut dx = v0.clone();
+        let mut dv = self.particles.accelerations().to_vec();
 
-        for i in 0..particles.len() {
-            let pt = particles.get_mut(i).unwrap();
+        for &(step, weight) in &[(dt / 2.0, 2.0), (dt / 2.0, 2.0), (dt, 1.0)] {
+            // Each stage starts from x0 and v0 using the rates of the previous one
+            let columns = self.particles.columns_mut();
+            for i in 0..x0.len() {
+                if !columns.fixed[i] {
+                    columns.positions[i] = x0[i] + columns.velocities[i] * step;
+                    columns.velocities[i] = v0[i] + columns.accelerations[i] * step;
+                }
+            }
+            compute_forces(self);
+            self.apply_external_forces(None);
+            for i in 0..x0.len() {
+                dx[i] += self.particles.velocities()[i] * weight;
+                dv[i] += self.particles.accelerations()[i] * weight;
+            }
+        }
+
+        let columns = self.particles.columns_mut();
+        for i in 0..x0.len() {
+            if !columns.fixed[i] {
+                columns.positions[i] = x0[i] + dx[i] * (dt / 6.0);
+                columns.velocities[i] = v0[i] + dv[i] * (dt / 6.0);
+            }
+        }
+        self.apply_boundary();
+    }
+
+    /// Adds the pull of every external potential and the drag to the current
+    /// accelerations of all particles, or of the ones flagged in `active` if it is given
+    fn apply_external_forces(&mut self, active: Option<&[bool]>) {
+        if self.potentials.is_empty() && self.drag.is_none() && self.script_accelerations.is_empty()
+        {
+            return;
+        }
+        let (potentials, g) = (&self.potentials, self.g);
+        let softening2 = self.softening * self.softening;
+        let gas_velocities = self.drag.map(|drag| {
+            let particles = &self.particles;
+            drag.gas_velocities(
+                particles.positions(),
+                particles.velocities(),
+                particles.masses(),
+            )
+        });
+        let columns = self.particles.columns_mut();
+        let bodies = columns
+            .accelerations
+            .iter_mut()
+            .zip(columns.positions.iter())
+            .zip(columns.velocities.iter());
+        for (i, ((acceleration, &position), &velocity)) in bodies.enumerate() {
+            if !active.is_none_or(|active| active[i]) {
+                continue;
+            }
+            for potential in potentials {
+                *acceleration += potential.acceleration(position, g, softening2);
+            }
+            if let (Some(drag), Some(gas)) = (self.drag, &gas_velocities) {
+                *acceleration += drag.acceleration(velocity, gas[i]);
+            }
+            if let Some(&pushed) = self.script_accelerations.get(i) {
+                *acceleration += pushed;
+            }
+        }
+    }
 
-            let ts = self.time_step;
-            pt.velocity += pt.acceleration * ts;
-            let position = pt.velocity * ts;
-            pt.position += position;
+    /// Updates velocities from the current accelerations. Fixed particles are skipped.
+    fn kick(&mut self, dt: Scalar) {
+        let columns = self.particles.columns_mut();
+        let bodies = columns
+            .velocities
+            .iter_mut()
+            .zip(columns.accelerations.iter())
+            .zip(columns.fixed);
+        for ((v, a), &fixed) in bodies {
+            if !fixed {
+                *v += a * dt;
+            }
+        }
+    }
 
-            // Position vector of the vertex closest to the boundary
-            let pv = pt.position + pt.velocity.normalize_to(pt.radius);
-            if pv.x > 1000.0 || pv.y > 1000.0 || pv.x < 0.0 || pv.y < 0.0 {
-                let m1 = pt.mass;
-                let m2 = pt.mass * 100.0;
-                pt.velocity = ((m1 - m2) / (m1 + m2)) * pt.velocity;
+    /// Updates positions from the current velocities and applies the boundary condition.
+    /// Fixed particles are skipped.
+    fn drift(&mut self, dt: Scalar) {
+        let columns = self.particles.columns_mut();
+        let bodies = columns
+            .positions
+            .iter_mut()
+            .zip(columns.velocities.iter())
+            .zip(columns.fixed);
+        for ((x, v), &fixed) in bodies {
+            if !fixed {
+                *x += v * dt;
             }
         }
+        self.apply_boundary();
     }
 
+    /// Removes all particles from the simulation
     pub fn reset(&mut self) {
+        for &id in self.particles.ids() {
+            self.observers.emit(SimulationEvent::ParticleRemoved(id));
+        }
         self.particles.clear();
+        self.elapsed = 0.0;
+    }
+
+    /// Lets the boundary condition move or remove particles, reporting the removed ones
+    fn apply_boundary(&mut self) {
+        let before = match self.boundary {
+            BoundaryCondition::Open if !self.observers.is_empty() => self.particles.ids().to_vec(),
+            _ => Vec::new(),
+        };
+        let count = self.particles.len();
+        self.boundary.apply(&mut self.particles);
+        if self.particles.len() != count {
+            self.script_accelerations.clear();
+        }
+        for id in before {
+            if !self.particles.contains(id) {
+                self.observers.emit(SimulationEvent::ParticleRemoved(id));
+            }
+        }
     }
 
+    /// Offsets the time step by `step_offset`. The time step never drops below the
+    /// base step the simulation was created with.
     pub fn change_time_step(&mut self, step_offset: Scalar) {
         let dt = self.time_step;
         let new_step = dt + step_offset;
@@ -154,29 +1080,1531 @@ impl Simulation {
         }
     }
 
+    /// Returns the current time step
     pub fn get_time_step(&self) -> Scalar {
         self.time_step
     }
 
+    /// Size of the last step taken. Differs from `get_time_step` with an adaptive time
+    /// step.
+    pub fn get_effective_time_step(&self) -> Scalar {
+        self.effective_step
+    }
+
+    pub fn get_adaptive_step(&self) -> Option<AdaptiveTimeStep> {
+        self.adaptive_step
+    }
+
+    /// Sets how the step size is picked from the accelerations each step. `None` always
+    /// steps by `get_time_step`.
+    pub fn set_adaptive_step(&mut self, adaptive_step: Option<AdaptiveTimeStep>) {
+        self.adaptive_step = adaptive_step;
+    }
+
+    pub fn get_block_steps(&self) -> Option<BlockTimeSteps> {
+        self.block_steps
+    }
+
+    /// Lets every particle step by its own fraction of the time step when set. Block
+    /// time steps always use kick-drift-kick leapfrog, whatever the integrator.
+    pub fn set_block_steps(&mut self, block_steps: Option<BlockTimeSteps>) {
+        self.block_steps = block_steps;
+    }
+
+    /// Largest acceleration of any particle that is free to move
+    fn max_acceleration(&self) -> Scalar {
+        self.particles
+            .accelerations()
+            .iter()
+            .zip(self.particles.fixed())
+            .filter(|(_, &fixed)| !fixed)
+            .map(|(a, _)| a.magnitude2())
+            .fold(0.0, Scalar::max)
+            .sqrt()
+    }
+
+    /// Returns the simulated time that has passed since the simulation started
+    pub fn get_elapsed(&self) -> Scalar {
+        self.elapsed
+    }
+
+    /// Gravitational constant passed to `new`
+    pub fn get_g(&self) -> Scalar {
+        self.g
+    }
+
+    /// Softening length passed to `new`. Other force solvers (e.g. on the GPU) should
+    /// use the same value.
+    pub fn get_softening(&self) -> Scalar {
+        self.softening
+    }
+
+    /// Timings of the last call to `advance`
+    pub fn get_stats(&self) -> StepStats {
+        self.stats
+    }
+
+    /// Shape of the Barnes-Hut tree of all particles as the last step left it. Stale
+    /// while forces are summed directly or over the separate trees of groups that do
+    /// not all feel each other.
+    pub fn get_tree_stats(&self) -> TreeStats {
+        self.quadtree.stats()
+    }
+
+    /// Returns the integrator used to advance particles through time
+    pub fn get_integrator(&self) -> Integrator {
+        self.integrator
+    }
+
+    pub fn set_integrator(&mut self, integrator: Integrator) {
+        self.integrator = integrator;
+    }
+
+    /// Barnes-Hut opening angle
+    pub fn get_theta(&self) -> Scalar {
+        self.theta
+    }
+
+    /// Sets the Barnes-Hut opening angle, clamped to `MIN_THETA..=MAX_THETA`
+    pub fn set_theta(&mut self, theta: Scalar) {
+        self.theta = theta.clamp(constants::MIN_THETA, constants::MAX_THETA);
+    }
+
+    pub fn get_expansion(&self) -> Expansion {
+        self.expansion
+    }
+
+    /// Selects what the Barnes-Hut tree nodes keep of the mass below them. Only affects
+    /// `ForceSolver::BarnesHut`.
+    pub fn set_expansion(&mut self, expansion: Expansion) {
+        self.expansion = expansion;
+    }
+
+    pub fn get_incremental_tree(&self) -> bool {
+        self.incremental_tree
+    }
+
+    /// Updates the Barnes-Hut tree in place from one step to the next instead of
+    /// building it from scratch. Building the tree gets cheaper, but its nodes drift out
+    /// of depth first order, which makes walking it slower on large runs.
+    pub fn set_incremental_tree(&mut self, incremental_tree: bool) {
+        self.incremental_tree = incremental_tree;
+    }
+
+    pub fn get_leaf_capacity(&self) -> usize {
+        self.quadtree.leaf_capacity()
+    }
+
+    /// Sets how many particles a leaf of the Barnes-Hut tree holds before it is split.
+    /// See `QuadTree::set_leaf_capacity`.
+    pub fn set_leaf_capacity(&mut self, capacity: usize) {
+        self.quadtree.set_leaf_capacity(capacity);
+    }
+
+    pub fn get_morton_sort(&self) -> Option<usize> {
+        self.morton_sort
+    }
+
+    /// Sorts the particles with `sort_particles` every `interval` steps, or never if it
+    /// is `None`. Particles drift apart slowly, so sorting every few dozen steps keeps
+    /// most of the benefit at a fraction of the cost.
+    pub fn set_morton_sort(&mut self, interval: Option<usize>) {
+        self.morton_sort = interval.map(|n| n.max(1));
+        self.steps_since_sort = 0;
+    }
+
+    pub fn get_solver(&self) -> ForceSolver {
+        self.solver
+    }
+
+    /// Switches the way accelerations are computed from the next step on
+    pub fn set_solver(&mut self, solver: ForceSolver) {
+        self.solver = solver;
+    }
+
+    pub fn get_boundary(&self) -> BoundaryCondition {
+        self.boundary
+    }
+
+    pub fn get_merge_model(&self) -> MergeModel {
+        self.merge_model
+    }
+
+    /// Changes how colliding particles are combined from the next collision on
+    pub fn set_merge_model(&mut self, merge_model: MergeModel) {
+        self.merge_model = merge_model;
+    }
+
+    pub fn get_fragmentation(&self) -> Option<Fragmentation> {
+        self.fragmentation
+    }
+
+    /// Lets particles colliding faster than the threshold of `fragmentation` shatter
+    /// instead of merging, or merges every collision again with `None`
+    pub fn set_fragmentation(&mut self, fragmentation: Option<Fragmentation>) {
+        self.fragmentation = fragmentation;
+    }
+
+    pub fn get_groups(&self) -> &GroupMatrix {
+        &self.groups
+    }
+
+    /// Changes which groups of particles feel and collide with which from the next step
+    /// on. Forces between groups that do not all feel each other are only computed on
+    /// the CPU, with one tree per set of groups felt.
+    pub fn set_groups(&mut self, groups: GroupMatrix) {
+        self.groups = groups;
+    }
+
+    /// Groups of particles sharing the same sources, unless every group feels every
+    /// other one
+    fn group_views(&self) -> Option<GroupViews> {
+        if self.groups.feels_all() {
+            return None;
+        }
+        let mut present = [false; GROUPS];
+        for &group in self.particles.groups() {
+            present[group as usize] = true;
+        }
+        let mut views = GroupViews {
+            view_of: [0; GROUPS],
+            sources: Vec::new(),
+        };
+        for group in (0..GROUPS).filter(|&group| present[group]) {
+            let mut sources = [false; GROUPS];
+            for from in (0..GROUPS).filter(|&from| present[from]) {
+                sources[from] = self.groups.feels(group as u8, from as u8);
+            }
+            views.view_of[group] = match views.sources.iter().position(|s| *s == sources) {
+                Some(view) => view,
+                None => {
+                    views.sources.push(sources);
+                    views.sources.len() - 1
+                }
+            };
+        }
+        Some(views)
+    }
+
+    pub fn get_evaporation(&self) -> Option<Evaporation> {
+        self.evaporation
+    }
+
+    /// Lets particles lose mass and expire as they age from the next step on, or keeps
+    /// them forever with `None`
+    pub fn set_evaporation(&mut self, evaporation: Option<Evaporation>) {
+        self.evaporation = evaporation;
+    }
+
+    pub fn get_drag(&self) -> Option<Drag> {
+        self.drag
+    }
+
+    /// Turns drag on or off. Drag is added during `integrate`, whichever solver computed
+    /// the forces between the particles.
+    pub fn set_drag(&mut self, drag: Option<Drag>) {
+        self.drag = drag;
+    }
+
+    pub fn get_interaction(&self) -> Interaction {
+        self.interaction
+    }
+
+    /// Chooses whether masses, charges or both act on each other from the next step on.
+    /// Charges (`Particle::charge`) are only felt with forces on the CPU.
+    pub fn set_interaction(&mut self, interaction: Interaction) {
+        self.interaction = interaction;
+    }
+
+    pub fn get_coulomb_constant(&self) -> Scalar {
+        self.coulomb_constant
+    }
+
+    /// Sets the Coulomb constant k that scales the forces between charges. 1 unless set
+    /// otherwise.
+    pub fn set_coulomb_constant(&mut self, coulomb_constant: Scalar) {
+        self.coulomb_constant = coulomb_constant;
+    }
+
+    pub fn get_force_law(&self) -> Option<&dyn ForceLaw> {
+        self.force_law.as_deref()
+    }
+
+    /// Makes the particles pull on each other with `force_law` (scaled by G) instead of
+    /// the softened gravity set up in `new`, or goes back to it with `None`. Custom laws
+    /// are summed one pair at a time, skip the quadrupole expansion and are not used by
+    /// the GPU solvers or external potentials.
+    pub fn set_force_law(&mut self, force_law: Option<Box<dyn ForceLaw>>) {
+        self.force_law = force_law;
+    }
+
+    #[cfg(feature = "scripting")]
+    pub fn get_script(&self) -> Option<&StepScript> {
+        self.script.as_ref()
+    }
+
+    /// Runs `script` at the start of every step from now on, or stops running one with
+    /// `None`
+    #[cfg(feature = "scripting")]
+    pub fn set_script(&mut self, script: Option<StepScript>) {
+        self.script = script;
+        self.script_error = None;
+    }
+
+    /// Why the script stopped, if it failed since the last call. Scripts that fail are
+    /// not run again.
+    #[cfg(feature = "scripting")]
+    pub fn take_script_error(&mut self) -> Option<ScriptError> {
+        self.script_error.take()
+    }
+
+    /// Changes what happens at the edges of the world. Particles already outside of the
+    /// world are dealt with right away.
+    pub fn set_boundary(&mut self, boundary: BoundaryCondition) {
+        self.boundary = boundary;
+        self.apply_boundary();
+    }
+
     /// Adds a particle to the simulation system and also checks for collision
     /// (merges particles if any of them overlap regardless of whether or not
-    ///  the simulation is paused)
-    pub fn add_particle(&mut self, p: Particle) {
-        self.particles.push(p);
+    ///  the simulation is paused). Returns the handle of the new particle, which no
+    /// longer refers to anything if it was merged into a heavier one right away.
+    pub fn add_particle(&mut self, p: Particle) -> ParticleId {
+        let id = self.particles.insert(massless_tracer(p));
+        self.observers.emit(SimulationEvent::ParticleAdded(id));
+        self.resolve_collisions();
+        id
+    }
+
+    /// Registers a fixed field that pulls on every particle from the next step on
+    pub fn add_potential(&mut self, potential: ExternalPotential) {
+        self.potentials.push(potential);
+    }
+
+    pub fn get_potentials(&self) -> &[ExternalPotential] {
+        &self.potentials
+    }
+
+    /// Removes every external potential
+    pub fn clear_potentials(&mut self) {
+        self.potentials.clear();
+    }
+
+    /// Adds several particles at once and then merges any overlapping ones. Prefer this
+    /// over repeated `add_particle` calls when loading many particles. Returns the
+    /// handles of the new particles in order.
+    pub fn add_particles<I: IntoIterator<Item = Particle>>(
+        &mut self,
+        particles: I,
+    ) -> Vec<ParticleId> {
+        let ids: Vec<_> = particles
+            .into_iter()
+            .map(|p| {
+                let id = self.particles.insert(massless_tracer(p));
+                self.observers.emit(SimulationEvent::ParticleAdded(id));
+                id
+            })
+            .collect();
+        self.resolve_collisions();
+        ids
+    }
+
+    /// Puts back a particle returned by `remove_particle`, under its old handle if
+    /// nothing took its place since. Returns the handle it ends up with.
+    pub fn restore_particle(&mut self, p: Particle) -> ParticleId {
+        let id = self.particles.restore(massless_tracer(p));
+        self.observers.emit(SimulationEvent::ParticleAdded(id));
         self.resolve_collisions();
+        id
     }
 
-    /// Returns a shared reference to particles
-    pub fn get_particles(&self) -> &Vec<Particle> {
+    /// Returns a shared reference to particles. Their order changes whenever particles
+    /// are removed, so hold on to `Particle::id` to keep track of one.
+    pub fn get_particles(&self) -> &ParticleStore {
         &self.particles
     }
 
-    /// Returns a vector containing all the particle instances (copy)
-    pub fn get_instances(&self) -> Vec<Instance> {
+    /// Returns the particle behind `id` unless it has been merged or removed since
+    pub fn get_particle(&self, id: ParticleId) -> Option<Particle> {
+        self.particles.get(id)
+    }
+
+    /// Velocity that puts a particle at `position` on a circular orbit around the center
+    /// of mass of all particles: `sqrt(G M / r)` counterclockwise, where `M` is the mass
+    /// within `r` of the center of mass, on top of the velocity of the center of mass
+    /// itself. Returns `None` if there is nothing to orbit at `position`.
+    pub fn circular_orbit_velocity(
+        &self,
+        position: cgmath::Vector2<Scalar>,
+    ) -> Option<cgmath::Vector2<Scalar>> {
+        let particles = &self.particles;
+        let total: Scalar = particles.masses().iter().sum();
+        if total <= 0.0 {
+            return None;
+        }
+        let (mut center, mut momentum) = (cgmath::vec2(0.0, 0.0), cgmath::vec2(0.0, 0.0));
+        for i in 0..particles.len() {
+            center += particles.positions()[i] * particles.masses()[i];
+            momentum += particles.velocities()[i] * particles.masses()[i];
+        }
+        let (center, drift) = (center / total, momentum / total);
+
+        let offset = position - center;
+        let r = offset.magnitude();
+        let enclosed: Scalar = particles
+            .positions()
+            .iter()
+            .zip(particles.masses())
+            .filter(|&(&p, _)| (p - center).magnitude2() <= r * r)
+            .map(|(_, &m)| m)
+            .sum();
+        if r == 0.0 || enclosed == 0.0 {
+            return None;
+        }
+        let speed = (self.g * enclosed / r).sqrt();
+        Some(drift + cgmath::vec2(-offset.y, offset.x) * (speed / r))
+    }
+
+    /// Removes a single particle from the simulation, returning it if it was still there
+    pub fn remove_particle(&mut self, id: ParticleId) -> Option<Particle> {
+        let removed = self.particles.remove(id);
+        if removed.is_some() {
+            self.observers.emit(SimulationEvent::ParticleRemoved(id));
+        }
+        removed
+    }
+
+    /// Changes the particle behind `id` with `edit`, e.g. to give it a push or another
+    /// color. Returns false if it is gone. The particle keeps its id whatever `edit` does
+    /// with it.
+    pub fn edit_particle<F: FnOnce(&mut Particle)>(&mut self, id: ParticleId, edit: F) -> bool {
+        let i = match self.particles.index_of(id) {
+            Some(i) => i,
+            None => return false,
+        };
+        let mut particle = self.particles.particle(i);
+        edit(&mut particle);
+        self.particles.set(i, &massless_tracer(particle));
+        true
+    }
+
+    /// Every particle whose center lies in the rectangle spanned by the opposite corners
+    /// `corner` and `opposite`
+    pub fn find_particles_in(
+        &self,
+        corner: cgmath::Vector2<Scalar>,
+        opposite: cgmath::Vector2<Scalar>,
+    ) -> Vec<ParticleId> {
+        let (min_x, max_x) = (corner.x.min(opposite.x), corner.x.max(opposite.x));
+        let (min_y, max_y) = (corner.y.min(opposite.y), corner.y.max(opposite.y));
         self.particles
-            .clone()
-            .into_iter()
-            .map(|p| p.to_instance())
+            .ids()
+            .iter()
+            .zip(self.particles.positions())
+            .filter(|(_, p)| p.x >= min_x && p.x <= max_x && p.y >= min_y && p.y <= max_y)
+            .map(|(&id, _)| id)
             .collect()
     }
+
+    /// Returns the particle covering `position`, the one whose center is closest if
+    /// several overlap there
+    pub fn find_particle_at(&mut self, position: cgmath::Vector2<Scalar>) -> Option<ParticleId> {
+        self.find_particle_near(position, 0.0)
+    }
+
+    /// Same as `find_particle_at` but particles also count if their edge is within
+    /// `tolerance` of `position`. Updates the quadtree to search it, since it is only
+    /// kept up to date while stepping with Barnes-Hut.
+    pub fn find_particle_near(
+        &mut self,
+        position: cgmath::Vector2<Scalar>,
+        tolerance: Scalar,
+    ) -> Option<ParticleId> {
+        let max_radius = self
+            .particles
+            .radii()
+            .iter()
+            .copied()
+            .fold(0.0, Scalar::max);
+        self.update_quadtree();
+        self.quadtree
+            .query_radius(position, max_radius + tolerance)
+            .into_iter()
+            .map(|p| {
+                (
+                    p.id,
+                    (p.position - position).magnitude2(),
+                    p.radius + tolerance,
+                )
+            })
+            .filter(|&(_, d2, reach)| d2 <= reach * reach)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(id, ..)| id)
+    }
+
+    /// Replaces the contents of `instances` with an instance for every particle, colored
+    /// according to `mapping`. Meant to be called with the same staging vector every
+    /// frame so that its allocation is reused.
+    /// Returns the range of the mapped quantity, or None if particles keep their own color
+    /// (or there are none).
+    pub fn get_instances(
+        &self,
+        mapping: ColorMapping,
+        colormap: Colormap,
+        instances: &mut Vec<Instance>,
+    ) -> Option<(Scalar, Scalar)> {
+        let particles = &self.particles;
+        let value = |i: usize| match mapping {
+            ColorMapping::Fixed => 0.0,
+            ColorMapping::Mass => particles.masses()[i],
+            ColorMapping::Speed => particles.velocities()[i].magnitude(),
+            ColorMapping::Acceleration => particles.accelerations()[i].magnitude(),
+        };
+        // Mapped values are normalized against the range spanned by all particles except
+        // for tracers, which are drawn in a color of their own
+        let (min, max) = (0..particles.len())
+            .filter(|&i| particles.kinds()[i] != BodyKind::Tracer)
+            .map(value)
+            .fold((Scalar::MAX, Scalar::MIN), |(min, max), v| {
+                (min.min(v), max.max(v))
+            });
+        let range = (max - min).max(Scalar::EPSILON);
+
+        instances.clear();
+        for i in 0..particles.len() {
+            let position = particles.positions()[i];
+            Instance::push_body(
+                instances,
+                particles.kinds()[i],
+                [to_f32(position.x), to_f32(position.y)],
+                to_f32(particles.radii()[i]),
+                match mapping {
+                    ColorMapping::Fixed => particles.colors()[i],
+                    _ => colormap.sample((value(i) - min) / range),
+                },
+            );
+        }
+        match mapping {
+            ColorMapping::Fixed => None,
+            _ if particles.is_empty() => None,
+            _ => Some((min, max)),
+        }
+    }
+}
+
+/// Zeroes the mass and charge of tracers, which only follow the field
+fn massless_tracer(mut p: Particle) -> Particle {
+    if p.kind == BodyKind::Tracer {
+        p.mass = 0.0;
+        p.charge = 0.0;
+    }
+    p
+}
+
+/// Number of groups a particle can belong to
+const GROUPS: usize = u8::MAX as usize + 1;
+
+/// Particles grouped by the groups they feel. Particles of group `g` feel the particles
+/// of every group flagged in `sources[view_of[g]]`.
+struct GroupViews {
+    view_of: [usize; GROUPS],
+    sources: Vec<[bool; GROUPS]>,
+}
+
+/// Settings of a force evaluation, copied out of the simulation so that the tree can be
+/// walked while the simulation is borrowed mutably
+#[derive(Clone, Copy)]
+struct TreeWalk<'a> {
+    theta: Scalar,
+    g: Scalar,
+    softening2: Scalar,
+    period: Option<cgmath::Vector2<Scalar>>,
+    /// Whether masses attract each other
+    gravity: bool,
+    quadrupole: bool,
+    /// Replaces softened gravity if set
+    law: Option<&'a dyn ForceLaw>,
+    /// Coulomb constant, if charges act on each other
+    coulomb: Option<Scalar>,
+}
+
+impl TreeWalk<'_> {
+    /// Acceleration of `p` due to every node of `quadtree` accepted by the opening
+    /// criterion. The nodes and leaf particles are gathered into an interaction list
+    /// first and their pull is then summed several at a time.
+    fn acceleration(self, quadtree: &QuadTree, p: &Particle) -> cgmath::Vector2<Scalar> {
+        let position = p.position;
+        let charged = self.coulomb.is_some() && p.charge != 0.0;
+        INTERACTIONS.with(|interactions| {
+            let (masses, charges) = &mut *interactions.borrow_mut();
+            masses.clear();
+            charges.clear();
+            let mut acceleration = cgmath::vec2(0.0, 0.0);
+            for node in QuadTreeIter::with_period(position, self.theta, quadtree, self.period) {
+                // The monopole terms are summed once the walk is done. Massless nodes,
+                // such as those only holding tracers, pull on nothing.
+                if self.gravity && node.particle.mass != 0.0 {
+                    masses.push(node.particle.position, node.particle.mass);
+                }
+                if self.quadrupole {
+                    let d = minimum_image(node.particle.position - position, self.period);
+                    let d2 = d.magnitude2() + self.softening2;
+                    acceleration += quadrupole_acceleration(*node.quadrupole, d, d2);
+                }
+                if charged {
+                    for &(charge, center) in &[node.charge.positive, node.charge.negative] {
+                        if charge != 0.0 {
+                            charges.push(center, charge);
+                        }
+                    }
+                }
+            }
+            (acceleration + self.sum(p, masses)) * self.g + self.electric(p, charges)
+        })
+    }
+
+    /// Potential energy of `p` in the field of every node of `quadtree` accepted by the
+    /// opening criterion
+    fn potential(self, quadtree: &QuadTree, p: &Particle) -> Scalar {
+        let charged = self.coulomb.is_some() && p.charge != 0.0;
+        let inverse_distance = |center: cgmath::Vector2<Scalar>| {
+            let d = minimum_image(center - p.position, self.period);
+            1.0 / (d.magnitude2() + self.softening2).sqrt()
+        };
+        QuadTreeIter::with_period(p.position, self.theta, quadtree, self.period)
+            .map(|node| {
+                let mut energy = 0.0;
+                if self.gravity && node.particle.mass != 0.0 {
+                    energy -= self.g
+                        * p.mass
+                        * node.particle.mass
+                        * inverse_distance(node.particle.position);
+                }
+                if let Some(k) = self.coulomb.filter(|_| charged) {
+                    for &(charge, center) in &[node.charge.positive, node.charge.negative] {
+                        if charge != 0.0 {
+                            energy += k * p.charge * charge * inverse_distance(center);
+                        }
+                    }
+                }
+                energy
+            })
+            .sum()
+    }
+
+    /// Pull of every source on `p` per unit of G
+    fn sum(self, p: &Particle, sources: &Sources) -> cgmath::Vector2<Scalar> {
+        match self.law {
+            Some(law) => law.accel_sum(p, sources, self.period),
+            None => sources.acceleration(p.position, self.softening2, self.period),
+        }
+    }
+
+    /// Acceleration of `p` due to `charges`, `-k q Σ qᵢ d / (d² + ε²)^(3/2) / m` along the
+    /// offsets d towards them. Nothing if electrostatics are off or `p` has no charge or
+    /// mass.
+    fn electric(self, p: &Particle, charges: &Sources) -> cgmath::Vector2<Scalar> {
+        match self.coulomb {
+            Some(k) if p.charge != 0.0 && p.mass > 0.0 => {
+                charges.acceleration(p.position, self.softening2, self.period)
+                    * (-k * p.charge / p.mass)
+            }
+            _ => cgmath::vec2(0.0, 0.0),
+        }
+    }
+}
+
+thread_local! {
+    /// Masses and charges of the nodes and particles accepted by the tree walk of the
+    /// current particle, gathered so that their pull can be summed with SIMD. Kept per
+    /// thread to reuse the memory.
+    static INTERACTIONS: RefCell<(Sources, Sources)> = RefCell::new((Sources::new(), Sources::new()));
+}
+
+/// Follows `merged_into` to the particle that `i` ended up in, shortening the path on the
+/// way so that long merge chains stay cheap
+fn root(merged_into: &mut [usize], mut i: usize) -> usize {
+    while merged_into[i] != i {
+        merged_into[i] = merged_into[merged_into[i]];
+        i = merged_into[i];
+    }
+    i
+}
+
+/// Pull of a quadrupole moment `q` (as stored in `QuadNode::quadrupole`) per unit of G on
+/// a particle at offset `d` from the node's center of mass towards the node, where `d2`
+/// is the softened squared distance. The negative gradient of the quadrupole term
+/// `-½ dᵀQd / |d|⁵` of the potential.
+fn quadrupole_acceleration(
+    q: [Scalar; 3],
+    d: cgmath::Vector2<Scalar>,
+    d2: Scalar,
+) -> cgmath::Vector2<Scalar> {
+    let qd = cgmath::vec2(q[0] * d.x + q[1] * d.y, q[1] * d.x + q[2] * d.y);
+    (d * (2.5 * d.dot(qd) / d2) - qd) / (d2 * d2 * d2.sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drag::{DragFrame, DragLaw};
+    use crate::primitives::{color, particle::BodyKind, scalar::consts};
+    use crate::test_support::particle_at;
+    use uuid::Uuid;
+
+    #[test]
+    fn it_merges_overlapping_particles() {
+        let mut sim = Simulation::new(0.05, 1.0, 1.0, 0.0, Integrator::Euler);
+        sim.add_particle(particle_at(500.0, 500.0, 3.0));
+        sim.add_particle(particle_at(501.0, 500.0, 1.0));
+        assert_eq!(sim.get_particles().len(), 1);
+    }
+
+    #[test]
+    fn it_tells_subscribers_what_happened() {
+        let mut sim = Simulation::new(0.05, 1.0, 1.0, 0.0, Integrator::Euler);
+        let events = sim.subscribe();
+        let big = sim.add_particle(particle_at(500.0, 500.0, 3.0));
+        let small = sim.add_particle(particle_at(501.0, 500.0, 1.0));
+        let far = sim.add_particle(particle_at(900.0, 900.0, 1.0));
+        sim.advance();
+        sim.remove_particle(far);
+        let received: Vec<SimulationEvent> = events.try_iter().collect();
+        assert!(matches!(
+            received[..],
+            [
+                SimulationEvent::ParticleAdded(a),
+                SimulationEvent::ParticleAdded(b),
+                SimulationEvent::ParticlesMerged { survivor, absorbed },
+                SimulationEvent::ParticleAdded(c),
+                SimulationEvent::StepCompleted(_),
+                SimulationEvent::ParticleRemoved(d),
+            ] if a == big && b == small && survivor == big && absorbed == small
+                && c == far && d == far
+        ));
+
+        // Particles leaving an open world are reported too, and nothing is sent once the
+        // receiver is gone
+        let mut escaping = particle_at(100.0, 100.0, 1.0);
+        escaping.velocity = cgmath::vec2(1e6, 0.0);
+        let escaping = sim.add_particle(escaping);
+        sim.set_boundary(BoundaryCondition::Open);
+        sim.advance();
+        let received: Vec<SimulationEvent> = events.try_iter().collect();
+        assert!(received
+            .iter()
+            .any(|e| matches!(e, SimulationEvent::ParticleRemoved(id) if *id == escaping)));
+        drop(events);
+        sim.advance();
+        assert!(sim.observers.is_empty());
+    }
+
+    /// Largest deviation from the initial orbital radius of a light body circling a
+    /// heavy one over roughly two orbits
+    fn orbit_radius_error(integrator: Integrator) -> Scalar {
+        let mut sim = Simulation::new(0.05, 1.0, 1.0, 0.0, integrator);
+        let (mass, r) = (10000.0, 100.0);
+        let mut sun = particle_at(500.0, 500.0, 5.0);
+        sun.mass = mass;
+        let mut planet = particle_at(500.0 + r, 500.0, 1.0);
+        planet.mass = 1.0;
+        planet.velocity = cgmath::vec2(0.0, (mass / r).sqrt());
+        sim.add_particle(sun);
+        sim.add_particle(planet);
+
+        let mut max_error: Scalar = 0.0;
+        for _ in 0..2500 {
+            sim.advance();
+            let particles = sim.get_particles();
+            let d = (particles.particle(1).position - particles.particle(0).position).magnitude();
+            max_error = max_error.max((d - r).abs());
+        }
+        max_error
+    }
+
+    #[test]
+    fn it_keeps_orbits_stable_with_leapfrog() {
+        let euler = orbit_radius_error(Integrator::Euler);
+        let leapfrog = orbit_radius_error(Integrator::Leapfrog);
+        assert!(leapfrog < 1.0, "leapfrog radius error {}", leapfrog);
+        assert!(leapfrog < euler, "leapfrog {} vs euler {}", leapfrog, euler);
+    }
+
+    /// Distance between where a planet ends up after half a circular orbit around a fixed
+    /// sun and where it should be, with a time step far too coarse for Euler
+    fn half_orbit_error(integrator: Integrator) -> Scalar {
+        let (mass, r): (Scalar, Scalar) = (10000.0, 100.0);
+        let speed = (mass / r).sqrt();
+        let period = 2.0 * consts::PI * r / speed;
+        let steps = 20;
+        let mut sim = Simulation::new(period / 2.0 / steps as Scalar, 1.0, 1.0, 0.0, integrator);
+        let mut sun = particle_at(500.0, 500.0, 5.0);
+        sun.mass = mass;
+        sun.fixed = true;
+        let mut planet = particle_at(500.0 + r, 500.0, 1.0);
+        planet.mass = 1.0;
+        planet.velocity = cgmath::vec2(0.0, speed);
+        sim.add_particle(sun);
+        sim.add_particle(planet);
+
+        for _ in 0..steps {
+            sim.advance();
+        }
+        (sim.get_particles().particle(1).position - cgmath::vec2(500.0 - r, 500.0)).magnitude()
+    }
+
+    #[test]
+    fn it_follows_orbits_closely_with_runge_kutta() {
+        let euler = half_orbit_error(Integrator::Euler);
+        let leapfrog = half_orbit_error(Integrator::Leapfrog);
+        let rk4 = half_orbit_error(Integrator::RungeKutta4);
+        assert!(rk4 < 1.0, "rk4 error {}", rk4);
+        assert!(rk4 < leapfrog, "rk4 {} vs leapfrog {}", rk4, leapfrog);
+        assert!(leapfrog < euler, "leapfrog {} vs euler {}", leapfrog, euler);
+    }
+
+    #[test]
+    fn it_shrinks_the_time_step_during_close_encounters() {
+        let mut sim = Simulation::new(0.05, 1.0, 1.0, 1.0, Integrator::Leapfrog);
+        sim.set_adaptive_step(Some(AdaptiveTimeStep::new(0.2, 0.001, 0.05)));
+        let mut a = particle_at(200.0, 500.0, 1.0);
+        a.mass = 2000.0;
+        let mut b = particle_at(800.0, 500.0, 1.0);
+        b.mass = 2000.0;
+        sim.add_particle(a.clone());
+        sim.add_particle(b.clone());
+        sim.advance();
+        assert_eq!(sim.get_effective_time_step(), 0.05);
+
+        // Same pair a few softening lengths apart
+        sim.reset();
+        a.position.x = 497.0;
+        b.position.x = 503.0;
+        sim.add_particle(a);
+        sim.add_particle(b);
+        sim.advance();
+        let dt = sim.get_effective_time_step();
+        assert!((0.001..0.05).contains(&dt), "{}", dt);
+        assert_eq!(sim.get_elapsed(), dt);
+        assert_eq!(sim.get_time_step(), 0.05);
+    }
+
+    /// Largest deviation from its starting radius of a planet on a tight circular orbit
+    /// around a fixed sun over one orbit, with a time step as long as a quarter orbit
+    fn tight_orbit_error(block_steps: Option<BlockTimeSteps>) -> (Scalar, u32) {
+        let (mass, r): (Scalar, Scalar) = (10000.0, 10.0);
+        // Circular speed with a softening length of 1
+        let speed = (mass * r * r / (r * r + 1.0).powf(1.5)).sqrt();
+        let period = 2.0 * consts::PI * r / speed;
+        let mut sim = Simulation::new(period / 4.0, 1.0, 1.0, 1.0, Integrator::Leapfrog);
+        sim.set_block_steps(block_steps);
+        let mut sun = particle_at(500.0, 500.0, 5.0);
+        sun.mass = mass;
+        sun.fixed = true;
+        let mut planet = particle_at(500.0 + r, 500.0, 1.0);
+        planet.mass = 1.0;
+        planet.velocity = cgmath::vec2(0.0, speed);
+        sim.add_particle(sun);
+        sim.add_particle(planet);
+        // Far away and barely pulled, so it can take the whole step at once
+        sim.add_particle(particle_at(100.0, 100.0, 1.0));
+
+        let mut max_error: Scalar = 0.0;
+        let mut deepest = 0;
+        for _ in 0..4 {
+            sim.advance();
+            deepest = deepest.max(sim.get_stats().deepest_level);
+            let d =
+                (sim.get_particles().particle(1).position - cgmath::vec2(500.0, 500.0)).magnitude();
+            max_error = max_error.max((d - r).abs());
+        }
+        (max_error, deepest)
+    }
+
+    #[test]
+    fn it_resolves_tight_orbits_with_block_time_steps() {
+        let (single, _) = tight_orbit_error(None);
+        let (blocks, deepest) = tight_orbit_error(Some(BlockTimeSteps::new(0.2, 8)));
+        assert!(blocks < 0.1, "block time step radius error {}", blocks);
+        assert!(single > 1.0, "single time step radius error {}", single);
+        assert!(deepest > 0 && deepest < 8, "deepest level {}", deepest);
+    }
+
+    #[test]
+    fn it_saves_and_restores_snapshots() {
+        let mut sim = Simulation::new(0.05, 0.5, 1.0, 1.0, Integrator::Leapfrog);
+        sim.add_particle(particle_at(400.0, 500.0, 2.0));
+        sim.add_particle(particle_at(600.0, 500.0, 2.0));
+        sim.advance();
+
+        let path = std::env::temp_dir().join(format!("nbody-{}.snapshot", Uuid::new_v4()));
+        sim.save(&path).unwrap();
+        let restored = Simulation::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(restored.get_elapsed(), sim.get_elapsed());
+        assert_eq!(restored.get_integrator(), Integrator::Leapfrog);
+        assert_eq!(restored.theta, 0.5);
+        assert_eq!(restored.get_softening(), 1.0);
+        let (a, b) = (
+            restored.get_particles().particle(1),
+            sim.get_particles().particle(1),
+        );
+        assert_eq!(a.id, b.id);
+        assert_eq!(a.position, b.position);
+        assert_eq!(a.velocity, b.velocity);
+    }
+
+    #[test]
+    fn it_colors_instances_by_mass() {
+        let mut sim = Simulation::new(0.05, 1.0, 1.0, 0.0, Integrator::Euler);
+        let mut light = particle_at(100.0, 100.0, 1.0);
+        light.color = [0.0, 1.0, 0.0, 1.0];
+        sim.add_particle(light);
+        sim.add_particle(particle_at(900.0, 900.0, 4.0));
+
+        let mut instances = Vec::new();
+        let range = sim.get_instances(ColorMapping::Fixed, Colormap::Heat, &mut instances);
+        assert_eq!(instances[0].color, [0.0, 1.0, 0.0, 1.0]);
+        assert_eq!(range, None);
+
+        // The staging vector is overwritten rather than appended to
+        let range = sim.get_instances(ColorMapping::Mass, Colormap::Heat, &mut instances);
+        assert_eq!(instances.len(), 2);
+        assert_eq!(instances[0].color, color::gradient(0.0));
+        assert_eq!(instances[1].color, color::gradient(1.0));
+        assert_eq!(range, Some((50.0, 200.0)));
+
+        sim.get_instances(ColorMapping::Mass, Colormap::Viridis, &mut instances);
+        assert_eq!(instances[1].color, Colormap::Viridis.sample(1.0));
+    }
+
+    #[test]
+    fn it_conserves_mass_and_momentum_when_merging() {
+        let mut sim = Simulation::new(0.05, 1.0, 1.0, 0.0, Integrator::Euler);
+        let mut a = particle_at(500.0, 500.0, 3.0);
+        a.velocity = cgmath::vec2(2.0, 0.0);
+        let mut b = particle_at(502.0, 500.0, 4.0);
+        b.velocity = cgmath::vec2(-1.0, 3.0);
+        let mut c = particle_at(504.0, 501.0, 1.0);
+        c.velocity = cgmath::vec2(0.0, -5.0);
+        let mass = a.mass + b.mass + c.mass;
+        let momentum = a.mass * a.velocity + b.mass * b.velocity + c.mass * c.velocity;
+        let center = (a.mass * a.position + b.mass * b.position + c.mass * c.position) / mass;
+
+        sim.add_particles(vec![a, b, c]);
+        let particles = sim.get_particles();
+        assert_eq!(particles.len(), 1);
+        let merged = particles.particle(0);
+        assert!((merged.mass - mass).abs() < 1e-3);
+        assert!((merged.mass * merged.velocity - momentum).magnitude() < 1e-2);
+        assert!((merged.position - center).magnitude() < 1e-3);
+        assert!((merged.radius - (9.0 + 16.0 + 1.0 as Scalar).sqrt()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn it_lets_black_holes_absorb_without_growing() {
+        for &merge_model in &[MergeModel::Conservative, MergeModel::Legacy] {
+            let mut sim = Simulation::new(0.05, 1.0, 1.0, 0.0, Integrator::Euler);
+            sim.set_merge_model(merge_model);
+            // Lighter than the star it swallows
+            let mut hole = particle_at(500.0, 500.0, 2.0);
+            hole.kind = BodyKind::BlackHole;
+            hole.mass = 10.0;
+            let mut star = particle_at(503.0, 500.0, 4.0);
+            star.velocity = cgmath::vec2(0.0, 1.0);
+            let mass = hole.mass + star.mass;
+            let momentum = star.mass * star.velocity;
+
+            sim.add_particles(vec![hole, star]);
+            let particles = sim.get_particles();
+            assert_eq!(particles.len(), 1);
+            let merged = particles.particle(0);
+            assert_eq!(merged.kind, BodyKind::BlackHole);
+            assert_eq!(merged.radius, 2.0);
+            assert!((merged.mass - mass).abs() < 1e-3);
+            assert!((merged.mass * merged.velocity - momentum).magnitude() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn it_shatters_fast_collisions() {
+        let mut sim = Simulation::new(0.05, 1.0, 1.0, 0.0, Integrator::Euler);
+        sim.set_fragmentation(Some(Fragmentation::new(10.0, 5, 0.5, 1.0)));
+        let mut a = particle_at(500.0, 500.0, 3.0);
+        a.velocity = cgmath::vec2(20.0, 0.0);
+        let mut b = particle_at(505.0, 500.0, 3.0);
+        b.velocity = cgmath::vec2(-20.0, 0.0);
+        let mut c = particle_at(600.0, 500.0, 3.0);
+        c.velocity = cgmath::vec2(5.0, 0.0);
+        let mut d = particle_at(605.0, 500.0, 3.0);
+        d.velocity = cgmath::vec2(-5.0, 0.0);
+        let mass = a.mass + b.mass + c.mass + d.mass;
+        sim.add_particles(vec![a, b, c, d]);
+
+        // The fast pair broke into five pieces, the slow one merged
+        let particles = sim.get_particles();
+        assert_eq!(particles.len(), 6);
+        assert!((particles.masses().iter().sum::<Scalar>() - mass).abs() < 1e-2);
+        let momentum = (0..particles.len()).fold(cgmath::vec2(0.0, 0.0), |sum, i| {
+            sum + particles.velocities()[i] * particles.masses()[i]
+        });
+        assert!(momentum.magnitude() < 1e-2);
+
+        // The fragments fly apart without touching each other
+        sim.advance();
+        assert_eq!(sim.get_particles().len(), 6);
+        assert_eq!(sim.get_stats().collisions, 0);
+    }
+
+    #[test]
+    fn it_merges_chains_of_colliding_particles_once() {
+        let mut sim = Simulation::new(0.05, 1.0, 1.0, 0.0, Integrator::Euler);
+        // Each particle only touches its neighbors
+        let particles: Vec<_> = (0..5)
+            .map(|i| particle_at(100.0 + 3.5 * i as Scalar, 500.0, 2.0))
+            .collect();
+        let mass: Scalar = particles.iter().map(|p| p.mass).sum();
+
+        sim.add_particles(particles);
+        assert_eq!(sim.get_particles().len(), 1);
+        assert!((sim.get_particles().particle(0).mass - mass).abs() < 1e-3);
+    }
+
+    #[test]
+    fn it_attracts_distant_particles() {
+        let mut sim = Simulation::new(0.05, 1.0, 1.0, 0.0, Integrator::Euler);
+        sim.add_particle(particle_at(400.0, 500.0, 2.0));
+        sim.add_particle(particle_at(600.0, 500.0, 2.0));
+        sim.advance();
+
+        let particles = sim.get_particles();
+        assert!(particles.particle(0).velocity.x > 0.0);
+        assert!(particles.particle(1).velocity.x < 0.0);
+    }
+
+    #[test]
+    fn it_only_pulls_on_groups_that_feel_each_other() {
+        for &solver in &[ForceSolver::BarnesHut, ForceSolver::Direct] {
+            let mut sim = Simulation::new(0.05, 0.5, 1.0, 0.0, Integrator::Euler);
+            sim.set_solver(solver);
+            // Group 1 feels group 0 but not itself, group 0 feels nothing of group 1
+            let mut groups = GroupMatrix::new();
+            groups.set_feels(0, 1, false);
+            groups.set_feels(1, 1, false);
+            sim.set_groups(groups);
+            let star = sim.add_particle(particle_at(500.0, 500.0, 2.0));
+            let mut tracers = Vec::new();
+            for &x in &[300.0, 700.0, 720.0] {
+                let mut tracer = particle_at(x, 500.0, 2.0);
+                tracer.group = 1;
+                tracers.push(sim.add_particle(tracer));
+            }
+            sim.step();
+
+            let acceleration = |id| sim.get_particle(id).unwrap().acceleration;
+            assert_eq!(acceleration(star), cgmath::vec2(0.0, 0.0), "{:?}", solver);
+            // Pulled toward the star by the star alone
+            let expected = 100.0 / (200.0 * 200.0);
+            assert!((acceleration(tracers[0]).x - expected).abs() < 1e-6);
+            assert!((acceleration(tracers[1]).x + expected).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn it_lets_groups_pass_through_each_other() {
+        let mut sim = Simulation::new(0.05, 1.0, 1.0, 0.0, Integrator::Euler);
+        let mut groups = GroupMatrix::new();
+        groups.set_collide(0, 2, false);
+        sim.set_groups(groups);
+        let mut dark = particle_at(501.0, 500.0, 2.0);
+        dark.group = 2;
+        sim.add_particles(vec![particle_at(500.0, 500.0, 2.0), dark]);
+        assert_eq!(sim.get_particles().len(), 2);
+        // The same group still merges
+        sim.add_particle(particle_at(499.0, 500.0, 2.0));
+        assert_eq!(sim.get_particles().len(), 2);
+    }
+
+    #[test]
+    fn it_moves_tracers_without_letting_them_pull_or_collide() {
+        for &solver in &[ForceSolver::BarnesHut, ForceSolver::Direct] {
+            let mut sim = Simulation::new(0.05, 0.5, 1.0, 0.0, Integrator::Euler);
+            sim.set_solver(solver);
+            let star = sim.add_particle(particle_at(500.0, 500.0, 2.0));
+            let mut tracers = Vec::new();
+            // One of them right on top of the star
+            for &x in &[300.0, 500.5, 700.0, 701.0] {
+                let mut tracer = particle_at(x, 500.0, 2.0);
+                tracer.kind = BodyKind::Tracer;
+                tracers.push(sim.add_particle(tracer));
+            }
+            assert_eq!(sim.get_particles().len(), 5);
+            sim.step();
+
+            let get = |id| sim.get_particle(id).unwrap();
+            assert_eq!(
+                get(star).acceleration,
+                cgmath::vec2(0.0, 0.0),
+                "{:?}",
+                solver
+            );
+            assert_eq!(get(tracers[0]).mass, 0.0);
+            let expected = 100.0 / (200.0 * 200.0);
+            assert!((get(tracers[0]).acceleration.x - expected).abs() < 1e-6);
+            assert!((get(tracers[2]).acceleration.x + expected).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn it_sums_contributions_from_every_body() {
+        let (g, softening) = (2.0, 5.0);
+        let mut sim = Simulation::new(0.05, 0.0, g, softening, Integrator::Euler);
+        sim.add_particle(particle_at(400.0, 500.0, 2.0));
+        sim.add_particle(particle_at(500.0, 500.0, 2.0));
+        sim.add_particle(particle_at(600.0, 500.0, 2.0));
+        sim.step();
+
+        let particles = sim.get_particles();
+        // The middle body is pulled equally in both directions
+        assert!(particles.particle(1).acceleration.magnitude() < 1e-6);
+        // The outer bodies feel both of the others
+        let pull = |d: Scalar| {
+            let d2 = d * d + softening * softening;
+            g * particles.particle(0).mass * d / (d2 * d2.sqrt())
+        };
+        let expected = pull(100.0) + pull(200.0);
+        assert!((particles.particle(0).acceleration.x - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn it_measures_kinetic_and_potential_energy() {
+        let (g, softening) = (2.0, 5.0);
+        let mut sim = Simulation::new(0.05, 0.0, g, softening, Integrator::Euler);
+        let mut moving = particle_at(500.0, 500.0, 3.0);
+        moving.velocity = cgmath::vec2(3.0, 4.0);
+        sim.add_particle(particle_at(400.0, 500.0, 2.0));
+        sim.add_particle(moving);
+
+        // Masses of 100 and 150
+        let energy = sim.energy();
+        assert!((energy.kinetic - 0.5 * 150.0 * 25.0).abs() < 1e-3);
+        let potential = -g * 100.0 * 150.0 / (100.0 * 100.0 + softening * softening).sqrt();
+        assert!((energy.potential - potential).abs() < 1e-3);
+        assert!((energy.total() - energy.kinetic - potential).abs() < 1e-3);
+    }
+
+    #[test]
+    fn it_attracts_across_periodic_boundaries() {
+        let mut sim = Simulation::new(0.05, 0.5, 1.0, 1.0, Integrator::Euler);
+        sim.set_boundary(BoundaryCondition::Periodic);
+        sim.add_particle(particle_at(10.0, 500.0, 2.0));
+        sim.add_particle(particle_at(990.0, 500.0, 2.0));
+        sim.add_particle(particle_at(500.0, 100.0, 2.0));
+        sim.step();
+
+        // The two bodies near the left and right edges are only 20 apart through the seam
+        let particles = sim.get_particles();
+        assert!(particles.particle(0).acceleration.x < 0.0);
+        assert!(particles.particle(1).acceleration.x > 0.0);
+    }
+
+    #[test]
+    fn it_measures_the_tree_error_against_direct_summation() {
+        let mut sim = Simulation::new(0.05, 0.0, 1.0, 1.0, Integrator::Euler);
+        for i in 0..20 {
+            let (x, y) = ((i * 37 % 900) as Scalar, (i * 53 % 900) as Scalar);
+            sim.add_particle(particle_at(50.0 + x, 50.0 + y, 1.0));
+        }
+        // Opening every node makes the tree code exact
+        assert!(sim.acceleration_error() < 1e-5);
+
+        sim.theta = 1.5;
+        let error = sim.acceleration_error();
+        assert!(error > 1e-5 && error < 1.0, "error {}", error);
+
+        sim.set_solver(ForceSolver::Direct);
+        sim.step();
+        let direct: Vec<_> = sim.get_particles().iter().map(|p| p.acceleration).collect();
+        sim.theta = 0.0;
+        sim.set_solver(ForceSolver::BarnesHut);
+        sim.step();
+        for (p, a) in sim.get_particles().iter().zip(direct) {
+            assert!((p.acceleration - a).magnitude() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn it_steps_the_same_with_an_incremental_tree() {
+        let cluster = || {
+            let mut sim = Simulation::new(0.5, 0.5, 1.0, 1.0, Integrator::Leapfrog);
+            for i in 0..60 {
+                let (x, y) = ((i * 37 % 300) as Scalar, (i * 53 % 300) as Scalar);
+                sim.add_particle(particle_at(350.0 + x, 350.0 + y, 0.1));
+            }
+            sim
+        };
+        let (mut full, mut incremental) = (cluster(), cluster());
+        incremental.set_incremental_tree(true);
+        for _ in 0..20 {
+            full.advance();
+            incremental.advance();
+        }
+        for (a, b) in full
+            .get_particles()
+            .iter()
+            .zip(incremental.get_particles().iter())
+        {
+            assert!((a.position - b.position).magnitude() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn it_steps_the_same_after_sorting_in_morton_order() {
+        let cluster = || {
+            let mut sim = Simulation::new(0.5, 0.7, 1.0, 1.0, Integrator::Leapfrog);
+            for i in 0..60 {
+                let (x, y) = ((i * 37 % 300) as Scalar, (i * 53 % 300) as Scalar);
+                sim.add_particle(particle_at(350.0 + x, 350.0 + y, 0.1));
+            }
+            sim
+        };
+        let (mut unsorted, mut sorted) = (cluster(), cluster());
+        sorted.set_morton_sort(Some(3));
+        for _ in 0..10 {
+            unsorted.advance();
+            sorted.advance();
+        }
+        assert_ne!(unsorted.get_particles().ids(), sorted.get_particles().ids());
+        for a in unsorted.get_particles().iter() {
+            let b = sorted.get_particle(a.id).unwrap();
+            assert!((a.position - b.position).magnitude() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn it_is_more_accurate_with_quadrupoles() {
+        let mut sim = Simulation::new(0.05, 1.0, 1.0, 1.0, Integrator::Euler);
+        for i in 0..200 {
+            // A few tight clumps spread over the world
+            let (cx, cy) = ((i % 5 * 190) as Scalar, (i % 3 * 300) as Scalar);
+            let (x, y) = ((i * 37 % 60) as Scalar, (i * 53 % 60) as Scalar);
+            sim.add_particle(particle_at(50.0 + cx + x, 50.0 + cy + y, 0.1));
+        }
+        let monopole = sim.acceleration_error();
+        sim.set_expansion(Expansion::Quadrupole);
+        let quadrupole = sim.acceleration_error();
+        assert!(
+            quadrupole < monopole / 2.0,
+            "monopole {}, quadrupole {}",
+            monopole,
+            quadrupole
+        );
+    }
+
+    #[test]
+    fn it_orbits_in_an_external_potential() {
+        let mut sim = Simulation::new(0.01, 1.0, 1.0, 0.0, Integrator::Leapfrog);
+        let (mass, r) = (10000.0, 100.0);
+        sim.add_potential(ExternalPotential::PointMass {
+            position: (500.0, 500.0),
+            mass,
+        });
+        let mut planet = particle_at(500.0 + r, 500.0, 1.0);
+        planet.velocity = cgmath::vec2(0.0, (mass / r).sqrt());
+        sim.add_particle(planet);
+
+        for _ in 0..1000 {
+            sim.advance();
+            let d = sim.get_particles().particle(0).position - cgmath::vec2(500.0, 500.0);
+            assert!((d.magnitude() - r).abs() < 0.5, "radius {}", d.magnitude());
+        }
+    }
+
+    #[test]
+    fn it_never_moves_fixed_particles() {
+        let mut sim = Simulation::new(0.05, 1.0, 1.0, 1.0, Integrator::Leapfrog);
+        let mut sun = particle_at(500.0, 500.0, 5.0);
+        sun.fixed = true;
+        let sun = sim.add_particle(sun);
+        let planet = sim.add_particle(particle_at(600.0, 500.0, 1.0));
+        let comet = sim.add_particle(particle_at(480.0, 500.0, 1.0));
+
+        for _ in 0..200 {
+            sim.advance();
+        }
+        let p = sim.get_particle(sun).unwrap();
+        assert_eq!(p.position, cgmath::vec2(500.0, 500.0));
+        assert!(p.fixed);
+        // The sun still pulls, and stays put even when absorbing what falls into it
+        assert!(sim.get_particle(planet).unwrap().position.x < 600.0);
+        assert!(sim.get_particle(comet).is_none());
+        assert!(p.mass > 50.0 * 5.0);
+    }
+
+    #[test]
+    fn it_finds_the_particle_under_a_point() {
+        let mut sim = Simulation::new(0.05, 1.0, 1.0, 1.0, Integrator::Euler);
+        let big = sim.add_particle(particle_at(500.0, 500.0, 8.0));
+        let small = sim.add_particle(particle_at(520.0, 500.0, 2.0));
+
+        assert_eq!(sim.find_particle_at(cgmath::vec2(506.0, 503.0)), Some(big));
+        assert_eq!(
+            sim.find_particle_at(cgmath::vec2(521.0, 499.0)),
+            Some(small)
+        );
+        assert_eq!(sim.find_particle_at(cgmath::vec2(514.0, 500.0)), None);
+        assert_eq!(
+            sim.find_particle_near(cgmath::vec2(514.0, 500.0), 5.0),
+            Some(small)
+        );
+
+        sim.remove_particle(big);
+        assert_eq!(sim.find_particle_at(cgmath::vec2(500.0, 500.0)), None);
+    }
+
+    #[test]
+    fn it_selects_and_edits_particles_in_a_rectangle() {
+        let mut sim = Simulation::new(0.05, 1.0, 1.0, 1.0, Integrator::Euler);
+        let inside = sim.add_particle(particle_at(500.0, 500.0, 2.0));
+        let edge = sim.add_particle(particle_at(600.0, 450.0, 2.0));
+        sim.add_particle(particle_at(700.0, 500.0, 2.0));
+        let mut found =
+            sim.find_particles_in(cgmath::vec2(600.0, 450.0), cgmath::vec2(400.0, 550.0));
+        found.sort();
+        assert_eq!(found, vec![inside, edge]);
+
+        assert!(sim.edit_particle(inside, |p| {
+            p.velocity += cgmath::vec2(1.0, 0.0);
+            p.group = 2;
+        }));
+        let p = sim.get_particle(inside).unwrap();
+        assert_eq!(
+            (p.velocity, p.group, p.id),
+            (cgmath::vec2(1.0, 0.0), 2, inside)
+        );
+        sim.remove_particle(inside);
+        assert!(!sim.edit_particle(inside, |p| p.mass = 1.0));
+    }
+
+    #[test]
+    fn it_computes_circular_orbit_velocities() {
+        let mut sim = Simulation::new(0.05, 1.0, 1.0, 1.0, Integrator::Euler);
+        assert!(sim
+            .circular_orbit_velocity(cgmath::vec2(0.0, 0.0))
+            .is_none());
+
+        let mut sun = particle_at(500.0, 500.0, 5.0);
+        sun.mass = 10000.0;
+        sun.velocity = cgmath::vec2(1.0, 0.0);
+        sim.add_particle(sun);
+        let v = sim
+            .circular_orbit_velocity(cgmath::vec2(500.0, 600.0))
+            .unwrap();
+        // 10 = sqrt(10000 / 100) to the left, plus the drift of the sun
+        assert!((v - cgmath::vec2(-9.0, 0.0)).magnitude() < 1e-4, "{:?}", v);
+        assert!(sim
+            .circular_orbit_velocity(cgmath::vec2(500.0, 500.0))
+            .is_none());
+    }
+
+    #[test]
+    fn it_predicts_trajectories_against_frozen_particles() {
+        let mut sim = Simulation::new(0.01, 1.0, 1.0, 0.0, Integrator::Leapfrog);
+        let (mass, r) = (10000.0, 100.0);
+        let mut sun = particle_at(500.0, 500.0, 5.0);
+        sun.mass = mass;
+        sim.add_particle(sun);
+
+        let center = cgmath::vec2(500.0, 500.0);
+        let start = center + cgmath::vec2(r, 0.0);
+        let path = sim.predict_trajectory(start, cgmath::vec2(0.0, (mass / r).sqrt()), 500);
+        assert_eq!(path.len(), 501);
+        assert_eq!(path[0], start);
+        for p in &path {
+            assert!(((p - center).magnitude() - r).abs() < 0.5);
+        }
+        // Nothing moved for real
+        assert_eq!(sim.get_particles().positions(), &[center]);
+
+        // Falling straight in ends at the surface of the sun
+        let path = sim.predict_trajectory(start, cgmath::vec2(0.0, 0.0), 10000);
+        assert!(path.len() < 10000);
+        assert!((path.last().unwrap() - center).magnitude() <= 5.0);
+    }
+
+    #[test]
+    fn it_clamps_theta() {
+        let mut sim = Simulation::new(0.05, 1.0, 1.0, 1.0, Integrator::Euler);
+        sim.set_theta(0.7);
+        assert_eq!(sim.get_theta(), 0.7);
+        sim.set_theta(-0.1);
+        assert_eq!(sim.get_theta(), constants::MIN_THETA);
+        sim.set_theta(100.0);
+        assert_eq!(sim.get_theta(), constants::MAX_THETA);
+    }
+
+    #[test]
+    fn it_pulls_with_a_custom_force_law() {
+        /// Pushes instead of pulls
+        #[derive(Debug)]
+        struct Repulsion;
+        impl ForceLaw for Repulsion {
+            fn accel(
+                &self,
+                p: &Particle,
+                other_pos: cgmath::Vector2<Scalar>,
+                other_mass: Scalar,
+            ) -> cgmath::Vector2<Scalar> {
+                (p.position - other_pos) * other_mass
+            }
+        }
+
+        for &solver in &[ForceSolver::BarnesHut, ForceSolver::Direct] {
+            let mut sim = Simulation::new(0.01, 1.0, 1.0, 1.0, Integrator::Leapfrog);
+            sim.set_solver(solver);
+            sim.set_force_law(Some(Box::new(Repulsion)));
+            sim.add_particle(particle_at(490.0, 500.0, 1.0));
+            sim.add_particle(particle_at(510.0, 500.0, 1.0));
+            for _ in 0..10 {
+                sim.advance();
+            }
+            let positions = sim.get_particles().positions();
+            assert!((positions[1].x - positions[0].x).abs() > 20.0);
+
+            // Back to gravity
+            sim.set_force_law(None);
+            assert!(sim.get_force_law().is_none());
+        }
+    }
+
+    #[test]
+    fn it_repels_like_and_attracts_opposite_charges() {
+        let charged = |x: Scalar, charge: Scalar| Particle {
+            mass: 1.0,
+            charge,
+            ..particle_at(x, 500.0, 0.5)
+        };
+        for &solver in &[ForceSolver::BarnesHut, ForceSolver::Direct] {
+            let mut sim = Simulation::new(0.05, 0.5, 1.0, 1.0, Integrator::Euler);
+            sim.set_solver(solver);
+            sim.set_interaction(Interaction::Electrostatic);
+            sim.add_particles(vec![
+                charged(300.0, 10.0),
+                charged(320.0, 10.0),
+                charged(700.0, 10.0),
+                charged(720.0, -10.0),
+            ]);
+            sim.step();
+            let accelerations = sim.get_particles().accelerations();
+            assert!(accelerations[0].x < 0.0 && accelerations[1].x > 0.0);
+            assert!(accelerations[2].x > 0.0 && accelerations[3].x < 0.0);
+            // Like k q² / (m d²), softened
+            assert!((accelerations[1].x - 100.0 / 401.0).abs() < 0.01);
+
+            // Gravity alone ignores charge and pulls all of them together
+            sim.set_interaction(Interaction::Gravity);
+            sim.step();
+            assert!(sim.get_particles().accelerations()[0].x > 0.0);
+        }
+    }
+
+    #[test]
+    fn it_keeps_the_tree_accurate_for_mixed_charges() {
+        let mut sim = Simulation::new(0.05, 0.0, 1.0, 1.0, Integrator::Euler);
+        sim.set_interaction(Interaction::Both);
+        for i in 0..40 {
+            let (x, y) = ((i * 37 % 900) as Scalar, (i * 53 % 900) as Scalar);
+            sim.add_particle(Particle {
+                charge: if i % 2 == 0 { 5.0 } else { -5.0 },
+                ..particle_at(50.0 + x, 50.0 + y, 1.0)
+            });
+        }
+        assert!(sim.acceleration_error() < 1e-5);
+        sim.theta = 0.5;
+        let error = sim.acceleration_error();
+        assert!(error < 0.05, "error {}", error);
+    }
+
+    #[test]
+    fn it_slows_particles_down_with_drag() {
+        for &integrator in &[
+            Integrator::Euler,
+            Integrator::Leapfrog,
+            Integrator::RungeKutta4,
+        ] {
+            let mut sim = Simulation::new(0.01, 1.0, 1.0, 1.0, integrator);
+            sim.set_drag(Some(Drag::new(DragLaw::Linear, 0.5, DragFrame::Rest, 50.0)));
+            let mut p = particle_at(500.0, 500.0, 1.0);
+            p.velocity = cgmath::vec2(10.0, 0.0);
+            sim.add_particle(p);
+            for _ in 0..200 {
+                sim.advance();
+            }
+            // v = v0 e^(-c t)
+            let v = sim.get_particles().velocities()[0].x;
+            assert!(
+                (v - 10.0 * (-1.0 as Scalar).exp()).abs() < 0.05,
+                "{:?}: {}",
+                integrator,
+                v
+            );
+        }
+    }
+
+    #[cfg(feature = "scripting")]
+    #[test]
+    fn it_runs_a_script_every_step() {
+        let mut sim = Simulation::new(0.1, 1.0, 1.0, 1.0, Integrator::Leapfrog);
+        let script = StepScript::new(
+            "if sim.time == 0.0 { sim.add_particle(900.0, 900.0, 0.0, 0.0, 1.0, 1.0); }
+             sim.apply_force(0, 2.0 * sim.mass(0), 0.0);",
+        )
+        .unwrap();
+        sim.set_script(Some(script));
+        sim.add_particle(particle_at(100.0, 500.0, 1.0));
+        for _ in 0..10 {
+            sim.advance();
+        }
+        assert_eq!(sim.get_particles().len(), 2);
+        let v = sim.get_particles().velocities()[0].x;
+        assert!((v - 2.0).abs() < 1e-3, "{}", v);
+
+        // Scripts that fail are stopped and say why
+        sim.set_script(Some(StepScript::new("sim.mass(5);").unwrap()));
+        sim.advance();
+        assert!(sim.take_script_error().is_some());
+        assert!(sim.get_script().is_none() && sim.take_script_error().is_none());
+    }
+
+    #[test]
+    fn it_evaporates_and_expires_particles_as_they_age() {
+        let mut sim = Simulation::new(0.1, 1.0, 1.0, 1.0, Integrator::Euler);
+        sim.set_evaporation(Some(Evaporation::new(Some(5.0), 0.1, 10.0)));
+        let heavy = sim.add_particle(particle_at(100.0, 100.0, 2.0));
+        let mut light = particle_at(900.0, 900.0, 1.0);
+        light.mass = 12.0;
+        let light = sim.add_particle(light);
+        let mut sun = particle_at(500.0, 900.0, 1.0);
+        sun.fixed = true;
+        let sun = sim.add_particle(sun);
+        let mass = sim.get_particle(heavy).unwrap().mass;
+
+        for _ in 0..20 {
+            sim.advance();
+        }
+        // m = m0 e^(-0.1 t) after 2 units of time, below the minimum mass for the light one
+        let p = sim.get_particle(heavy).unwrap();
+        assert!((p.age - 2.0).abs() < 1e-3);
+        assert!((p.mass - mass * (-0.2 as Scalar).exp()).abs() < 1e-2);
+        assert!((p.radius - 2.0 * (-0.1 as Scalar).exp()).abs() < 1e-3);
+        assert!(sim.get_particle(light).is_none());
+
+        for _ in 0..35 {
+            sim.advance();
+        }
+        // Too old after 5 units of time, except for the fixed one
+        assert!(sim.get_particle(heavy).is_none());
+        assert_eq!(sim.get_particle(sun).unwrap().mass, 50.0);
+    }
 }
@@ -0,0 +1,202 @@
+use cgmath::InnerSpace;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+use crate::integrator::Integrator;
+use crate::octree::octree::{Octree, OctreeIter};
+use crate::primitives::{instance::Instance3D, particle3d::Particle3D, scalar::Scalar};
+
+/// 3D counterpart of `Simulation` built on the Barnes-Hut octree. Bodies pass through
+/// each other (there are no collisions) and there are no walls, the octree simply grows
+/// to fit bodies wherever they go.
+pub struct Simulation3D {
+    particles: Vec<Particle3D>,
+    time_step: Scalar,
+    theta: Scalar,
+    g: Scalar,
+    softening: Scalar,
+    integrator: Integrator,
+    elapsed: Scalar,
+    octree: Octree,
+}
+
+impl Simulation3D {
+    /// Creates an empty simulation. The parameters mean the same as in `Simulation::new`.
+    pub fn new(
+        time_step: Scalar,
+        theta: Scalar,
+        g: Scalar,
+        softening: Scalar,
+        integrator: Integrator,
+    ) -> Self {
+        Self {
+            particles: Vec::new(),
+            time_step,
+            theta,
+            g,
+            softening,
+            integrator,
+            elapsed: 0.0,
+            octree: Octree::empty(),
+        }
+    }
+
+    /// Advances the simulation by a single step
+    pub fn advance(&mut self) {
+        let dt = self.time_step;
+        self.step();
+        match self.integrator {
+            Integrator::Euler => {
+                self.kick(dt);
+                self.drift(dt);
+            }
+            Integrator::Leapfrog => {
+                self.kick(dt / 2.0);
+                self.drift(dt);
+                self.step();
+                self.kick(dt / 2.0);
+            }
+            Integrator::RungeKutta4 => self.runge_kutta4(dt),
+        }
+        self.elapsed += dt;
+    }
+
+    /// Same as `Simulation`'s fourth order Runge-Kutta step
+    fn runge_kutta4(&mut self, dt: Scalar) {
+        let start: Vec<_> = self
+            .particles
+            .iter()
+            .map(|p| (p.position, p.velocity))
+            .collect();
+        let mut sums: Vec<_> = self
+            .particles
+            .iter()
+            .map(|p| (p.velocity, p.acceleration))
+            .collect();
+
+        for &(step, weight) in &[(dt / 2.0, 2.0), (dt / 2.0, 2.0), (dt, 1.0)] {
+            for (p, &(x0, v0)) in self.particles.iter_mut().zip(&start) {
+                p.position = x0 + p.velocity * step;
+                p.velocity = v0 + p.acceleration * step;
+            }
+            self.step();
+            for (p, (dx, dv)) in self.particles.iter().zip(&mut sums) {
+                *dx += p.velocity * weight;
+                *dv += p.acceleration * weight;
+            }
+        }
+
+        for (p, (&(x0, v0), &(dx, dv))) in self.particles.iter_mut().zip(start.iter().zip(&sums)) {
+            p.position = x0 + dx * (dt / 6.0);
+            p.velocity = v0 + dv * (dt / 6.0);
+        }
+    }
+
+    /// Computes the acceleration of each particle by walking a freshly built octree
+    pub fn step(&mut self) {
+        self.octree.rebuild(&self.particles);
+        let octree = &self.octree;
+        let (theta, g) = (self.theta, self.g);
+        let softening2 = self.softening * self.softening;
+
+        #[cfg(feature = "parallel")]
+        let particles = self.particles.par_iter_mut();
+        #[cfg(not(feature = "parallel"))]
+        let particles = self.particles.iter_mut();
+
+        particles.for_each(|p| {
+            p.acceleration = cgmath::vec3(0.0, 0.0, 0.0);
+            for node in OctreeIter::new(p.position, theta, octree) {
+                let d = node.particle.position - p.position;
+                let d2 = d.magnitude2() + softening2;
+                p.acceleration += d * (g * node.particle.mass / (d2 * d2.sqrt()));
+            }
+        });
+    }
+
+    /// Updates velocities from the current accelerations
+    fn kick(&mut self, dt: Scalar) {
+        for p in &mut self.particles {
+            p.velocity += p.acceleration * dt;
+        }
+    }
+
+    /// Updates positions from the current velocities
+    fn drift(&mut self, dt: Scalar) {
+        for p in &mut self.particles {
+            p.position += p.velocity * dt;
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.particles.clear();
+        self.elapsed = 0.0;
+    }
+
+    pub fn add_particles<I: IntoIterator<Item = Particle3D>>(&mut self, particles: I) {
+        self.particles.extend(particles);
+    }
+
+    pub fn get_particles(&self) -> &Vec<Particle3D> {
+        &self.particles
+    }
+
+    pub fn get_time_step(&self) -> Scalar {
+        self.time_step
+    }
+
+    pub fn get_elapsed(&self) -> Scalar {
+        self.elapsed
+    }
+
+    pub fn get_g(&self) -> Scalar {
+        self.g
+    }
+
+    /// Returns a vector containing all the particle instances (copy)
+    pub fn get_instances(&self) -> Vec<Instance3D> {
+        self.particles.iter().map(|p| p.to_instance()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitives::{color, particle3d::Particle3DProperties};
+
+    #[test]
+    fn it_keeps_inclined_orbits_stable_with_leapfrog() {
+        let mut sim = Simulation3D::new(0.05, 1.0, 1.0, 0.0, Integrator::Leapfrog);
+        let (mass, r) = (10000.0, 100.0);
+        let center = cgmath::vec3(500.0, 500.0, 500.0);
+        // Orbit in a plane tilted 45° around the x axis
+        let speed = (mass / r as Scalar).sqrt();
+        let tilt = cgmath::vec3(0.0, 1.0, 1.0).normalize();
+        sim.add_particles(vec![
+            Particle3D::new(Particle3DProperties {
+                position: center,
+                mass,
+                radius: 5.0,
+                velocity: cgmath::vec3(0.0, 0.0, 0.0),
+                color: color::WHITE,
+            }),
+            Particle3D::new(Particle3DProperties {
+                position: center + cgmath::vec3(r, 0.0, 0.0),
+                mass: 1.0,
+                radius: 1.0,
+                velocity: tilt * speed,
+                color: color::WHITE,
+            }),
+        ]);
+
+        for _ in 0..2500 {
+            sim.advance();
+            let particles = sim.get_particles();
+            let d = (particles[1].position - particles[0].position).magnitude();
+            assert!((d - r).abs() < 1.0, "radius {}", d);
+        }
+        // The orbit stays in its plane
+        let p = sim.get_particles()[1].position - center;
+        assert!(p.dot(cgmath::vec3(0.0, 1.0, -1.0)).abs() < 1e-2);
+    }
+}
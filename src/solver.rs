@@ -0,0 +1,23 @@
+use serde::{Deserialize, Serialize};
+
+/// Method used to compute the gravitational acceleration of each particle
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ForceSolver {
+    /// Barnes-Hut tree code. O(n log n), with an error controlled by the opening angle
+    /// theta.
+    #[default]
+    BarnesHut,
+    /// Sums the pull of every other particle directly. O(n²) but exact, which makes it
+    /// the reference to measure the tree code against.
+    Direct,
+}
+
+impl ForceSolver {
+    /// The solver to switch to when cycling through them
+    pub fn next(self) -> Self {
+        match self {
+            ForceSolver::BarnesHut => ForceSolver::Direct,
+            ForceSolver::Direct => ForceSolver::BarnesHut,
+        }
+    }
+}
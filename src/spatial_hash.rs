@@ -0,0 +1,92 @@
+use cgmath::InnerSpace;
+use std::collections::HashMap;
+
+use crate::primitives::scalar::Scalar;
+use crate::store::ParticleStore;
+
+/// Uniform grid bucketing particles by the cell their center falls into. Used as the
+/// collision broad phase: with cells at least as wide as the largest particle diameter,
+/// two particles can only overlap if their cells are neighbors.
+#[derive(Debug, Clone, Default)]
+pub struct SpatialHash {
+    cell_size: Scalar,
+    /// Indices of the particles in each occupied cell
+    cells: HashMap<(i32, i32), Vec<usize>>,
+}
+
+impl SpatialHash {
+    /// Clears the grid and buckets `particles` into cells sized for their largest radius
+    pub fn rebuild(&mut self, particles: &ParticleStore) {
+        let max_radius = particles.radii().iter().copied().fold(0.0, Scalar::max);
+        // Any cell size of at least the largest diameter works. The lower bound keeps
+        // the cells from degenerating when all particles are (close to) points.
+        self.cell_size = (2.0 * max_radius).max(1.0);
+        self.cells.clear();
+        for (i, &position) in particles.positions().iter().enumerate() {
+            self.cells.entry(self.cell(position)).or_default().push(i);
+        }
+    }
+
+    fn cell(&self, position: cgmath::Vector2<Scalar>) -> (i32, i32) {
+        (
+            (position.x / self.cell_size).floor() as i32,
+            (position.y / self.cell_size).floor() as i32,
+        )
+    }
+
+    /// Calls `f` with the indices of every pair of overlapping particles, lower index
+    /// first. `particles` must be the store the grid was built from.
+    pub fn for_each_collision<F: FnMut(usize, usize)>(&self, particles: &ParticleStore, mut f: F) {
+        let (positions, radii) = (particles.positions(), particles.radii());
+        for (i, &position) in positions.iter().enumerate() {
+            let (cx, cy) = self.cell(position);
+            for x in cx - 1..=cx + 1 {
+                for y in cy - 1..=cy + 1 {
+                    let cell = match self.cells.get(&(x, y)) {
+                        Some(cell) => cell,
+                        None => continue,
+                    };
+                    for &j in cell {
+                        let reach = radii[i] + radii[j];
+                        if j > i && (positions[j] - position).magnitude2() <= reach * reach {
+                            f(i, j);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::particle_at;
+
+    #[test]
+    fn it_finds_the_same_pairs_as_brute_force() {
+        let particles: Vec<_> = (0..200)
+            .map(|i| {
+                let (x, y) = ((i * 37 % 300) as Scalar, (i * 91 % 300) as Scalar);
+                particle_at(x - 150.0, y, 1.0 + (i % 7) as Scalar)
+            })
+            .collect();
+        let mut expected = Vec::new();
+        for i in 0..particles.len() {
+            for j in i + 1..particles.len() {
+                if particles[i].check_collision(&particles[j]) {
+                    expected.push((i, j));
+                }
+            }
+        }
+        assert!(!expected.is_empty());
+
+        let store: ParticleStore = particles.into_iter().collect();
+        let mut grid = SpatialHash::default();
+        grid.rebuild(&store);
+        let mut found = Vec::new();
+        grid.for_each_collision(&store, |i, j| found.push((i, j)));
+        found.sort_unstable();
+        assert_eq!(found, expected);
+    }
+}
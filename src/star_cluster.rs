@@ -0,0 +1,285 @@
+use cgmath::InnerSpace;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::primitives::scalar::{consts::PI, Scalar};
+
+/// Distribution function star clusters are sampled from. Positions and velocities are
+/// drawn from the model itself rather than filled in after the fact, so clusters start
+/// out close to virial equilibrium instead of collapsing like uniform clouds do.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ClusterModel {
+    /// Plummer sphere, with a density falling off as `(1 + r²/a²)^(-5/2)`. Cut off at ten
+    /// scale radii, which leaves out about 1.5% of the mass.
+    Plummer,
+    /// King model of dimensionless central potential `w0`, a lowered isothermal sphere
+    /// with a finite tidal radius. Around 1 to 12, higher being more concentrated.
+    King { w0: Scalar },
+}
+
+/// Positions and velocities of a cluster of `count` stars of equal mass, sampled from a
+/// model
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClusterSample {
+    pub positions: Vec<cgmath::Vector3<Scalar>>,
+    pub velocities: Vec<cgmath::Vector3<Scalar>>,
+}
+
+impl ClusterModel {
+    /// `count` stars of total `mass` whose half-mass radius is `radius`, at rest around
+    /// the origin as a whole. `g` is the gravitational constant the velocities are
+    /// computed for.
+    pub fn sample<R: Rng>(
+        self,
+        rng: &mut R,
+        count: usize,
+        mass: Scalar,
+        radius: Scalar,
+        g: Scalar,
+    ) -> ClusterSample {
+        // Sampled in units where G and the scale radius of the model are 1
+        let (mut positions, mut velocities, model_mass): (Vec<_>, Vec<_>, _) = match self {
+            ClusterModel::Plummer => {
+                let (positions, velocities) = (0..count).map(|_| plummer_star(rng)).unzip();
+                (positions, velocities, 1.0)
+            }
+            ClusterModel::King { w0 } => {
+                let profile = KingProfile::new(w0);
+                let (positions, velocities) = (0..count).map(|_| profile.sample_star(rng)).unzip();
+                (positions, velocities, profile.mass())
+            }
+        };
+        at_rest(&mut positions);
+        at_rest(&mut velocities);
+
+        // Scaling lengths by `s` and masses by `m` keeps the cluster in equilibrium if
+        // velocities scale by √(G m / s)
+        let scale = radius / half_mass_radius(&positions).max(Scalar::EPSILON);
+        let speed = (g * mass / model_mass / scale).sqrt();
+        ClusterSample {
+            positions: positions.into_iter().map(|x| x * scale).collect(),
+            velocities: velocities.into_iter().map(|v| v * speed).collect(),
+        }
+    }
+}
+
+/// Position and velocity of a star of a Plummer sphere of unit mass and scale radius,
+/// following Aarseth, Hénon & Wielen (1974)
+fn plummer_star<R: Rng>(rng: &mut R) -> (cgmath::Vector3<Scalar>, cgmath::Vector3<Scalar>) {
+    let r = loop {
+        let enclosed: Scalar = rng.gen_range(Scalar::EPSILON..1.0);
+        let r = 1.0 / (enclosed.powf(-2.0 / 3.0) - 1.0).sqrt();
+        if r <= 10.0 {
+            break r;
+        }
+    };
+    // Speeds in units of the escape speed are distributed as q² (1 - q²)^(7/2), which
+    // peaks below 0.1
+    let q = loop {
+        let q: Scalar = rng.gen_range(0.0..1.0);
+        if rng.gen_range(0.0..0.1) < q * q * (1.0 - q * q).powf(3.5) {
+            break q;
+        }
+    };
+    let escape_speed = (2.0 as Scalar).sqrt() * (1.0 + r * r).powf(-0.25);
+    (
+        random_direction(rng) * r,
+        random_direction(rng) * q * escape_speed,
+    )
+}
+
+/// Potential and mass of a King model as a function of radius, found by integrating
+/// Poisson's equation outwards from the center until the potential reaches zero at the
+/// tidal radius. In units where G, the velocity dispersion parameter σ and the King
+/// radius `r₀ = √(9σ² / 4πGρ₀)` are 1.
+struct KingProfile {
+    radii: Vec<Scalar>,
+    /// Dimensionless potential `W = Ψ / σ²` at each radius
+    potentials: Vec<Scalar>,
+    /// Mass within each radius
+    masses: Vec<Scalar>,
+}
+
+impl KingProfile {
+    fn new(w0: Scalar) -> Self {
+        let w0 = w0.max(0.1);
+        let central_density = king_density(w0);
+        // W'' = -2 W' / r - 9 ρ(W) / ρ₀
+        let derivatives = |r: Scalar, (w, dw): (Scalar, Scalar)| {
+            (dw, -2.0 * dw / r - 9.0 * king_density(w) / central_density)
+        };
+
+        // Close to the center W ≈ W₀ - 3r²/2
+        let mut r: Scalar = 1e-3;
+        let (mut w, mut dw) = (w0 - 1.5 * r * r, -3.0 * r);
+        let mut profile = Self {
+            radii: vec![0.0, r],
+            potentials: vec![w0, w],
+            masses: vec![0.0, -r * r * dw],
+        };
+        while w > 0.0 {
+            let h = 1e-3 * (1.0 + r);
+            let k1 = derivatives(r, (w, dw));
+            let k2 = derivatives(r + h / 2.0, (w + k1.0 * h / 2.0, dw + k1.1 * h / 2.0));
+            let k3 = derivatives(r + h / 2.0, (w + k2.0 * h / 2.0, dw + k2.1 * h / 2.0));
+            let k4 = derivatives(r + h, (w + k3.0 * h, dw + k3.1 * h));
+            let (next_w, next_dw) = (
+                w + h / 6.0 * (k1.0 + 2.0 * k2.0 + 2.0 * k3.0 + k4.0),
+                dw + h / 6.0 * (k1.1 + 2.0 * k2.1 + 2.0 * k3.1 + k4.1),
+            );
+            if next_w <= 0.0 {
+                // Step exactly onto the tidal radius
+                let t = w / (w - next_w);
+                r += h * t;
+                dw += (next_dw - dw) * t;
+                w = 0.0;
+            } else {
+                r += h;
+                (w, dw) = (next_w, next_dw);
+            }
+            profile.radii.push(r);
+            profile.potentials.push(w);
+            profile.masses.push(-r * r * dw);
+        }
+        profile
+    }
+
+    fn mass(&self) -> Scalar {
+        *self.masses.last().unwrap()
+    }
+
+    fn sample_star<R: Rng>(
+        &self,
+        rng: &mut R,
+    ) -> (cgmath::Vector3<Scalar>, cgmath::Vector3<Scalar>) {
+        // Inverts the mass profile, so that radii are spread the way the mass is
+        let enclosed = rng.gen_range(0.0..self.mass());
+        let i = self
+            .masses
+            .partition_point(|&m| m < enclosed)
+            .clamp(1, self.masses.len() - 1);
+        let t = (enclosed - self.masses[i - 1]) / (self.masses[i] - self.masses[i - 1]);
+        let r = self.radii[i - 1] + (self.radii[i] - self.radii[i - 1]) * t;
+        let w = self.potentials[i - 1] + (self.potentials[i] - self.potentials[i - 1]) * t;
+
+        // Speeds up to the escape speed √(2W), distributed as v² (e^(W - v²/2) - 1)
+        let escape_speed = (2.0 * w.max(0.0)).sqrt();
+        let weight = |q: Scalar| q * q * ((w * (1.0 - q * q)).exp() - 1.0);
+        let peak = (1..64)
+            .map(|i| weight(i as Scalar / 64.0))
+            .fold(0.0, Scalar::max)
+            * 1.1;
+        let q = loop {
+            let q: Scalar = rng.gen_range(0.0..1.0);
+            if peak <= 0.0 || rng.gen_range(0.0..peak) < weight(q) {
+                break q;
+            }
+        };
+        (
+            random_direction(rng) * r,
+            random_direction(rng) * q * escape_speed,
+        )
+    }
+}
+
+/// Density of a King model at dimensionless potential `w`, up to a constant factor
+fn king_density(w: Scalar) -> Scalar {
+    if w <= 0.0 {
+        return 0.0;
+    }
+    let density = w.exp() * erf(w.sqrt()) - (4.0 * w / PI).sqrt() * (1.0 + 2.0 * w / 3.0);
+    density.max(0.0)
+}
+
+/// Error function, accurate to about 1e-7 (Abramowitz & Stegun 7.1.26)
+#[allow(clippy::excessive_precision)]
+fn erf(x: Scalar) -> Scalar {
+    let t = 1.0 / (1.0 + 0.3275911 * x.abs());
+    let polynomial = t
+        * (0.254829592
+            + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    (1.0 - polynomial * (-x * x).exp()).copysign(x)
+}
+
+/// Unit vector pointing in a random direction, uniformly over the sphere
+fn random_direction<R: Rng>(rng: &mut R) -> cgmath::Vector3<Scalar> {
+    let z: Scalar = rng.gen_range(-1.0..1.0);
+    let angle = rng.gen_range(0.0..2.0 * PI);
+    let xy = (1.0 - z * z).sqrt();
+    cgmath::vec3(xy * angle.cos(), xy * angle.sin(), z)
+}
+
+/// Subtracts the mean from every vector, which for equal masses moves the center of mass
+/// (or the momentum) to zero
+fn at_rest(vectors: &mut [cgmath::Vector3<Scalar>]) {
+    if vectors.is_empty() {
+        return;
+    }
+    let mean = vectors
+        .iter()
+        .fold(cgmath::vec3(0.0, 0.0, 0.0), |sum, &v| sum + v)
+        / vectors.len() as Scalar;
+    for v in vectors {
+        *v -= mean;
+    }
+}
+
+/// Radius around the origin holding half of the stars
+fn half_mass_radius(positions: &[cgmath::Vector3<Scalar>]) -> Scalar {
+    let mut distances: Vec<Scalar> = positions.iter().map(|x| x.magnitude()).collect();
+    distances.sort_by(Scalar::total_cmp);
+    distances.get(distances.len() / 2).copied().unwrap_or(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// 2T / |U| of equal mass stars
+    fn virial_ratio(sample: &ClusterSample, mass: Scalar, g: Scalar) -> Scalar {
+        let m = mass / sample.positions.len() as Scalar;
+        let kinetic: Scalar = sample
+            .velocities
+            .iter()
+            .map(|v| 0.5 * m * v.magnitude2())
+            .sum();
+        let mut potential = 0.0;
+        for (i, a) in sample.positions.iter().enumerate() {
+            for b in &sample.positions[i + 1..] {
+                potential -= g * m * m / (a - b).magnitude();
+            }
+        }
+        2.0 * kinetic / potential.abs()
+    }
+
+    #[test]
+    fn it_samples_plummer_spheres_in_virial_equilibrium() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let sample = ClusterModel::Plummer.sample(&mut rng, 2000, 1000.0, 50.0, 2.0);
+        assert_eq!(sample.positions.len(), 2000);
+        assert!((half_mass_radius(&sample.positions) - 50.0).abs() < 1e-3);
+        let ratio = virial_ratio(&sample, 1000.0, 2.0);
+        assert!((ratio - 1.0).abs() < 0.1, "virial ratio {}", ratio);
+    }
+
+    #[test]
+    fn it_samples_king_models_in_virial_equilibrium() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let sample = ClusterModel::King { w0: 6.0 }.sample(&mut rng, 2000, 1000.0, 50.0, 2.0);
+        let ratio = virial_ratio(&sample, 1000.0, 2.0);
+        assert!((ratio - 1.0).abs() < 0.1, "virial ratio {}", ratio);
+    }
+
+    #[test]
+    fn it_finds_the_concentration_of_king_models() {
+        // Tabulated log10(r_t / r₀) of King (1966)
+        for &(w0, concentration) in &[(3.0, 0.67), (6.0, 1.26), (9.0, 2.12)] {
+            let profile = KingProfile::new(w0);
+            // The profile ends at the tidal radius
+            let found = profile.radii.last().unwrap().log10();
+            assert!((found - concentration).abs() < 0.03, "W0 {}: {}", w0, found);
+        }
+        assert!((erf(0.5) - 0.5204999).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,164 @@
+use bytemuck::{Pod, Zeroable};
+use nbody::{
+    constants,
+    primitives::{draw, scalar::to_f32},
+};
+use wgpu::{
+    util::DeviceExt, BindGroupLayoutDescriptor, PipelineLayoutDescriptor, RenderPipelineDescriptor,
+};
+
+use crate::{bloom, camera::Camera};
+
+/// View data laid out the way `starfield.frag.wgsl` expects it
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct StarfieldUniform {
+    /// Camera center in window heights at zoom 1
+    center: [f32; 2],
+    zoom: f32,
+    aspect: f32,
+    /// Size of a pixel in window heights
+    pixel: f32,
+    seed: f32,
+    _padding: [f32; 2],
+}
+
+unsafe impl Pod for StarfieldUniform {}
+unsafe impl Zeroable for StarfieldUniform {}
+
+/// Background of faint stars behind the particles. The stars are hashed from grid cells
+/// in the fragment shader, so there is nothing to store however far the camera moves.
+/// They are spread over layers that follow the camera less the farther away they are,
+/// which makes panning and zooming visible even with few particles on screen.
+pub struct Starfield {
+    pipeline: wgpu::RenderPipeline,
+    bind_group: wgpu::BindGroup,
+    uniform_buffer: wgpu::Buffer,
+    /// Full screen triangle
+    vertex_buffer: wgpu::Buffer,
+    /// Picks the star layout, so that runs with the same seed look the same
+    seed: f32,
+}
+
+impl Starfield {
+    /// Creates the pipeline drawing into the scene target of the bloom pass
+    pub fn new(
+        device: &wgpu::Device,
+        multisample: wgpu::MultisampleState,
+        camera: &Camera,
+        seed: u64,
+    ) -> Self {
+        let vx_module = device.create_shader_module(&include_wgsl!("shaders/fullscreen.vert.wgsl"));
+        let fg_module = device.create_shader_module(&include_wgsl!("shaders/starfield.frag.wgsl"));
+
+        // Small enough for the hash to stay precise in f32
+        let seed = (seed % 1024) as f32;
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Starfield Buffer"),
+            contents: bytemuck::bytes_of(&Self::uniform(camera, seed)),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Starfield Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Starfield Bind Group"),
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Starfield Pipeline"),
+            layout: Some(&pipeline_layout),
+            depth_stencil: None,
+            multisample,
+            primitive: wgpu::PrimitiveState::default(),
+            fragment: Some(wgpu::FragmentState {
+                entry_point: "main",
+                module: &fg_module,
+                targets: &[wgpu::ColorTargetState {
+                    alpha_blend: draw::ALPHA_BLEND,
+                    color_blend: draw::ALPHA_BLEND,
+                    write_mask: wgpu::ColorWrite::ALL,
+                    format: bloom::HDR_FORMAT,
+                }],
+            }),
+            vertex: wgpu::VertexState {
+                entry_point: "main",
+                module: &vx_module,
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<[f32; 2]>() as wgpu::BufferAddress,
+                    step_mode: wgpu::InputStepMode::Vertex,
+                    attributes: &[wgpu::VertexAttribute {
+                        shader_location: 0,
+                        offset: 0,
+                        format: wgpu::VertexFormat::Float2,
+                    }],
+                }],
+            },
+        });
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Starfield Vertex Buffer"),
+            contents: bytemuck::cast_slice(&[[-1.0f32, -1.0], [3.0, -1.0], [-1.0, 3.0]]),
+            usage: wgpu::BufferUsage::VERTEX,
+        });
+
+        Self {
+            pipeline,
+            bind_group,
+            uniform_buffer,
+            vertex_buffer,
+            seed,
+        }
+    }
+
+    fn uniform(camera: &Camera, seed: f32) -> StarfieldUniform {
+        // Window heights are measured at zoom 1, where the world box fits the window
+        let world =
+            to_f32((constants::MAX_X - constants::MIN_X).max(constants::MAX_Y - constants::MIN_Y));
+        StarfieldUniform {
+            center: (camera.center / world).into(),
+            zoom: camera.zoom,
+            aspect: camera.aspect,
+            pixel: 1.0 / camera.height,
+            seed,
+            _padding: [0.0; 2],
+        }
+    }
+
+    /// Follows the camera after it moved, zoomed or the window was resized
+    pub fn update(&self, queue: &wgpu::Queue, camera: &Camera) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::bytes_of(&Self::uniform(camera, self.seed)),
+        );
+    }
+
+    /// Draws the stars into a pass on the scene target. Has to come first so that
+    /// everything else is drawn on top.
+    pub fn draw<'a>(&'a self, rpass: &mut wgpu::RenderPass<'a>) {
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, &self.bind_group, &[]);
+        rpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        rpass.draw(0..3, 0..1);
+    }
+}
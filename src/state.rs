@@ -1,129 +1,640 @@
-use crate::{
+use crate::bloom::{self, Bloom};
+use crate::camera::{self, Camera, CameraUniform};
+use crate::capture::{Capture, ClipRecorder};
+use crate::cli::Options;
+use crate::config::Config;
+use crate::dynamic_buffer::DynamicBuffer;
+use crate::gpu_timer::GpuTimer;
+use crate::keys::{Action, Keymap};
+use crate::physics::{Physics, Snapshot, Stepper, ENERGY_HISTORY, REPLAY_PATH};
+use crate::scene3d::Scene3D;
+use crate::starfield::Starfield;
+use cgmath::InnerSpace;
+use futures::executor::{LocalPool, LocalSpawner};
+use futures::task::SpawnExt;
+use instant::{Duration, Instant};
+use nbody::presets::{self, Preset};
+use nbody::primitives::color::{self, Color, ColorMapping};
+use nbody::primitives::particle::ParticleProperties;
+use nbody::replay::Replay;
+use nbody::scenario::Scenario;
+#[cfg(feature = "scripting")]
+use nbody::scripting::StepScript;
+use nbody::{
     constants,
-    primitives::{draw, draw::DrawBuffers, instance::Instance, particle::Particle, vertex::Vertex},
+    primitives::{
+        draw,
+        draw::{DrawBuffers, VectorOverlay},
+        instance::Instance,
+        particle::Particle,
+        scalar::{consts::FRAC_PI_2, from_f32, to_f32},
+        vertex::Vertex,
+    },
     simulation::Simulation,
+    AdaptiveTimeStep, BlockTimeSteps, BodyKind, Drag, DragFrame, Edit, EditHistory, Expansion,
+    ExternalPotential, ForceSolver, Fragmentation, GroupMatrix, LagrangePoints, ParticleId, Scalar,
+    TreeStats,
 };
-use crate::{primitives::particle::ParticleProperties, utils};
-use futures::executor::{LocalPool, LocalSpawner};
-use futures::task::SpawnExt;
-use rand::Rng;
-use std::borrow::Cow;
+use rand::{rngs::StdRng, SeedableRng};
+use std::{fmt, path::PathBuf, sync::Arc};
 use wgpu::{
     util::DeviceExt, CommandEncoderDescriptor, DeviceDescriptor, PipelineLayoutDescriptor,
     RenderPassColorAttachmentDescriptor, RenderPassDescriptor, RenderPipelineDescriptor,
-    RequestAdapterOptions, ShaderModuleDescriptor, SwapChainDescriptor,
+    RequestAdapterOptions, SwapChainDescriptor,
+};
+use wgpu_glyph::{
+    ab_glyph, GlyphBrush, GlyphBrushBuilder, HorizontalAlign, Layout, Section, Text, VerticalAlign,
 };
-use wgpu_glyph::{ab_glyph, GlyphBrush, GlyphBrushBuilder, Section, Text};
 use winit::{
     dpi::{PhysicalPosition, PhysicalSize},
-    event::WindowEvent,
+    event::{ModifiersState, MouseScrollDelta, WindowEvent},
     window::Window,
 };
 
+/// File that F5 saves the simulation to and F9 restores it from
+const SNAPSHOT_PATH: &str = "nbody.snapshot";
+/// Velocity given to a spawned particle per world unit the mouse was dragged
+const DRAG_VELOCITY_SCALE: Scalar = 0.1;
+/// Maximum number of vertices in the drag preview arrow (shaft plus two head strokes)
+const MAX_ARROW_VERTICES: usize = 6;
+/// Number of steps the path of a particle about to be placed is predicted for
+const TRAJECTORY_STEPS: usize = 400;
+/// Length in pixels of the dashes (and gaps) of the predicted path
+const TRAJECTORY_DASH_PIXELS: Scalar = 6.0;
+/// How close (in pixels) a click has to land to the edge of a particle to select it
+const SELECT_TOLERANCE_PIXELS: Scalar = 6.0;
+/// Gap in pixels between a selected particle and the ring highlighting it
+const SELECTION_RING_GAP: Scalar = 4.0;
+/// File Ctrl + S saves the group selection to as a scenario
+const SELECTION_PATH: &str = "selection.ron";
+/// Colors Y gives the group selection, one after the other
+const GROUP_COLORS: [Color; 6] = [
+    [1.0, 0.4, 0.4, 1.0],
+    [1.0, 0.8, 0.3, 1.0],
+    [0.4, 1.0, 0.5, 1.0],
+    [0.3, 0.8, 1.0, 1.0],
+    [0.8, 0.5, 1.0, 1.0],
+    [1.0, 1.0, 1.0, 1.0],
+];
+/// Number of edits Ctrl + Z can take back
+const HISTORY_LENGTH: usize = 100;
+/// Distance in pixels between the edge of a particle and its label
+const LABEL_GAP_PIXELS: f32 = 2.0;
+/// Length in pixels of the crosshairs marking the barycenter and Lagrange points
+const MARKER_SIZE_PIXELS: Scalar = 12.0;
+/// Length in world units of the velocity arrows per unit of speed
+const VELOCITY_ARROW_SCALE: Scalar = 5.0;
+/// Length in world units of the acceleration arrows per unit of acceleration
+const ACCELERATION_ARROW_SCALE: Scalar = 50.0;
+/// Size of the color bar of the legend in pixels
+const LEGEND_WIDTH_PIXELS: usize = 200;
+const LEGEND_HEIGHT_PIXELS: f32 = 12.0;
+/// Size of the energy plot in pixels
+const ENERGY_PLOT_WIDTH_PIXELS: f32 = 300.0;
+const ENERGY_PLOT_HEIGHT_PIXELS: f32 = 100.0;
+/// Directory F12 records PNG frames into
+const CAPTURE_DIR: &str = "capture";
+/// Where F7 exports particle states to unless `--export` says otherwise
+const EXPORT_PATH: &str = "nbody.csv";
+/// Number of instances the instance buffer is first allocated for
+const INITIAL_INSTANCE_CAPACITY: usize = 1024;
+/// Width of the replay progress bar in characters
+const PROGRESS_BAR_WIDTH: usize = 30;
+/// Amount [ and ] change the Barnes-Hut opening angle by
+const THETA_STEP: Scalar = 0.1;
+/// Slowest slow motion + and - can reach
+const MIN_STEPS_PER_FRAME: f32 = 1.0 / 16.0;
+/// Most simulation steps + and - allow per frame
+const MAX_STEPS_PER_FRAME: f32 = 64.0;
+/// Weight of the newest frame in the smoothed frame time, keeps the FPS readable
+const FRAME_TIME_SMOOTHING: f64 = 0.1;
+/// Radius of the particles a click spawns, until changed with Ctrl + scroll
+const SPAWN_RADIUS: Scalar = 2.0;
+/// Mass per unit of radius of spawned particles, until changed with Alt + scroll
+const SPAWN_MASS_PER_RADIUS: Scalar = 50.0;
+/// Range the radius and mass of spawned particles can be changed in
+const MIN_SPAWN_RADIUS: Scalar = 0.5;
+const MAX_SPAWN_RADIUS: Scalar = 50.0;
+const MIN_SPAWN_MASS: Scalar = 1.0;
+const MAX_SPAWN_MASS: Scalar = 1e6;
+/// Factor one notch of the mouse wheel scales the spawned particles by
+const SPAWN_SCALE_STEP: Scalar = 1.1;
+/// Color of the particle about to be spawned and of its predicted path
+const GHOST_COLOR: [f32; 3] = [0.6, 0.6, 0.6];
+/// Distance in pixels an arrow key moves the keyboard crosshair
+const CROSSHAIR_STEP_PIXELS: Scalar = 10.0;
+/// Velocity Alt + an arrow key adds to particles placed with the keyboard crosshair
+const CROSSHAIR_VELOCITY_STEP: Scalar = 0.5;
+/// Fraction of a step the crosshair moves, or its velocity changes, while Shift is held
+const CROSSHAIR_FINE_STEP: Scalar = 0.1;
+const CROSSHAIR_COLOR: [f32; 3] = [1.0, 0.8, 0.2];
+/// Mouse tools listed by the help overlay after the keys
+const MOUSE_HELP: [(&str, &str); 11] = [
+    ("Left click", "spawn, or select what is under the cursor"),
+    ("Left drag", "spawn with a velocity"),
+    ("Shift+Left click", "spawn a cluster"),
+    ("Ctrl+Left click", "scatter massless tracers"),
+    ("Shift+Left drag", "select a rectangle"),
+    ("Alt+Left drag", "push the selection"),
+    ("Right click", "delete what is under the cursor"),
+    ("Right hold", "pull toward the cursor (Shift: push)"),
+    ("Wheel", "zoom"),
+    ("Ctrl/Alt+Wheel", "spawn size / spawn mass"),
+    ("Middle drag", "pan the camera"),
+];
+/// Text size of the help overlay
+const HELP_SCALE: f32 = 18.0;
+/// Height in pixels of a line of the help overlay
+const HELP_LINE_HEIGHT: f32 = 22.0;
+/// Width in pixels of a column of the help overlay
+const HELP_COLUMN_WIDTH: f32 = 480.0;
+/// Distance in pixels of the help overlay from the top left corner of the window
+const HELP_MARGIN: f32 = 30.0;
+/// Mass the cursor pulls with while the right mouse button is held, until scrolled
+const ATTRACTOR_MASS: Scalar = 20000.0;
+/// Range scrolling can change the mass of the attractor in
+const MIN_ATTRACTOR_MASS: Scalar = 100.0;
+const MAX_ATTRACTOR_MASS: Scalar = 1e7;
+/// Scale radius of the Plummer sphere the attractor is. Keeps particles passing right
+/// by the cursor from being flung away.
+const ATTRACTOR_RADIUS: Scalar = 20.0;
+
+/// A replay being played back together with the frame currently shown
+struct Playback {
+    replay: Replay,
+    frame: usize,
+}
+
+impl Playback {
+    /// Moves the current frame by `offset`, clamped to the frames of the replay
+    fn seek(&mut self, offset: isize) {
+        let last = self.replay.len().saturating_sub(1) as isize;
+        self.frame = (self.frame as isize + offset).clamp(0, last) as usize;
+    }
+
+    fn progress_bar(&self) -> String {
+        let filled = (self.frame + 1) * PROGRESS_BAR_WIDTH / self.replay.len().max(1);
+        format!(
+            "[{}{}]",
+            "#".repeat(filled),
+            "-".repeat(PROGRESS_BAR_WIDTH - filled)
+        )
+    }
+}
+
+/// Where the time of a frame went, shown with F3
+#[derive(Default)]
+struct FrameTimings {
+    /// Time between two frames, smoothed over the last few frames
+    frame: Duration,
+    /// Simulation steps taken during the last physics tick, tree builds included
+    physics: Duration,
+    /// Building Barnes-Hut trees during the last physics tick
+    tree_build: Duration,
+    /// Encoding and submitting the last frame
+    render: Duration,
+    /// GPU time of every render pass of the last frame, with `--gpu-timing`
+    gpu: Vec<(&'static str, Duration)>,
+    /// Shape of the Barnes-Hut tree after the last physics tick, if it was built
+    tree: Option<TreeStats>,
+}
+
+impl FrameTimings {
+    fn add_frame(&mut self, interval: Duration) {
+        self.frame = if self.frame == Duration::default() {
+            interval
+        } else {
+            self.frame.mul_f64(1.0 - FRAME_TIME_SMOOTHING) + interval.mul_f64(FRAME_TIME_SMOOTHING)
+        };
+    }
+
+    fn text(&self) -> String {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        let mut text = format!(
+            "\nfps: {:.0} ({:.2} ms)\nphysics: {:.2} ms\ntree build: {:.2} ms\nrender: {:.2} ms",
+            1.0 / self.frame.as_secs_f64().max(f64::EPSILON),
+            ms(self.frame),
+            ms(self.physics),
+            ms(self.tree_build),
+            ms(self.render)
+        );
+        for (pass, time) in &self.gpu {
+            text.push_str(&format!("\ngpu {}: {:.2} ms", pass, ms(*time)));
+        }
+        if let Some(tree) = &self.tree {
+            text.push_str(&tree_text(tree));
+        }
+        text
+    }
+}
+
+/// Size and depth of the Barnes-Hut tree, followed by how many leaves hold how many
+/// particles. A deep tree with few particles per leaf points at particles piling up.
+fn tree_text(tree: &TreeStats) -> String {
+    let occupancy: Vec<String> = tree
+        .occupancy
+        .iter()
+        .enumerate()
+        .filter(|&(_, &leaves)| leaves > 0)
+        .map(|(held, leaves)| format!("{}:{}", held, leaves))
+        .collect();
+    format!(
+        "\ntree: {} nodes, {} leaves, depth {}, {:.2} ms\nleaf occupancy: {} (mean {:.1})",
+        tree.nodes,
+        tree.leaves,
+        tree.max_depth,
+        tree.build_time.as_secs_f64() * 1000.0,
+        occupancy.join(" "),
+        tree.mean_occupancy()
+    )
+}
+
 pub struct State {
     surface: wgpu::Surface,
-    device: wgpu::Device,
-    queue: wgpu::Queue,
+    /// Shared with the physics thread, which evaluates forces on the GPU when asked to
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
     sc_desc: wgpu::SwapChainDescriptor,
     swap_chain: wgpu::SwapChain,
     size: winit::dpi::PhysicalSize<u32>,
     clear_color: wgpu::Color,
     render_pipeline: wgpu::RenderPipeline,
+    /// Draws world space line lists such as the drag preview arrow
+    line_pipeline: wgpu::RenderPipeline,
+    /// Background drawn behind the particles of the 2D view
+    starfield: Starfield,
+    show_starfield: bool,
     vertex_buffer: wgpu::Buffer,
     index_buffer: wgpu::Buffer,
     num_indices: u32,
-    instance_buffer: wgpu::Buffer,
-    num_instances: u32,
+    instance_buffer: DynamicBuffer<Instance>,
+    /// Staging copy of the replay frame uploaded into `instance_buffer`
+    instances: Vec<Instance>,
+    arrow_buffer: wgpu::Buffer,
+    num_arrow_vertices: u32,
+    /// Dashed line along the predicted path of the particle about to be placed
+    trajectory_buffer: DynamicBuffer<Vertex>,
+    /// Whether spawned particles are put on a circular orbit around the center of mass
+    orbit_placement: bool,
+    /// Whether every spawned particle comes with a twin mirrored through the center of
+    /// the world
+    mirror_placement: bool,
+    /// World position of the crosshair the arrow keys move in keyboard placement mode,
+    /// while on. Enter spawns a particle there.
+    crosshair: Option<cgmath::Vector2<Scalar>>,
+    /// Velocity of the particles placed with the keyboard crosshair, on top of the
+    /// orbital velocity in orbit placement mode
+    crosshair_velocity: cgmath::Vector2<Scalar>,
+    /// Particle shown in the inspector and highlighted with a ring
+    selected: Option<ParticleId>,
+    selection_buffer: wgpu::Buffer,
+    num_selection_vertices: u32,
+    /// Particles picked by dragging out a rectangle with Shift held, which Delete, U, Y,
+    /// Alt + drag and Ctrl + S act on
+    group_selection: Vec<ParticleId>,
+    /// World position where a Shift + drag started, while it lasts. Released in place it
+    /// spawns a cluster instead of selecting.
+    rectangle_start: Option<cgmath::Vector2<Scalar>>,
+    /// World position where an Alt + drag started. The drag is added to the velocity of
+    /// the group selection on release, scaled like the velocity of spawned particles.
+    impulse_start: Option<cgmath::Vector2<Scalar>>,
+    /// Rectangle being dragged out, the push being given and rings around the group
+    /// selection
+    group_buffer: DynamicBuffer<Vertex>,
+    /// Particles added, removed and pushed by hand, which Ctrl + Z and Ctrl + Y undo and
+    /// redo
+    history: EditHistory,
+    /// Vectors drawn as arrows on top of every particle
+    vector_overlay: VectorOverlay,
+    /// Arrows of `vector_overlay`
+    vector_buffer: DynamicBuffer<Vertex>,
+    /// Whether the barycenter and Lagrange points of the two heaviest particles are
+    /// marked
+    show_lagrange_points: bool,
+    /// Crosshairs of `show_lagrange_points`
+    marker_buffer: DynamicBuffer<Vertex>,
+    /// World position where the left mouse button was pressed. While set, dragging
+    /// defines the velocity of the particle spawned on release.
+    drag_start: Option<cgmath::Vector2<Scalar>>,
     cursor_pos: PhysicalPosition<f64>,
-    sim: Simulation,
+    /// Modifier keys currently held down
+    modifiers: ModifiersState,
+    /// Actions the keys are bound to
+    keymap: Keymap,
+    /// Number of particles, disk radius and rotation of the clusters Shift + click spawns
+    burst_size: usize,
+    burst_radius: Scalar,
+    burst_spin: bool,
+    /// Radius and mass of the particles a click spawns, shown as a ghost circle under the
+    /// cursor
+    spawn_radius: Scalar,
+    spawn_mass: Scalar,
+    camera: Camera,
+    camera_buffer: wgpu::Buffer,
+    camera_bind_group: wgpu::BindGroup,
+    /// Whether the camera is currently being dragged with the middle mouse button
+    panning: bool,
+    /// Whether the right mouse button is held away from any particle, which pulls every
+    /// particle towards the cursor (or pushes them away while Shift is held)
+    attracting: bool,
+    /// Mass of the attractor, changed by scrolling while attracting
+    attractor_mass: Scalar,
+    /// Steps the 2D simulation on a thread of its own
+    physics: Physics,
+    /// State of the 2D simulation that is drawn, the latest one the physics published
+    snapshot: Arc<Snapshot>,
+    /// Adaptive time step settings the A key switches to
+    adaptive_step: AdaptiveTimeStep,
+    /// Block time step settings the T key switches to
+    block_steps: BlockTimeSteps,
+    /// Drag settings the D key switches to
+    drag: Drag,
+    /// Fragmentation settings the F key switches to
+    fragmentation: Fragmentation,
     glyph_brush: GlyphBrush<()>,
     staging_belt: wgpu::util::StagingBelt,
     local_pool: LocalPool,
     local_spawner: LocalSpawner,
     /// Whether or not the simulation is paused
     paused: bool,
+    /// Whether `timings` is shown in the HUD
+    show_timings: bool,
+    /// Whether the keys and mouse tools are listed instead of the HUD
+    show_help: bool,
+    /// Whether the names of named particles are drawn next to them
+    show_labels: bool,
+    /// Last position of what the camera follows, the selected particle or else the
+    /// center of mass. While set, the camera moves along with it every snapshot.
+    following: Option<cgmath::Vector2<Scalar>>,
+    /// Whether the camera also turns so that the velocity of the followed particle
+    /// points up
+    heading_up: bool,
+    timings: FrameTimings,
+    /// Shortest time between two frames when not presenting with Fifo, from `--max-fps`
+    min_frame_interval: Option<Duration>,
+    /// When the last frame was rendered
+    last_frame: Instant,
+    /// Scenario file the simulation is (re)loaded from
+    scenario_path: Option<PathBuf>,
+    /// Whether the settings are left out of the config when the app closes
+    no_config: bool,
+    /// Range of the quantity mapped to colors, shown in the legend
+    color_range: Option<(Scalar, Scalar)>,
+    /// Color bar of the legend
+    legend_buffer: DynamicBuffer<Vertex>,
+    /// Energy of the last steps plotted over time, while the physics thread measures it
+    energy_buffer: DynamicBuffer<Vertex>,
+    /// Post-processing the scene goes through on its way to the screen
+    bloom: Bloom,
+    /// Active recording, if any
+    capture: Option<Capture>,
+    /// Keeps the last few seconds of frames to save as a GIF, started with F10
+    clip: Option<ClipRecorder>,
+    clip_seconds: f32,
+    /// Set when running in 3D mode. The 2D simulation is left untouched while it is.
+    scene3d: Option<Scene3D>,
+    /// While set, frames come from a replay instead of the simulation
+    playback: Option<Playback>,
+    /// Simulation steps taken per frame, by the physics thread per tick. Below 1 the
+    /// simulation runs in slow motion, taking a step only every few frames.
+    steps_per_frame: f32,
+    /// Fraction of a step of the 3D mode or replay left over from previous frames
+    step_budget: f32,
+    /// Steps of the 3D mode or replay requested with N to be taken on the next frame
+    /// while paused
+    queued_steps: usize,
+    /// Source of all randomness (presets, spawned particles). Seeded from the command
+    /// line so that runs can be repeated exactly.
+    rng: StdRng,
+    /// Set once the app should close, e.g. after Escape was pressed
+    exit_requested: bool,
+    /// Measures the render passes on the GPU, with `--gpu-timing`
+    gpu_timer: Option<GpuTimer>,
 }
 
-impl State {
-    pub async fn new(window: &Window) -> Self {
-        let window_size = window.inner_size();
-        let instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY);
+/// Why drawing into the window could not be set up
+#[derive(Debug)]
+pub enum StateError {
+    /// No adapter of any backend can draw into the window
+    NoAdapter,
+    /// No adapter has a name containing the one given with `--adapter`
+    AdapterNotFound(String),
+    Device(wgpu::RequestDeviceError),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::NoAdapter => write!(f, "no graphics adapter can draw into the window"),
+            StateError::AdapterNotFound(name) => write!(
+                f,
+                "no adapter is named like {:?}, see --list-adapters",
+                name
+            ),
+            StateError::Device(e) => write!(f, "could not open the graphics device: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+impl From<wgpu::RequestDeviceError> for StateError {
+    fn from(e: wgpu::RequestDeviceError) -> Self {
+        StateError::Device(e)
+    }
+}
+
+/// Prints the name, backend and type of every adapter wgpu can find
+#[cfg(not(target_arch = "wasm32"))]
+pub fn list_adapters() {
+    let instance = wgpu::Instance::new(wgpu::BackendBit::all());
+    for adapter in instance.enumerate_adapters(wgpu::BackendBit::all()) {
+        let info = adapter.get_info();
+        println!("{} ({:?}, {:?})", info.name, info.backend, info.device_type);
+    }
+}
+
+/// Finds an adapter for the window and creates the surface it draws into. The adapter
+/// named with `--adapter` is used if given. Otherwise a high performance adapter of the
+/// chosen backends is preferred, then a low power one, and then the same again on every
+/// backend, which also reaches software renderers such as llvmpipe or WARP.
+async fn request_adapter(
+    window: &Window,
+    options: &Options,
+) -> Result<(wgpu::Surface, wgpu::Adapter), StateError> {
+    let mut attempts = vec![options.backends];
+    if options.backends != wgpu::BackendBit::all() {
+        attempts.push(wgpu::BackendBit::all());
+    }
+    for backends in attempts {
+        let instance = wgpu::Instance::new(backends);
         let surface = unsafe { instance.create_surface(window) };
-        let adapter = instance
-            .request_adapter({
-                &RequestAdapterOptions {
-                    power_preference: wgpu::PowerPreference::default(),
+        // Adapters cannot be listed in browsers
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(name) = &options.adapter {
+            let needle = name.to_lowercase();
+            let adapter = instance
+                .enumerate_adapters(backends)
+                .find(|adapter| adapter.get_info().name.to_lowercase().contains(&needle));
+            match adapter {
+                Some(adapter) => return Ok((surface, adapter)),
+                None => continue,
+            }
+        }
+        for &power_preference in &[
+            wgpu::PowerPreference::HighPerformance,
+            wgpu::PowerPreference::LowPower,
+        ] {
+            let adapter = instance
+                .request_adapter(&RequestAdapterOptions {
+                    power_preference,
                     compatible_surface: Some(&surface),
-                }
-            })
-            .await
-            .unwrap();
+                })
+                .await;
+            if let Some(adapter) = adapter {
+                return Ok((surface, adapter));
+            }
+        }
+        eprintln!("no adapter found on {:?}", backends);
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(name) = &options.adapter {
+        return Err(StateError::AdapterNotFound(name.clone()));
+    }
+    Err(StateError::NoAdapter)
+}
+
+/// Picks the number of samples per pixel closest to `requested` that `adapter` can draw
+/// the scene with. wgpu cannot be asked which counts an adapter supports, so only the
+/// counts every adapter has to support (1 and 4) are trusted in browsers and on OpenGL,
+/// while the native backends are also given 2 and 8.
+fn supported_sample_count(adapter: &wgpu::Adapter, requested: u32) -> u32 {
+    let native = matches!(
+        adapter.get_info().backend,
+        wgpu::Backend::Vulkan | wgpu::Backend::Metal | wgpu::Backend::Dx12
+    );
+    match requested {
+        1 | 4 => requested,
+        2 | 8 if native => requested,
+        _ => {
+            eprintln!("{}x MSAA is not available, using 4x", requested);
+            4
+        }
+    }
+}
+
+impl State {
+    pub async fn new(window: &Window, options: &Options) -> Result<Self, StateError> {
+        let window_size = window.inner_size();
+        let (surface, adapter) = request_adapter(window, options).await?;
+        let info = adapter.get_info();
+        println!("adapter: {} ({:?})", info.name, info.backend);
+        let timestamps =
+            options.gpu_timing && adapter.features().contains(wgpu::Features::TIMESTAMP_QUERY);
+        if options.gpu_timing && !timestamps {
+            eprintln!(
+                "{} does not support timestamp queries, GPU timing is off",
+                info.name
+            );
+        }
         let (device, queue) = adapter
             .request_device(
                 &DeviceDescriptor {
-                    features: wgpu::Features::empty(),
+                    features: if timestamps {
+                        wgpu::Features::TIMESTAMP_QUERY
+                    } else {
+                        wgpu::Features::empty()
+                    },
                     limits: wgpu::Limits::default(),
                     label: None,
                 },
                 None,
             )
-            .await
-            .unwrap();
+            .await?;
+        let (device, queue) = (Arc::new(device), Arc::new(queue));
         let staging_belt = wgpu::util::StagingBelt::new(1024);
         let local_pool = LocalPool::new();
         let local_spawner = local_pool.spawner();
 
-        let options = shaderc::CompileOptions::new().unwrap();
-        let mut compiler = shaderc::Compiler::new().unwrap();
-
-        // vertex shader module
-        let vx_module = device.create_shader_module(&ShaderModuleDescriptor {
-            label: Some("Vertex Shader"),
-            flags: wgpu::ShaderFlags::default(),
-            source: wgpu::ShaderSource::SpirV(Cow::Borrowed(
-                compiler
-                    .compile_into_spirv(
-                        include_str!("shaders/shader.vert"),
-                        shaderc::ShaderKind::Vertex,
-                        "shader.vert",
-                        "main",
-                        Some(&options),
-                    )
-                    .unwrap()
-                    .as_binary(),
-            )),
+        let vx_module = device.create_shader_module(&include_wgsl!("shaders/shader.vert.wgsl"));
+        let fg_module = device.create_shader_module(&include_wgsl!("shaders/shader.frag.wgsl"));
+        // Cuts anti-aliased circles out of the unit quads drawn for each particle
+        let circle_module = device.create_shader_module(&include_wgsl!("shaders/circle.frag.wgsl"));
+        let line_module = device.create_shader_module(&include_wgsl!("shaders/line.vert.wgsl"));
+
+        let camera = Camera::new(window_size);
+        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Camera Buffer"),
+            contents: bytemuck::bytes_of(&CameraUniform::from_camera(&camera)),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
         });
-        // fragment shader module
-        let fg_module = device.create_shader_module(&ShaderModuleDescriptor {
-            label: Some("Fragment Shader"),
-            flags: wgpu::ShaderFlags::default(),
-            source: wgpu::ShaderSource::SpirV(Cow::Borrowed(
-                compiler
-                    .compile_into_spirv(
-                        include_str!("shaders/shader.frag"),
-                        shaderc::ShaderKind::Fragment,
-                        "shader.frag",
-                        "main",
-                        Some(&options),
-                    )
-                    .unwrap()
-                    .as_binary(),
-            )),
+        let camera_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Camera Bind Group Layout"),
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Camera Bind Group"),
+            layout: &camera_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            }],
         });
 
         let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
             label: None,
-            bind_group_layouts: &[],
+            bind_group_layouts: &[&camera_bind_group_layout],
             push_constant_ranges: &[],
         });
 
         let format = adapter.get_swap_chain_preferred_format(&surface);
+        let sample_count = supported_sample_count(&adapter, options.msaa);
+        let multisample = wgpu::MultisampleState {
+            count: sample_count,
+            ..wgpu::MultisampleState::default()
+        };
         let render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
             label: Some("Render Pipeline"),
             layout: Some(&pipeline_layout),
             depth_stencil: None,
-            multisample: wgpu::MultisampleState::default(),
+            multisample: multisample.clone(),
             primitive: wgpu::PrimitiveState::default(),
+            fragment: Some(wgpu::FragmentState {
+                entry_point: "main",
+                module: &circle_module,
+                targets: &[wgpu::ColorTargetState {
+                    alpha_blend: draw::ALPHA_BLEND,
+                    color_blend: draw::ALPHA_BLEND,
+                    write_mask: wgpu::ColorWrite::ALL,
+                    format: bloom::HDR_FORMAT,
+                }],
+            }),
+            vertex: wgpu::VertexState {
+                entry_point: "main",
+                module: &vx_module,
+                buffers: &[Vertex::desc(), Instance::desc()],
+            },
+        });
+        let starfield = Starfield::new(&device, multisample.clone(), &camera, options.seed);
+        let line_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Line Pipeline"),
+            layout: Some(&pipeline_layout),
+            depth_stencil: None,
+            multisample,
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..wgpu::PrimitiveState::default()
+            },
             fragment: Some(wgpu::FragmentState {
                 entry_point: "main",
                 module: &fg_module,
@@ -131,22 +642,35 @@ impl State {
                     alpha_blend: wgpu::BlendState::default(),
                     color_blend: wgpu::BlendState::default(),
                     write_mask: wgpu::ColorWrite::ALL,
-                    format,
+                    format: bloom::HDR_FORMAT,
                 }],
             }),
             vertex: wgpu::VertexState {
                 entry_point: "main",
-                module: &vx_module,
-                buffers: &[Vertex::desc(), Instance::desc()],
+                module: &line_module,
+                buffers: &[Vertex::desc()],
             },
         });
+        let scene3d = if options.three_d {
+            Some(Scene3D::new(
+                &device,
+                &queue,
+                &circle_module,
+                bloom::HDR_FORMAT,
+                sample_count,
+                window_size,
+                options.seed,
+            ))
+        } else {
+            None
+        };
         let font =
             ab_glyph::FontArc::try_from_slice(include_bytes!("font/Hack-Regular.ttf")).unwrap();
         let glyph_brush = GlyphBrushBuilder::using_font(font).build(&device, format);
 
-        // Construction of a unit circle via a triangle fan
+        // Every particle is drawn as a unit quad that circle.frag.wgsl turns into a circle
         let DrawBuffers { vertices, indices } =
-            draw::create_unit_circle(cgmath::Vector3::new(1.0, 1.0, 1.0), window_size);
+            draw::create_unit_quad(cgmath::Vector3::new(1.0, 1.0, 1.0));
         let indices = indices.unwrap();
 
         let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -161,15 +685,59 @@ impl State {
             usage: wgpu::BufferUsage::INDEX,
         });
 
-        let instances: Vec<Instance> = Vec::new();
-        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Instance Buffer"),
-            contents: bytemuck::cast_slice(instances.as_slice()),
-            usage: wgpu::BufferUsage::VERTEX,
+        let instance_buffer = DynamicBuffer::new(
+            &device,
+            "Instance Buffer",
+            wgpu::BufferUsage::VERTEX,
+            INITIAL_INSTANCE_CAPACITY,
+        );
+
+        let arrow_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Arrow Buffer"),
+            size: (MAX_ARROW_VERTICES * std::mem::size_of::<Vertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let trajectory_buffer = DynamicBuffer::new(
+            &device,
+            "Trajectory Buffer",
+            wgpu::BufferUsage::VERTEX,
+            2 * TRAJECTORY_STEPS,
+        );
+
+        let legend_buffer = DynamicBuffer::new(
+            &device,
+            "Legend Buffer",
+            wgpu::BufferUsage::VERTEX,
+            2 * LEGEND_WIDTH_PIXELS,
+        );
+
+        let energy_buffer =
+            DynamicBuffer::new(&device, "Energy Buffer", wgpu::BufferUsage::VERTEX, 1);
+
+        let vector_buffer =
+            DynamicBuffer::new(&device, "Vector Buffer", wgpu::BufferUsage::VERTEX, 1);
+
+        let marker_buffer = DynamicBuffer::new(
+            &device,
+            "Marker Buffer",
+            wgpu::BufferUsage::VERTEX,
+            4 * (1 + 5),
+        );
+
+        let group_buffer =
+            DynamicBuffer::new(&device, "Group Buffer", wgpu::BufferUsage::VERTEX, 1);
+
+        let selection_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Selection Buffer"),
+            size: (2 * draw::RING_SEGMENTS * std::mem::size_of::<Vertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
         });
 
         let sc_desc = SwapChainDescriptor {
-            present_mode: wgpu::PresentMode::Fifo,
+            present_mode: options.present_mode,
             height: window_size.height,
             width: window_size.width,
             format,
@@ -177,8 +745,62 @@ impl State {
             usage: wgpu::TextureUsage::RENDER_ATTACHMENT,
         };
         let swap_chain = device.create_swap_chain(&surface, &sc_desc);
+        // The scene is drawn offscreen and copied to the swap chain by the bloom pass
+        let bloom = Bloom::new(&device, window_size, format, sample_count);
 
-        Self {
+        let mut sim = Simulation::new(
+            options.time_step,
+            options.theta,
+            constants::SIM_G,
+            constants::SOFTENING,
+            options.integrator,
+        );
+        sim.set_merge_model(options.merge_model);
+        sim.set_expansion(options.expansion);
+        sim.set_interaction(options.interaction);
+        sim.set_incremental_tree(options.incremental_tree);
+        sim.set_leaf_capacity(options.leaf_capacity);
+        sim.set_morton_sort(options.morton_sort);
+        if options.adaptive {
+            sim.set_adaptive_step(Some(options.adaptive_step));
+        }
+        if options.block {
+            sim.set_block_steps(Some(options.block_steps));
+        }
+        if options.drag {
+            sim.set_drag(Some(options.drag_settings));
+        }
+        if options.fragment {
+            sim.set_fragmentation(Some(options.fragmentation));
+        }
+        let mut rng = StdRng::seed_from_u64(options.seed);
+        if let Some(count) = options.particles {
+            sim.add_particles(presets::random_cloud(&mut rng, count));
+        }
+        let export_path = options
+            .export
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(EXPORT_PATH));
+        let steps_per_frame = options
+            .steps_per_frame
+            .clamp(MIN_STEPS_PER_FRAME, MAX_STEPS_PER_FRAME);
+        let mut stepper = Stepper::new(sim, Arc::clone(&device), Arc::clone(&queue), export_path);
+        stepper.running = !options.paused && scene3d.is_none();
+        stepper.set_steps_per_tick(steps_per_frame);
+        stepper.color_mapping = options.color_mapping;
+        stepper.colormap = options.colormap;
+        if options.export.is_some() {
+            stepper.toggle_export();
+        }
+        let physics = Physics::start(stepper, options.physics_rate);
+        let snapshot = physics.latest();
+
+        let gpu_timer = if timestamps {
+            Some(GpuTimer::new(&device, adapter.get_timestamp_period()))
+        } else {
+            None
+        };
+        let mut state = Self {
             surface,
             device,
             queue,
@@ -192,36 +814,179 @@ impl State {
                 a: 1.0,
             },
             render_pipeline,
+            line_pipeline,
+            starfield,
+            show_starfield: true,
             vertex_buffer,
             index_buffer,
             num_indices: indices.len() as u32,
             instance_buffer,
-            num_instances: instances.len() as u32,
+            instances: Vec::new(),
+            arrow_buffer,
+            num_arrow_vertices: 0,
+            trajectory_buffer,
+            orbit_placement: false,
+            mirror_placement: false,
+            crosshair: None,
+            crosshair_velocity: cgmath::vec2(0.0, 0.0),
+            selected: None,
+            selection_buffer,
+            num_selection_vertices: 0,
+            group_selection: Vec::new(),
+            rectangle_start: None,
+            impulse_start: None,
+            group_buffer,
+            history: EditHistory::new(HISTORY_LENGTH),
+            vector_overlay: VectorOverlay::default(),
+            vector_buffer,
+            show_lagrange_points: false,
+            marker_buffer,
+            drag_start: None,
             // Keep track of cursor position so that we can later add new
             // particles whenever there's a mouse left click event
             cursor_pos: PhysicalPosition::new(0.0, 0.0),
-            sim: Simulation::new(0.05, 1.0),
+            modifiers: ModifiersState::empty(),
+            keymap: Keymap::new(&options.keys),
+            burst_size: options.burst_size,
+            burst_radius: options.burst_radius,
+            burst_spin: options.burst_spin,
+            spawn_radius: SPAWN_RADIUS,
+            spawn_mass: SPAWN_MASS_PER_RADIUS * SPAWN_RADIUS,
+            camera,
+            camera_buffer,
+            camera_bind_group,
+            panning: false,
+            attracting: false,
+            attractor_mass: ATTRACTOR_MASS,
+            physics,
+            snapshot,
+            adaptive_step: options.adaptive_step,
+            block_steps: options.block_steps,
+            drag: options.drag_settings,
+            fragmentation: options.fragmentation,
             glyph_brush,
             staging_belt,
             local_pool,
             local_spawner,
-            paused: true,
+            paused: options.paused,
+            show_timings: false,
+            show_help: false,
+            show_labels: true,
+            following: None,
+            heading_up: false,
+            timings: FrameTimings::default(),
+            min_frame_interval: options
+                .max_fps
+                .filter(|&fps| fps > 0.0)
+                .map(|fps| Duration::from_secs_f32(1.0 / fps)),
+            last_frame: Instant::now(),
+            scenario_path: options.scenario.clone(),
+            no_config: options.no_config,
+            color_range: None,
+            legend_buffer,
+            energy_buffer,
+            bloom,
+            capture: None,
+            clip: None,
+            clip_seconds: options.clip_seconds.max(0.1),
+            scene3d,
+            playback: None,
+            steps_per_frame,
+            step_budget: 0.0,
+            queued_steps: 0,
+            rng,
+            exit_requested: false,
+            gpu_timer,
+        };
+        if options.bloom {
+            state.bloom.set_intensity(&state.queue, bloom::INTENSITY);
         }
+        state.update_instance_buffer();
+        state.load_scenario();
+
+        Ok(state)
     }
 
-    pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
-        // Reconstruct the unit circle with the new size and recreate the vertex buffer
-        let DrawBuffers { vertices, .. } =
-            draw::create_unit_circle(cgmath::Vector3::new(1.0, 1.0, 1.0), new_size);
+    /// Replaces all particles with the ones described by the scenario file (if any)
+    fn load_scenario(&mut self) {
+        let path = match &self.scenario_path {
+            Some(path) => path,
+            None => return,
+        };
 
-        self.vertex_buffer.destroy();
-        self.vertex_buffer = self
-            .device
-            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Vertex Buffer"),
-                contents: bytemuck::cast_slice(vertices.as_slice()),
-                usage: wgpu::BufferUsage::VERTEX,
-            });
+        match Scenario::load(path) {
+            Ok(scenario) => {
+                #[cfg(feature = "scripting")]
+                let script = scenario.script.as_deref().and_then(|source| {
+                    StepScript::new(source)
+                        .map_err(|e| eprintln!("{}: {}", path.display(), e))
+                        .ok()
+                });
+                #[cfg(not(feature = "scripting"))]
+                if scenario.script.is_some() {
+                    eprintln!("{}: scripts need the scripting feature", path.display());
+                }
+                self.change_simulation(|stepper| {
+                    let sim = &mut stepper.sim;
+                    sim.reset();
+                    sim.clear_potentials();
+                    for potential in scenario.to_potentials() {
+                        sim.add_potential(potential);
+                    }
+                    sim.set_evaporation(scenario.to_evaporation());
+                    sim.set_groups(scenario.groups.clone());
+                    #[cfg(feature = "scripting")]
+                    sim.set_script(script);
+                    sim.add_particles(scenario.to_particles());
+                    stepper.clear_energy_history();
+                });
+                self.history.clear();
+            }
+            Err(e) => eprintln!("{}: {}", path.display(), e),
+        }
+    }
+
+    /// Replaces the current particles with the ones generated by `preset`
+    fn load_preset(&mut self, preset: Preset) {
+        let g = self.physics.lock().sim.get_g();
+        let particles = preset.generate(&mut self.rng, g);
+        self.change_simulation(|stepper| {
+            stepper.sim.reset();
+            stepper.sim.clear_potentials();
+            stepper.sim.set_evaporation(None);
+            stepper.sim.set_groups(GroupMatrix::default());
+            #[cfg(feature = "scripting")]
+            stepper.sim.set_script(None);
+            stepper.sim.add_particles(particles);
+            stepper.clear_energy_history();
+        });
+        self.history.clear();
+    }
+
+    /// Changes the 2D simulation in between two steps and shows the result right away,
+    /// even while paused
+    fn change_simulation<F: FnOnce(&mut Stepper)>(&mut self, change: F) {
+        change(&mut self.physics.lock());
+        self.update_instance_buffer();
+    }
+
+    /// Tells the physics thread how fast to step. The 2D simulation stands still while
+    /// paused, in 3D mode and while a replay is played back.
+    fn update_pace(&self) {
+        let mut stepper = self.physics.lock();
+        stepper.running = !self.paused && self.scene3d.is_none() && self.playback.is_none();
+        stepper.set_steps_per_tick(self.steps_per_frame);
+    }
+
+    pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
+        // Minimized windows (and some fullscreen transitions) report a zero size, which
+        // no swap chain can be created for
+        if new_size.width == 0 || new_size.height == 0 {
+            return;
+        }
+        // The projection takes care of the aspect ratio so only the camera needs updating
+        self.camera.resize(new_size);
+        self.update_camera_buffer();
 
         // We'll need to recreate the swap chain on resize events. We'll just mutate
         // the internal state then just recreate the swap chain with the now
@@ -231,130 +996,1558 @@ impl State {
         self.sc_desc.width = new_size.width;
         // Swap chain will be recreated with the new values
         self.recreate_swap_chain();
+        self.bloom.resize(&self.device, new_size);
+        if let Some(capture) = &mut self.capture {
+            capture.resize(&self.device, new_size);
+        }
+        if let Some(clip) = &mut self.clip {
+            clip.resize(&self.device, new_size);
+        }
+        if let Some(scene) = &mut self.scene3d {
+            scene.resize(&self.device, new_size);
+            scene.update_camera_buffer(&self.queue);
+        }
+    }
+
+    /// Switches between playing back `REPLAY_PATH` and the live simulation
+    fn toggle_playback(&mut self) {
+        if self.playback.take().is_none() {
+            match Replay::load(REPLAY_PATH) {
+                Ok(replay) if !replay.is_empty() => {
+                    self.playback = Some(Playback { replay, frame: 0 })
+                }
+                Ok(_) => eprintln!("{}: replay is empty", REPLAY_PATH),
+                Err(e) => eprintln!("{}: {}", REPLAY_PATH, e),
+            }
+        }
+        self.update_pace();
+        self.update_instance_buffer();
+    }
+
+    /// Starts recording frames into `CAPTURE_DIR` or stops an ongoing recording
+    fn toggle_capture(&mut self) {
+        if self.capture.take().is_some() {
+            return;
+        }
+        match Capture::new(&self.device, self.size, self.sc_desc.format, CAPTURE_DIR) {
+            Ok(capture) => self.capture = Some(capture),
+            Err(e) => eprintln!("{}: {}", CAPTURE_DIR, e),
+        }
+    }
+
+    /// Starts keeping the last `clip_seconds` of frames or, once started, saves them as
+    /// the next free `CAPTURE_DIR/clip_000.gif`
+    fn save_clip(&mut self) {
+        let clip = match &mut self.clip {
+            Some(clip) => clip,
+            None => {
+                self.clip = Some(ClipRecorder::new(
+                    &self.device,
+                    self.size,
+                    self.sc_desc.format,
+                    self.clip_seconds,
+                ));
+                return;
+            }
+        };
+        if let Err(e) = std::fs::create_dir_all(CAPTURE_DIR) {
+            eprintln!("{}: {}", CAPTURE_DIR, e);
+            return;
+        }
+        let path = (0..)
+            .map(|n| PathBuf::from(CAPTURE_DIR).join(format!("clip_{:03}.gif", n)))
+            .find(|path| !path.exists())
+            .expect("ran out of clip names");
+        println!("saving clip to {}", path.display());
+        clip.save(path);
     }
 
     pub fn recreate_swap_chain(&mut self) {
         self.swap_chain = self.device.create_swap_chain(&self.surface, &self.sc_desc);
     }
 
+    /// Switches to the next present mode. Modes the surface does not support fall back
+    /// to Fifo.
+    fn cycle_present_mode(&mut self) {
+        self.sc_desc.present_mode = match self.sc_desc.present_mode {
+            wgpu::PresentMode::Fifo => wgpu::PresentMode::Mailbox,
+            wgpu::PresentMode::Mailbox => wgpu::PresentMode::Immediate,
+            wgpu::PresentMode::Immediate => wgpu::PresentMode::Fifo,
+        };
+        self.recreate_swap_chain();
+    }
+
+    /// When the next frame should be drawn if the frame rate is capped, `None` to draw it
+    /// right away. Fifo is already held back by the vertical blank.
+    pub fn next_frame_time(&self) -> Option<Instant> {
+        match (self.sc_desc.present_mode, self.min_frame_interval) {
+            (wgpu::PresentMode::Fifo, _) => None,
+            (_, interval) => interval.map(|interval| self.last_frame + interval),
+        }
+    }
+
+    /// Uploads the current camera matrix. Must be called whenever the camera changes.
+    fn update_camera_buffer(&mut self) {
+        self.queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytemuck::bytes_of(&CameraUniform::from_camera(&self.camera)),
+        );
+        self.starfield.update(&self.queue, &self.camera);
+    }
+
+    /// Uploads an arrow from the drag start to the cursor
+    fn update_arrow_buffer(&mut self) {
+        if let Some(start) = self.drag_start {
+            let end = self.camera.screen_to_world(self.cursor_pos, self.size);
+            let DrawBuffers { vertices, .. } =
+                draw::create_arrow(start, end, cgmath::Vector3::new(1.0, 0.8, 0.2));
+            self.queue
+                .write_buffer(&self.arrow_buffer, 0, bytemuck::cast_slice(&vertices));
+            self.num_arrow_vertices = vertices.len() as u32;
+        }
+    }
+
+    /// Velocity given to a particle placed at `start` in `sim` with the mouse released at
+    /// `end`
+    fn spawn_velocity(
+        &self,
+        sim: &Simulation,
+        start: cgmath::Vector2<Scalar>,
+        end: cgmath::Vector2<Scalar>,
+    ) -> cgmath::Vector2<Scalar> {
+        let velocity = (end - start) * DRAG_VELOCITY_SCALE;
+        // Dragging still adds to the orbital velocity, which makes the orbit eccentric
+        if self.orbit_placement {
+            velocity
+                + sim
+                    .circular_orbit_velocity(start)
+                    .unwrap_or_else(|| cgmath::vec2(0.0, 0.0))
+        } else {
+            velocity
+        }
+    }
+
+    /// Positions and velocities of the particles placing one at `position` with `velocity`
+    /// spawns: just that one, plus its twin in mirror placement mode. The twin is
+    /// reflected through the center of the world and moves the opposite way, so that
+    /// the pair has no momentum. It is left out if it would overlap the original.
+    fn placements(
+        &self,
+        position: cgmath::Vector2<Scalar>,
+        velocity: cgmath::Vector2<Scalar>,
+    ) -> Vec<(cgmath::Vector2<Scalar>, cgmath::Vector2<Scalar>)> {
+        let mut placements = vec![(position, velocity)];
+        let center = cgmath::vec2(
+            (constants::MIN_X + constants::MAX_X) / 2.0,
+            (constants::MIN_Y + constants::MAX_Y) / 2.0,
+        );
+        let twin = center * 2.0 - position;
+        if self.mirror_placement && (twin - position).magnitude() > 2.0 * self.spawn_radius {
+            placements.push((twin, -velocity));
+        }
+        placements
+    }
+
+    /// Spawns a particle at `start` moving as if dragged to `end`, along with its twin in
+    /// mirror placement mode
+    fn place_particle(&mut self, start: cgmath::Vector2<Scalar>, end: cgmath::Vector2<Scalar>) {
+        let added = {
+            let sim = &mut self.physics.lock().sim;
+            let velocity = self.spawn_velocity(sim, start, end);
+            let particles: Vec<_> = self
+                .placements(start, velocity)
+                .into_iter()
+                .map(|(position, velocity)| {
+                    Particle::new(ParticleProperties {
+                        position,
+                        radius: self.spawn_radius,
+                        mass: self.spawn_mass,
+                        velocity,
+                        acceleration: cgmath::vec2(0.0, 0.0),
+                        color: color::WHITE,
+                    })
+                })
+                .collect();
+            sim.add_particles(particles)
+        };
+        self.history.record(Edit::Added(added));
+        self.update_instance_buffer();
+    }
+
+    /// Where dragging from the keyboard crosshair at `crosshair` would have to end to give
+    /// the particle spawned there `crosshair_velocity`
+    fn crosshair_drag_end(&self, crosshair: cgmath::Vector2<Scalar>) -> cgmath::Vector2<Scalar> {
+        crosshair + self.crosshair_velocity / DRAG_VELOCITY_SCALE
+    }
+
+    /// Moves the keyboard crosshair one step in the direction of the crosshair action
+    /// `action`, or changes the velocity of the particle placed there while Alt is held.
+    /// Shift makes the steps smaller.
+    fn steer_crosshair(&mut self, action: Action) {
+        let direction = match action {
+            Action::CrosshairLeft => cgmath::vec2(-1.0, 0.0),
+            Action::CrosshairRight => cgmath::vec2(1.0, 0.0),
+            Action::CrosshairUp => cgmath::vec2(0.0, 1.0),
+            Action::CrosshairDown => cgmath::vec2(0.0, -1.0),
+            _ => return,
+        };
+        let scale = if self.modifiers.shift() {
+            CROSSHAIR_FINE_STEP
+        } else {
+            1.0
+        };
+        if self.modifiers.alt() {
+            self.crosshair_velocity += direction * (CROSSHAIR_VELOCITY_STEP * scale);
+        } else if let Some(crosshair) = &mut self.crosshair {
+            let step = CROSSHAIR_STEP_PIXELS * from_f32(self.camera.pixel_size());
+            *crosshair += direction * (step * scale);
+        }
+    }
+
+    /// Scales the radius and the mass of the particles a click spawns by the given
+    /// factors, within bounds
+    fn scale_spawn(&mut self, radius_factor: Scalar, mass_factor: Scalar) {
+        self.spawn_radius =
+            (self.spawn_radius * radius_factor).clamp(MIN_SPAWN_RADIUS, MAX_SPAWN_RADIUS);
+        self.spawn_mass = (self.spawn_mass * mass_factor).clamp(MIN_SPAWN_MASS, MAX_SPAWN_MASS);
+    }
+
+    /// Uploads the ghost of the particle that is being placed, under the cursor until the
+    /// mouse is pressed (or under the keyboard crosshair), and its predicted path. In
+    /// orbit placement mode the path of a particle placed at the cursor is shown even
+    /// before the mouse is pressed.
+    fn update_trajectory_buffer(&mut self) {
+        let cursor = self.camera.screen_to_world(self.cursor_pos, self.size);
+        let placing = self.scene3d.is_none()
+            && self.playback.is_none()
+            && !self.attracting
+            && self.rectangle_start.is_none()
+            && self.impulse_start.is_none();
+        let (start, end, show_path) = match self.crosshair {
+            Some(crosshair) => (crosshair, self.crosshair_drag_end(crosshair), true),
+            None => (
+                self.drag_start.unwrap_or(cursor),
+                cursor,
+                self.drag_start.is_some() || (self.orbit_placement && self.selected.is_none()),
+            ),
+        };
+        let mut vertices = Vec::new();
+        if placing {
+            let pixel_size = from_f32(self.camera.pixel_size());
+            let dash = TRAJECTORY_DASH_PIXELS * pixel_size;
+            if self.crosshair.is_some() {
+                let size = MARKER_SIZE_PIXELS * pixel_size;
+                vertices
+                    .extend(draw::create_crosshair(start, size, CROSSHAIR_COLOR.into()).vertices);
+            }
+            let sim = &mut self.physics.lock().sim;
+            let velocity = if show_path {
+                self.spawn_velocity(sim, start, end)
+            } else {
+                cgmath::vec2(0.0, 0.0)
+            };
+            for (position, velocity) in self.placements(start, velocity) {
+                vertices.extend(
+                    draw::create_ring(position, self.spawn_radius, GHOST_COLOR.into()).vertices,
+                );
+                if show_path {
+                    let path = sim.predict_trajectory(position, velocity, TRAJECTORY_STEPS);
+                    vertices
+                        .extend(draw::create_dashed_path(&path, dash, GHOST_COLOR.into()).vertices);
+                }
+            }
+        }
+        self.trajectory_buffer
+            .write(&self.device, &self.queue, &vertices);
+    }
+
+    /// Uploads arrows along the velocity (and acceleration) of every particle, or hides
+    /// them if the overlay is off
+    fn update_vector_buffer(&mut self) {
+        let particles = &self.snapshot.particles;
+        let mut vertices = Vec::new();
+        if self.scene3d.is_none() && self.playback.is_none() {
+            if self.vector_overlay != VectorOverlay::Off {
+                vertices = draw::create_vector_arrows(
+                    particles.positions(),
+                    particles.velocities(),
+                    VELOCITY_ARROW_SCALE,
+                    cgmath::Vector3::new(0.3, 0.9, 0.4),
+                )
+                .vertices;
+            }
+            if self.vector_overlay == VectorOverlay::Both {
+                vertices.extend(
+                    draw::create_vector_arrows(
+                        particles.positions(),
+                        particles.accelerations(),
+                        ACCELERATION_ARROW_SCALE,
+                        cgmath::Vector3::new(1.0, 0.35, 0.3),
+                    )
+                    .vertices,
+                );
+            }
+        }
+        self.vector_buffer
+            .write(&self.device, &self.queue, &vertices);
+    }
+
+    /// Uploads crosshairs on the barycenter and the Lagrange points of the two heaviest
+    /// particles, or hides them if they are not shown
+    fn update_marker_buffer(&mut self) {
+        let mut vertices = Vec::new();
+        if let Some(lagrange) = self.lagrange_points() {
+            let size = MARKER_SIZE_PIXELS * from_f32(self.camera.pixel_size());
+            vertices = draw::create_crosshair(
+                lagrange.barycenter,
+                size,
+                cgmath::Vector3::new(1.0, 0.85, 0.3),
+            )
+            .vertices;
+            for &point in lagrange.points.iter() {
+                vertices.extend(
+                    draw::create_crosshair(point, size, cgmath::Vector3::new(0.9, 0.4, 1.0))
+                        .vertices,
+                );
+            }
+        }
+        self.marker_buffer
+            .write(&self.device, &self.queue, &vertices);
+    }
+
+    /// Barycenter and Lagrange points of the two heaviest particles, if they are shown
+    fn lagrange_points(&self) -> Option<LagrangePoints> {
+        if !self.show_lagrange_points || self.scene3d.is_some() || self.playback.is_some() {
+            return None;
+        }
+        LagrangePoints::of_heaviest(&self.snapshot.particles)
+    }
+
+    /// Uploads the color bar of the legend, or hides it if particles keep their own color
+    fn update_legend_buffer(&mut self) {
+        let vertices = match self.color_range {
+            Some(_) if self.scene3d.is_none() => {
+                let (left, top) = self.legend_origin();
+                let bar = draw::create_color_bar(
+                    cgmath::vec2(left, top + LEGEND_HEIGHT_PIXELS).map(from_f32),
+                    cgmath::vec2(left + LEGEND_WIDTH_PIXELS as f32, top).map(from_f32),
+                    LEGEND_WIDTH_PIXELS,
+                    self.snapshot.status.colormap,
+                );
+                self.pin_to_screen(bar.vertices)
+            }
+            _ => Vec::new(),
+        };
+        self.legend_buffer
+            .write(&self.device, &self.queue, &vertices);
+    }
+
+    /// Moves `vertices` given in window space (pixels) to where they show up at those
+    /// pixels in world space, so that overlays stay put on screen however the camera
+    /// moves or turns
+    fn pin_to_screen(&self, mut vertices: Vec<Vertex>) -> Vec<Vertex> {
+        for vertex in &mut vertices {
+            let [x, y] = vertex.position;
+            let world = self
+                .camera
+                .screen_to_world(PhysicalPosition::new(x as f64, y as f64), self.size);
+            vertex.position = [to_f32(world.x), to_f32(world.y)];
+        }
+        vertices
+    }
+
+    /// Uploads the plot of the energy over the last steps, or hides it if the energy is
+    /// not measured
+    fn update_energy_buffer(&mut self) {
+        let energy = &self.snapshot.energy;
+        let mut vertices = Vec::new();
+        if !energy.is_empty() && self.scene3d.is_none() && self.playback.is_none() {
+            let (left, top) = self.energy_plot_origin();
+            let min = cgmath::vec2(left, top + ENERGY_PLOT_HEIGHT_PIXELS).map(from_f32);
+            let max = cgmath::vec2(left + ENERGY_PLOT_WIDTH_PIXELS, top).map(from_f32);
+            let series: [(Vec<Scalar>, cgmath::Vector3<f32>); 3] = [
+                (
+                    energy.iter().map(|e| e.kinetic).collect(),
+                    cgmath::Vector3::new(1.0, 0.5, 0.3),
+                ),
+                (
+                    energy.iter().map(|e| e.potential).collect(),
+                    cgmath::Vector3::new(0.4, 0.6, 1.0),
+                ),
+                (
+                    energy.iter().map(|e| e.total()).collect(),
+                    cgmath::Vector3::new(0.9, 0.9, 0.9),
+                ),
+            ];
+            // All three share a scale so that they add up on screen as well
+            let range = series
+                .iter()
+                .flat_map(|(values, _)| values)
+                .fold((Scalar::MAX, Scalar::MIN), |(low, high), &value| {
+                    (low.min(value), high.max(value))
+                });
+            vertices =
+                draw::create_rectangle(min, max, cgmath::Vector3::new(0.4, 0.4, 0.4)).vertices;
+            for (values, color) in &series {
+                vertices.extend(
+                    draw::create_line_graph(values, min, max, ENERGY_HISTORY, range, *color)
+                        .vertices,
+                );
+            }
+            vertices = self.pin_to_screen(vertices);
+        }
+        self.energy_buffer
+            .write(&self.device, &self.queue, &vertices);
+    }
+
+    /// Window position of the top left corner of the energy plot
+    fn energy_plot_origin(&self) -> (f32, f32) {
+        (
+            self.size.width as f32 - 30.0 - ENERGY_PLOT_WIDTH_PIXELS,
+            self.size.height as f32 - 30.0 - ENERGY_PLOT_HEIGHT_PIXELS,
+        )
+    }
+
+    /// Queues the current energies above the energy plot, along with how far the total
+    /// drifted over the plotted steps
+    fn queue_energy_legend(&mut self) {
+        let (first, last) = match (self.snapshot.energy.first(), self.snapshot.energy.last()) {
+            (Some(first), Some(last)) if self.scene3d.is_none() && self.playback.is_none() => {
+                (*first, *last)
+            }
+            _ => return,
+        };
+        let drift = if first.total() != 0.0 {
+            format!(
+                " (drift {:+.3}%)",
+                (last.total() - first.total()) / first.total().abs() * 100.0
+            )
+        } else {
+            String::new()
+        };
+        let (left, top) = self.energy_plot_origin();
+        let texts = [
+            (
+                format!("total {:.3e}{}\n", last.total(), drift),
+                [0.9, 0.9, 0.9, 1.0],
+            ),
+            (
+                format!("kinetic {:.3e}  ", last.kinetic),
+                [1.0, 0.5, 0.3, 1.0],
+            ),
+            (
+                format!("potential {:.3e}", last.potential),
+                [0.4, 0.6, 1.0, 1.0],
+            ),
+        ];
+        self.glyph_brush.queue(Section {
+            screen_position: (left, top - 44.0),
+            bounds: (self.size.width as f32, self.size.height as f32),
+            text: texts
+                .iter()
+                .map(|(text, color)| Text::new(text).with_color(*color).with_scale(18.0))
+                .collect(),
+            ..Section::default()
+        });
+    }
+
+    /// Window position of the top left corner of the legend color bar
+    fn legend_origin(&self) -> (f32, f32) {
+        (30.0, self.size.height as f32 - 60.0)
+    }
+
+    /// Queues the name of the mapped quantity above the color bar and its range below
+    fn queue_legend(&mut self) {
+        let (min, max) = match self.color_range {
+            Some(range) if self.scene3d.is_none() => range,
+            _ => return,
+        };
+        let (left, top) = self.legend_origin();
+        let bounds = (self.size.width as f32, self.size.height as f32);
+        let name = match self.snapshot.status.color_mapping {
+            ColorMapping::Fixed => "",
+            ColorMapping::Mass => "mass",
+            ColorMapping::Speed => "speed",
+            ColorMapping::Acceleration => "acceleration",
+        };
+        let bottom = top + LEGEND_HEIGHT_PIXELS + 4.0;
+        let right = left + LEGEND_WIDTH_PIXELS as f32;
+        for (text, position, align) in [
+            (name.to_string(), (left, top - 22.0), HorizontalAlign::Left),
+            (format!("{:.2}", min), (left, bottom), HorizontalAlign::Left),
+            (
+                format!("{:.2}", max),
+                (right, bottom),
+                HorizontalAlign::Right,
+            ),
+        ]
+        .iter()
+        {
+            self.glyph_brush.queue(Section {
+                screen_position: *position,
+                bounds,
+                text: vec![Text::new(text)
+                    .with_color([0.8, 0.8, 0.8, 1.0])
+                    .with_scale(18.0)],
+                layout: Layout::default_single_line().h_align(*align),
+            });
+        }
+    }
+
+    /// Queues every action with its keys, followed by the mouse tools, in as many columns
+    /// as the window height requires
+    fn queue_help(&mut self) {
+        let mut lines: Vec<(String, String)> = self
+            .keymap
+            .bindings()
+            .iter()
+            .filter(|(_, keys)| !keys.is_empty())
+            .map(|(action, keys)| {
+                let keys: Vec<String> = keys.iter().map(|key| key.to_string()).collect();
+                (keys.join(", "), action.description())
+            })
+            .collect();
+        lines.push((String::new(), String::new()));
+        lines.extend(
+            MOUSE_HELP
+                .iter()
+                .map(|(input, description)| (input.to_string(), description.to_string())),
+        );
+        let rows = ((self.size.height as f32 - 2.0 * HELP_MARGIN) / HELP_LINE_HEIGHT).max(1.0);
+        let bounds = (HELP_COLUMN_WIDTH, self.size.height as f32);
+        for (column, chunk) in lines.chunks(rows as usize).enumerate() {
+            let text = chunk
+                .iter()
+                .flat_map(|(keys, description)| {
+                    vec![
+                        Text::new(keys)
+                            .with_color([1.0, 0.9, 0.5, 1.0])
+                            .with_scale(HELP_SCALE),
+                        Text::new(" ").with_scale(HELP_SCALE),
+                        Text::new(description)
+                            .with_color([0.8, 0.8, 0.8, 1.0])
+                            .with_scale(HELP_SCALE),
+                        Text::new("\n").with_scale(HELP_SCALE),
+                    ]
+                })
+                .collect();
+            self.glyph_brush.queue(Section {
+                screen_position: (HELP_MARGIN + column as f32 * HELP_COLUMN_WIDTH, HELP_MARGIN),
+                bounds,
+                text,
+                layout: Layout::default_wrap(),
+            });
+        }
+    }
+
+    /// Queues a large PAUSED at the top of the window while the simulation is paused
+    fn queue_pause_indicator(&mut self) {
+        if !self.paused {
+            return;
+        }
+        let (width, height) = (self.size.width as f32, self.size.height as f32);
+        self.glyph_brush.queue(Section {
+            screen_position: (width / 2.0, 30.0),
+            bounds: (width, height),
+            text: vec![Text::new("PAUSED")
+                .with_color([1.0, 0.4, 0.3, 1.0])
+                .with_scale(48.0)],
+            layout: Layout::default_single_line().h_align(HorizontalAlign::Center),
+        });
+    }
+
+    /// Queues which key shows the help in the bottom right corner
+    fn queue_help_hint(&mut self) {
+        let hint = match self
+            .keymap
+            .bindings()
+            .iter()
+            .find(|(action, _)| *action == Action::ToggleHelp)
+        {
+            Some((_, keys)) if !keys.is_empty() => format!("{}: help", keys[0]),
+            _ => return,
+        };
+        let (width, height) = (self.size.width as f32, self.size.height as f32);
+        self.glyph_brush.queue(Section {
+            screen_position: (width - 30.0, height - 30.0),
+            bounds: (width, height),
+            text: vec![Text::new(&hint)
+                .with_color([0.6, 0.6, 0.6, 1.0])
+                .with_scale(16.0)],
+            layout: Layout::default_single_line()
+                .h_align(HorizontalAlign::Right)
+                .v_align(VerticalAlign::Bottom),
+        });
+    }
+
+    /// Uploads a ring around the selected particle, or hides it if there is nothing to
+    /// highlight
+    fn update_selection_buffer(&mut self) {
+        self.num_selection_vertices = 0;
+        if self.scene3d.is_some() || self.playback.is_some() {
+            return;
+        }
+        if let Some(p) = self.selected.and_then(|id| self.snapshot.particles.get(id)) {
+            let gap = SELECTION_RING_GAP * from_f32(self.camera.pixel_size());
+            let DrawBuffers { vertices, .. } = draw::create_ring(
+                p.position,
+                p.radius + gap,
+                cgmath::Vector3::new(0.3, 0.9, 1.0),
+            );
+            self.queue
+                .write_buffer(&self.selection_buffer, 0, bytemuck::cast_slice(&vertices));
+            self.num_selection_vertices = vertices.len() as u32;
+        }
+    }
+
+    /// Uploads the rectangle being dragged out, the push being given to the group
+    /// selection and rings around its particles, or hides them if there are none
+    fn update_group_buffer(&mut self) {
+        let mut vertices = Vec::new();
+        if self.scene3d.is_none() && self.playback.is_none() {
+            let color = cgmath::Vector3::new(1.0, 0.6, 0.2);
+            let cursor = self.camera.screen_to_world(self.cursor_pos, self.size);
+            if let Some(start) = self.rectangle_start {
+                vertices.extend(draw::create_rectangle(start, cursor, color).vertices);
+            }
+            if let Some(start) = self.impulse_start {
+                vertices.extend(draw::create_arrow(start, cursor, color).vertices);
+            }
+            let gap = SELECTION_RING_GAP * from_f32(self.camera.pixel_size());
+            for &id in &self.group_selection {
+                if let Some(p) = self.snapshot.particles.get(id) {
+                    vertices.extend(draw::create_ring(p.position, p.radius + gap, color).vertices);
+                }
+            }
+        }
+        self.group_buffer
+            .write(&self.device, &self.queue, &vertices);
+    }
+
+    /// Queues the name of every named particle on screen, just right of its edge
+    fn queue_labels(&mut self) {
+        if !self.show_labels || self.scene3d.is_some() || self.playback.is_some() {
+            return;
+        }
+        let particles = &self.snapshot.particles;
+        let pixels_per_unit = 1.0 / self.camera.pixel_size();
+        let (width, height) = (self.size.width as f32, self.size.height as f32);
+        for ((name, &position), &radius) in particles
+            .names()
+            .iter()
+            .zip(particles.positions())
+            .zip(particles.radii())
+        {
+            let name = match name {
+                Some(name) => name,
+                None => continue,
+            };
+            let screen = self.camera.world_to_screen(position, self.size);
+            if screen.x < 0.0 || screen.y < 0.0 || screen.x > width || screen.y > height {
+                continue;
+            }
+            let offset = to_f32(radius) * pixels_per_unit + LABEL_GAP_PIXELS;
+            self.glyph_brush.queue(Section {
+                screen_position: (screen.x + offset, screen.y - offset),
+                bounds: (width, height),
+                text: vec![Text::new(name)
+                    .with_color([0.8, 0.8, 0.8, 1.0])
+                    .with_scale(18.0)],
+                ..Section::default()
+            });
+        }
+    }
+
+    /// Queues the names of the Lagrange points next to their crosshairs
+    fn queue_marker_labels(&mut self) {
+        let lagrange = match self.lagrange_points() {
+            Some(lagrange) => lagrange,
+            None => return,
+        };
+        let offset = to_f32(MARKER_SIZE_PIXELS) / 2.0 + LABEL_GAP_PIXELS;
+        for (i, &point) in lagrange.points.iter().enumerate() {
+            let screen = self.camera.world_to_screen(point, self.size);
+            self.glyph_brush.queue(Section {
+                screen_position: (screen.x + offset, screen.y - offset),
+                bounds: (self.size.width as f32, self.size.height as f32),
+                text: vec![Text::new(&format!("L{}", i + 1))
+                    .with_color([0.9, 0.4, 1.0, 1.0])
+                    .with_scale(16.0)],
+                ..Section::default()
+            });
+        }
+    }
+
+    /// Properties of the selected particle for the HUD
+    fn inspector_text(&self) -> String {
+        if self.scene3d.is_some() || self.playback.is_some() {
+            return String::new();
+        }
+        match self
+            .selected
+            .and_then(|id| self.snapshot.particles.get(id))
+        {
+            Some(p) => format!(
+                "\n\nparticle {}{}{}\nmass: {:.2}\nradius: {:.2}\nposition: ({:.1}, {:.1})\nspeed: {:.3}\nacceleration: {:.3}\nage: {:.1}  group: {}",
+                p.id,
+                match p.kind {
+                    BodyKind::Normal => "",
+                    BodyKind::BlackHole => " (black hole)",
+                    BodyKind::Tracer => " (tracer)",
+                },
+                if p.fixed { " (fixed)" } else { "" },
+                p.mass,
+                p.radius,
+                p.position.x,
+                p.position.y,
+                p.velocity.magnitude(),
+                p.acceleration.magnitude(),
+                p.age,
+                p.group
+            ),
+            None => String::new(),
+        }
+    }
+
+    /// Remembers the window, the colors, the time step, theta and the scenario for the
+    /// next session, unless `--no-config` was given. The key bindings are kept as they
+    /// are in the file, which is left alone if it cannot be read.
+    pub fn save_config(&self) {
+        if self.no_config {
+            return;
+        }
+        let saved = match Config::load() {
+            Ok(saved) => saved,
+            Err(e) => {
+                let path = Config::path().unwrap_or_default();
+                eprintln!("{}: {}, not saving over it", path.display(), e);
+                return;
+            }
+        };
+        let config = {
+            let stepper = self.physics.lock();
+            Config {
+                window_size: Some((self.size.width, self.size.height)),
+                present_mode: Some(self.sc_desc.present_mode.into()),
+                color_mapping: Some(stepper.color_mapping),
+                colormap: Some(stepper.colormap),
+                time_step: Some(stepper.sim.get_time_step()),
+                theta: Some(stepper.sim.get_theta()),
+                scenario: self.scenario_path.clone(),
+                ..saved
+            }
+        };
+        if let Err(e) = config.save() {
+            let path = Config::path().unwrap_or_default();
+            eprintln!("{}: {}", path.display(), e);
+        }
+    }
+
+    /// Whether the app should close. Checked by the event loop after every batch of events.
+    pub fn exit_requested(&self) -> bool {
+        self.exit_requested
+    }
+
+    /// Modifier keys currently held down
+    pub fn modifiers(&self) -> ModifiersState {
+        self.modifiers
+    }
+
     /// Returns true if an event was captured otherwise this will return false
     pub fn input(&mut self, window_event: &WindowEvent) -> bool {
         match window_event {
             // Keep track of cursor position on cursor movement in state
             WindowEvent::CursorMoved { position, .. } => {
+                if self.panning {
+                    let delta = cgmath::vec2(
+                        position.x - self.cursor_pos.x,
+                        position.y - self.cursor_pos.y,
+                    );
+                    // Middle-drag orbits around the scene in 3D mode
+                    if let Some(scene) = &mut self.scene3d {
+                        scene.camera.orbit(delta);
+                        scene.update_camera_buffer(&self.queue);
+                    } else {
+                        self.camera.pan(delta, self.size);
+                        self.update_camera_buffer();
+                    }
+                }
                 self.cursor_pos = *position;
+                self.update_arrow_buffer();
+                if self.attracting {
+                    self.update_attractor();
+                }
+            }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = *modifiers;
+                if self.attracting {
+                    self.update_attractor();
+                }
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let scroll = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    // Roughly one line per 20 pixels
+                    MouseScrollDelta::PixelDelta(pos) => pos.y as f32 / 20.0,
+                };
+                if self.attracting {
+                    // Scrolling while attracting changes the strength instead of zooming
+                    let factor = (1.1 as Scalar).powf(from_f32(scroll));
+                    self.attractor_mass = (self.attractor_mass * factor)
+                        .clamp(MIN_ATTRACTOR_MASS, MAX_ATTRACTOR_MASS);
+                    self.update_attractor();
+                } else if self.scene3d.is_none() && self.modifiers.ctrl() {
+                    // Ctrl + scroll grows or shrinks the next particle at the same density
+                    let factor = SPAWN_SCALE_STEP.powf(from_f32(scroll));
+                    self.scale_spawn(factor, factor);
+                } else if self.scene3d.is_none() && self.modifiers.alt() {
+                    // Alt + scroll only makes it heavier or lighter
+                    self.scale_spawn(1.0, SPAWN_SCALE_STEP.powf(from_f32(scroll)));
+                } else if let Some(scene) = &mut self.scene3d {
+                    scene.camera.zoom(1.1_f32.powf(scroll));
+                    scene.update_camera_buffer(&self.queue);
+                } else {
+                    self.camera
+                        .zoom_at(1.1_f32.powf(scroll), self.cursor_pos, self.size);
+                    self.update_camera_buffer();
+                }
             }
             WindowEvent::KeyboardInput {
                 device_id: _,
                 input,
                 ..
             } => {
-                let keycode = input.virtual_keycode;
-                if input.state == winit::event::ElementState::Pressed && keycode.is_some() {
-                    let kc = keycode.unwrap();
-                    let step_offset = 0.05;
-
-                    match kc {
-                        winit::event::VirtualKeyCode::Space => {
-                            self.paused = !self.paused;
+                if let (winit::event::ElementState::Pressed, Some(kc)) =
+                    (input.state, input.virtual_keycode)
+                {
+                    let actions = self.keymap.actions(kc, self.modifiers).to_vec();
+                    for action in actions {
+                        if self.perform(action) {
+                            break;
+                        }
+                    }
+                }
+            }
+            WindowEvent::MouseInput { button, state, .. } => {
+                if let winit::event::MouseButton::Middle = button {
+                    self.panning = *state == winit::event::ElementState::Pressed;
+                }
+                // Right-click deletes the particle under the cursor, unless the particles
+                // shown come from a replay. Held down anywhere else it attracts.
+                if let (
+                    winit::event::MouseButton::Right,
+                    winit::event::ElementState::Pressed,
+                    None,
+                    None,
+                ) = (button, state, &self.scene3d, &self.playback)
+                {
+                    let world_pos = self.camera.screen_to_world(self.cursor_pos, self.size);
+                    let removed = {
+                        let sim = &mut self.physics.lock().sim;
+                        sim.find_particle_at(world_pos)
+                            .and_then(|id| sim.remove_particle(id))
+                    };
+                    if let Some(removed) = removed {
+                        self.history.record(Edit::Removed(vec![removed]));
+                        self.update_instance_buffer();
+                    } else {
+                        self.attracting = true;
+                        self.update_attractor();
+                    }
+                }
+                if let (winit::event::MouseButton::Right, winit::event::ElementState::Released) =
+                    (button, state)
+                {
+                    if self.attracting {
+                        self.attracting = false;
+                        self.update_attractor();
+                    }
+                }
+                // Spawning particles is only supported in 2D
+                if let (winit::event::MouseButton::Left, None) = (button, &self.scene3d) {
+                    let world_pos = self.camera.screen_to_world(self.cursor_pos, self.size);
+                    match state {
+                        // Alt + drag pushes the group selection
+                        winit::event::ElementState::Pressed
+                            if self.modifiers.alt() && !self.group_selection.is_empty() =>
+                        {
+                            self.impulse_start = Some(world_pos);
                         }
-                        winit::event::VirtualKeyCode::Up => {
-                            self.sim.change_time_step(step_offset);
+                        // Shift + drag selects a group, Shift + click spawns a whole cluster
+                        // at once
+                        winit::event::ElementState::Pressed if self.modifiers.shift() => {
+                            self.rectangle_start = Some(world_pos);
+                        }
+                        // Ctrl + click scatters tracers, each on a circular orbit around
+                        // the center of mass
+                        winit::event::ElementState::Pressed if self.modifiers.ctrl() => {
+                            let mut tracers = presets::tracers(
+                                &mut self.rng,
+                                world_pos,
+                                self.burst_size,
+                                self.burst_radius,
+                            );
+                            let mut added = Vec::new();
+                            self.change_simulation(|s| {
+                                let sim = &mut s.sim;
+                                for tracer in &mut tracers {
+                                    if let Some(v) = sim.circular_orbit_velocity(tracer.position) {
+                                        tracer.velocity = v;
+                                    }
+                                }
+                                added = sim.add_particles(tracers);
+                            });
+                            self.history.record(Edit::Added(added));
                         }
-                        winit::event::VirtualKeyCode::Down => {
-                            self.sim.change_time_step(-step_offset);
+                        // Pressing on a particle selects it. Anywhere else pressing sets the
+                        // position of a new particle, dragging its velocity.
+                        winit::event::ElementState::Pressed => {
+                            self.group_selection.clear();
+                            let tolerance =
+                                SELECT_TOLERANCE_PIXELS * from_f32(self.camera.pixel_size());
+                            self.selected = self
+                                .physics
+                                .lock()
+                                .sim
+                                .find_particle_near(world_pos, tolerance);
+                            if self.selected.is_none() {
+                                self.drag_start = Some(world_pos);
+                                self.update_arrow_buffer();
+                            }
                         }
-                        winit::event::VirtualKeyCode::R => {
-                            self.sim.reset();
-                            self.recreate_instance_buffer();
+                        winit::event::ElementState::Released => {
+                            if let Some(start) = self.impulse_start.take() {
+                                let impulse = (world_pos - start) * DRAG_VELOCITY_SCALE;
+                                self.edit_group_selection(|p| p.velocity += impulse);
+                                self.history.record(Edit::Pushed {
+                                    ids: self.group_selection.clone(),
+                                    impulse,
+                                });
+                            }
+                            if let Some(start) = self.rectangle_start.take() {
+                                let pixels = (world_pos - start).magnitude()
+                                    / from_f32(self.camera.pixel_size());
+                                if pixels < SELECT_TOLERANCE_PIXELS {
+                                    self.spawn_cluster(start);
+                                } else {
+                                    self.group_selection =
+                                        self.physics.lock().sim.find_particles_in(start, world_pos);
+                                }
+                            }
+                            if let Some(start) = self.drag_start.take() {
+                                self.place_particle(start, world_pos);
+                            }
                         }
-                        _ => (),
                     }
                 }
             }
-            WindowEvent::MouseInput { button, state, .. } => {
-                if let winit::event::MouseButton::Left = button {
-                    if let winit::event::ElementState::Released = state {
-                        let cx = self.cursor_pos.x;
-                        let cy = self.cursor_pos.y;
-                        let ndc =
-                            utils::normalize_window_coordinates(&utils::ViewportTransformOptions {
-                                window_pos: cgmath::Vector2::new(cx, cy),
-                                xw: utils::MinMax::<f64> {
-                                    min: 0.0,
-                                    max: self.size.width as f64,
-                                },
-                                // Min and max needs to be swapped here as the axes in window space begins at
-                                // the top left corner and not the bottom left corner.
-                                // Since the direction of the y axis is reversed as opposed to the convention, min
-                                // and max needs to be swapped
-                                yw: utils::MinMax::<f64> {
-                                    min: self.size.height as f64,
-                                    max: 0.0,
-                                },
-                                xv: utils::MinMax::<f64> {
-                                    min: constants::MIN_X as f64,
-                                    max: constants::MAX_X as f64,
-                                },
-                                yv: utils::MinMax::<f64> {
-                                    min: constants::MIN_Y as f64,
-                                    max: constants::MAX_Y as f64,
-                                },
-                            });
+            _ => return false,
+        }
+        true
+    }
 
-                        let mut rng = rand::thread_rng();
-                        let radius = rng.gen_range(1..4) as f32;
-                        self.sim.add_particle(Particle::new(ParticleProperties {
-                            position: cgmath::vec2(ndc.x, ndc.y),
-                            radius,
-                            mass: 50.0 * radius,
-                            velocity: cgmath::vec2(0.0, 0.0),
-                            acceleration: cgmath::vec2(0.0, 0.0),
-                        }));
-
-                        self.recreate_instance_buffer();
+    /// Does what `action` stands for. Returns false if it does not apply right now, e.g.
+    /// steering the crosshair while it is off, so that the next action bound to the same
+    /// key gets its turn.
+    fn perform(&mut self, action: Action) -> bool {
+        let step_offset = 0.05;
+        let placing = self.scene3d.is_none() && self.playback.is_none();
+        match action {
+            Action::Quit if self.show_help => {
+                self.show_help = false;
+            }
+            Action::Quit => {
+                self.exit_requested = true;
+            }
+            Action::ToggleHelp => {
+                self.show_help = !self.show_help;
+            }
+            Action::TogglePause => {
+                self.paused = !self.paused;
+                self.update_pace();
+            }
+            Action::StepOnce => {
+                self.paused = true;
+                self.update_pace();
+                if self.scene3d.is_some() || self.playback.is_some() {
+                    self.queued_steps += 1;
+                } else {
+                    self.physics.lock().queued_steps += 1;
+                }
+            }
+            Action::Reset => {
+                if let Some(scene) = &mut self.scene3d {
+                    scene.reset(&self.device, &self.queue);
+                } else {
+                    self.change_simulation(|s| s.sim.reset());
+                    self.history.clear();
+                }
+            }
+            Action::CrosshairUp
+            | Action::CrosshairDown
+            | Action::CrosshairLeft
+            | Action::CrosshairRight => {
+                if self.crosshair.is_none() || !placing {
+                    return false;
+                }
+                self.steer_crosshair(action);
+            }
+            Action::PlaceAtCrosshair => {
+                if !placing {
+                    return false;
+                }
+                if let Some(crosshair) = self.crosshair {
+                    self.place_particle(crosshair, self.crosshair_drag_end(crosshair));
+                }
+            }
+            Action::ToggleCrosshair => {
+                // The crosshair starts out in the middle of the window
+                let middle = PhysicalPosition::new(
+                    self.size.width as f64 / 2.0,
+                    self.size.height as f64 / 2.0,
+                );
+                self.crosshair = match self.crosshair {
+                    Some(_) => None,
+                    None => Some(self.camera.screen_to_world(middle, self.size)),
+                };
+            }
+            Action::IncreaseTimeStep => {
+                self.change_simulation(|s| s.sim.change_time_step(step_offset));
+            }
+            Action::DecreaseTimeStep => {
+                self.change_simulation(|s| s.sim.change_time_step(-step_offset));
+            }
+            Action::SpeedUp => self.change_steps_per_frame(true),
+            Action::SlowDown => self.change_steps_per_frame(false),
+            Action::IncreaseTheta => {
+                self.change_simulation(|s| s.sim.set_theta(s.sim.get_theta() + THETA_STEP));
+            }
+            Action::DecreaseTheta => {
+                self.change_simulation(|s| s.sim.set_theta(s.sim.get_theta() - THETA_STEP));
+            }
+            Action::CycleIntegrator => {
+                self.change_simulation(|s| s.sim.set_integrator(s.sim.get_integrator().next()));
+            }
+            Action::CycleSolver => {
+                self.change_simulation(|s| s.sim.set_solver(s.sim.get_solver().next()));
+            }
+            // Reading the accelerations back blocks until the GPU is done, which browsers
+            // do not allow
+            Action::CycleGpuSolver => {
+                #[cfg(not(target_arch = "wasm32"))]
+                self.change_simulation(|s| {
+                    s.gpu_solver = match s.gpu_solver {
+                        None => Some(ForceSolver::Direct),
+                        Some(ForceSolver::Direct) => Some(ForceSolver::BarnesHut),
+                        Some(ForceSolver::BarnesHut) => None,
                     }
+                });
+            }
+            Action::CycleExpansion => {
+                self.change_simulation(|s| s.sim.set_expansion(s.sim.get_expansion().next()));
+            }
+            Action::CycleInteraction => {
+                self.change_simulation(|s| s.sim.set_interaction(s.sim.get_interaction().next()));
+            }
+            Action::CycleBoundary => {
+                self.change_simulation(|s| s.sim.set_boundary(s.sim.get_boundary().next()));
+            }
+            Action::ToggleAdaptiveStep => {
+                let adaptive_step = self.adaptive_step;
+                self.change_simulation(|s| {
+                    let adaptive = match s.sim.get_adaptive_step() {
+                        Some(_) => None,
+                        None => Some(adaptive_step),
+                    };
+                    s.sim.set_adaptive_step(adaptive);
+                });
+            }
+            Action::ToggleBlockSteps => {
+                let block_steps = self.block_steps;
+                self.change_simulation(|s| {
+                    let blocks = match s.sim.get_block_steps() {
+                        Some(_) => None,
+                        None => Some(block_steps),
+                    };
+                    s.sim.set_block_steps(blocks);
+                });
+            }
+            Action::CycleDrag => {
+                let settings = self.drag;
+                self.change_simulation(|s| {
+                    let drag = match s.sim.get_drag() {
+                        None => Some(Drag {
+                            frame: DragFrame::Rest,
+                            ..settings
+                        }),
+                        Some(drag) if drag.frame == DragFrame::Rest => Some(Drag {
+                            frame: drag.frame.next(),
+                            ..drag
+                        }),
+                        Some(_) => None,
+                    };
+                    s.sim.set_drag(drag);
+                });
+            }
+            Action::ToggleFragmentation => {
+                let fragmentation = self.fragmentation;
+                self.change_simulation(|s| {
+                    let fragmentation = match s.sim.get_fragmentation() {
+                        Some(_) => None,
+                        None => Some(fragmentation),
+                    };
+                    s.sim.set_fragmentation(fragmentation);
+                });
+            }
+            Action::ToggleSolverComparison => {
+                self.change_simulation(Stepper::toggle_compare_solvers);
+            }
+            Action::LoadPreset(preset) => self.load_preset(preset),
+            Action::ReloadScenario => self.load_scenario(),
+            Action::SaveSnapshot => {
+                if let Err(e) = self.physics.lock().sim.save(SNAPSHOT_PATH) {
+                    eprintln!("{}: {}", SNAPSHOT_PATH, e);
                 }
             }
-            _ => return false,
+            Action::LoadSnapshot => match Simulation::load(SNAPSHOT_PATH) {
+                Ok(sim) => {
+                    self.change_simulation(|s| s.sim = sim);
+                    // Handles of the old simulation mean nothing in the new one
+                    self.selected = None;
+                    self.history.clear();
+                }
+                Err(e) => eprintln!("{}: {}", SNAPSHOT_PATH, e),
+            },
+            Action::ToggleOrbitPlacement => {
+                self.orbit_placement = !self.orbit_placement;
+            }
+            Action::ToggleMirrorPlacement => {
+                self.mirror_placement = !self.mirror_placement;
+            }
+            Action::GrowSpawn => self.scale_spawn(SPAWN_SCALE_STEP, SPAWN_SCALE_STEP),
+            Action::ShrinkSpawn => {
+                self.scale_spawn(1.0 / SPAWN_SCALE_STEP, 1.0 / SPAWN_SCALE_STEP);
+            }
+            Action::Undo => self.undo(),
+            Action::Redo => self.redo(),
+            Action::DeleteSelection => {
+                let group = std::mem::take(&mut self.group_selection);
+                let mut removed = Vec::new();
+                self.change_simulation(|stepper| {
+                    removed = group
+                        .into_iter()
+                        .filter_map(|id| stepper.sim.remove_particle(id))
+                        .collect();
+                });
+                self.history.record(Edit::Removed(removed));
+            }
+            Action::NextGroup => {
+                self.edit_group_selection(|p| p.group = p.group.wrapping_add(1));
+            }
+            Action::RecolorSelection => {
+                let current = self
+                    .group_selection
+                    .first()
+                    .and_then(|&id| self.snapshot.particles.get(id))
+                    .map(|p| p.color);
+                let next = GROUP_COLORS
+                    .iter()
+                    .position(|&c| Some(c) == current)
+                    .map_or(0, |i| (i + 1) % GROUP_COLORS.len());
+                self.edit_group_selection(|p| p.color = GROUP_COLORS[next]);
+            }
+            Action::SaveSelection => self.save_group_selection(),
+            Action::ToggleFollow => self.toggle_following(false),
+            Action::ToggleFollowHeadingUp => self.toggle_following(true),
+            Action::CycleColorMapping => {
+                self.change_simulation(|s| s.color_mapping = s.color_mapping.next());
+            }
+            Action::CycleColormap => {
+                self.change_simulation(|s| s.colormap = s.colormap.next());
+            }
+            Action::CycleVectorOverlay => {
+                self.vector_overlay = self.vector_overlay.next();
+            }
+            Action::ToggleLabels => {
+                self.show_labels = !self.show_labels;
+            }
+            Action::ToggleLagrangePoints => {
+                self.show_lagrange_points = !self.show_lagrange_points;
+            }
+            Action::ToggleEnergyPlot => {
+                self.change_simulation(Stepper::toggle_energy_plot);
+            }
+            Action::ToggleStarfield => {
+                self.show_starfield = !self.show_starfield;
+            }
+            Action::ToggleBloom => {
+                let intensity = if self.bloom.is_enabled() {
+                    0.0
+                } else {
+                    bloom::INTENSITY
+                };
+                self.bloom.set_intensity(&self.queue, intensity);
+            }
+            Action::ToggleTimings => {
+                self.show_timings = !self.show_timings;
+            }
+            Action::CyclePresentMode => self.cycle_present_mode(),
+            Action::ToggleReplayRecording => {
+                self.change_simulation(Stepper::toggle_replay_recording);
+            }
+            Action::ToggleExport => {
+                self.change_simulation(Stepper::toggle_export);
+            }
+            Action::TogglePlayback => self.toggle_playback(),
+            Action::ReplayBack | Action::ReplayForward => {
+                let offset = if action == Action::ReplayBack { -1 } else { 1 };
+                match &mut self.playback {
+                    Some(playback) => playback.seek(offset),
+                    None => return false,
+                }
+                self.update_instance_buffer();
+            }
+            Action::ToggleCapture => self.toggle_capture(),
+            Action::SaveClip => self.save_clip(),
+            Action::StopClip => {
+                self.clip = None;
+            }
         }
         true
     }
 
     pub fn update(&mut self) {
-        if !self.paused && !self.sim.get_particles().is_empty() {
-            // As long as the simulation isn't paused and we have particles in
-            // the system, check for collision, step the simulation, integrate the forces
-            // for each body and finally recreate the instance buffer.
-            self.sim.resolve_collisions();
-            self.sim.step();
-            self.sim.integrate();
-            self.recreate_instance_buffer();
-        }
-    }
-
-    /// Destroys the existing instance buffer and recreates it with
-    /// current instances. This function must be called each time the
-    /// data within instances change.
-    fn recreate_instance_buffer(&mut self) {
-        let instances = self.sim.get_instances();
-        self.instance_buffer.destroy();
-        self.instance_buffer = self
-            .device
-            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Instance Buffer"),
-                contents: bytemuck::cast_slice(instances.as_slice()),
-                usage: wgpu::BufferUsage::VERTEX,
-            });
-        self.num_instances = instances.len() as u32;
+        let _span = tracing::info_span!("update").entered();
+        if self.scene3d.is_none() && self.playback.is_none() {
+            self.update_snapshot();
+            return;
+        }
+        self.timings.physics = Duration::default();
+        self.timings.tree_build = Duration::default();
+        self.timings.tree = None;
+        let steps = if self.paused {
+            std::mem::take(&mut self.queued_steps)
+        } else {
+            self.take_steps()
+        };
+        if steps == 0 {
+            return;
+        }
+        if let Some(scene) = &mut self.scene3d {
+            let start = Instant::now();
+            scene.update(&self.device, &self.queue, steps);
+            self.timings.physics = start.elapsed();
+            return;
+        }
+        if let Some(playback) = &mut self.playback {
+            playback.seek(steps as isize);
+            self.update_instance_buffer();
+        }
     }
 
-    pub fn render(&mut self) -> Result<(), wgpu::SwapChainError> {
-        let frame;
-        match self.swap_chain.get_current_frame() {
-            Ok(sc_frame) => { frame = sc_frame.output },
-            Err(sc_err) => return Err(sc_err),
+    /// Spawns a cluster of `burst_size` particles around `center`
+    fn spawn_cluster(&mut self, center: cgmath::Vector2<Scalar>) {
+        let g = self.physics.lock().sim.get_g();
+        let cluster = presets::cluster(
+            &mut self.rng,
+            center,
+            self.burst_size,
+            self.burst_radius,
+            self.burst_spin,
+            g,
+        );
+        let mut added = Vec::new();
+        self.change_simulation(|s| added = s.sim.add_particles(cluster));
+        self.history.record(Edit::Added(added));
+    }
+
+    /// Takes back the last edit made by hand
+    fn undo(&mut self) {
+        if self.history.undo(&mut self.physics.lock().sim) {
+            self.update_instance_buffer();
+        }
+    }
+
+    /// Makes the last edit taken back by `undo` again
+    fn redo(&mut self) {
+        if self.history.redo(&mut self.physics.lock().sim) {
+            self.update_instance_buffer();
+        }
+    }
+
+    /// Changes every particle of the group selection with `edit`
+    fn edit_group_selection<F: FnMut(&mut Particle)>(&mut self, mut edit: F) {
+        if self.group_selection.is_empty() {
+            return;
         }
+        let group = self.group_selection.clone();
+        self.change_simulation(|stepper| {
+            for id in group {
+                stepper.sim.edit_particle(id, &mut edit);
+            }
+        });
+    }
+
+    /// Writes the particles of the group selection to `SELECTION_PATH` as a scenario
+    fn save_group_selection(&self) {
+        let particles: Vec<Particle> = self
+            .group_selection
+            .iter()
+            .filter_map(|&id| self.snapshot.particles.get(id))
+            .collect();
+        if particles.is_empty() {
+            return;
+        }
+        if let Err(e) = Scenario::from_particles(particles).save(SELECTION_PATH) {
+            eprintln!("{}: {}", SELECTION_PATH, e);
+        }
+    }
+
+    /// Puts the attractor where the cursor is, or removes it once no longer attracting
+    fn update_attractor(&mut self) {
+        let attractor = if self.attracting {
+            let center = self.camera.screen_to_world(self.cursor_pos, self.size);
+            // A negative mass pushes
+            let sign = if self.modifiers.shift() { -1.0 } else { 1.0 };
+            Some(ExternalPotential::Plummer {
+                center: (center.x, center.y),
+                mass: sign * self.attractor_mass,
+                scale_radius: ATTRACTOR_RADIUS,
+            })
+        } else {
+            None
+        };
+        self.physics.lock().attractor = attractor;
+    }
+
+    /// Picks up the snapshot the physics thread published last, if it is a new one
+    fn update_snapshot(&mut self) {
+        #[cfg(target_arch = "wasm32")]
+        self.physics.update();
+        let snapshot = self.physics.latest();
+        if Arc::ptr_eq(&snapshot, &self.snapshot) {
+            return;
+        }
+        self.snapshot = snapshot;
+        // Forget the particles of the group selection that merged or left
+        let particles = &self.snapshot.particles;
+        self.group_selection.retain(|&id| particles.contains(id));
+        self.timings.physics = self.snapshot.status.physics_time;
+        self.timings.tree_build = self.snapshot.status.tree_build_time;
+        self.timings.tree = self.snapshot.tree.clone();
+        self.upload_instances();
+        self.follow();
+    }
+
+    /// Starts or stops following with the camera, turning it along with the selected
+    /// particle if `heading_up`. Starting centers the camera on the target right away,
+    /// and switching between the two modes keeps following. Only the live 2D simulation
+    /// can be followed.
+    fn toggle_following(&mut self, heading_up: bool) {
+        if self.scene3d.is_some() || self.playback.is_some() {
+            return;
+        }
+        let stop = self.following.is_some() && self.heading_up == heading_up;
+        self.heading_up = heading_up && !stop;
+        self.camera.rotation = 0.0;
+        self.following = if stop { None } else { self.follow_target() };
+        if let Some(target) = self.following {
+            self.camera.center = target.map(to_f32);
+        }
+        self.follow();
+        self.update_camera_buffer();
+    }
+
+    /// What the camera follows, the selected particle or else the center of mass
+    fn follow_target(&self) -> Option<cgmath::Vector2<Scalar>> {
+        let particles = &self.snapshot.particles;
+        match self.selected.and_then(|id| particles.get(id)) {
+            Some(p) => Some(p.position),
+            None => particles.center_of_mass(),
+        }
+    }
+
+    /// Camera rotation that makes the velocity of the selected particle point up, if
+    /// turning with it and it is moving
+    fn heading_rotation(&self) -> Option<f32> {
+        let p = self
+            .selected
+            .filter(|_| self.heading_up)
+            .and_then(|id| self.snapshot.particles.get(id))?;
+        let heading = p.velocity.y.atan2(p.velocity.x) - FRAC_PI_2;
+        Some(to_f32(heading)).filter(|_| p.velocity.magnitude2() > 0.0)
+    }
+
+    /// Moves the camera by as much as its target moved since the last snapshot, so that
+    /// panning and zooming still work while following. When turning with the particle,
+    /// the offset to it turns as well so that it stays put on screen. Selecting another
+    /// particle carries the camera over to it.
+    fn follow(&mut self) {
+        if let (Some(last), Some(target)) = (self.following, self.follow_target()) {
+            let rotation = self.heading_rotation().unwrap_or(self.camera.rotation);
+            let offset = self.camera.center.map(from_f32) - last;
+            let offset = camera::rotate(offset, from_f32(rotation - self.camera.rotation));
+            self.camera.center = (target + offset).map(to_f32);
+            self.camera.rotation = rotation;
+            self.following = Some(target);
+            self.update_camera_buffer();
+        }
+    }
+
+    /// Number of steps the 3D mode or replay takes this frame. Fractions of a step are
+    /// carried over, so below one step per frame they only advance every few frames.
+    fn take_steps(&mut self) -> usize {
+        self.step_budget += self.steps_per_frame;
+        let steps = self.step_budget.floor();
+        self.step_budget -= steps;
+        steps as usize
+    }
+
+    /// Doubles or halves the number of steps per frame
+    fn change_steps_per_frame(&mut self, faster: bool) {
+        let steps = if faster {
+            self.steps_per_frame * 2.0
+        } else {
+            self.steps_per_frame / 2.0
+        };
+        self.steps_per_frame = steps.clamp(MIN_STEPS_PER_FRAME, MAX_STEPS_PER_FRAME);
+        self.step_budget = 0.0;
+        self.update_pace();
+    }
+
+    /// Uploads the current instances into the instance buffer. This function must be
+    /// called each time the data within instances change. Changes to the simulation are
+    /// published by the physics thread right away for this.
+    fn update_instance_buffer(&mut self) {
+        let _span = tracing::info_span!("instance buffer").entered();
+        if self.playback.is_none() {
+            self.physics.publish();
+            self.snapshot = self.physics.latest();
+        }
+        self.upload_instances();
+    }
+
+    /// Uploads the instances of the replay frame shown, or those of `snapshot`
+    fn upload_instances(&mut self) {
+        let instances = match &self.playback {
+            Some(playback) => {
+                self.instances = playback
+                    .replay
+                    .frame(playback.frame)
+                    .map(|frame| frame.get_instances())
+                    .unwrap_or_default();
+                self.color_range = None;
+                &self.instances
+            }
+            None => {
+                self.color_range = self.snapshot.color_range;
+                &self.snapshot.instances
+            }
+        };
+        self.instance_buffer
+            .write(&self.device, &self.queue, instances);
+    }
+
+    /// Clears the scene target of the bloom pass and draws the particles (and the drag
+    /// preview) into it
+    fn draw_scene(&self, encoder: &mut wgpu::CommandEncoder) {
+        let (view, resolve_target) = self.bloom.scene_attachment();
+        if let Some(scene) = &self.scene3d {
+            scene.draw(encoder, view, resolve_target, self.clear_color);
+            return;
+        }
+
+        let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: None,
+            color_attachments: &[RenderPassColorAttachmentDescriptor {
+                attachment: view,
+                // Multisampled targets are resolved into the single sampled scene texture
+                resolve_target,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(self.clear_color),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+
+        if self.show_starfield {
+            self.starfield.draw(&mut rpass);
+        }
+        rpass.set_pipeline(&self.render_pipeline);
+        rpass.set_bind_group(0, &self.camera_bind_group, &[]);
+        rpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        rpass.set_vertex_buffer(1, self.instance_buffer.buffer().slice(..));
+        rpass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        rpass.draw_indexed(0..self.num_indices, 0, 0..self.instance_buffer.len());
+
+        if self.drag_start.is_some() {
+            rpass.set_pipeline(&self.line_pipeline);
+            rpass.set_vertex_buffer(0, self.arrow_buffer.slice(..));
+            rpass.draw(0..self.num_arrow_vertices, 0..1);
+        }
+        if self.trajectory_buffer.len() > 0 {
+            rpass.set_pipeline(&self.line_pipeline);
+            rpass.set_vertex_buffer(0, self.trajectory_buffer.buffer().slice(..));
+            rpass.draw(0..self.trajectory_buffer.len(), 0..1);
+        }
+        if self.vector_buffer.len() > 0 {
+            rpass.set_pipeline(&self.line_pipeline);
+            rpass.set_vertex_buffer(0, self.vector_buffer.buffer().slice(..));
+            rpass.draw(0..self.vector_buffer.len(), 0..1);
+        }
+        if self.marker_buffer.len() > 0 {
+            rpass.set_pipeline(&self.line_pipeline);
+            rpass.set_vertex_buffer(0, self.marker_buffer.buffer().slice(..));
+            rpass.draw(0..self.marker_buffer.len(), 0..1);
+        }
+        if self.legend_buffer.len() > 0 {
+            rpass.set_pipeline(&self.line_pipeline);
+            rpass.set_vertex_buffer(0, self.legend_buffer.buffer().slice(..));
+            rpass.draw(0..self.legend_buffer.len(), 0..1);
+        }
+        if self.energy_buffer.len() > 0 {
+            rpass.set_pipeline(&self.line_pipeline);
+            rpass.set_vertex_buffer(0, self.energy_buffer.buffer().slice(..));
+            rpass.draw(0..self.energy_buffer.len(), 0..1);
+        }
+        if self.group_buffer.len() > 0 {
+            rpass.set_pipeline(&self.line_pipeline);
+            rpass.set_vertex_buffer(0, self.group_buffer.buffer().slice(..));
+            rpass.draw(0..self.group_buffer.len(), 0..1);
+        }
+        if self.num_selection_vertices > 0 {
+            rpass.set_pipeline(&self.line_pipeline);
+            rpass.set_vertex_buffer(0, self.selection_buffer.slice(..));
+            rpass.draw(0..self.num_selection_vertices, 0..1);
+        }
+    }
+
+    /// Ends the GPU time of the pass encoded last under `pass`, with `--gpu-timing`
+    fn mark_gpu_time(&mut self, encoder: &mut wgpu::CommandEncoder, pass: &'static str) {
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.mark(encoder, pass);
+        }
+    }
+
+    pub fn render(&mut self) -> Result<(), wgpu::SwapChainError> {
+        let _span = tracing::info_span!("render").entered();
+        let start = Instant::now();
+        self.timings.add_frame(start - self.last_frame);
+        self.last_frame = start;
+        let frame = self.swap_chain.get_current_frame()?.output;
+        let buffers = tracing::info_span!("buffer updates").entered();
+        // The selected particle moves every step and the ring keeps its gap in pixels
+        self.update_selection_buffer();
+        self.update_group_buffer();
+        // The other particles move as well, so the prediction has to be redone
+        self.update_trajectory_buffer();
+        self.update_vector_buffer();
+        self.update_marker_buffer();
+        self.update_legend_buffer();
+        self.update_energy_buffer();
+        buffers.exit();
 
         let mut encoder = self
             .device
@@ -362,38 +2555,219 @@ impl State {
                 label: Some("Render Encoder"),
             });
 
-        // render pass should be locally scoped so that the mutable borrow to encoder is dropped when we try to `encoder.finish()`
-        {
-            let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
-                label: None,
-                color_attachments: &[RenderPassColorAttachmentDescriptor {
-                    attachment: &frame.view,
-                    // No need to specify target view as the default is `attachment` unless multisampling is enabled
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(self.clear_color),
-                        store: true,
-                    },
-                }],
-                depth_stencil_attachment: None,
-            });
-
-            rpass.set_pipeline(&self.render_pipeline);
-            rpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            rpass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-            rpass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-            rpass.draw_indexed(0..self.num_indices, 0, 0..self.num_instances);
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.start(&mut encoder);
+        }
+        self.draw_scene(&mut encoder);
+        self.mark_gpu_time(&mut encoder, "scene");
+        self.bloom.blur(&mut encoder);
+        self.bloom.composite(&mut encoder, &frame.view);
+        self.mark_gpu_time(&mut encoder, "bloom");
+        // Recorded frames are composited a second time into the capture texture since the
+        // swap chain texture cannot be copied from. The HUD is left out on purpose.
+        if let Some(capture) = &self.capture {
+            self.bloom.composite(&mut encoder, capture.view());
+            capture.copy_frame(&mut encoder);
+        }
+        let keep_clip_frame = match &self.clip {
+            Some(clip) if clip.wants_frame(start) => {
+                self.bloom.composite(&mut encoder, clip.view());
+                clip.copy_frame(&mut encoder);
+                true
+            }
+            _ => false,
+        };
+        if self.capture.is_some() || keep_clip_frame {
+            self.mark_gpu_time(&mut encoder, "capture");
         }
 
-        let dt = self.sim.get_time_step();
-        self.glyph_brush.queue(Section {
-            screen_position: (30.0, 30.0),
-            bounds: (self.size.width as f32, self.size.height as f32),
-            text: vec![Text::new(format!("time_step: {:.2}", dt).as_str())
+        let status = match (&self.scene3d, &self.playback) {
+            (_, Some(playback)) => format!(
+                "replay: frame {}/{}\n{}",
+                playback.frame + 1,
+                playback.replay.len(),
+                playback.progress_bar()
+            ),
+            (Some(scene), None) => format!(
+                "3D mode: {} bodies\nelapsed: {:.1}",
+                scene.sim.get_particles().len(),
+                scene.sim.get_elapsed()
+            ),
+            (None, None) => {
+                let status = &self.snapshot.status;
+                format!(
+                    "time: {:.2}  particles: {}\ntime_step: {}  theta: {:.2}\nintegrator: {}\nforces: {} ({:.2} ms)\ninteraction: {:?}{}{}\nboundary: {:?}\ncolors: {}\nangular momentum: {:.4e}{}{}",
+                    status.elapsed,
+                    status.particles,
+                    if status.adaptive {
+                        format!("{:.4} (adaptive)", status.time_step)
+                    } else {
+                        format!("{:.2}", status.time_step)
+                    },
+                    status.theta,
+                    match status.block_level {
+                        Some(level) => format!("block time steps (down to 1/{})", 1 << level),
+                        None => format!("{:?}", status.integrator),
+                    },
+                    match (status.gpu_solver, status.solver) {
+                        (Some(ForceSolver::Direct), _) => "brute force (GPU)",
+                        (Some(ForceSolver::BarnesHut), _) => "Barnes-Hut, Morton tree (GPU)",
+                        (None, ForceSolver::BarnesHut) => match status.expansion {
+                            Expansion::Monopole => "Barnes-Hut (CPU)",
+                            Expansion::Quadrupole => "Barnes-Hut, quadrupole (CPU)",
+                        },
+                        (None, ForceSolver::Direct) => "direct (CPU)",
+                    },
+                    status.force_time.as_secs_f64() * 1000.0,
+                    status.interaction,
+                    match status.drag {
+                        Some(drag) => format!(
+                            "\ndrag: {:?} {} toward {}",
+                            drag.law,
+                            drag.coefficient,
+                            match drag.frame {
+                                DragFrame::Rest => "rest",
+                                DragFrame::LocalMean => "local mean",
+                            }
+                        ),
+                        None => String::new(),
+                    },
+                    match status.fragmentation {
+                        Some(fragmentation) => format!(
+                            "\nfragmentation: above speed {} into {}",
+                            fragmentation.min_speed, fragmentation.fragments
+                        ),
+                        None => String::new(),
+                    },
+                    status.boundary,
+                    match status.color_mapping {
+                        ColorMapping::Fixed => format!("{:?}", status.color_mapping),
+                        _ => format!("{:?} ({:?})", status.color_mapping, status.colormap),
+                    },
+                    status.angular_momentum,
+                    match status.virial_ratio {
+                        Some(ratio) => format!("  virial ratio: {:.3}", ratio),
+                        None => String::new(),
+                    },
+                    match status.acceleration_error {
+                        Some(error) => format!("\nRMS error: {:.3e}", error),
+                        None => String::new(),
+                    },
+                )
+            }
+        };
+        self.queue_labels();
+        self.queue_marker_labels();
+        self.queue_legend();
+        self.queue_energy_legend();
+        self.queue_help_hint();
+        self.queue_pause_indicator();
+        if self.show_help {
+            self.queue_help();
+        } else {
+            self.glyph_brush.queue(Section {
+                screen_position: (30.0, 30.0),
+                bounds: (self.size.width as f32, self.size.height as f32),
+                text: vec![Text::new(
+                    format!(
+                        "{}\nsteps/frame: {}{}{}{}{}{}{}{}{}{}{}{}{}{}",
+                        status,
+                        if self.steps_per_frame >= 1.0 {
+                            format!("{}", self.steps_per_frame)
+                        } else {
+                            format!("1/{}", (1.0 / self.steps_per_frame).round())
+                        },
+                        match (&self.scene3d, &self.playback) {
+                            (None, None) => format!(
+                                "\nspawn: radius {:.1}  mass {:.1}",
+                                self.spawn_radius, self.spawn_mass
+                            ),
+                            _ => String::new(),
+                        },
+                        match (self.orbit_placement, self.mirror_placement) {
+                            (false, false) => "",
+                            (true, false) => "\nplacement: circular orbit",
+                            (false, true) => "\nplacement: mirrored",
+                            (true, true) => "\nplacement: circular orbit, mirrored",
+                        },
+                        match self.crosshair {
+                            Some(crosshair) => format!(
+                                "\ncrosshair: ({:.1}, {:.1}) velocity ({:.2}, {:.2})",
+                                crosshair.x,
+                                crosshair.y,
+                                self.crosshair_velocity.x,
+                                self.crosshair_velocity.y
+                            ),
+                            None => String::new(),
+                        },
+                        match self.group_selection.len() {
+                            0 => String::new(),
+                            count => format!("\nselection: {} particles", count),
+                        },
+                        if self.attracting {
+                            format!(
+                                "\nattractor: {} with mass {:.0}",
+                                if self.modifiers.shift() {
+                                    "pushing"
+                                } else {
+                                    "pulling"
+                                },
+                                self.attractor_mass
+                            )
+                        } else {
+                            String::new()
+                        },
+                        match (self.following, self.selected) {
+                            (None, _) => "",
+                            (Some(_), Some(_)) if self.heading_up => {
+                                "\ncamera: following selection, heading up"
+                            }
+                            (Some(_), Some(_)) => "\ncamera: following selection",
+                            (Some(_), None) => "\ncamera: following center of mass",
+                        },
+                        match self.vector_overlay {
+                            VectorOverlay::Off => "",
+                            VectorOverlay::Velocity => "\narrows: velocity",
+                            VectorOverlay::Both => "\narrows: velocity, acceleration",
+                        },
+                        match &self.capture {
+                            Some(capture) => format!("\nrecording: {} frames", capture.frames()),
+                            None => String::new(),
+                        },
+                        match &self.clip {
+                            Some(clip) => format!("\nclip: last {:.1} s", clip.seconds()),
+                            None => String::new(),
+                        },
+                        match self.snapshot.status.replay_frames {
+                            Some(frames) => format!("\nrecording replay: {} steps", frames),
+                            None => String::new(),
+                        },
+                        match self.snapshot.status.exported_steps {
+                            Some(steps) => format!("\nexporting: {} steps", steps),
+                            None => String::new(),
+                        },
+                        if self.show_timings {
+                            format!(
+                                "{}\npresent: {:?}{}",
+                                self.timings.text(),
+                                self.sc_desc.present_mode,
+                                match self.next_frame_time() {
+                                    Some(_) => " (capped)",
+                                    None => "",
+                                }
+                            )
+                        } else {
+                            String::new()
+                        },
+                        self.inspector_text()
+                    )
+                    .as_str(),
+                )
                 .with_color([1.0, 1.0, 1.0, 1.0])
                 .with_scale(25.0)],
-            ..Section::default()
-        });
+                ..Section::default()
+            });
+        }
         self.glyph_brush
             .draw_queued(
                 &self.device,
@@ -404,11 +2778,30 @@ impl State {
                 self.size.height,
             )
             .expect("queue draw");
+        self.mark_gpu_time(&mut encoder, "text");
+        if let Some(timer) = &self.gpu_timer {
+            timer.resolve(&mut encoder);
+        }
 
         self.staging_belt.finish();
         let cb = encoder.finish();
         // An iterator that'll just yield once
         self.queue.submit(std::iter::once(cb));
+        self.timings.render = start.elapsed();
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.read(&self.device);
+            self.timings.gpu = timer.spans().to_vec();
+            // Puts the GPU time of every pass into the `--trace` file, inside this frame
+            for &(pass, time) in timer.spans() {
+                tracing::info!(target: "gpu", pass = %pass, micros = time.as_secs_f64() * 1e6);
+            }
+        }
+        if let Some(capture) = &mut self.capture {
+            capture.save_frame(&self.device);
+        }
+        if let (Some(clip), true) = (&mut self.clip, keep_clip_frame) {
+            clip.keep_frame(&self.device, start);
+        }
         // Recall unused buffers after finishing
         self.local_spawner
             .spawn(self.staging_belt.recall())
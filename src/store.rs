@@ -0,0 +1,498 @@
+use serde::{Deserialize, Serialize};
+use std::{fmt, iter::FromIterator, sync::Arc};
+
+use crate::primitives::{
+    color::Color,
+    particle::{BodyKind, Particle},
+    scalar::Scalar,
+};
+
+/// Stable handle to a particle in a `ParticleStore`. A handle keeps referring to the same
+/// particle while others are added and removed, and never refers to anything once its
+/// particle is gone, even if the slot is reused. The default handle refers to nothing.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
+pub struct ParticleId {
+    index: u32,
+    /// Generation of the slot the handle was created in. Live slots start at 1.
+    generation: u32,
+}
+
+impl ParticleId {
+    /// Index of the slot the particle lives in. Unique among the live particles of a
+    /// store, and small enough to index a table with.
+    pub(crate) fn slot(self) -> usize {
+        self.index as usize
+    }
+}
+
+impl fmt::Display for ParticleId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}v{}", self.index, self.generation)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+struct Slot {
+    generation: u32,
+    /// Position of the particle in the dense arrays, if the slot is occupied
+    dense: Option<usize>,
+}
+
+/// Particle storage with O(1) insertion, lookup and removal by `ParticleId`.
+///
+/// Every property lives in its own contiguous array (struct of arrays) so that the hot
+/// loops only pull the fields they need through the cache. All arrays are indexed by the
+/// same dense index, and removal swaps the last particle into the gap to keep them
+/// packed. Dense indices therefore change on removal while handles do not.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ParticleStore {
+    ids: Vec<ParticleId>,
+    positions: Vec<cgmath::Vector2<Scalar>>,
+    velocities: Vec<cgmath::Vector2<Scalar>>,
+    accelerations: Vec<cgmath::Vector2<Scalar>>,
+    masses: Vec<Scalar>,
+    radii: Vec<Scalar>,
+    colors: Vec<Color>,
+    fixed: Vec<bool>,
+    names: Vec<Option<Arc<str>>>,
+    charges: Vec<Scalar>,
+    kinds: Vec<BodyKind>,
+    ages: Vec<Scalar>,
+    groups: Vec<u8>,
+    slots: Vec<Slot>,
+    /// Indices of unoccupied slots
+    free: Vec<u32>,
+}
+
+/// Mutable views of the property arrays of a `ParticleStore`, borrowed all at once so
+/// that one array can be updated from another
+pub(crate) struct ColumnsMut<'a> {
+    pub positions: &'a mut [cgmath::Vector2<Scalar>],
+    pub velocities: &'a mut [cgmath::Vector2<Scalar>],
+    pub accelerations: &'a mut [cgmath::Vector2<Scalar>],
+    pub masses: &'a mut [Scalar],
+    pub radii: &'a mut [Scalar],
+    pub ages: &'a mut [Scalar],
+    pub kinds: &'a [BodyKind],
+    pub fixed: &'a [bool],
+}
+
+impl ParticleStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a particle and returns its new handle. Any id the particle had before is
+    /// replaced.
+    pub fn insert(&mut self, particle: Particle) -> ParticleId {
+        let dense = self.len();
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    dense: None,
+                });
+                (self.slots.len() - 1) as u32
+            }
+        };
+        let slot = &mut self.slots[index as usize];
+        slot.generation += 1;
+        slot.dense = Some(dense);
+
+        let id = ParticleId {
+            index,
+            generation: slot.generation,
+        };
+        self.push(id, particle);
+        id
+    }
+
+    /// Puts a removed particle back under its old id, e.g. to undo removing it. Takes a
+    /// new id like `insert` if the old one is still in use or its slot was reused since.
+    pub fn restore(&mut self, particle: Particle) -> ParticleId {
+        let id = particle.id;
+        let reusable = self
+            .slots
+            .get(id.slot())
+            .is_some_and(|slot| slot.generation == id.generation && slot.dense.is_none());
+        let free = self.free.iter().position(|&index| index == id.index);
+        match free {
+            Some(free) if reusable => {
+                self.free.swap_remove(free);
+                self.slots[id.slot()].dense = Some(self.len());
+                self.push(id, particle);
+                id
+            }
+            _ => self.insert(particle),
+        }
+    }
+
+    /// Appends `particle` to the property arrays under `id`
+    fn push(&mut self, id: ParticleId, particle: Particle) {
+        self.ids.push(id);
+        self.positions.push(particle.position);
+        self.velocities.push(particle.velocity);
+        self.accelerations.push(particle.acceleration);
+        self.masses.push(particle.mass);
+        self.radii.push(particle.radius);
+        self.colors.push(particle.color);
+        self.fixed.push(particle.fixed);
+        self.names.push(particle.name);
+        self.charges.push(particle.charge);
+        self.kinds.push(particle.kind);
+        self.ages.push(particle.age);
+        self.groups.push(particle.group);
+    }
+
+    /// Removes the particle behind `id`, returning it if it was still there
+    pub fn remove(&mut self, id: ParticleId) -> Option<Particle> {
+        let dense = self.index_of(id)?;
+        let particle = self.particle(dense);
+        self.slots[id.index as usize].dense = None;
+        self.free.push(id.index);
+
+        self.ids.swap_remove(dense);
+        self.positions.swap_remove(dense);
+        self.velocities.swap_remove(dense);
+        self.accelerations.swap_remove(dense);
+        self.masses.swap_remove(dense);
+        self.radii.swap_remove(dense);
+        self.colors.swap_remove(dense);
+        self.fixed.swap_remove(dense);
+        self.names.swap_remove(dense);
+        self.charges.swap_remove(dense);
+        self.kinds.swap_remove(dense);
+        self.ages.swap_remove(dense);
+        self.groups.swap_remove(dense);
+        if let Some(moved) = self.ids.get(dense) {
+            self.slots[moved.index as usize].dense = Some(dense);
+        }
+        Some(particle)
+    }
+
+    /// Returns a copy of the particle behind `id` if it is still there
+    pub fn get(&self, id: ParticleId) -> Option<Particle> {
+        self.index_of(id).map(|i| self.particle(i))
+    }
+
+    pub fn contains(&self, id: ParticleId) -> bool {
+        self.index_of(id).is_some()
+    }
+
+    /// Current dense index of the particle behind `id`
+    pub fn index_of(&self, id: ParticleId) -> Option<usize> {
+        self.slots
+            .get(id.index as usize)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.dense)
+    }
+
+    /// Gathers the particle at dense index `i` from the property arrays
+    pub fn particle(&self, i: usize) -> Particle {
+        Particle {
+            id: self.ids[i],
+            position: self.positions[i],
+            mass: self.masses[i],
+            radius: self.radii[i],
+            velocity: self.velocities[i],
+            acceleration: self.accelerations[i],
+            color: self.colors[i],
+            fixed: self.fixed[i],
+            name: self.names[i].clone(),
+            charge: self.charges[i],
+            kind: self.kinds[i],
+            age: self.ages[i],
+            group: self.groups[i],
+        }
+    }
+
+    /// Overwrites every property of the particle at dense index `i` except for its id
+    pub(crate) fn set(&mut self, i: usize, particle: &Particle) {
+        self.positions[i] = particle.position;
+        self.masses[i] = particle.mass;
+        self.radii[i] = particle.radius;
+        self.velocities[i] = particle.velocity;
+        self.accelerations[i] = particle.acceleration;
+        self.colors[i] = particle.color;
+        self.fixed[i] = particle.fixed;
+        self.names[i] = particle.name.clone();
+        self.charges[i] = particle.charge;
+        self.kinds[i] = particle.kind;
+        self.ages[i] = particle.age;
+        self.groups[i] = particle.group;
+    }
+
+    /// Copies of all particles in dense order
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = Particle> + '_ {
+        (0..self.len()).map(move |i| self.particle(i))
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    pub fn ids(&self) -> &[ParticleId] {
+        &self.ids
+    }
+
+    pub fn positions(&self) -> &[cgmath::Vector2<Scalar>] {
+        &self.positions
+    }
+
+    pub fn velocities(&self) -> &[cgmath::Vector2<Scalar>] {
+        &self.velocities
+    }
+
+    pub fn accelerations(&self) -> &[cgmath::Vector2<Scalar>] {
+        &self.accelerations
+    }
+
+    pub fn masses(&self) -> &[Scalar] {
+        &self.masses
+    }
+
+    pub fn radii(&self) -> &[Scalar] {
+        &self.radii
+    }
+
+    pub fn colors(&self) -> &[Color] {
+        &self.colors
+    }
+
+    /// Whether each particle is pinned in place
+    pub fn fixed(&self) -> &[bool] {
+        &self.fixed
+    }
+
+    /// Label of each particle, if it has one
+    pub fn names(&self) -> &[Option<Arc<str>>] {
+        &self.names
+    }
+
+    /// Electric charge of each particle
+    pub fn charges(&self) -> &[Scalar] {
+        &self.charges
+    }
+
+    /// What kind of body each particle is
+    pub fn kinds(&self) -> &[BodyKind] {
+        &self.kinds
+    }
+
+    /// Simulated time each particle has existed for
+    pub fn ages(&self) -> &[Scalar] {
+        &self.ages
+    }
+
+    /// Group each particle belongs to
+    pub fn groups(&self) -> &[u8] {
+        &self.groups
+    }
+
+    /// Mass weighted mean position of the particles, or `None` if they have no mass
+    pub fn center_of_mass(&self) -> Option<cgmath::Vector2<Scalar>> {
+        let mass: Scalar = self.masses.iter().sum();
+        if mass <= 0.0 {
+            return None;
+        }
+        let moment = self
+            .positions
+            .iter()
+            .zip(&self.masses)
+            .fold(cgmath::vec2(0.0, 0.0), |sum, (&x, &m)| sum + x * m);
+        Some(moment / mass)
+    }
+
+    /// Mutable access to the properties that change during a step
+    pub(crate) fn columns_mut(&mut self) -> ColumnsMut<'_> {
+        ColumnsMut {
+            positions: &mut self.positions,
+            velocities: &mut self.velocities,
+            accelerations: &mut self.accelerations,
+            masses: &mut self.masses,
+            radii: &mut self.radii,
+            ages: &mut self.ages,
+            kinds: &self.kinds,
+            fixed: &self.fixed,
+        }
+    }
+
+    /// Moves the particles around so that dense index `i` holds the one that was at
+    /// `order[i]`. `order` has to be a permutation of the dense indices. Handles keep
+    /// referring to the same particles.
+    pub fn reorder(&mut self, order: &[usize]) {
+        assert_eq!(order.len(), self.len(), "order has to cover every particle");
+        fn permute<T: Clone>(values: &mut Vec<T>, order: &[usize]) {
+            *values = order.iter().map(|&i| values[i].clone()).collect();
+        }
+        permute(&mut self.ids, order);
+        permute(&mut self.positions, order);
+        permute(&mut self.velocities, order);
+        permute(&mut self.accelerations, order);
+        permute(&mut self.masses, order);
+        permute(&mut self.radii, order);
+        permute(&mut self.colors, order);
+        permute(&mut self.fixed, order);
+        permute(&mut self.names, order);
+        permute(&mut self.charges, order);
+        permute(&mut self.kinds, order);
+        permute(&mut self.ages, order);
+        permute(&mut self.groups, order);
+        for (dense, id) in self.ids.iter().enumerate() {
+            self.slots[id.index as usize].dense = Some(dense);
+        }
+    }
+
+    /// Removes every particle for which `keep` returns false
+    pub fn retain<F: FnMut(&Particle) -> bool>(&mut self, mut keep: F) {
+        let mut i = 0;
+        while i < self.len() {
+            if keep(&self.particle(i)) {
+                i += 1;
+            } else {
+                // The last particle takes its place, so look at index `i` again
+                self.remove(self.ids[i]);
+            }
+        }
+    }
+
+    /// Removes every particle. Handles to them stay invalid.
+    pub fn clear(&mut self) {
+        for id in self.ids.drain(..) {
+            self.slots[id.index as usize].dense = None;
+            self.free.push(id.index);
+        }
+        self.positions.clear();
+        self.velocities.clear();
+        self.accelerations.clear();
+        self.masses.clear();
+        self.radii.clear();
+        self.colors.clear();
+        self.fixed.clear();
+        self.names.clear();
+        self.charges.clear();
+        self.kinds.clear();
+        self.ages.clear();
+        self.groups.clear();
+    }
+}
+
+impl Extend<Particle> for ParticleStore {
+    fn extend<I: IntoIterator<Item = Particle>>(&mut self, particles: I) {
+        for p in particles {
+            self.insert(p);
+        }
+    }
+}
+
+impl FromIterator<Particle> for ParticleStore {
+    fn from_iter<I: IntoIterator<Item = Particle>>(particles: I) -> Self {
+        let mut store = Self::new();
+        store.extend(particles);
+        store
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn particle(mass: Scalar) -> Particle {
+        Particle {
+            position: cgmath::vec2(mass, -mass),
+            mass,
+            radius: 1.0,
+            ..Particle::empty()
+        }
+    }
+
+    #[test]
+    fn it_keeps_handles_valid_across_removals() {
+        let mut store = ParticleStore::new();
+        let ids: Vec<_> = (0..4)
+            .map(|i| store.insert(particle(i as Scalar)))
+            .collect();
+
+        assert_eq!(store.remove(ids[0]).map(|p| p.mass), Some(0.0));
+        assert_eq!(store.len(), 3);
+        for (i, &id) in ids.iter().enumerate().skip(1) {
+            let p = store.get(id).unwrap();
+            assert_eq!(p.id, id);
+            assert_eq!(p.mass, i as Scalar);
+            // The arrays are moved around in lockstep
+            assert_eq!(p.position, cgmath::vec2(p.mass, -p.mass));
+        }
+        assert!(store.remove(ids[0]).is_none());
+    }
+
+    #[test]
+    fn it_weighs_the_center_of_mass_by_mass() {
+        let mut store = ParticleStore::new();
+        assert_eq!(store.center_of_mass(), None);
+        store.insert(particle(0.0));
+        assert_eq!(store.center_of_mass(), None);
+        store.insert(particle(1.0));
+        store.insert(particle(3.0));
+        assert_eq!(store.center_of_mass(), Some(cgmath::vec2(2.5, -2.5)));
+    }
+
+    #[test]
+    fn it_keeps_handles_valid_across_reordering() {
+        let mut store = ParticleStore::new();
+        let ids: Vec<_> = (0..4)
+            .map(|i| store.insert(particle(i as Scalar)))
+            .collect();
+
+        store.reorder(&[2, 0, 3, 1]);
+        assert_eq!(store.masses(), &[2.0, 0.0, 3.0, 1.0]);
+        for (i, &id) in ids.iter().enumerate() {
+            let p = store.get(id).unwrap();
+            assert_eq!(p.mass, i as Scalar);
+            assert_eq!(p.position, cgmath::vec2(p.mass, -p.mass));
+        }
+        assert_eq!(store.index_of(ids[3]), Some(2));
+    }
+
+    #[test]
+    fn it_does_not_reuse_handles_of_removed_particles() {
+        let mut store = ParticleStore::new();
+        let old = store.insert(particle(1.0));
+        store.remove(old);
+        let new = store.insert(particle(2.0));
+
+        assert_ne!(old, new);
+        assert!(store.get(old).is_none());
+        assert!(!store.contains(ParticleId::default()));
+
+        store.clear();
+        assert!(store.is_empty());
+        assert!(store.get(new).is_none());
+    }
+
+    #[test]
+    fn it_restores_removed_particles_under_their_old_handle() {
+        let mut store = ParticleStore::new();
+        let kept = store.insert(particle(1.0));
+        let removed = store.insert(particle(2.0));
+        let p = store.remove(removed).unwrap();
+        assert_eq!(store.restore(p.clone()), removed);
+        assert_eq!(store.get(removed).unwrap().mass, 2.0);
+        assert_eq!(store.get(kept).unwrap().mass, 1.0);
+
+        // A handle still in use or one whose slot was taken since is not given out twice
+        let copy = store.restore(p.clone());
+        assert_ne!(copy, removed);
+        store.remove(removed);
+        let reused = store.insert(particle(3.0));
+        let restored = store.restore(p);
+        assert!(restored != removed && restored != reused);
+        assert_eq!(store.len(), 4);
+    }
+}
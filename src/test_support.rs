@@ -0,0 +1,17 @@
+use crate::primitives::{particle::Particle, scalar::Scalar};
+
+/// `n` positions spread over the square from 0 to 1000 in a fixed pattern, for tests that
+/// need more particles than are worth placing by hand
+pub fn scattered(n: usize) -> impl Iterator<Item = cgmath::Vector2<Scalar>> {
+    (0..n).map(|i| cgmath::vec2((i * 37 % 1000) as Scalar, (i * 91 % 1000) as Scalar))
+}
+
+/// Particle of the given size at `(x, y)`, as heavy as new particles of that size are
+pub fn particle_at(x: Scalar, y: Scalar, radius: Scalar) -> Particle {
+    Particle {
+        position: cgmath::vec2(x, y),
+        mass: 50.0 * radius,
+        radius,
+        ..Particle::empty()
+    }
+}
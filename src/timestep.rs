@@ -0,0 +1,93 @@
+use serde::{Deserialize, Serialize};
+
+use crate::primitives::scalar::Scalar;
+
+/// Picks the time step of every step from the largest acceleration in the system, so
+/// that close encounters are resolved with small steps while quiet stretches move on
+/// with large ones. The step is `eta * sqrt(ε / a_max)` for softening length `ε`,
+/// clamped to `min..=max`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AdaptiveTimeStep {
+    /// Accuracy parameter. Lower takes smaller steps.
+    pub eta: Scalar,
+    /// Smallest step ever taken, however violent an encounter gets
+    pub min: Scalar,
+    /// Largest step ever taken, however quiet the system is
+    pub max: Scalar,
+}
+
+impl AdaptiveTimeStep {
+    pub fn new(eta: Scalar, min: Scalar, max: Scalar) -> Self {
+        Self { eta, min, max }
+    }
+
+    /// Time step for a system whose largest acceleration is `max_acceleration`
+    pub fn time_step(&self, softening: Scalar, max_acceleration: Scalar) -> Scalar {
+        if max_acceleration <= 0.0 {
+            return self.max;
+        }
+        let dt = self.eta * (softening / max_acceleration).sqrt();
+        dt.max(self.min).min(self.max)
+    }
+}
+
+/// Lets every particle step by its own power-of-two fraction `dt / 2^level` of the time
+/// step, so that the few particles in close encounters take small steps without slowing
+/// everything else down. Particles ask for steps of `eta * sqrt(ε / a)` like
+/// `AdaptiveTimeStep` does for the whole system.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BlockTimeSteps {
+    /// Accuracy parameter. Lower takes smaller steps.
+    pub eta: Scalar,
+    /// Deepest level, i.e. the smallest step is `dt / 2^max_level`
+    pub max_level: u32,
+}
+
+impl BlockTimeSteps {
+    pub fn new(eta: Scalar, max_level: u32) -> Self {
+        Self { eta, max_level }
+    }
+
+    /// Level of a particle with an acceleration of magnitude `acceleration` when the
+    /// time step is `time_step`. Its steps are `time_step / 2^level` long.
+    pub fn level(&self, time_step: Scalar, softening: Scalar, acceleration: Scalar) -> u32 {
+        if acceleration <= 0.0 {
+            return 0;
+        }
+        let wanted = self.eta * (softening / acceleration).sqrt();
+        let level = (time_step / wanted).log2().ceil();
+        level.max(0.0).min(self.max_level as Scalar) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_takes_smaller_steps_under_stronger_pulls() {
+        let adaptive = AdaptiveTimeStep::new(0.2, 0.001, 1.0);
+        let weak = adaptive.time_step(1.0, 1.0);
+        let strong = adaptive.time_step(1.0, 100.0);
+        assert!((weak - 0.2).abs() < 1e-6, "{}", weak);
+        assert!((strong - 0.02).abs() < 1e-6, "{}", strong);
+    }
+
+    #[test]
+    fn it_stays_within_bounds() {
+        let adaptive = AdaptiveTimeStep::new(0.2, 0.01, 0.05);
+        assert_eq!(adaptive.time_step(1.0, 0.0), 0.05);
+        assert_eq!(adaptive.time_step(1.0, 1e-6), 0.05);
+        assert_eq!(adaptive.time_step(1.0, 1e6), 0.01);
+    }
+
+    #[test]
+    fn it_picks_the_largest_power_of_two_step_that_is_short_enough() {
+        let blocks = BlockTimeSteps::new(0.2, 4);
+        // Wants steps of 0.2, 0.02 and 0.002
+        assert_eq!(blocks.level(0.1, 1.0, 1.0), 0);
+        assert_eq!(blocks.level(0.1, 1.0, 100.0), 3);
+        assert_eq!(blocks.level(0.1, 1.0, 10000.0), 4);
+        assert_eq!(blocks.level(0.1, 1.0, 0.0), 0);
+    }
+}
@@ -0,0 +1,61 @@
+use std::path::Path;
+use tracing::Subscriber;
+use tracing_chrome::{ChromeLayer, ChromeLayerBuilder, EventOrSpan, FlushGuard};
+use tracing_subscriber::{prelude::*, registry::LookupSpan};
+
+/// Starts recording the spans of every thread into the `layer` trace written to `path`.
+/// Can only be called once.
+pub fn install(path: &Path) -> FlushGuard {
+    let (layer, guard) = layer(path);
+    tracing_subscriber::registry().with(layer).init();
+    guard
+}
+
+/// Records spans as a trace that chrome://tracing and Perfetto open. Events, such as
+/// the GPU time of the render passes, go in as instants named after their target and
+/// keep their fields. The trace is written to `path` in the background and finished
+/// once the returned guard is dropped.
+fn layer<S>(path: &Path) -> (ChromeLayer<S>, FlushGuard)
+where
+    S: Subscriber + for<'span> LookupSpan<'span> + Send + Sync,
+{
+    ChromeLayerBuilder::new()
+        .file(path)
+        .include_args(true)
+        .name_fn(Box::new(|event_or_span| match event_or_span {
+            EventOrSpan::Event(event) => event.metadata().target().to_string(),
+            EventOrSpan::Span(span) => span.name().to_string(),
+        }))
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn it_writes_spans_and_gpu_times_to_the_trace() {
+        let path = std::env::temp_dir().join(format!("nbody-{}.json", Uuid::new_v4()));
+        // Scoped to this thread, so that spans of other tests stay out of the trace
+        let (layer, guard) = layer(&path);
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+            tracing::info_span!("render").in_scope(|| {
+                tracing::info!(target: "gpu", pass = %"scene", micros = 1.5);
+            });
+        });
+        drop(guard);
+
+        let trace = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(trace.starts_with('['));
+        assert!(trace.trim_end().ends_with(']'));
+        let line = |needle: &str| trace.lines().find(|line| line.contains(needle));
+        assert!(line(r#""ph":"B""#).unwrap().contains(r#""name":"render""#));
+        assert!(line(r#""ph":"E""#).unwrap().contains(r#""name":"render""#));
+        let gpu = line(r#""ph":"i""#).unwrap();
+        assert!(gpu.contains(r#""name":"gpu""#));
+        assert!(gpu.contains(r#""pass":"scene""#));
+        assert!(gpu.contains(r#""micros":"1.5""#));
+    }
+}
@@ -0,0 +1,178 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{constants, primitives::scalar::Scalar};
+
+/// Units lengths can be given in
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Length {
+    Meter,
+    Kilometer,
+    /// Astronomical unit, the mean distance between the earth and the sun
+    Au,
+    LightYear,
+    Parsec,
+}
+
+impl Length {
+    pub fn meters(self) -> f64 {
+        match self {
+            Length::Meter => 1.0,
+            Length::Kilometer => 1e3,
+            Length::Au => 1.495_978_707e11,
+            Length::LightYear => 9.460_730_472_580_8e15,
+            Length::Parsec => 3.085_677_581_49e16,
+        }
+    }
+}
+
+/// Units masses can be given in
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Mass {
+    Kilogram,
+    EarthMass,
+    JupiterMass,
+    SolarMass,
+}
+
+impl Mass {
+    pub fn kilograms(self) -> f64 {
+        match self {
+            Mass::Kilogram => 1.0,
+            Mass::EarthMass => 5.972_2e24,
+            Mass::JupiterMass => 1.898_13e27,
+            Mass::SolarMass => 1.988_47e30,
+        }
+    }
+}
+
+/// Units durations can be given in
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Time {
+    Second,
+    Hour,
+    Day,
+    /// Julian year of 365.25 days
+    Year,
+}
+
+impl Time {
+    pub fn seconds(self) -> f64 {
+        match self {
+            Time::Second => 1.0,
+            Time::Hour => 3600.0,
+            Time::Day => 86400.0,
+            Time::Year => 31_557_600.0,
+        }
+    }
+}
+
+/// Physical size of one simulation unit of length, time and mass. The simulation runs
+/// with a gravitational constant of `constants::SIM_G` = 1, so only the length and time
+/// units can be picked freely and the mass unit follows from them: it is the one that
+/// turns `constants::G` into 1 once converted to simulation units.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct UnitSystem {
+    /// Meters per simulation unit of length
+    pub length: f64,
+    /// Seconds per simulation unit of time
+    pub time: f64,
+}
+
+// Conversions are done in f64 whatever the precision of `Scalar` is
+#[allow(clippy::unnecessary_cast)]
+impl UnitSystem {
+    pub fn new(length: f64, time: f64) -> Self {
+        Self { length, time }
+    }
+
+    /// Unit system in which one `length` spans `world_per_length` world units and one
+    /// `time` lasts `sim_per_time` units of simulated time, e.g. an AU drawn as 100
+    /// pixels and a year taking 100 time units
+    pub fn from_scales(
+        length: Length,
+        world_per_length: Scalar,
+        time: Time,
+        sim_per_time: Scalar,
+    ) -> Self {
+        Self::new(
+            length.meters() / world_per_length as f64,
+            time.seconds() / sim_per_time as f64,
+        )
+    }
+
+    /// Kilograms per simulation unit of mass
+    pub fn mass(&self) -> f64 {
+        let g = constants::G as f64 / constants::SIM_G as f64;
+        self.length.powi(3) / (g * self.time * self.time)
+    }
+
+    pub fn to_sim_length(&self, value: Scalar, unit: Length) -> Scalar {
+        (value as f64 * unit.meters() / self.length) as Scalar
+    }
+
+    pub fn to_sim_time(&self, value: Scalar, unit: Time) -> Scalar {
+        (value as f64 * unit.seconds() / self.time) as Scalar
+    }
+
+    pub fn to_sim_mass(&self, value: Scalar, unit: Mass) -> Scalar {
+        (value as f64 * unit.kilograms() / self.mass()) as Scalar
+    }
+
+    /// Converts a velocity given in `length` per `time`
+    pub fn to_sim_velocity(&self, value: Scalar, length: Length, time: Time) -> Scalar {
+        let unit = length.meters() / time.seconds();
+        (value as f64 * unit * self.time / self.length) as Scalar
+    }
+
+    /// Converts an acceleration given in `length` per `time` squared
+    pub fn to_sim_acceleration(&self, value: Scalar, length: Length, time: Time) -> Scalar {
+        let unit = length.meters() / (time.seconds() * time.seconds());
+        (value as f64 * unit * self.time * self.time / self.length) as Scalar
+    }
+
+    pub fn from_sim_length(&self, value: Scalar, unit: Length) -> f64 {
+        value as f64 * self.length / unit.meters()
+    }
+
+    pub fn from_sim_time(&self, value: Scalar, unit: Time) -> f64 {
+        value as f64 * self.time / unit.seconds()
+    }
+
+    pub fn from_sim_mass(&self, value: Scalar, unit: Mass) -> f64 {
+        value as f64 * self.mass() / unit.kilograms()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitives::scalar::consts;
+
+    #[test]
+    fn it_converts_back_and_forth() {
+        let units = UnitSystem::from_scales(Length::Au, 100.0, Time::Year, 50.0);
+        assert!((units.to_sim_length(2.0, Length::Au) - 200.0).abs() < 1e-3);
+        assert!((units.to_sim_time(1.0, Time::Year) - 50.0).abs() < 1e-4);
+        assert!((units.from_sim_length(200.0, Length::Au) - 2.0).abs() < 1e-6);
+        assert!((units.from_sim_time(25.0, Time::Day) - 182.625).abs() < 1e-3);
+        let mass = units.to_sim_mass(1.0, Mass::EarthMass);
+        assert!((units.from_sim_mass(mass, Mass::EarthMass) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn it_keeps_kepler_orbits_intact() {
+        // The earth moves 2π AU per year around one solar mass, which is the circular
+        // speed sqrt(G M / r) once everything is in simulation units with G = 1
+        let units = UnitSystem::from_scales(Length::Au, 250.0, Time::Year, 100.0);
+        let speed = units.to_sim_velocity(2.0 * consts::PI, Length::Au, Time::Year);
+        let sun = units.to_sim_mass(1.0, Mass::SolarMass);
+        let r = units.to_sim_length(1.0, Length::Au);
+        let circular = (constants::SIM_G * sun / r).sqrt();
+        assert!(
+            (speed - circular).abs() < circular * 1e-3,
+            "{} vs {}",
+            speed,
+            circular
+        );
+    }
+}
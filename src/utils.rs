@@ -1,5 +1,7 @@
 use uuid::Uuid;
 
+use crate::primitives::scalar::Scalar;
+
 #[derive(Clone, Copy, Debug)]
 pub struct MinMax<T> {
     pub min: T,
@@ -20,7 +22,7 @@ pub struct ViewportTransformOptions {
 }
 
 /// Window to viewport transformation
-pub fn normalize_window_coordinates(options: &ViewportTransformOptions) -> cgmath::Vector2<f32> {
+pub fn normalize_window_coordinates(options: &ViewportTransformOptions) -> cgmath::Vector2<Scalar> {
     let ViewportTransformOptions {
         window_pos,
         xw,
@@ -34,8 +36,8 @@ pub fn normalize_window_coordinates(options: &ViewportTransformOptions) -> cgmat
     // Scaling factors
     let sx = (xv.max - xv.min) / (xw.max - xw.min);
     let sy = (yv.max - yv.min) / (yw.max - yw.min);
-    let x = (xv.min + (xw_val - xw.min) * sx) as f32;
-    let y = (yv.min + (yw_val - yw.min) * sy) as f32;
+    let x = (xv.min + (xw_val - xw.min) * sx) as Scalar;
+    let y = (yv.min + (yw_val - yw.min) * sy) as Scalar;
 
     cgmath::Vector2::new(x, y)
 }